@@ -0,0 +1,175 @@
+//! alert.rs
+//!
+//! rules the db thread checks live ticks and calculations against, and the alerts they fire; see
+//! db::alert for how they're evaluated. Rules are saved as one json list (config "alerts", default
+//! ./alerts.json) and managed at /api/v1/alerts/rules.
+//!
+//! {"name":"btc_turn","source":"coinbase","symbol":"btc_usd",
+//!  "condition":{"type":"crossover","fast":{"calc":"MovingAvg0100"},"slow":{"calc":"MovingAvg1000"},"direction":"up"},
+//!  "cooldown":"5m","webhook":"https://hooks.example.com/crate"}
+
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+use crate::cb_ticker::Datasource;
+use crate::view::{Resolution, ViewSeries};
+use crate::SymbolCommon;
+
+const NAME_LEN_MAX: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    pub condition: Condition,
+    /// how far back past the level (in the series' units, percentage points for a percent move)
+    /// the value has to go before the rule can fire again
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// nothing more from this rule for this long after it fires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<Resolution>,
+    /// posted the alert as json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// series crosses level
+    Threshold { series: ViewSeries, level: f64, direction: Direction },
+    /// fast crosses slow, eg a short moving average over a long one
+    Crossover { fast: ViewSeries, slow: ViewSeries, direction: Direction },
+    /// series moves at least percent from its oldest value inside window
+    PercentMove { series: ViewSeries, percent: f64, window: Resolution, direction: Direction },
+    /// no tick for the symbol from the source for after; checked on the db heartbeat (10s)
+    Stale { after: Resolution },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Direction {
+    Up,
+    Down,
+    Either,
+}
+
+/// what a rule fired on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule: String,
+    /// the tick's time, or the check's for staleness
+    pub dtg: DateTime<Utc>,
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    /// the series value for a threshold, fast minus slow for a crossover, the percent for a move,
+    /// and seconds since the last tick for staleness
+    pub value: f64,
+    pub message: String,
+}
+
+/// an alert and where its rule wants it posted; the url stays out of the alert since that's shown
+/// to every chart client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fired {
+    pub alert: Alert,
+    pub webhook: Option<String>,
+}
+
+impl AlertRule {
+    pub fn validate(&self) -> Result<(), AlertError> {
+        let invalid = |reason: String| Err(AlertError::Invalid(format!("{}: {reason}", self.name)));
+        if self.name.is_empty() || self.name.len() > NAME_LEN_MAX || !self.name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-') {
+            return Err(AlertError::Name(self.name.clone()));
+        }
+        if !self.hysteresis.is_finite() || self.hysteresis < 0.0 {
+            return invalid(format!("hysteresis {} isn't 0 or more", self.hysteresis));
+        }
+        match &self.condition {
+            Condition::Threshold { level, .. } if !level.is_finite() => return invalid(format!("level {level} isn't a number")),
+            Condition::Crossover { fast, slow, .. } if fast == slow => return invalid(format!("{} can't cross itself", fast.name())),
+            Condition::PercentMove { percent, .. } if !percent.is_finite() || *percent <= 0.0 => return invalid(format!("percent {percent} isn't above 0")),
+            _ => {}
+        }
+        if let Some(url) = &self.webhook {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return invalid(format!("webhook {url} isn't an http url"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// every rule in path; no file means no rules yet
+pub fn load_rules(path: &Path) -> Result<Vec<AlertRule>, AlertError> {
+    let rules: Vec<AlertRule> = match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| AlertError::Json(format!("{path:?}: {e}")))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(AlertError::Io(format!("{path:?}: {e}"))),
+    };
+    for rule in rules.iter() {
+        rule.validate()?;
+    }
+    Ok(rules)
+}
+
+/// replace the file, through a temp file so a crash can't leave half a list
+pub fn save_rules(path: &Path, rules: &[AlertRule]) -> Result<(), AlertError> {
+    let json = serde_json::to_vec_pretty(rules).map_err(|e| AlertError::Json(e.to_string()))?;
+    if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| AlertError::Io(e.to_string()))?;
+    }
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, json).map_err(|e| AlertError::Io(e.to_string()))?;
+    fs::rename(&temp, path).map_err(|e| AlertError::Io(e.to_string()))
+}
+
+#[derive(Debug, Display)]
+pub enum AlertError {
+    Name(String),
+    Invalid(String),
+    NotFound(String),
+    Json(String),
+    Io(String),
+}
+
+impl std::error::Error for AlertError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::alert::{load_rules, save_rules, AlertError, AlertRule, Condition, Direction};
+    use crate::cb_ticker::Datasource;
+    use crate::view::{Resolution, ViewSeries};
+    use crate::{CalculationId, SymbolCommon};
+
+    #[test]
+    fn test_rules_json() {
+        let json = r#"[
+            {"name":"btc_turn","source":"coinbase","symbol":"btc_usd","cooldown":"5m","webhook":"http://127.0.0.1:9000/hook",
+             "condition":{"type":"crossover","fast":{"calc":"MovingAvg0100"},"slow":{"calc":"MovingAvg1000"},"direction":"up"}},
+            {"name":"quiet","source":"alpaca","symbol":"btc_usd","condition":{"type":"stale","after":"1m"}}
+        ]"#;
+        let rules: Vec<AlertRule> = serde_json::from_str(json).unwrap();
+        assert_eq!(rules[0].condition, Condition::Crossover { fast: ViewSeries::Calc(CalculationId::MovingAvg0100), slow: ViewSeries::Calc(CalculationId::MovingAvg1000), direction: Direction::Up });
+        assert_eq!(rules[0].cooldown, Some(Resolution { millis: 300_000 }));
+        assert_eq!(rules[1].hysteresis, 0.0);
+        assert!(rules.iter().all(|x| x.validate().is_ok()));
+
+        let path = std::env::temp_dir().join(format!("test_rules_json_{}", std::process::id())).join("alerts.json");
+        assert!(load_rules(&path).unwrap().is_empty());
+        save_rules(&path, &rules).unwrap();
+        assert_eq!(load_rules(&path).unwrap(), rules);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let rule = |name: &str, condition: Condition| AlertRule { name: name.to_string(), source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, condition, hysteresis: 0.0, cooldown: None, webhook: None };
+        assert!(matches!(rule("a b", Condition::Stale { after: Resolution { millis: 1000 } }).validate(), Err(AlertError::Name(_))));
+        assert!(matches!(rule("x", Condition::Crossover { fast: ViewSeries::Price, slow: ViewSeries::Price, direction: Direction::Up }).validate(), Err(AlertError::Invalid(_))));
+        assert!(matches!(rule("x", Condition::PercentMove { series: ViewSeries::Price, percent: 0.0, window: Resolution { millis: 1000 }, direction: Direction::Either }).validate(), Err(AlertError::Invalid(_))));
+        assert!(matches!(AlertRule { webhook: Some("ftp://x".to_string()), ..rule("x", Condition::Threshold { series: ViewSeries::Price, level: 1.0, direction: Direction::Up }) }.validate(), Err(AlertError::Invalid(_))));
+    }
+}
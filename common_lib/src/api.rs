@@ -0,0 +1,73 @@
+//! api.rs
+//!
+//! what the versioned json api (/api/v1) asks the db thread for and gets back
+//!
+//! GET /api/v1/series/coinbase/btc_usd/MovingAvg0100?start=2024-01-14T23:30:00Z&limit=500&resolution=1s
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::cb_ticker::Datasource;
+use crate::view::{Resolution, ViewSeries};
+use crate::SymbolCommon;
+
+pub const API_LIMIT_DEFAULT: usize = 1000;
+pub const API_LIMIT_MAX: usize = 100_000;
+
+/// a series the db has points for: a symbol's price or one of its calculations, from one source
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeriesInfo {
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    /// "price" or the calculation id; the last segment of the series path
+    pub series: String,
+    /// same label as the charts use
+    pub label: String,
+    pub points: usize,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+}
+
+/// one series' points with start <= dtg < end, oldest first; the newest limit points (or buckets,
+/// with a resolution) are kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesQuery {
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    pub series: ViewSeries,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub resolution: Option<Resolution>,
+    pub limit: usize,
+}
+
+impl SeriesQuery {
+    pub fn new(source: Datasource, symbol: SymbolCommon, series: ViewSeries) -> SeriesQuery {
+        SeriesQuery { source, symbol, series, start: None, end: None, resolution: None, limit: API_LIMIT_DEFAULT }
+    }
+
+    pub fn start(mut self, start: DateTime<Utc>) -> SeriesQuery {
+        self.start = Some(start);
+        self
+    }
+
+    pub fn end(mut self, end: DateTime<Utc>) -> SeriesQuery {
+        self.end = Some(end);
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> SeriesQuery {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// capped at API_LIMIT_MAX
+    pub fn limit(mut self, limit: usize) -> SeriesQuery {
+        self.limit = limit.min(API_LIMIT_MAX);
+        self
+    }
+
+    /// chart label of the series
+    pub fn label(&self) -> String {
+        self.series.label(&self.symbol, &self.source)
+    }
+}
@@ -0,0 +1,325 @@
+//! auth.rs
+//!
+//! local users for the web ui, the json apis, flight sql and postgres. Each user is read-only or
+//! an admin and logs in with a password (argon2) or an api token (only its sha-256 is stored).
+//! Users come from the json file given by "--users <file>":
+//!
+//! {"users":[
+//!     {"name":"gp","role":"admin","password":"$argon2id$v=19$m=19456,t=2,p=1$..."},
+//!     {"name":"grafana","role":"read","tokens":["5e88489...8a5e"]}
+//! ]}
+//!
+//! Without a users file auth is off and every caller is an admin, which is only safe on localhost.
+//! `main --hash-password` (password on stdin) and `main --new-token` print the values to store.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use strum_macros::{Display, EnumString};
+use crate::config::AuthConfig;
+
+/// how long a token from a login (a flight sql handshake) lasts
+pub const SESSION_TTL_HOURS: i64 = 12;
+
+/// bytes of randomness in a token
+const TOKEN_BYTES: usize = 32;
+
+/// read-only callers can query and chart; admins can also write points and save dashboards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display, EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Role {
+    Read,
+    Admin,
+}
+
+/// who a request is from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Caller {
+    pub fn allows(&self, required: Role) -> bool {
+        self.role >= required
+    }
+}
+
+/// a user as stored in the users file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub name: String,
+    pub role: Role,
+    /// argon2 phc string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// sha-256 of each api token, hex
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsersFile {
+    pub users: Vec<UserRecord>,
+}
+
+/// what an authorization header (or a postgres login) carries
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credentials {
+    /// "Bearer <token>", or influx's "Token <token>"
+    Token(String),
+    /// "Basic base64(name:password)"
+    Basic { name: String, password: String },
+}
+
+impl Credentials {
+    pub fn from_header(header: &str) -> Option<Credentials> {
+        let (scheme, value) = header.trim().split_once(' ')?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "bearer" | "token" => Some(Credentials::Token(value.trim().to_string())),
+            "basic" => {
+                let decoded = String::from_utf8(BASE64.decode(value.trim()).ok()?).ok()?;
+                let (name, password) = decoded.split_once(':')?;
+                Some(Credentials::Basic { name: name.to_string(), password: password.to_string() })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// who may connect, shared by every server; cheap to clone
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    users: Option<Arc<Users>>,
+    /// tokens issued at login, by digest
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+#[derive(Debug)]
+struct Session {
+    caller: Caller,
+    expires: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Users {
+    by_name: HashMap<String, UserRecord>,
+    /// token digest to user name
+    tokens: HashMap<String, String>,
+}
+
+impl Auth {
+    /// auth off: every caller is an admin
+    pub fn open() -> Auth {
+        Auth::default()
+    }
+
+    pub fn new(file: UsersFile) -> Result<Auth, AuthError> {
+        let mut by_name = HashMap::new();
+        let mut tokens = HashMap::new();
+        for user in file.users {
+            if user.name.is_empty() || by_name.contains_key(&user.name) {
+                return Err(AuthError::Users(format!("user name \"{}\" is empty or repeated", user.name)));
+            }
+            if user.password.is_none() && user.tokens.is_empty() {
+                return Err(AuthError::Users(format!("{} has no password or token", user.name)));
+            }
+            if let Some(password) = &user.password {
+                PasswordHash::new(password).map_err(|e| AuthError::Users(format!("{}: password isn't an argon2 hash: {e}", user.name)))?;
+            }
+            for digest in &user.tokens {
+                if digest.len() != 64 || !digest.chars().all(|x| x.is_ascii_hexdigit()) {
+                    return Err(AuthError::Users(format!("{}: token isn't a sha-256 hex digest", user.name)));
+                }
+                if tokens.insert(digest.to_ascii_lowercase(), user.name.clone()).is_some() {
+                    return Err(AuthError::Users(format!("{}: token is also another user's", user.name)));
+                }
+            }
+            by_name.insert(user.name.clone(), user);
+        }
+        Ok(Auth { users: Some(Arc::new(Users { by_name, tokens })), sessions: Arc::default() })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Auth, AuthError> {
+        let bytes = std::fs::read(path.as_ref()).map_err(|e| AuthError::Users(format!("{:?}: {e}", path.as_ref())))?;
+        let file: UsersFile = serde_json::from_slice(&bytes).map_err(|e| AuthError::Users(format!("{:?}: {e}", path.as_ref())))?;
+        Auth::new(file)
+    }
+
+    /// the users file in config, else auth is off
+    pub fn from_config(config: &AuthConfig) -> Result<Auth, AuthError> {
+        match &config.users {
+            Some(path) => Auth::from_file(path),
+            None => Ok(Auth::open()),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.users.is_none()
+    }
+
+    /// name and password; slow on purpose (argon2), so run it off async workers
+    pub fn login(&self, name: &str, password: &str) -> Option<Caller> {
+        let Some(users) = &self.users else {
+            return Some(Caller { name: name.to_string(), role: Role::Admin });
+        };
+        match users.by_name.get(name) {
+            Some(UserRecord { password: Some(hash), role, .. }) if verify_password(password, hash) => Some(Caller { name: name.to_string(), role: *role }),
+            Some(_) => None,
+            None => {
+                // as slow as a wrong password, so names can't be probed
+                verify_password(password, dummy_hash());
+                None
+            }
+        }
+    }
+
+    /// an api token, or one issued at login
+    pub fn token(&self, token: &str) -> Option<Caller> {
+        let Some(users) = &self.users else {
+            return Some(Caller { name: "anonymous".to_string(), role: Role::Admin });
+        };
+        let digest = token_digest(token);
+        if let Some(user) = users.tokens.get(&digest).and_then(|name| users.by_name.get(name)) {
+            return Some(Caller { name: user.name.clone(), role: user.role });
+        }
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        match sessions.get(&digest) {
+            Some(session) if session.expires > Utc::now() => Some(session.caller.clone()),
+            Some(_) => {
+                sessions.remove(&digest);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn credentials(&self, credentials: &Credentials) -> Option<Caller> {
+        match credentials {
+            Credentials::Token(token) => self.token(token),
+            Credentials::Basic { name, password } => self.login(name, password),
+        }
+    }
+
+    /// a user named by a session cookie, if they still exist
+    pub fn user(&self, name: &str) -> Option<Caller> {
+        match &self.users {
+            None => Some(Caller { name: name.to_string(), role: Role::Admin }),
+            Some(users) => users.by_name.get(name).map(|user| Caller { name: user.name.clone(), role: user.role }),
+        }
+    }
+
+    /// a token for caller good for SESSION_TTL_HOURS
+    pub fn issue(&self, caller: &Caller) -> String {
+        let (token, digest) = new_token();
+        let now = Utc::now();
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(digest, Session { caller: caller.clone(), expires: now + Duration::hours(SESSION_TTL_HOURS) });
+        token
+    }
+}
+
+/// an argon2 phc string for the users file
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map(|x| x.to_string())
+        .map_err(|e| AuthError::Hash(e.to_string()))
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("dummy").unwrap_or_default())
+}
+
+/// a random token and the digest to store for it
+pub fn new_token() -> (String, String) {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    let token = hex(&bytes);
+    let digest = token_digest(&token);
+    (token, digest)
+}
+
+/// sha-256 of a token, hex; what the users file stores
+pub fn token_digest(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+#[derive(Debug, Display)]
+pub enum AuthError {
+    Users(String),
+    Hash(String),
+}
+
+impl std::error::Error for AuthError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{hash_password, new_token, token_digest, Auth, Caller, Credentials, Role, UserRecord, UsersFile};
+    use crate::config::AuthConfig;
+
+    #[test]
+    fn test_auth() {
+        let (token, digest) = new_token();
+        assert_eq!(token.len(), 64);
+        assert_eq!(token_digest(&token), digest);
+        let auth = Auth::new(UsersFile { users: vec![
+            UserRecord { name: "gp".to_string(), role: Role::Admin, password: Some(hash_password("hunter2").unwrap()), tokens: vec![] },
+            UserRecord { name: "grafana".to_string(), role: Role::Read, password: None, tokens: vec![digest] },
+        ]}).unwrap();
+        assert!(!auth.is_open());
+
+        let gp = Caller { name: "gp".to_string(), role: Role::Admin };
+        assert_eq!(auth.login("gp", "hunter2"), Some(gp.clone()));
+        assert_eq!(auth.login("gp", "hunter3"), None);
+        assert_eq!(auth.login("nobody", "hunter2"), None);
+        assert_eq!(auth.login("grafana", ""), None);
+
+        let grafana = auth.token(&token).unwrap();
+        assert_eq!(grafana.name, "grafana");
+        assert!(grafana.allows(Role::Read) && !grafana.allows(Role::Admin));
+        assert_eq!(auth.token("nope"), None);
+        assert_eq!(auth.user("gp"), Some(gp.clone()));
+        assert_eq!(auth.user("nobody"), None);
+
+        let issued = auth.issue(&gp);
+        assert_eq!(auth.token(&issued), Some(gp.clone()));
+        assert_eq!(auth.credentials(&Credentials::from_header(&format!("Bearer {issued}")).unwrap()), Some(gp));
+        assert_eq!(Credentials::from_header("Basic Z3A6aHVudGVyMg=="), Some(Credentials::Basic { name: "gp".to_string(), password: "hunter2".to_string() }));
+        assert_eq!(Credentials::from_header("Token abc"), Some(Credentials::Token("abc".to_string())));
+        assert_eq!(Credentials::from_header("Digest abc"), None);
+
+        assert_eq!(Auth::open().token("anything").map(|x| x.role), Some(Role::Admin));
+        for bad in [
+            r#"{"users":[{"name":"a","role":"read"}]}"#,
+            r#"{"users":[{"name":"a","role":"read","password":"plaintext"}]}"#,
+            r#"{"users":[{"name":"a","role":"read","tokens":["abc"]}]}"#,
+            r#"{"users":[{"name":"a","role":"read","tokens":["0000000000000000000000000000000000000000000000000000000000000000"]},{"name":"a","role":"admin","tokens":["1111111111111111111111111111111111111111111111111111111111111111"]}]}"#,
+        ] {
+            assert!(Auth::new(serde_json::from_str(bad).unwrap()).is_err(), "{bad}");
+        }
+
+        assert!(Auth::from_config(&AuthConfig::default()).unwrap().is_open());
+        assert!(Auth::from_config(&AuthConfig { users: Some("/nonexistent/users.json".into()) }).is_err());
+    }
+}
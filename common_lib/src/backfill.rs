@@ -0,0 +1,180 @@
+//! backfill.rs
+//!
+//! describes a file of historical ticks or bars to load into the event book
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
+use crate::cb_ticker::Datasource;
+
+#[derive(Debug, Clone, PartialEq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum BackfillFormat {
+    Csv,
+    Parquet,
+}
+
+/// ticks carry a single price; bars are loaded using their close
+#[derive(Debug, Clone, PartialEq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum BackfillKind {
+    Tick,
+    Bar,
+}
+
+/// which column in the file holds each TickerCommon field
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfillColumns {
+    pub dtg: String,
+    pub symbol: String,
+    pub price: String,
+}
+
+impl BackfillColumns {
+    pub fn new(kind: &BackfillKind) -> BackfillColumns {
+        BackfillColumns {
+            dtg: "dtg".to_string(),
+            symbol: "symbol".to_string(),
+            price: match kind {
+                BackfillKind::Tick => "price".to_string(),
+                BackfillKind::Bar => "close".to_string(),
+            },
+        }
+    }
+
+    /// override defaults from "dtg=time,symbol=product_id,price=close"
+    pub fn with_mapping(mut self, mapping: &str) -> Result<BackfillColumns, BackfillError> {
+        for pair in mapping.split(',').filter(|x| !x.is_empty()) {
+            match pair.split_once('=') {
+                Some(("dtg", col)) => self.dtg = col.to_string(),
+                Some(("symbol", col)) => self.symbol = col.to_string(),
+                Some(("price", col)) => self.price = col.to_string(),
+                _ => return Err(BackfillError::ColumnMapping(pair.to_string())),
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfillSpec {
+    pub source: Datasource,
+    pub path: PathBuf,
+    pub format: BackfillFormat,
+    pub kind: BackfillKind,
+    pub columns: BackfillColumns,
+}
+
+impl BackfillSpec {
+    /// tick file with default column names; format comes from the file extension
+    pub fn new(source: Datasource, path: &str) -> Result<BackfillSpec, BackfillError> {
+        let path = PathBuf::from(path);
+        let format = match path.extension().and_then(|x| x.to_str()) {
+            Some(ext) => BackfillFormat::from_str(&ext.to_lowercase()).map_err(|_| BackfillError::Format(ext.to_string()))?,
+            None => return Err(BackfillError::Format(path.display().to_string())),
+        };
+        Ok(BackfillSpec {
+            source,
+            path,
+            format,
+            kind: BackfillKind::Tick,
+            columns: BackfillColumns::new(&BackfillKind::Tick),
+        })
+    }
+
+    /// switch to bars, resetting the price column to the bar close
+    pub fn bars(mut self) -> BackfillSpec {
+        self.kind = BackfillKind::Bar;
+        self.columns.price = BackfillColumns::new(&BackfillKind::Bar).price;
+        self
+    }
+
+    pub fn columns(mut self, mapping: &str) -> Result<BackfillSpec, BackfillError> {
+        self.columns = self.columns.with_mapping(mapping)?;
+        Ok(self)
+    }
+
+    /// parse the command line form "coinbase=ticks.csv" or "alpaca:bar=bars.parquet"
+    pub fn from_arg(arg: &str) -> Result<BackfillSpec, BackfillError> {
+        let (source, path) = arg.split_once('=').ok_or_else(|| BackfillError::Argument(arg.to_string()))?;
+        let (source, kind) = match source.split_once(':') {
+            Some((source, kind)) => (source, BackfillKind::from_str(kind).map_err(|_| BackfillError::Argument(arg.to_string()))?),
+            None => (source, BackfillKind::Tick),
+        };
+        let source = Datasource::from_str(source).map_err(|_| BackfillError::Argument(arg.to_string()))?;
+        let spec = BackfillSpec::new(source, path)?;
+        match kind {
+            BackfillKind::Tick => Ok(spec),
+            BackfillKind::Bar => Ok(spec.bars()),
+        }
+    }
+
+    /// collect every "--backfill <source>=<path>" from the command line; a following
+    /// "--backfill-columns dtg=..,symbol=..,price=.." applies to the file before it
+    pub fn from_args(args: &[String]) -> Result<Vec<BackfillSpec>, BackfillError> {
+        let mut specs: Vec<BackfillSpec> = vec![];
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--backfill" => {
+                    let value = iter.next().ok_or_else(|| BackfillError::Argument(arg.to_string()))?;
+                    specs.push(BackfillSpec::from_arg(value)?);
+                }
+                "--backfill-columns" => {
+                    let value = iter.next().ok_or_else(|| BackfillError::Argument(arg.to_string()))?;
+                    let spec = specs.pop().ok_or_else(|| BackfillError::Argument(arg.to_string()))?;
+                    specs.push(spec.columns(value)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(specs)
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum BackfillError {
+    Argument(String),
+    Format(String),
+    ColumnMapping(String),
+}
+
+impl std::error::Error for BackfillError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::backfill::{BackfillFormat, BackfillKind, BackfillSpec};
+    use crate::cb_ticker::Datasource;
+
+    #[test]
+    fn test_from_arg() {
+        let spec = BackfillSpec::from_arg("coinbase=tests/data/ticks.csv").unwrap();
+        assert_eq!(spec.source, Datasource::Coinbase);
+        assert_eq!(spec.format, BackfillFormat::Csv);
+        assert_eq!(spec.kind, BackfillKind::Tick);
+        assert_eq!(spec.columns.price, "price");
+
+        let spec = BackfillSpec::from_arg("alpaca:bar=bars.PARQUET").unwrap().columns("dtg=t,symbol=S").unwrap();
+        assert_eq!(spec.source, Datasource::Alpaca);
+        assert_eq!(spec.format, BackfillFormat::Parquet);
+        assert_eq!(spec.columns.dtg, "t");
+        assert_eq!(spec.columns.symbol, "S");
+        assert_eq!(spec.columns.price, "close");
+
+        assert!(BackfillSpec::from_arg("coinbase=ticks.json").is_err());
+        assert!(BackfillSpec::from_arg("kraken=ticks.csv").is_err());
+        assert!(BackfillSpec::from_arg("coinbase=ticks.csv").unwrap().columns("volume=v").is_err());
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args: Vec<String> = ["main", "--backfill", "coinbase=a.csv", "--backfill-columns", "dtg=time", "--backfill", "alpaca:bar=b.parquet"].iter().map(|x| x.to_string()).collect();
+        let specs = BackfillSpec::from_args(&args).unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].columns.dtg, "time");
+        assert_eq!(specs[1].kind, BackfillKind::Bar);
+
+        assert!(BackfillSpec::from_args(&["--backfill-columns".to_string(), "dtg=time".to_string()]).is_err());
+        assert!(BackfillSpec::from_args(&["--backfill".to_string()]).is_err());
+    }
+}
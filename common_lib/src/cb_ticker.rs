@@ -2,13 +2,14 @@
 
 use chrono::{DateTime, Utc};
 use serde::de::Error;
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
-use strum_macros::{Display, EnumIter};
+use strum_macros::{Display, EnumIter, EnumString};
 use crate::{CalculationId, SymbolCommon, TickerCommon};
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash, Display)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Display, EnumString, EnumIter)]
 // #[strum(serialize_all = "snake_case")]
+#[strum(ascii_case_insensitive)]
 #[serde(rename_all = "snake_case")]
 pub enum Datasource {
     Coinbase,
@@ -26,6 +27,8 @@ pub struct TickerCoinbase {
     pub symbol: SymbolCoinbase,
     #[serde(deserialize_with = "f64_from_str")]
     pub price: f64,
+    pub trade_id: Option<u64>,
+    pub sequence: Option<u64>,
 }
 
 impl TickerCoinbase {
@@ -35,6 +38,7 @@ impl TickerCoinbase {
             dtg: self.dtg,
             symbol: self.symbol.to_common(),
             price: self.price,
+            trade_id: self.trade_id.or(self.sequence),
         }
     }
 }
@@ -0,0 +1,631 @@
+//! config.rs
+//!
+//! every setting in one place. Defaults, then a toml file, then environment variables, then the
+//! command line; the file is deserialized over the defaults (what it leaves out keeps its default,
+//! an unknown key is an error), the other two go through Config::set. The result is validated
+//! before anything starts.
+//!
+//! cargo run -p main -- --config crate.toml --set retention.chart_limit=2000
+//!
+//! An environment variable is a key upper cased with "__" for each dot, eg
+//! CRATE_LISTENERS__HTTP=0.0.0.0:8080 or CRATE_FEEDS__ALPACA__ENABLED=false; lists are comma
+//! separated. The older COINBASE_URL, ALPACA_CRYPTOCURRENCY_URL, ALPACA_API_ID and
+//! ALPACA_API_SECRET still work. Secrets are never serialized, so a Config can be shown as is.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use strum::IntoEnumIterator;
+use strum_macros::Display;
+use crate::watermark::Lateness;
+use crate::{CalculationId, SymbolCommon};
+
+pub const HTTP_ADDR: &str = "127.0.0.1:8080";
+pub const FLIGHT_SQL_ADDR: &str = "127.0.0.1:50051";
+pub const PG_SERVER_ADDR: &str = "127.0.0.1:5432";
+pub const INFLUX_UDP_ADDR: &str = "127.0.0.1:8089";
+pub const COINBASE_URL: &str = "wss://ws-feed.exchange.coinbase.com";
+pub const ALPACA_CRYPTOCURRENCY_URL: &str = "wss://stream.data.alpaca.markets/v1beta3/crypto/us";
+/// newest points per series in a chart
+pub const CHART_LIMIT: usize = 1000;
+/// venue ids remembered per series; a repeat older than this many trades gets through
+pub const DEDUP_WINDOW: usize = 10_000;
+pub const DASHBOARD_DIR: &str = "dashboards";
+pub const ALERT_RULES: &str = "alerts.json";
+pub const STRATEGIES: &str = "strategies.json";
+
+const ENV_PREFIX: &str = "CRATE_";
+/// the file to read when there's no --config
+const ENV_CONFIG: &str = "CRATE_CONFIG";
+const REDACTED: &str = "********";
+
+/// env names from before this file, still read so existing .env files work
+const LEGACY_ENV: [(&str, &str); 4] = [
+    ("COINBASE_URL", "feeds.coinbase.url"),
+    ("ALPACA_CRYPTOCURRENCY_URL", "feeds.alpaca.url"),
+    ("ALPACA_API_ID", "feeds.alpaca.key_id"),
+    ("ALPACA_API_SECRET", "feeds.alpaca.secret"),
+];
+
+/// command line flags and the key each one sets; "--set <key>=<value>" sets any key
+const ARGS: [(&str, &str); 10] = [
+    ("--http-addr", "listeners.http"),
+    ("--tls-cert", "listeners.tls.cert"),
+    ("--tls-key", "listeners.tls.key"),
+    ("--tls-ocsp", "listeners.tls.ocsp"),
+    ("--tls-reload", "listeners.tls.reload"),
+    ("--users", "auth.users"),
+    ("--dashboards", "dashboards"),
+    ("--alerts", "alerts"),
+    ("--strategies", "strategies"),
+    ("--lateness", "retention.lateness"),
+];
+
+/// every key Config::set takes
+pub const KEYS: [&str; 26] = [
+    "feeds.coinbase.enabled", "feeds.coinbase.url", "feeds.coinbase.symbols", "feeds.coinbase.key_id", "feeds.coinbase.secret",
+    "feeds.alpaca.enabled", "feeds.alpaca.url", "feeds.alpaca.symbols", "feeds.alpaca.key_id", "feeds.alpaca.secret",
+    "retention.lateness", "retention.chart_limit", "retention.dedup_window",
+    "indicators",
+    "listeners.http", "listeners.flight_sql", "listeners.pg", "listeners.influx_udp",
+    "listeners.tls.cert", "listeners.tls.key", "listeners.tls.ocsp", "listeners.tls.reload",
+    "auth.users",
+    "dashboards",
+    "alerts",
+    "strategies",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub feeds: Feeds,
+    pub retention: Retention,
+    /// calculations kept for every symbol, see db::calculation
+    pub indicators: Vec<CalculationId>,
+    pub listeners: Listeners,
+    pub auth: AuthConfig,
+    /// saved dashboard layouts
+    pub dashboards: PathBuf,
+    /// alert rules, see alert.rs
+    pub alerts: PathBuf,
+    /// paper trading strategies, see paper.rs
+    pub strategies: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Feeds {
+    #[serde(deserialize_with = "coinbase")]
+    pub coinbase: Feed,
+    #[serde(deserialize_with = "alpaca")]
+    pub alpaca: Feed,
+}
+
+impl Default for Feeds {
+    fn default() -> Self {
+        let feed = |url: &str, symbols: Vec<SymbolCommon>| Feed { enabled: true, url: url.to_string(), symbols, key_id: None, secret: None };
+        Feeds {
+            coinbase: feed(COINBASE_URL, SymbolCommon::iter().collect()),
+            alpaca: feed(ALPACA_CRYPTOCURRENCY_URL, vec![SymbolCommon::BtcUsd]),
+        }
+    }
+}
+
+/// a venue websocket and what to subscribe to
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Feed {
+    pub enabled: bool,
+    pub url: String,
+    pub symbols: Vec<SymbolCommon>,
+    #[serde(serialize_with = "redact")]
+    pub key_id: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub secret: Option<String>,
+}
+
+/// a [feeds.<venue>] table; what it leaves out keeps the venue's default
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeedTable {
+    enabled: Option<bool>,
+    url: Option<String>,
+    symbols: Option<Vec<SymbolCommon>>,
+    key_id: Option<String>,
+    secret: Option<String>,
+}
+
+impl FeedTable {
+    fn over(self, mut feed: Feed) -> Feed {
+        feed.enabled = self.enabled.unwrap_or(feed.enabled);
+        feed.url = self.url.unwrap_or(feed.url);
+        feed.symbols = self.symbols.unwrap_or(feed.symbols);
+        feed.key_id = self.key_id.filter(|x| !x.is_empty()).or(feed.key_id);
+        feed.secret = self.secret.filter(|x| !x.is_empty()).or(feed.secret);
+        feed
+    }
+}
+
+/// how much the db keeps and hands back
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    #[serde(serialize_with = "display", deserialize_with = "from_str")]
+    pub lateness: Lateness,
+    pub chart_limit: usize,
+    pub dedup_window: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention { lateness: Lateness::default(), chart_limit: CHART_LIMIT, dedup_window: DEDUP_WINDOW }
+    }
+}
+
+/// "ip:port" for each server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listeners {
+    pub http: String,
+    pub flight_sql: String,
+    pub pg: String,
+    pub influx_udp: String,
+    pub tls: TlsSettings,
+}
+
+/// the web server is https when cert and key are set, see visual::tls
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    #[serde(deserialize_with = "optional_path")]
+    pub cert: Option<PathBuf>,
+    #[serde(deserialize_with = "optional_path")]
+    pub key: Option<PathBuf>,
+    /// a stapled ocsp response, der
+    #[serde(deserialize_with = "optional_path")]
+    pub ocsp: Option<PathBuf>,
+    #[serde(serialize_with = "interval", deserialize_with = "optional_interval")]
+    pub reload: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// no users file means auth is off
+    #[serde(deserialize_with = "optional_path")]
+    pub users: Option<PathBuf>,
+}
+
+impl Default for Listeners {
+    fn default() -> Self {
+        Listeners {
+            http: HTTP_ADDR.to_string(),
+            flight_sql: FLIGHT_SQL_ADDR.to_string(),
+            pg: PG_SERVER_ADDR.to_string(),
+            influx_udp: INFLUX_UDP_ADDR.to_string(),
+            tls: TlsSettings::default(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            feeds: Feeds::default(),
+            retention: Retention::default(),
+            indicators: vec![
+                CalculationId::MovingAvg0010,
+                CalculationId::MovingAvg0100,
+                CalculationId::MovingAvg1000,
+                CalculationId::MovAvgDiff0100_1000,
+                CalculationId::MovAvgDiffSlope0100_1000,
+            ],
+            listeners: Listeners::default(),
+            auth: AuthConfig::default(),
+            dashboards: PathBuf::from(DASHBOARD_DIR),
+            alerts: PathBuf::from(ALERT_RULES),
+            strategies: PathBuf::from(STRATEGIES),
+        }
+    }
+}
+
+impl Config {
+    /// the file from "--config <toml>" (or CRATE_CONFIG), then the environment, then the rest of
+    /// the command line; call after init so .env is loaded
+    pub fn from_args(args: &[String]) -> Result<Config, ConfigError> {
+        let mut path = std::env::var(ENV_CONFIG).ok().map(PathBuf::from);
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--config" {
+                path = Some(PathBuf::from(iter.next().ok_or_else(|| ConfigError::Argument(arg.to_string()))?));
+            }
+        }
+        let toml = match &path {
+            Some(path) => Some(std::fs::read_to_string(path).map_err(|e| ConfigError::Io(format!("{path:?}: {e}")))?),
+            None => None,
+        };
+        Config::build(toml.as_deref(), std::env::vars(), args)
+    }
+
+    /// from_args without the process environment, for tests
+    pub fn build(toml: Option<&str>, env: impl IntoIterator<Item = (String, String)>, args: &[String]) -> Result<Config, ConfigError> {
+        let mut config = match toml {
+            Some(toml) => toml::from_str::<Config>(toml).map_err(|e| ConfigError::Toml(e.to_string()))?,
+            None => Config::default(),
+        };
+        for (key, value) in env_pairs(env)? {
+            config.set(&key, &value)?;
+        }
+        for (key, value) in arg_pairs(args)? {
+            config.set(&key, &value)?;
+        }
+        // without keys there's nothing alpaca would accept; run the other feeds rather than none
+        let alpaca = &mut config.feeds.alpaca;
+        if alpaca.enabled && (alpaca.key_id.is_none() || alpaca.secret.is_none()) {
+            tracing::warn!("[Config::build] feeds.alpaca has no key_id and secret (ALPACA_API_ID and ALPACA_API_SECRET); feed disabled");
+            alpaca.enabled = false;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// one setting from text; lists are comma separated and an empty value clears an option
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let bad = |reason: String| ConfigError::Value(format!("{key} = {value:?}: {reason}"));
+        let path = || (!value.is_empty()).then(|| PathBuf::from(value));
+        let parts: Vec<&str> = key.split('.').collect();
+        match parts.as_slice() {
+            ["feeds", name, field] => {
+                let feed = match *name {
+                    "coinbase" => &mut self.feeds.coinbase,
+                    "alpaca" => &mut self.feeds.alpaca,
+                    _ => return Err(ConfigError::Key(key.to_string())),
+                };
+                match *field {
+                    "enabled" => feed.enabled = bool::from_str(value).map_err(|e| bad(e.to_string()))?,
+                    "url" => feed.url = value.to_string(),
+                    "symbols" => feed.symbols = list(value, |x| SymbolCommon::from_str(x).map_err(|_| bad(format!("unknown symbol {x}"))))?,
+                    "key_id" => feed.key_id = (!value.is_empty()).then(|| value.to_string()),
+                    "secret" => feed.secret = (!value.is_empty()).then(|| value.to_string()),
+                    _ => return Err(ConfigError::Key(key.to_string())),
+                }
+            }
+            ["retention", "lateness"] => self.retention.lateness = Lateness::from_str(value).map_err(|_| bad("a duration like 250ms, 5s or 2m".to_string()))?,
+            ["retention", "chart_limit"] => self.retention.chart_limit = usize::from_str(value).map_err(|e| bad(e.to_string()))?,
+            ["retention", "dedup_window"] => self.retention.dedup_window = usize::from_str(value).map_err(|e| bad(e.to_string()))?,
+            ["indicators"] => self.indicators = list(value, |x| CalculationId::iter().find(|c| c.to_string() == x).ok_or_else(|| bad(format!("unknown indicator {x}"))))?,
+            ["listeners", "http"] => self.listeners.http = value.to_string(),
+            ["listeners", "flight_sql"] => self.listeners.flight_sql = value.to_string(),
+            ["listeners", "pg"] => self.listeners.pg = value.to_string(),
+            ["listeners", "influx_udp"] => self.listeners.influx_udp = value.to_string(),
+            ["listeners", "tls", "cert"] => self.listeners.tls.cert = path(),
+            ["listeners", "tls", "key"] => self.listeners.tls.key = path(),
+            ["listeners", "tls", "ocsp"] => self.listeners.tls.ocsp = path(),
+            ["listeners", "tls", "reload"] => self.listeners.tls.reload = match value {
+                "" => None,
+                _ => Some(parse_interval(value).ok_or_else(|| bad("an interval like 30s or 5m".to_string()))?),
+            },
+            ["auth", "users"] => self.auth.users = path(),
+            ["dashboards"] => self.dashboards = PathBuf::from(value),
+            ["alerts"] => self.alerts = PathBuf::from(value),
+            ["strategies"] => self.strategies = PathBuf::from(value),
+            _ => return Err(ConfigError::Key(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// settings that parse but can't work together
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+        for (name, feed) in [("coinbase", &self.feeds.coinbase), ("alpaca", &self.feeds.alpaca)] {
+            if !feed.enabled {
+                continue;
+            }
+            if !feed.url.starts_with("wss://") && !feed.url.starts_with("ws://") {
+                return invalid(format!("feeds.{name}.url {} isn't a websocket url", feed.url));
+            }
+            if feed.symbols.is_empty() {
+                return invalid(format!("feeds.{name} has no symbols; set enabled = false instead"));
+            }
+        }
+        if self.retention.chart_limit == 0 || self.retention.dedup_window == 0 {
+            return invalid("retention.chart_limit and retention.dedup_window are at least 1".to_string());
+        }
+
+        // only what db::calculation computes; the slope is of the stored diff
+        let computed = Config::default().indicators;
+        if let Some(x) = self.indicators.iter().find(|x| !computed.contains(x)) {
+            return invalid(format!("indicator {x} isn't computed"));
+        }
+        if self.indicators.contains(&CalculationId::MovAvgDiffSlope0100_1000) && !self.indicators.contains(&CalculationId::MovAvgDiff0100_1000) {
+            return invalid("indicator MovAvgDiffSlope0100_1000 needs MovAvgDiff0100_1000".to_string());
+        }
+
+        let listeners = &self.listeners;
+        let mut tcp = HashSet::new();
+        for (name, addr, is_tcp) in [("http", &listeners.http, true), ("flight_sql", &listeners.flight_sql, true), ("pg", &listeners.pg, true), ("influx_udp", &listeners.influx_udp, false)] {
+            let addr = match SocketAddr::from_str(addr) {
+                Ok(addr) => addr,
+                Err(_) => return invalid(format!("listeners.{name} {addr} isn't ip:port")),
+            };
+            if is_tcp && !tcp.insert(addr.port()) {
+                return invalid(format!("listeners.{name} port {} is taken by another listener", addr.port()));
+            }
+        }
+        let tls = &listeners.tls;
+        if tls.cert.is_some() != tls.key.is_some() {
+            return invalid("listeners.tls.cert and listeners.tls.key go together".to_string());
+        }
+        if tls.cert.is_none() && (tls.ocsp.is_some() || tls.reload.is_some()) {
+            return invalid("listeners.tls.ocsp and listeners.tls.reload need a cert and key".to_string());
+        }
+        Ok(())
+    }
+
+    /// for logs
+    pub fn http_url(&self) -> String {
+        match self.listeners.tls.cert {
+            Some(_) => format!("https://{}", self.listeners.http),
+            None => format!("http://{}", self.listeners.http),
+        }
+    }
+}
+
+fn list<T>(value: &str, parse: impl Fn(&str) -> Result<T, ConfigError>) -> Result<Vec<T>, ConfigError> {
+    value.split(',').map(str::trim).filter(|x| !x.is_empty()).map(parse).collect()
+}
+
+/// "30s" or "5m"
+pub fn parse_interval(value: &str) -> Option<Duration> {
+    let (n, unit) = value.split_at(value.find(|x: char| !x.is_ascii_digit())?);
+    match (u64::from_str(n).ok()?, unit) {
+        (0, _) => None,
+        (n, "s") => Some(Duration::from_secs(n)),
+        (n, "m") => n.checked_mul(60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// CRATE_A__B_C=x sets a.b_c; an unknown CRATE_ name is an error rather than a silent typo
+fn env_pairs(env: impl IntoIterator<Item = (String, String)>) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut legacy = vec![];
+    let mut pairs = vec![];
+    for (name, value) in env {
+        if let Some((_, key)) = LEGACY_ENV.iter().find(|(legacy, _)| *legacy == name) {
+            legacy.push((key.to_string(), value));
+        } else if let Some(key) = name.strip_prefix(ENV_PREFIX).filter(|_| name != ENV_CONFIG) {
+            let key = key.to_lowercase().replace("__", ".");
+            if !KEYS.contains(&key.as_str()) {
+                return Err(ConfigError::Key(name));
+            }
+            pairs.push((key, value));
+        }
+    }
+    // the new names win
+    Ok(legacy.into_iter().chain(pairs).collect())
+}
+
+fn arg_pairs(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut pairs = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| ConfigError::Argument(arg.to_string()));
+        if arg == "--set" {
+            let (key, value) = value()?.split_once('=').ok_or_else(|| ConfigError::Argument(format!("{arg} wants <key>=<value>")))?;
+            pairs.push((key.trim().to_string(), value.trim().to_string()));
+        } else if let Some((_, key)) = ARGS.iter().find(|(flag, _)| flag == arg) {
+            pairs.push((key.to_string(), value()?.to_string()));
+        }
+    }
+    Ok(pairs)
+}
+
+fn coinbase<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Feed, D::Error> {
+    Ok(FeedTable::deserialize(deserializer)?.over(Feeds::default().coinbase))
+}
+
+fn alpaca<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Feed, D::Error> {
+    Ok(FeedTable::deserialize(deserializer)?.over(Feeds::default().alpaca))
+}
+
+/// a string parsed the way Config::set parses it
+fn from_str<'de, T: FromStr, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    T::from_str(&value).map_err(|_| D::Error::custom(format!("bad value {value:?}")))
+}
+
+/// an empty string is no path, like Config::set
+fn optional_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.filter(|x| !x.is_empty()).map(PathBuf::from))
+}
+
+fn optional_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    match Option::<String>::deserialize(deserializer)?.filter(|x| !x.is_empty()) {
+        Some(value) => parse_interval(&value).map(Some).ok_or_else(|| D::Error::custom(format!("{value:?} isn't an interval like 30s or 5m"))),
+        None => Ok(None),
+    }
+}
+
+fn display<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn interval<S: Serializer>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value.map(|x| x.as_secs()) {
+        Some(secs) if secs % 60 == 0 => serializer.serialize_some(&format!("{}m", secs / 60)),
+        Some(secs) => serializer.serialize_some(&format!("{secs}s")),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn redact<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(_) => serializer.serialize_some(REDACTED),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    Argument(String),
+    Io(String),
+    Toml(String),
+    Key(String),
+    Value(String),
+    Invalid(String),
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use chrono::Duration as ChronoDuration;
+    use crate::config::{Config, ConfigError, ALPACA_CRYPTOCURRENCY_URL, COINBASE_URL, HTTP_ADDR};
+    use crate::watermark::Lateness;
+    use crate::{CalculationId, SymbolCommon};
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|x| x.to_string()).collect()
+    }
+
+    const KEYS: [(&str, &str); 2] = [("ALPACA_API_ID", "id"), ("ALPACA_API_SECRET", "secret")];
+
+    #[test]
+    fn test_config_layers() {
+        let toml = r#"
+            # comment
+            dashboards = "/var/lib/crate/dashboards"   # trailing comment
+            indicators = ["MovingAvg0010", "MovingAvg0100"]
+
+            [feeds.coinbase]
+            symbols = [ "btc_usd", "eth_usd" ]
+
+            [retention]
+            lateness = "250ms"
+            chart_limit = 2_000
+
+            [listeners]
+            http = "0.0.0.0:8443"
+
+            [listeners.tls]
+            cert = 'cert.pem'
+            key = "key.pem"
+            reload = "5m"
+        "#;
+        let config = Config::build(Some(toml), env(&KEYS), &[]).unwrap();
+        assert_eq!(config.dashboards, PathBuf::from("/var/lib/crate/dashboards"));
+        assert_eq!(config.indicators, vec![CalculationId::MovingAvg0010, CalculationId::MovingAvg0100]);
+        assert_eq!(config.feeds.coinbase.symbols, vec![SymbolCommon::BtcUsd, SymbolCommon::EthUsd]);
+        assert_eq!(config.retention.lateness, Lateness(ChronoDuration::milliseconds(250)));
+        assert_eq!(config.retention.chart_limit, 2000);
+        assert_eq!(config.listeners.tls.reload, Some(Duration::from_secs(300)));
+        assert_eq!(config.feeds.alpaca.secret.as_deref(), Some("secret"));
+        assert_eq!(config.http_url(), "https://0.0.0.0:8443");
+
+        // the environment beats the file, the command line beats both
+        let environment = env(&[("CRATE_LISTENERS__HTTP", "127.0.0.1:9000"), ("CRATE_RETENTION__CHART_LIMIT", "10"), ("ALPACA_API_ID", "id"), ("CRATE_FEEDS__ALPACA__SECRET", "new"), ("ALPACA_API_SECRET", "old")]);
+        let config = Config::build(Some(toml), environment.clone(), &args(&["main", "--http-addr", "127.0.0.1:9001", "--set", "feeds.coinbase.enabled=false"])).unwrap();
+        assert_eq!(config.listeners.http, "127.0.0.1:9001");
+        assert_eq!(config.retention.chart_limit, 10);
+        assert_eq!(config.feeds.alpaca.secret.as_deref(), Some("new"));
+        assert!(!config.feeds.coinbase.enabled);
+
+        let config = Config::build(None, env(&KEYS), &args(&["main", "--users", "users.json", "--dashboards", "/tmp/x", "--alerts", "/tmp/alerts.json", "--strategies", "/tmp/strategies.json", "--lateness", "2m", "--tls-cert", "c.pem", "--tls-key", "k.pem"])).unwrap();
+        assert_eq!(config.auth.users, Some(PathBuf::from("users.json")));
+        assert_eq!(config.dashboards, PathBuf::from("/tmp/x"));
+        assert_eq!(config.alerts, PathBuf::from("/tmp/alerts.json"));
+        assert_eq!(config.strategies, PathBuf::from("/tmp/strategies.json"));
+        assert_eq!(config.retention.lateness, Lateness(ChronoDuration::minutes(2)));
+        assert_eq!(config.listeners.tls.key, Some(PathBuf::from("k.pem")));
+        assert_eq!(Config::build(None, env(&KEYS), &[]).unwrap().listeners.http, HTTP_ADDR);
+
+        // a feed's table keeps the venue's defaults for what it leaves out
+        let config = Config::build(Some("[feeds.alpaca]\nenabled = false\n\n[feeds.coinbase]\nsymbols = [\"eth_usd\"]\n[listeners.tls]\ncert = \"\""), vec![], &[]).unwrap();
+        assert_eq!(config.feeds.alpaca.url, ALPACA_CRYPTOCURRENCY_URL);
+        assert_eq!(config.feeds.alpaca.symbols, vec![SymbolCommon::BtcUsd]);
+        assert_eq!(config.feeds.coinbase.url, COINBASE_URL);
+        assert_eq!(config.listeners.tls.cert, None);
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let build = |toml: &str, extra: &[&str]| Config::build(Some(toml), env(&KEYS), &args(extra));
+        for toml in [
+            "[listeners]\nhttps = \"127.0.0.1:1\"",
+            "[feeds.kraken]\nenabled = false",
+            "[retention]\nchart_limit = \"many\"",
+            "[retention]\nlateness = \"153722867280912931m\"",
+            "[listeners.tls]\nreload = \"307445734561825861m\"",
+            "indicators = [\"MovingAvg9999\"]",
+            "[feeds.coinbase]\nsymbols = [\"doge_usd\"]",
+            "dashboards = unquoted",
+            "indicators = [\"MovingAvg0010\"",
+        ] {
+            assert!(matches!(build(toml, &[]), Err(ConfigError::Toml(_))), "{toml}");
+        }
+        assert!(matches!(build("", &["main", "--set", "retention.chart_limit=many"]), Err(ConfigError::Value(_))));
+        assert!(matches!(build("", &["main", "--set", "listeners.https=127.0.0.1:1"]), Err(ConfigError::Key(_))));
+        assert!(matches!(build("", &["main", "--lateness"]), Err(ConfigError::Argument(_))));
+        assert!(matches!(build("", &["main", "--set", "dashboards"]), Err(ConfigError::Argument(_))));
+        assert!(matches!(Config::build(None, env(&[("CRATE_LISTENER__HTTP", "x")]), &[]), Err(ConfigError::Key(_))));
+        for (toml, reason) in [
+            ("[feeds.alpaca]\nenabled = false\n[feeds.coinbase]\nurl = \"https://x\"", "not a websocket"),
+            ("[feeds.alpaca]\nenabled = false\n[feeds.coinbase]\nsymbols = []", "no symbols"),
+            ("[feeds.alpaca]\nenabled = false\n[retention]\nchart_limit = 0", "no points"),
+            ("[feeds.alpaca]\nenabled = false\n[listeners]\npg = \"localhost:5432\"", "not an ip"),
+            ("[feeds.alpaca]\nenabled = false\n[listeners]\npg = \"127.0.0.1:8080\"", "same port"),
+            ("[feeds.alpaca]\nenabled = false\n[listeners.tls]\ncert = \"c.pem\"", "no key"),
+            ("[feeds.alpaca]\nenabled = false\n[listeners.tls]\nreload = \"30s\"", "reload without tls"),
+            ("indicators = [\"MovAvgDiff0010_1000\"]\n[feeds.alpaca]\nenabled = false", "not computed"),
+            ("indicators = [\"MovAvgDiffSlope0100_1000\"]\n[feeds.alpaca]\nenabled = false", "slope without diff"),
+        ] {
+            assert!(matches!(Config::build(Some(toml), vec![], &[]), Err(ConfigError::Invalid(_))), "{reason}");
+        }
+        for bad in ["0s", "5", "5h", "s", "307445734561825861m"] {
+            assert!(build("", &["main", "--tls-cert", "c.pem", "--tls-key", "k.pem", "--tls-reload", bad]).is_err(), "{bad}");
+        }
+    }
+
+    /// a stock run with no file and no alpaca keys starts coinbase alone
+    #[test]
+    fn test_config_without_alpaca_keys() {
+        let config = Config::build(None, vec![], &[]).unwrap();
+        assert!(config.feeds.coinbase.enabled);
+        assert!(!config.feeds.alpaca.enabled);
+        assert!(config.validate().is_ok());
+
+        let config = Config::build(None, env(&KEYS[..1]), &[]).unwrap();
+        assert!(!config.feeds.alpaca.enabled);
+        assert!(Config::build(None, env(&KEYS), &[]).unwrap().feeds.alpaca.enabled);
+    }
+
+    /// secrets don't leave the process
+    #[test]
+    fn test_config_serialize() {
+        let config = Config::build(Some("[listeners.tls]\ncert = \"c.pem\"\nkey = \"k.pem\"\nreload = \"90s\""), env(&KEYS), &[]).unwrap();
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["feeds"]["alpaca"]["secret"], "********");
+        assert_eq!(json["feeds"]["coinbase"]["secret"], serde_json::Value::Null);
+        assert_eq!(json["retention"]["lateness"], "5s");
+        assert_eq!(json["listeners"]["tls"]["reload"], "90s");
+        assert_eq!(json["indicators"][0], "MovingAvg0010");
+        assert!(!json.to_string().contains("\"secret\":\"secret\""));
+    }
+
+    /// the example file documents the defaults
+    #[test]
+    fn test_config_example() {
+        let example = include_str!("../../crate.example.toml");
+        assert_eq!(Config::build(Some(example), env(&KEYS), &[]).unwrap(), Config::build(None, env(&KEYS), &[]).unwrap());
+    }
+}
@@ -0,0 +1,68 @@
+//! health.rs
+//!
+//! what /healthz and /readyz report: each started feed's connection, how long since each symbol's
+//! last stored tick, the db thread's queue and how full its event logs' ring buffers are. Alive
+//! means the db thread answered; ready also needs every started feed connected.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+use crate::cb_ticker::Datasource;
+use crate::SymbolCommon;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum FeedState {
+    Connecting,
+    Connected,
+    /// the socket closed or never opened; feeds don't reconnect
+    Disconnected,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedHealth {
+    pub source: Datasource,
+    pub state: FeedState,
+    /// when it entered the state
+    pub since: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolHealth {
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    /// wall clock time the newest tick was stored, not its event time
+    pub last_tick: DateTime<Utc>,
+    pub age_secs: f64,
+}
+
+/// one source's event log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BufferHealth {
+    pub source: Datasource,
+    pub len: usize,
+    pub capacity: usize,
+    /// len over capacity
+    pub fill: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub dtg: DateTime<Utc>,
+    pub ready: bool,
+    pub feeds: Vec<FeedHealth>,
+    pub symbols: Vec<SymbolHealth>,
+    /// messages waiting for the db thread when it was asked
+    pub db_queue: usize,
+    pub buffers: Vec<BufferHealth>,
+}
+
+impl Health {
+    /// ready once every started feed is connected; with none started (a backfill only, say) it
+    /// always is
+    pub fn new(dtg: DateTime<Utc>, feeds: Vec<FeedHealth>, symbols: Vec<SymbolHealth>, buffers: Vec<BufferHealth>) -> Health {
+        let ready = feeds.iter().all(|x| x.state == FeedState::Connected);
+        Health { dtg, ready, feeds, symbols, db_queue: 0, buffers }
+    }
+}
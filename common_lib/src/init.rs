@@ -6,17 +6,24 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{fmt, EnvFilter};
 
-#[derive(Display, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
 #[strum(serialize_all = "snake_case")]
 pub enum ConfigLocation {
     Docker,
     NotDocker,
 }
 
+impl ConfigLocation {
+    /// CONFIG_LOCATION, not_docker when unset; it decides where .env is, so it isn't in Config
+    pub fn from_env() -> ConfigLocation {
+        ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION")
+    }
+}
+
 pub fn init(package_name: &str) {
     // load .env
     // if docker, load .env from the root directory, otherwise use the cargo workspace directory
-    let config_location: ConfigLocation = ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION");
+    let config_location = ConfigLocation::from_env();
     println!("[init] config_location: {}", &config_location);
 
     let dot_env_path = match config_location {
@@ -1,15 +1,36 @@
 //! common_lib...lib.rs
+pub mod alert;
+pub mod api;
+pub mod auth;
+pub mod backfill;
 pub mod cb_ticker;
+pub mod config;
+pub mod health;
 pub mod heartbeat;
 pub mod init;
+pub mod metrics;
 pub mod operator;
+pub mod paper;
+pub mod point;
+pub mod view;
+pub mod watermark;
+pub mod wire;
 
 use chrono::{DateTime, Utc};
+use std::sync::Arc;
 use datafusion::dataframe::DataFrame;
+use datafusion::datasource::TableProvider;
 use serde::{Deserialize, Serialize};
-use strum_macros::{Display, EnumIter};
+use strum_macros::{Display, EnumIter, EnumString};
 use tokio::sync::oneshot;
+use crate::alert::{Alert, AlertError, AlertRule, Fired};
+use crate::api::{SeriesInfo, SeriesQuery};
 use crate::cb_ticker::{Datasource};
+use crate::config::Retention;
+use crate::health::{FeedState, Health};
+use crate::paper::{Account, Strategy};
+use crate::point::{MeasurementSchema, Point};
+use crate::view::{ChartFilter, ViewDelta, ViewSpec};
 
 #[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct TickerCommon {
@@ -17,9 +38,11 @@ pub struct TickerCommon {
     pub symbol: SymbolCommon,
     pub price: f64,
     pub dtg: DateTime<Utc>,
+    /// the venue's id for the trade, when it has one; a repeat is dropped as a duplicate
+    pub trade_id: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Display)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Display, EnumString, EnumIter)]
 #[strum(serialize_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum SymbolCommon {
@@ -30,13 +53,13 @@ pub enum SymbolCommon {
 }
 
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ChartDataset {
     pub label: String,
     pub data: Vec<ChartTimeSeries>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ChartTimeSeries {
     pub x: DateTime<Utc>,
     pub y: f64
@@ -56,30 +79,48 @@ impl std::error::Error for UniversalError {
 
 }
 
+/// named tables for a sql session
+pub struct SqlTables(pub Vec<(String, Arc<dyn TableProvider>)>);
+
+impl std::fmt::Debug for SqlTables {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|(name, _)| name)).finish()
+    }
+}
+
 #[derive(Debug, Display)]
 pub enum DbMsg {
     Insert(Datasource, TickerCommon),
+    SetRetention(Retention),
+    SetIndicators(Vec<CalculationId>),
+    SetAlertRules(Vec<AlertRule>),
+    SetStrategies(Vec<Strategy>),
+    /// a feed's websocket connecting, up or gone
+    FeedState(Datasource, FeedState),
     Ping,
     Pong,
     Start,
     Stop,
-    RqstChartMulti {sender: oneshot::Sender<Vec<ChartDataset>>, symbol:Vec<SymbolCommon> },
-    RqstChartSince {sender: oneshot::Sender<Vec<ChartDataset>>, symbol:Vec<SymbolCommon>, since:DateTime<Utc> },
+    RqstChartMulti {sender: oneshot::Sender<Vec<ChartDataset>>, filter: ChartFilter },
+    RqstChartSince {sender: oneshot::Sender<Vec<ChartDataset>>, filter: ChartFilter, since:DateTime<Utc> },
     RqstRaw {ticker_source: Datasource, sender: oneshot::Sender<DataFrame> },
-
-    // RequestChartJson{chart_type: ChartType, sender: oneshot::Sender<serde_json::Value> },
-    // RequestChartRust{sender: oneshot::Sender<Chart> },
-
-}
-
-#[derive(Debug)]
-pub enum ChartType{
-    BasicAsJson,
-    BasicAsRust,
-    Test
+    RqstBackfill {source: Datasource, tickers: Vec<TickerCommon>, sender: oneshot::Sender<usize> },
+    RqstView {spec: ViewSpec, sender: oneshot::Sender<crossbeam_channel::Receiver<ViewDelta>> },
+    RqstTables {sender: oneshot::Sender<Result<SqlTables, UniversalError>> },
+    RqstWritePoints {points: Vec<Point>, sender: oneshot::Sender<Result<usize, UniversalError>> },
+    RqstDefineMeasurement {schema: MeasurementSchema, sender: oneshot::Sender<Result<(), UniversalError>> },
+    RqstSeriesList {sender: oneshot::Sender<Vec<SeriesInfo>> },
+    RqstSeries {query: SeriesQuery, sender: oneshot::Sender<ChartDataset> },
+    RqstAlertRules {sender: oneshot::Sender<Vec<AlertRule>> },
+    RqstPutAlertRule {rule: AlertRule, sender: oneshot::Sender<Result<Vec<AlertRule>, AlertError>> },
+    RqstDeleteAlertRule {name: String, sender: oneshot::Sender<Result<Vec<AlertRule>, AlertError>> },
+    RqstAlertHistory {sender: oneshot::Sender<Vec<Alert>> },
+    RqstPaperAccounts {sender: oneshot::Sender<Vec<Account>> },
+    RqstAlerts {sender: oneshot::Sender<crossbeam_channel::Receiver<Fired>> },
+    RqstHealth {sender: oneshot::Sender<Health> },
 }
 
-#[derive(Debug, Serialize, Deserialize, Display, Clone, EnumIter, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Display, Clone, EnumIter, PartialEq, Eq, Hash)]
 pub enum CalculationId {
     // MovingAvg0004,
     MovingAvg0010,
@@ -0,0 +1,208 @@
+//! metrics.rs
+//!
+//! process-wide counters for GET /metrics in the prometheus text format. They're written where
+//! things happen (the db thread, the sql frontends, the chart websocket hubs) and read on a scrape
+//! together with the gauges of a Health:
+//!
+//! crate_ticks_total{source,arrival}           live ticks by how they arrived; rate() is the ingest rate
+//! crate_calc_seconds                          refreshing a ticker's calculations
+//! crate_query_seconds{frontend}               a sql query, planned and collected
+//! crate_ws_clients                            chart websocket clients
+//! crate_db_queue_depth                        messages waiting for the db thread
+//! crate_feed_connected{source}                1 while the feed's socket is open
+//! crate_last_tick_age_seconds{source,symbol}
+//! crate_ring_buffer_len{source}, crate_ring_buffer_capacity{source}
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::cb_ticker::Datasource;
+use crate::health::{FeedState, Health};
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// histogram upper bounds, in seconds
+const BUCKETS: [f64; 14] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+pub struct Metrics {
+    ticks: Mutex<BTreeMap<(String, String), u64>>,
+    calc: Mutex<Histogram>,
+    queries: Mutex<BTreeMap<String, Histogram>>,
+    ws_clients: AtomicI64,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    /// per bucket, not cumulative
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Metrics {
+        Metrics {
+            ticks: Mutex::new(BTreeMap::new()),
+            calc: Mutex::new(Histogram::new()),
+            queries: Mutex::new(BTreeMap::new()),
+            ws_clients: AtomicI64::new(0),
+        }
+    }
+
+    /// a live ticker from source; arrival is how the db took it, "in_order", "late", ...
+    pub fn tick(&self, source: &Datasource, arrival: &str) {
+        let key = (source.to_string().to_lowercase(), arrival.to_string());
+        *self.ticks.lock().unwrap_or_else(|e| e.into_inner()).entry(key).or_insert(0) += 1;
+    }
+
+    pub fn calc(&self, elapsed: Duration) {
+        self.calc.lock().unwrap_or_else(|e| e.into_inner()).observe(elapsed);
+    }
+
+    /// frontend is "flight_sql", "postgres", ...
+    pub fn query(&self, frontend: &str, elapsed: Duration) {
+        let mut queries = self.queries.lock().unwrap_or_else(|e| e.into_inner());
+        queries.entry(frontend.to_string()).or_insert_with(Histogram::new).observe(elapsed);
+    }
+
+    /// clients added (or, negative, removed) by a hub
+    pub fn ws_clients(&self, change: i64) {
+        self.ws_clients.fetch_add(change, Ordering::Relaxed);
+    }
+
+    /// everything in the prometheus text exposition format
+    pub fn render(&self, health: &Health) -> String {
+        let mut out = String::new();
+        header(&mut out, "crate_ticks_total", "counter", "live ticks by how the db took them");
+        for ((source, arrival), count) in self.ticks.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            let _ = writeln!(out, "crate_ticks_total{{source=\"{source}\",arrival=\"{arrival}\"}} {count}");
+        }
+        header(&mut out, "crate_calc_seconds", "histogram", "time to refresh a ticker's calculations");
+        self.calc.lock().unwrap_or_else(|e| e.into_inner()).render(&mut out, "crate_calc_seconds", "");
+        header(&mut out, "crate_query_seconds", "histogram", "time to plan and collect a sql query");
+        for (frontend, histogram) in self.queries.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            histogram.render(&mut out, "crate_query_seconds", &format!("frontend=\"{frontend}\","));
+        }
+        header(&mut out, "crate_ws_clients", "gauge", "chart websocket clients");
+        let _ = writeln!(out, "crate_ws_clients {}", self.ws_clients.load(Ordering::Relaxed));
+
+        header(&mut out, "crate_db_queue_depth", "gauge", "messages waiting for the db thread");
+        let _ = writeln!(out, "crate_db_queue_depth {}", health.db_queue);
+        header(&mut out, "crate_feed_connected", "gauge", "1 while the feed's socket is open");
+        for feed in health.feeds.iter() {
+            let _ = writeln!(out, "crate_feed_connected{{source=\"{}\"}} {}", source(&feed.source), u8::from(feed.state == FeedState::Connected));
+        }
+        header(&mut out, "crate_last_tick_age_seconds", "gauge", "since the symbol's newest tick was stored");
+        for symbol in health.symbols.iter() {
+            let _ = writeln!(out, "crate_last_tick_age_seconds{{source=\"{}\",symbol=\"{}\"}} {}", source(&symbol.source), symbol.symbol, symbol.age_secs);
+        }
+        header(&mut out, "crate_ring_buffer_len", "gauge", "ticks in the source's event log");
+        for buffer in health.buffers.iter() {
+            let _ = writeln!(out, "crate_ring_buffer_len{{source=\"{}\"}} {}", source(&buffer.source), buffer.len);
+        }
+        header(&mut out, "crate_ring_buffer_capacity", "gauge", "ticks the source's event log holds before it grows");
+        for buffer in health.buffers.iter() {
+            let _ = writeln!(out, "crate_ring_buffer_capacity{{source=\"{}\"}} {}", source(&buffer.source), buffer.capacity);
+        }
+        out
+    }
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram { counts: [0; BUCKETS.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|x| secs <= *x) {
+            self.counts[i] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    /// labels is empty or ends in a comma
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (le, count) in BUCKETS.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{le}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {}", self.count);
+        let labels = labels.trim_end_matches(',');
+        let labels = match labels.is_empty() {
+            true => "".to_string(),
+            false => format!("{{{labels}}}"),
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+fn source(source: &Datasource) -> String {
+    source.to_string().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use chrono::Utc;
+    use crate::cb_ticker::Datasource;
+    use crate::health::{BufferHealth, FeedHealth, FeedState, Health};
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        for _ in 0..3 {
+            metrics.tick(&Datasource::Coinbase, "in_order");
+        }
+        metrics.tick(&Datasource::Coinbase, "late");
+        metrics.calc(Duration::from_micros(300));
+        metrics.calc(Duration::from_secs(9));
+        metrics.query("postgres", Duration::from_millis(20));
+        metrics.ws_clients(2);
+        metrics.ws_clients(-1);
+
+        let feeds = vec![FeedHealth { source: Datasource::Coinbase, state: FeedState::Connected, since: Utc::now() }, FeedHealth { source: Datasource::Alpaca, state: FeedState::Disconnected, since: Utc::now() }];
+        let buffers = vec![BufferHealth { source: Datasource::Coinbase, len: 4, capacity: 100, fill: 0.04 }];
+        let mut health = Health::new(Utc::now(), feeds, vec![], buffers);
+        health.db_queue = 7;
+        assert!(!health.ready);
+
+        let text = metrics.render(&health);
+        for line in [
+            "# TYPE crate_ticks_total counter",
+            "crate_ticks_total{source=\"coinbase\",arrival=\"in_order\"} 3",
+            "crate_ticks_total{source=\"coinbase\",arrival=\"late\"} 1",
+            "crate_calc_seconds_bucket{le=\"0.00025\"} 0",
+            "crate_calc_seconds_bucket{le=\"0.0005\"} 1",
+            "crate_calc_seconds_bucket{le=\"5\"} 1",
+            "crate_calc_seconds_bucket{le=\"+Inf\"} 2",
+            "crate_calc_seconds_count 2",
+            "crate_query_seconds_bucket{frontend=\"postgres\",le=\"0.025\"} 1",
+            "crate_query_seconds_count{frontend=\"postgres\"} 1",
+            "crate_ws_clients 1",
+            "crate_db_queue_depth 7",
+            "crate_feed_connected{source=\"coinbase\"} 1",
+            "crate_feed_connected{source=\"alpaca\"} 0",
+            "crate_ring_buffer_len{source=\"coinbase\"} 4",
+        ] {
+            assert!(text.lines().any(|x| x == line), "{line} not in\n{text}");
+        }
+        assert!(text.lines().all(|x| x.starts_with('#') || x.split(' ').count() == 2), "{text}");
+    }
+}
@@ -0,0 +1,187 @@
+//! paper.rs
+//!
+//! paper trading strategies: each holds one position in one symbol from one source, sized by a
+//! signal on the live (or backfilled) series, and filled at the tick's price plus slippage and a
+//! fee; see db::paper for the accounting. Strategies are one json list (config "strategies",
+//! default ./strategies.json), read at startup.
+//!
+//! {"name":"ma_turn","source":"coinbase","symbol":"btc_usd","quantity":0.5,"short":true,
+//!  "slippage_bps":2,"fee_bps":10,"cash":100000,
+//!  "signal":{"type":"sign","series":{"calc":"MovAvgDiff0100_1000"}}}
+
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+use crate::cb_ticker::Datasource;
+use crate::view::ViewSeries;
+use crate::SymbolCommon;
+
+const NAME_LEN_MAX: usize = 64;
+const CASH_DEFAULT: f64 = 100_000.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Strategy {
+    pub name: String,
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    pub signal: Signal,
+    /// units held while the signal is up (and short while it's down, with short)
+    pub quantity: f64,
+    #[serde(default)]
+    pub short: bool,
+    /// fills are this much worse than the tick, in basis points
+    #[serde(default)]
+    pub slippage_bps: f64,
+    /// of each fill's notional, in basis points
+    #[serde(default)]
+    pub fee_bps: f64,
+    /// starting cash; pnl and drawdown are measured from it
+    #[serde(default = "cash_default")]
+    pub cash: f64,
+}
+
+fn cash_default() -> f64 {
+    CASH_DEFAULT
+}
+
+/// what the position follows; the first value only sets which side it's on, trades happen when it
+/// changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Signal {
+    /// up above zero, down below, eg MovAvgDiff0100_1000
+    Sign { series: ViewSeries },
+    /// up while fast is above slow
+    Crossover { fast: ViewSeries, slow: ViewSeries },
+}
+
+/// a strategy's charted series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Display, EnumIter)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum PaperField {
+    Position,
+    Equity,
+    Pnl,
+    Drawdown,
+}
+
+impl PaperField {
+    /// chart label, "paper_ma_turn_equity"
+    pub fn label(&self, strategy: &str) -> String {
+        format!("paper_{strategy}_{self}")
+    }
+}
+
+/// where a strategy stands, for /api/v1/paper
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    pub strategy: String,
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    /// the newest tick marked, None before any
+    pub dtg: Option<DateTime<Utc>>,
+    pub price: Option<f64>,
+    pub position: f64,
+    pub cash: f64,
+    /// cash plus the position at the newest price
+    pub equity: f64,
+    pub pnl: f64,
+    /// below the highest equity so far
+    pub drawdown: f64,
+    pub max_drawdown: f64,
+    pub fees: f64,
+    pub trades: usize,
+}
+
+impl Strategy {
+    pub fn validate(&self) -> Result<(), PaperError> {
+        let invalid = |reason: String| Err(PaperError::Invalid(format!("{}: {reason}", self.name)));
+        if self.name.is_empty() || self.name.len() > NAME_LEN_MAX || !self.name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-') {
+            return Err(PaperError::Name(self.name.clone()));
+        }
+        if !self.quantity.is_finite() || self.quantity <= 0.0 {
+            return invalid(format!("quantity {} isn't above 0", self.quantity));
+        }
+        if !self.cash.is_finite() || self.cash <= 0.0 {
+            return invalid(format!("cash {} isn't above 0", self.cash));
+        }
+        for (key, bps) in [("slippage_bps", self.slippage_bps), ("fee_bps", self.fee_bps)] {
+            if !bps.is_finite() || bps < 0.0 {
+                return invalid(format!("{key} {bps} isn't 0 or more"));
+            }
+        }
+        match &self.signal {
+            Signal::Crossover { fast, slow } if fast == slow => invalid(format!("{} can't cross itself", fast.name())),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// every strategy in path; no file means none. Names must be unique, they key the charts
+pub fn load_strategies(path: &Path) -> Result<Vec<Strategy>, PaperError> {
+    let strategies: Vec<Strategy> = match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| PaperError::Json(format!("{path:?}: {e}")))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(PaperError::Io(format!("{path:?}: {e}"))),
+    };
+    for (i, strategy) in strategies.iter().enumerate() {
+        strategy.validate()?;
+        if strategies[..i].iter().any(|x| x.name == strategy.name) {
+            return Err(PaperError::Invalid(format!("{}: named twice", strategy.name)));
+        }
+    }
+    Ok(strategies)
+}
+
+#[derive(Debug, Display)]
+pub enum PaperError {
+    Name(String),
+    Invalid(String),
+    Json(String),
+    Io(String),
+}
+
+impl std::error::Error for PaperError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::paper::{load_strategies, PaperError, PaperField, Signal, Strategy};
+    use crate::view::ViewSeries;
+    use crate::CalculationId;
+
+    #[test]
+    fn test_strategies_json() {
+        let json = r#"[
+            {"name":"ma_turn","source":"coinbase","symbol":"btc_usd","quantity":0.5,"short":true,"slippage_bps":2,"fee_bps":10,
+             "signal":{"type":"sign","series":{"calc":"MovAvgDiff0100_1000"}}},
+            {"name":"cross","source":"coinbase","symbol":"eth_usd","quantity":1,"cash":5000,
+             "signal":{"type":"crossover","fast":{"calc":"MovingAvg0010"},"slow":{"calc":"MovingAvg0100"}}}
+        ]"#;
+        let strategies: Vec<Strategy> = serde_json::from_str(json).unwrap();
+        assert_eq!(strategies[0].signal, Signal::Sign { series: ViewSeries::Calc(CalculationId::MovAvgDiff0100_1000) });
+        assert_eq!(strategies[0].cash, 100_000.0);
+        assert!(!strategies[1].short);
+        assert_eq!(strategies[1].fee_bps, 0.0);
+        assert!(strategies.iter().all(|x| x.validate().is_ok()));
+        assert_eq!(PaperField::Drawdown.label("ma_turn"), "paper_ma_turn_drawdown");
+
+        let dir = std::env::temp_dir().join(format!("test_strategies_json_{}", std::process::id()));
+        let path = dir.join("strategies.json");
+        assert!(load_strategies(&path).unwrap().is_empty());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, json).unwrap();
+        assert_eq!(load_strategies(&path).unwrap(), strategies);
+        std::fs::write(&path, serde_json::to_vec(&[strategies[0].clone(), strategies[0].clone()]).unwrap()).unwrap();
+        assert!(matches!(load_strategies(&path), Err(PaperError::Invalid(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let strategy = |quantity: f64, fee_bps: f64, signal: Signal| Strategy { quantity, fee_bps, signal, ..strategies[0].clone() };
+        assert!(matches!(strategy(0.0, 0.0, strategies[0].signal.clone()).validate(), Err(PaperError::Invalid(_))));
+        assert!(matches!(strategy(1.0, -1.0, strategies[0].signal.clone()).validate(), Err(PaperError::Invalid(_))));
+        assert!(matches!(strategy(1.0, 0.0, Signal::Crossover { fast: ViewSeries::Price, slow: ViewSeries::Price }).validate(), Err(PaperError::Invalid(_))));
+        assert!(matches!(Strategy { name: "a/b".to_string(), ..strategies[0].clone() }.validate(), Err(PaperError::Name(_))));
+    }
+}
@@ -0,0 +1,225 @@
+//! point.rs
+//!
+//! the store's general data model (influx line protocol's): a measurement is a table of points,
+//! tags name the series and fields carry the values. A MeasurementSchema says which tags and typed
+//! fields a table has; the ticks and calcs tables are measurements like any other.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use strum_macros::{Display, EnumString};
+
+/// the time column every measurement table starts with
+pub const COLUMN_DTG: &str = "dtg";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum FieldType {
+    Float,
+    Integer,
+    #[strum(to_string = "uinteger", serialize = "unsigned")]
+    UInteger,
+    Boolean,
+    String,
+}
+
+impl FieldType {
+    /// arrow column type the field is stored as
+    pub fn data_type(&self) -> DataType {
+        match self {
+            FieldType::Float => DataType::Float64,
+            FieldType::Integer => DataType::Int64,
+            FieldType::UInteger => DataType::UInt64,
+            FieldType::Boolean => DataType::Boolean,
+            FieldType::String => DataType::Utf8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    UInteger(u64),
+    Boolean(bool),
+    String(String),
+}
+
+impl FieldValue {
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            FieldValue::Float(_) => FieldType::Float,
+            FieldValue::Integer(_) => FieldType::Integer,
+            FieldValue::UInteger(_) => FieldType::UInteger,
+            FieldValue::Boolean(_) => FieldType::Boolean,
+            FieldValue::String(_) => FieldType::String,
+        }
+    }
+
+    /// numeric fields can be charted
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::Float(v) => Some(*v),
+            FieldValue::Integer(v) => Some(*v as f64),
+            FieldValue::UInteger(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, FieldValue>,
+    pub dtg: DateTime<Utc>,
+}
+
+impl Point {
+    /// "cpu,host=a,region=west"; tags are sorted, so the same tags always name the same series
+    pub fn series(&self) -> String {
+        let mut series = self.measurement.clone();
+        for (k, v) in self.tags.iter() {
+            series.push_str(&format!(",{k}={v}"));
+        }
+        series
+    }
+
+    /// chart label for one of this point's fields
+    pub fn label(&self, field: &str) -> String {
+        format!("{}_{}", self.series(), field)
+    }
+}
+
+/// a table's columns: dtg, then the tags, then the typed fields, in the order given
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementSchema {
+    pub name: String,
+    pub time: DataType,
+    pub tags: Vec<String>,
+    pub fields: Vec<(String, FieldType)>,
+}
+
+impl MeasurementSchema {
+    /// no columns yet, nanosecond UTC timestamps
+    pub fn new(name: &str) -> MeasurementSchema {
+        MeasurementSchema {
+            name: name.to_string(),
+            time: DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            tags: vec![],
+            fields: vec![],
+        }
+    }
+
+    pub fn time(mut self, data_type: DataType) -> MeasurementSchema {
+        self.time = data_type;
+        self
+    }
+
+    pub fn tag(mut self, name: &str) -> MeasurementSchema {
+        self.tags.push(name.to_string());
+        self
+    }
+
+    pub fn field(mut self, name: &str, field_type: FieldType) -> MeasurementSchema {
+        self.fields.push((name.to_string(), field_type));
+        self
+    }
+
+    pub fn field_type(&self, name: &str) -> Option<FieldType> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, t)| *t)
+    }
+
+    pub fn has_tag(&self, name: &str) -> bool {
+        self.tags.iter().any(|t| t == name)
+    }
+
+    /// tags and fields may be missing from a row, so only dtg is non-null
+    pub fn arrow_schema(&self) -> SchemaRef {
+        let mut columns = vec![Field::new(COLUMN_DTG, self.time.clone(), false)];
+        columns.extend(self.tags.iter().map(|t| Field::new(t, DataType::Utf8, true)));
+        columns.extend(self.fields.iter().map(|(f, t)| Field::new(f, t.data_type(), true)));
+        Arc::new(Schema::new(columns))
+    }
+
+    /// parse the command line form "fills,symbol,venue,side price=float,qty=integer", shaped
+    /// like a line of line protocol
+    pub fn from_arg(arg: &str) -> Result<MeasurementSchema, SchemaError> {
+        let bad = || SchemaError::Argument(arg.to_string());
+        let (series, fields) = arg.trim().split_once(' ').ok_or_else(bad)?;
+        let mut names = series.split(',');
+        let mut schema = match names.next() {
+            Some(name) if !name.is_empty() => MeasurementSchema::new(name),
+            _ => return Err(bad()),
+        };
+        for tag in names {
+            if tag.is_empty() || tag == COLUMN_DTG || schema.has_tag(tag) {
+                return Err(bad());
+            }
+            schema = schema.tag(tag);
+        }
+        for field in fields.trim().split(',') {
+            let (name, field_type) = field.split_once('=').ok_or_else(bad)?;
+            let field_type = FieldType::from_str(field_type).map_err(|_| SchemaError::FieldType(field.to_string()))?;
+            if name.is_empty() || name == COLUMN_DTG || schema.has_tag(name) || schema.field_type(name).is_some() {
+                return Err(bad());
+            }
+            schema = schema.field(name, field_type);
+        }
+        Ok(schema)
+    }
+
+    /// collect every "--measurement <schema>" from the command line
+    pub fn from_args(args: &[String]) -> Result<Vec<MeasurementSchema>, SchemaError> {
+        let mut schemas = vec![];
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--measurement" {
+                let value = iter.next().ok_or_else(|| SchemaError::Argument(arg.to_string()))?;
+                schemas.push(MeasurementSchema::from_arg(value)?);
+            }
+        }
+        Ok(schemas)
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum SchemaError {
+    Argument(String),
+    FieldType(String),
+}
+
+impl std::error::Error for SchemaError {}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::datatypes::DataType;
+    use crate::point::{FieldType, MeasurementSchema};
+
+    #[test]
+    fn test_from_arg() {
+        let schema = MeasurementSchema::from_arg("fills,symbol,venue,side price=float,qty=integer,fee=unsigned").unwrap();
+        assert_eq!(schema.name, "fills");
+        assert_eq!(schema.tags, vec!["symbol", "venue", "side"]);
+        assert_eq!(schema.field_type("qty"), Some(FieldType::Integer));
+        assert_eq!(schema.field_type("fee"), Some(FieldType::UInteger));
+
+        let arrow = schema.arrow_schema();
+        let names: Vec<&str> = arrow.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, vec!["dtg", "symbol", "venue", "side", "price", "qty", "fee"]);
+        assert_eq!(arrow.field(4).data_type(), &DataType::Float64);
+
+        for bad in ["fills", "fills price", ",symbol price=float", "fills price=decimal", "fills,dtg price=float", "fills,side side=float", "fills price=float,price=integer"] {
+            assert!(MeasurementSchema::from_arg(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args: Vec<String> = ["main", "--measurement", "fills,symbol price=float", "--backfill", "coinbase=a.csv"].iter().map(|x| x.to_string()).collect();
+        assert_eq!(MeasurementSchema::from_args(&args).unwrap().len(), 1);
+        assert!(MeasurementSchema::from_args(&["--measurement".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,536 @@
+//! view.rs
+//!
+//! continuous views: register once with the db thread, then receive deltas as ticks arrive
+//! instead of re-querying the whole event log
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::Display;
+use crate::alert::Alert;
+use crate::cb_ticker::Datasource;
+use crate::{CalculationId, ChartDataset, ChartTimeSeries, SymbolCommon};
+
+const VIEW_LIMIT_DEFAULT: usize = 1000;
+
+/// raw price or one of the derived calculations; json is "price" or {"calc":"MovingAvg0100"}
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewSeries {
+    Price,
+    Calc(CalculationId),
+}
+
+impl ViewSeries {
+    /// same labels as EventLog::chart_in so views and chart requests can be mixed on a page
+    pub fn label(&self, symbol: &SymbolCommon, source: &Datasource) -> String {
+        match self {
+            ViewSeries::Price => format!("{}_{}", symbol, source),
+            ViewSeries::Calc(calc_id) => format!("{}_{}_{}", symbol, calc_id, source),
+        }
+    }
+
+    /// "price" or the calculation id, as it appears in an api path
+    pub fn name(&self) -> String {
+        match self {
+            ViewSeries::Price => "price".to_string(),
+            ViewSeries::Calc(calc_id) => calc_id.to_string(),
+        }
+    }
+
+    /// price then every calculation
+    pub fn all() -> Vec<ViewSeries> {
+        std::iter::once(ViewSeries::Price).chain(CalculationId::iter().map(ViewSeries::Calc)).collect()
+    }
+}
+
+impl FromStr for ViewSeries {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ViewSeries::all().into_iter().find(|x| x.name() == value).ok_or_else(|| format!("unknown series: {value}"))
+    }
+}
+
+/// applied to every point falling inside a window
+#[derive(Debug, Clone, PartialEq, Display)]
+pub enum ViewAggregate {
+    Last,
+    Avg,
+    Min,
+    Max,
+    Count,
+}
+
+/// "select aggregate(val) from ticks where source in (..) and symbol in (..) group by symbol, series, window"
+///
+/// empty source/symbol/series lists mean "all"; no window means every point passes straight through
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewSpec {
+    pub name: String,
+    pub sources: Vec<Datasource>,
+    pub symbols: Vec<SymbolCommon>,
+    pub series: Vec<ViewSeries>,
+    pub window: Option<Duration>,
+    pub aggregate: ViewAggregate,
+    pub limit: usize,
+}
+
+impl ViewSpec {
+    pub fn new(name: &str) -> ViewSpec {
+        ViewSpec {
+            name: name.to_string(),
+            sources: vec![],
+            symbols: vec![],
+            series: vec![],
+            window: None,
+            aggregate: ViewAggregate::Last,
+            limit: VIEW_LIMIT_DEFAULT,
+        }
+    }
+
+    pub fn source(mut self, source: Datasource) -> ViewSpec {
+        self.sources.push(source);
+        self
+    }
+
+    pub fn symbol(mut self, symbol: SymbolCommon) -> ViewSpec {
+        self.symbols.push(symbol);
+        self
+    }
+
+    pub fn series(mut self, series: ViewSeries) -> ViewSpec {
+        self.series.push(series);
+        self
+    }
+
+    /// tumbling window; points are aggregated into buckets of this width
+    pub fn window(mut self, window: Duration, aggregate: ViewAggregate) -> ViewSpec {
+        self.window = Some(window);
+        self.aggregate = aggregate;
+        self
+    }
+
+    /// points kept per series
+    pub fn limit(mut self, limit: usize) -> ViewSpec {
+        self.limit = limit;
+        self
+    }
+
+    pub fn matches(&self, source: &Datasource, symbol: &SymbolCommon, series: &ViewSeries) -> bool {
+        (self.sources.is_empty() || self.sources.contains(source))
+            && (self.symbols.is_empty() || self.symbols.contains(symbol))
+            && (self.series.is_empty() || self.series.contains(series))
+    }
+}
+
+/// what a chart page shows, from its url so a view can be shared:
+///
+/// /?symbols=btc_usd,eth_usd&sources=coinbase&series=price,MovingAvg0100&window=15m
+///
+/// empty lists mean "all", like ViewSpec. start and end are rfc3339; a window without a start is
+/// the time before end, or before the newest tick when there's no end either. strategies adds
+/// those paper strategies' series (see paper.rs); an unfiltered static chart has all of them
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChartFilter {
+    pub sources: Vec<Datasource>,
+    pub symbols: Vec<SymbolCommon>,
+    pub series: Vec<ViewSeries>,
+    pub strategies: Vec<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub window: Option<Duration>,
+}
+
+impl ChartFilter {
+    /// from query parameters; lists are comma separated
+    pub fn from_params(params: &HashMap<String, String>) -> Result<ChartFilter, String> {
+        let list = |key: &str| params.get(key).map(|x| x.split(',').map(str::trim).filter(|x| !x.is_empty()).collect::<Vec<&str>>()).unwrap_or_default();
+        let time = |key: &str| params.get(key)
+            .map(|x| DateTime::parse_from_rfc3339(x).map(DateTime::<Utc>::from).map_err(|_| format!("bad {key}: {x}")))
+            .transpose();
+
+        let filter = ChartFilter {
+            sources: list("sources").into_iter().map(|x| Datasource::from_str(x).map_err(|_| format!("unknown source: {x}"))).collect::<Result<_, _>>()?,
+            symbols: list("symbols").into_iter().map(|x| SymbolCommon::from_str(x).map_err(|_| format!("unknown symbol: {x}"))).collect::<Result<_, _>>()?,
+            series: list("series").into_iter().map(ViewSeries::from_str).collect::<Result<_, _>>()?,
+            strategies: list("strategies").into_iter().map(str::to_string).collect(),
+            start: time("start")?,
+            end: time("end")?,
+            window: params.get("window").map(|x| Resolution::try_from(x.to_string()).map(|x| Duration::milliseconds(x.millis))).transpose()?,
+        };
+        match (filter.start, filter.end) {
+            (Some(start), Some(end)) if start >= end => Err(format!("start {start} isn't before end {end}")),
+            _ => Ok(filter),
+        }
+    }
+
+    pub fn matches(&self, source: &Datasource, symbol: &SymbolCommon, series: &ViewSeries) -> bool {
+        (self.sources.is_empty() || self.sources.contains(source))
+            && (self.symbols.is_empty() || self.symbols.contains(symbol))
+            && (self.series.is_empty() || self.series.contains(series))
+    }
+
+    /// line protocol measurements aren't from a datasource or symbol, so only an unfiltered chart has them
+    pub fn is_unfiltered(&self) -> bool {
+        self.sources.is_empty() && self.symbols.is_empty() && self.series.is_empty() && self.strategies.is_empty()
+    }
+
+    /// the start of the chart: start, or the window back from end (or newest)
+    pub fn since(&self, newest: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        match (self.start, self.window) {
+            (Some(start), _) => Some(start),
+            (None, Some(window)) => self.end.or(newest).map(|end| end - window),
+            (None, None) => None,
+        }
+    }
+
+    /// "btc_usd, eth_usd: Coinbase"
+    pub fn title(&self) -> String {
+        let sources: Vec<Datasource> = if self.sources.is_empty() { Datasource::iter().collect() } else { self.sources.clone() };
+        let sources = sources.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ");
+        if self.symbols.is_empty() {
+            sources
+        } else {
+            format!("{}: {sources}", self.symbols.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))
+        }
+    }
+
+    /// the websocket subscribe message for the same datasets
+    pub fn subscription(&self) -> serde_json::Value {
+        serde_json::json!({"action": "subscribe", "symbols": self.symbols, "sources": self.sources, "series": self.series, "strategies": self.strategies})
+    }
+}
+
+/// sent to subscribers; a snapshot replaces everything, a revision replaces each of its series
+/// from the first x in its dataset on (after a late tick), otherwise each point is appended or, if
+/// it has the same x as the last point of its series (an open window), replaces it
+#[derive(Debug, Clone)]
+pub struct ViewDelta {
+    pub view: String,
+    pub snapshot: bool,
+    pub revision: bool,
+    pub datasets: Vec<ChartDataset>,
+}
+
+impl ViewDelta {
+    /// fold a later delta into this one so several can be sent as one; appends are in time order,
+    /// so a revision taking in an append means the same thing
+    pub fn merge(&mut self, later: ViewDelta) {
+        if later.snapshot {
+            *self = later;
+            return;
+        }
+        self.revision |= later.revision && !self.snapshot;
+        for incoming in later.datasets.into_iter() {
+            match self.datasets.iter_mut().find(|x| x.label == incoming.label) {
+                Some(dataset) if later.revision => revise_points(&mut dataset.data, incoming.data),
+                Some(dataset) => {
+                    for point in incoming.data.into_iter() {
+                        upsert_point(&mut dataset.data, point);
+                    }
+                }
+                None => self.datasets.push(incoming),
+            }
+        }
+    }
+}
+
+/// what a chart websocket client receives: everything it subscribed to, then only the points it
+/// hasn't seen. Appended points with the same x as the last point of their series replace it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChartMessage {
+    Snapshot { datasets: Vec<ChartDataset> },
+    Append { datasets: Vec<ChartDataset> },
+    /// each series' points from the first x in its dataset on are replaced by the dataset's
+    Revise { datasets: Vec<ChartDataset> },
+    Error { message: String },
+    /// sent to every client with a subscription, see common_lib::alert
+    Alert { alert: Alert },
+}
+
+/// subscriber-side copy of a view's result, kept current by applying deltas
+#[derive(Debug, Clone, Default)]
+pub struct ViewResult {
+    pub datasets: Vec<ChartDataset>,
+    pub limit: usize,
+}
+
+impl ViewResult {
+    pub fn new(limit: usize) -> ViewResult {
+        ViewResult { datasets: vec![], limit }
+    }
+
+    pub fn apply(&mut self, delta: &ViewDelta) {
+        if delta.snapshot {
+            self.datasets.clear();
+        }
+        for incoming in delta.datasets.iter() {
+            let index = match self.datasets.iter().position(|x| x.label == incoming.label) {
+                Some(index) => index,
+                None => {
+                    self.datasets.push(ChartDataset { label: incoming.label.clone(), data: vec![] });
+                    self.datasets.len() - 1
+                }
+            };
+            let dataset = &mut self.datasets[index];
+            match delta.revision {
+                true => revise_points(&mut dataset.data, incoming.data.clone()),
+                false => {
+                    for point in incoming.data.iter() {
+                        upsert_point(&mut dataset.data, point.clone());
+                    }
+                }
+            }
+            if self.limit > 0 && dataset.data.len() > self.limit {
+                let excess = dataset.data.len() - self.limit;
+                dataset.data.drain(0..excess);
+            }
+        }
+    }
+
+    /// newest x across all series
+    pub fn most_recent(&self) -> Option<DateTime<Utc>> {
+        self.datasets.iter().filter_map(|x| x.data.last().map(|p| p.x)).max()
+    }
+}
+
+/// "500ms", "1s", "5m", "1h"; at least 1ms and at most a year
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Resolution {
+    pub millis: i64,
+}
+
+impl TryFrom<String> for Resolution {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let (count, unit) = value.split_at(split);
+        let count: i64 = count.parse().map_err(|_| format!("bad resolution: {value}"))?;
+        let unit_millis = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            _ => return Err(format!("bad resolution unit: {value}")),
+        };
+        match count.checked_mul(unit_millis) {
+            Some(millis) if (1..=Resolution::MAX_MILLIS).contains(&millis) => Ok(Resolution { millis }),
+            _ => Err(format!("bad resolution: {value}")),
+        }
+    }
+}
+
+/// in the largest unit that divides it
+impl From<Resolution> for String {
+    fn from(value: Resolution) -> Self {
+        match value.millis {
+            x if x % (60 * 60 * 1000) == 0 => format!("{}h", x / (60 * 60 * 1000)),
+            x if x % (60 * 1000) == 0 => format!("{}m", x / (60 * 1000)),
+            x if x % 1000 == 0 => format!("{}s", x / 1000),
+            x => format!("{x}ms"),
+        }
+    }
+}
+
+impl Resolution {
+    pub const MAX_MILLIS: i64 = 366 * 24 * 60 * 60 * 1000;
+
+    /// move every point to the start of its bucket, keeping the last value per bucket; a client
+    /// treats a point with the same x as its last point as a replacement, so buckets fill in live
+    pub fn downsample(&self, data: &[ChartTimeSeries]) -> Vec<ChartTimeSeries> {
+        let mut out: Vec<ChartTimeSeries> = vec![];
+        for point in data.iter() {
+            let x = self.bucket(point.x);
+            match out.last_mut() {
+                Some(last) if last.x == x => last.y = point.y,
+                _ => out.push(ChartTimeSeries { x, y: point.y }),
+            }
+        }
+        out
+    }
+
+    /// the start of the bucket dtg falls in
+    pub fn bucket(&self, dtg: DateTime<Utc>) -> DateTime<Utc> {
+        if self.millis <= 0 {
+            return dtg;
+        }
+        let millis = dtg.timestamp_millis();
+        Utc.timestamp_millis_opt(millis - millis.rem_euclid(self.millis)).unwrap()
+    }
+}
+
+/// append, or replace the last point when it's the same (still open) window
+pub fn upsert_point(data: &mut Vec<ChartTimeSeries>, point: ChartTimeSeries) {
+    match data.last_mut() {
+        Some(last) if last.x == point.x => *last = point,
+        _ => data.push(point),
+    }
+}
+
+/// replace the points at or after the first of revised with revised
+pub fn revise_points(data: &mut Vec<ChartTimeSeries>, revised: Vec<ChartTimeSeries>) {
+    let Some(first) = revised.first() else {
+        return;
+    };
+    let keep = data.partition_point(|x| x.x < first.x);
+    data.truncate(keep);
+    data.extend(revised);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use chrono::{DateTime, Utc};
+    use crate::cb_ticker::Datasource;
+    use crate::view::{ChartFilter, ChartMessage, Resolution, ViewDelta, ViewResult, ViewSeries, ViewSpec};
+    use crate::{CalculationId, ChartDataset, ChartTimeSeries, SymbolCommon};
+
+    fn point(rfc3339: &str, y: f64) -> ChartTimeSeries {
+        ChartTimeSeries { x: DateTime::<Utc>::from(DateTime::parse_from_rfc3339(rfc3339).unwrap()), y }
+    }
+
+    #[test]
+    fn test_spec_matches() {
+        let spec = ViewSpec::new("btc").symbol(SymbolCommon::BtcUsd).source(Datasource::Coinbase);
+        assert!(spec.matches(&Datasource::Coinbase, &SymbolCommon::BtcUsd, &ViewSeries::Price));
+        assert!(spec.matches(&Datasource::Coinbase, &SymbolCommon::BtcUsd, &ViewSeries::Calc(CalculationId::MovingAvg0010)));
+        assert!(!spec.matches(&Datasource::Alpaca, &SymbolCommon::BtcUsd, &ViewSeries::Price));
+        assert!(!spec.matches(&Datasource::Coinbase, &SymbolCommon::EthUsd, &ViewSeries::Price));
+
+        let spec = spec.series(ViewSeries::Price);
+        assert!(!spec.matches(&Datasource::Coinbase, &SymbolCommon::BtcUsd, &ViewSeries::Calc(CalculationId::MovingAvg0010)));
+    }
+
+    #[test]
+    fn test_result_apply() {
+        let mut result = ViewResult::new(2);
+        result.apply(&ViewDelta {
+            view: "v".to_string(),
+            snapshot: true,
+            revision: false,
+            datasets: vec![ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00Z", 1.0)] }],
+        });
+        // same x replaces, new x appends, limit trims the oldest
+        result.apply(&ViewDelta {
+            view: "v".to_string(),
+            snapshot: false,
+            revision: false,
+            datasets: vec![
+                ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00Z", 2.0), point("2024-01-14T23:30:01Z", 3.0), point("2024-01-14T23:30:02Z", 4.0)] },
+                ChartDataset { label: "b".to_string(), data: vec![point("2024-01-14T23:30:05Z", 5.0)] },
+            ],
+        });
+        assert_eq!(result.datasets.len(), 2);
+        assert_eq!(result.datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![3.0, 4.0]);
+        assert_eq!(result.most_recent(), Some(point("2024-01-14T23:30:05Z", 0.0).x));
+
+        // a revision replaces from its first x on and leaves the other series alone
+        result.apply(&ViewDelta {
+            view: "v".to_string(),
+            snapshot: false,
+            revision: true,
+            datasets: vec![ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:01.500Z", 6.0), point("2024-01-14T23:30:02Z", 7.0)] }],
+        });
+        assert_eq!(result.datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![6.0, 7.0]);
+        assert_eq!(result.datasets[1].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![5.0]);
+
+        result.apply(&ViewDelta { view: "v".to_string(), snapshot: true, revision: false, datasets: vec![] });
+        assert!(result.datasets.is_empty());
+    }
+
+    #[test]
+    fn test_delta_merge() {
+        let mut pending = ViewDelta {
+            view: "v".to_string(),
+            snapshot: false,
+            revision: false,
+            datasets: vec![ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00Z", 1.0)] }],
+        };
+        pending.merge(ViewDelta {
+            view: "v".to_string(),
+            snapshot: false,
+            revision: false,
+            datasets: vec![
+                ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00Z", 2.0), point("2024-01-14T23:30:01Z", 3.0)] },
+                ChartDataset { label: "b".to_string(), data: vec![point("2024-01-14T23:30:01Z", 4.0)] },
+            ],
+        });
+        assert_eq!(pending.datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![2.0, 3.0]);
+        assert_eq!(pending.datasets[1].label, "b");
+
+        pending.merge(ViewDelta {
+            view: "v".to_string(),
+            snapshot: false,
+            revision: true,
+            datasets: vec![ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00.500Z", 5.0), point("2024-01-14T23:30:01Z", 6.0)] }],
+        });
+        assert!(pending.revision);
+        assert_eq!(pending.datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![2.0, 5.0, 6.0]);
+
+        pending.merge(ViewDelta { view: "v".to_string(), snapshot: true, revision: false, datasets: vec![] });
+        assert!(pending.snapshot);
+        assert!(pending.datasets.is_empty());
+    }
+
+    #[test]
+    fn test_chart_message_json() {
+        let msg = ChartMessage::Append { datasets: vec![ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00Z", 1.0)] }] };
+        assert_eq!(serde_json::to_string(&msg).unwrap(), r#"{"type":"append","datasets":[{"label":"a","data":[{"x":"2024-01-14T23:30:00Z","y":1.0}]}]}"#);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let resolution = Resolution::try_from("1s".to_string()).unwrap();
+        let data = vec![point("2024-01-14T23:30:00.100Z", 1.0), point("2024-01-14T23:30:00.900Z", 2.0), point("2024-01-14T23:30:01.500Z", 3.0)];
+        let out = resolution.downsample(&data);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].x.to_rfc3339(), "2024-01-14T23:30:00+00:00");
+        assert_eq!(out[0].y, 2.0);
+        assert_eq!(out[1].y, 3.0);
+        assert_eq!(Resolution::try_from("250ms".to_string()).unwrap().millis, 250);
+        for x in ["250ms", "1500ms", "90s", "5m", "2h"] {
+            assert_eq!(String::from(Resolution::try_from(x.to_string()).unwrap()), x);
+        }
+        for x in ["0s", "0ms", "-5s", "144115188075855872h", "9223372036854775807ms", "9000h", "", "5", "1d"] {
+            assert!(Resolution::try_from(x.to_string()).is_err(), "{x}");
+        }
+        assert!(serde_json::from_str::<Resolution>(r#""0m""#).is_err());
+        let zero = Resolution { millis: 0 };
+        assert_eq!(zero.downsample(&data).len(), 3);
+    }
+
+    #[test]
+    fn test_chart_filter() {
+        let params = |query: &[(&str, &str)]| query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<String, String>>();
+        let filter = ChartFilter::from_params(&params(&[("symbols", "btc_usd,eth_usd"), ("sources", "coinbase"), ("series", "price,MovingAvg0100"), ("window", "15m")])).unwrap();
+        assert_eq!(filter.symbols, vec![SymbolCommon::BtcUsd, SymbolCommon::EthUsd]);
+        assert!(filter.matches(&Datasource::Coinbase, &SymbolCommon::EthUsd, &ViewSeries::Calc(CalculationId::MovingAvg0100)));
+        assert!(!filter.matches(&Datasource::Alpaca, &SymbolCommon::BtcUsd, &ViewSeries::Price));
+        assert!(!filter.matches(&Datasource::Coinbase, &SymbolCommon::BtcUsd, &ViewSeries::Calc(CalculationId::MovingAvg0010)));
+        assert_eq!(filter.title(), "btc_usd, eth_usd: Coinbase");
+        assert_eq!(filter.subscription().to_string(), r#"{"action":"subscribe","series":["price",{"calc":"MovingAvg0100"}],"sources":["coinbase"],"strategies":[],"symbols":["btc_usd","eth_usd"]}"#);
+
+        // the window runs back from end, or the newest point without one
+        let newest = point("2024-01-14T23:30:00Z", 0.0).x;
+        assert_eq!(filter.since(Some(newest)), Some(point("2024-01-14T23:15:00Z", 0.0).x));
+        assert_eq!(filter.since(None), None);
+        let filter = ChartFilter::from_params(&params(&[("end", "2024-01-14T23:00:00Z"), ("window", "1h")])).unwrap();
+        assert_eq!(filter.since(Some(newest)), Some(point("2024-01-14T22:00:00Z", 0.0).x));
+        assert!(filter.is_unfiltered());
+        assert_eq!(filter.title(), "Coinbase, Alpaca");
+        let filter = ChartFilter::from_params(&params(&[("strategies", "ma_turn, cross")])).unwrap();
+        assert_eq!(filter.strategies, vec!["ma_turn", "cross"]);
+        assert!(!filter.is_unfiltered());
+
+        for bad in [("symbols", "doge_usd"), ("sources", "nyse"), ("series", "MovingAvg9999"), ("window", "1d"), ("window", "0m"), ("window", "144115188075855872h"), ("start", "yesterday")] {
+            assert!(ChartFilter::from_params(&params(&[bad])).is_err(), "{bad:?}");
+        }
+        assert!(ChartFilter::from_params(&params(&[("start", "2024-01-14T23:00:00Z"), ("end", "2024-01-14T22:00:00Z")])).is_err());
+    }
+}
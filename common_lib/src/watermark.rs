@@ -0,0 +1,97 @@
+//! watermark.rs
+//!
+//! event time is authoritative: a tick older than its source's newest tick is late and goes in
+//! where it belongs, unless it's further behind than the lateness bound allows
+
+use std::str::FromStr;
+use chrono::{DateTime, Duration, Utc};
+use strum_macros::Display;
+use crate::cb_ticker::Datasource;
+
+/// how far behind its source's newest tick a tick may arrive and still be stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lateness(pub Duration);
+
+impl Default for Lateness {
+    fn default() -> Self {
+        Lateness(Duration::seconds(5))
+    }
+}
+
+impl Lateness {
+    /// a day; any more and the watermark could run off the start of time
+    pub const MAX_MILLIS: i64 = 24 * 60 * 60 * 1000;
+}
+
+/// "0s", "250ms", "5s", "2m"
+impl FromStr for Lateness {
+    type Err = LatenessError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bad = || LatenessError::Argument(value.to_string());
+        let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let (count, unit) = value.split_at(split);
+        let count: i64 = count.parse().map_err(|_| bad())?;
+        let unit_millis = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            _ => return Err(bad()),
+        };
+        match count.checked_mul(unit_millis) {
+            Some(millis) if millis <= Lateness::MAX_MILLIS => Ok(Lateness(Duration::milliseconds(millis))),
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// the largest unit that's exact, so it reads back the same
+impl std::fmt::Display for Lateness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.num_milliseconds() {
+            ms if ms != 0 && ms % 60_000 == 0 => write!(f, "{}m", ms / 60_000),
+            ms if ms % 1000 == 0 => write!(f, "{}s", ms / 1000),
+            ms => write!(f, "{ms}ms"),
+        }
+    }
+}
+
+/// a source's progress in event time; ticks before watermark are dropped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub source: Datasource,
+    pub newest: DateTime<Utc>,
+    pub watermark: DateTime<Utc>,
+    /// stored out of order
+    pub late: u64,
+    /// behind the watermark, not stored
+    pub dropped: u64,
+}
+
+#[derive(Debug, Display)]
+pub enum LatenessError {
+    Argument(String),
+}
+
+impl std::error::Error for LatenessError {}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use chrono::Duration;
+    use crate::watermark::Lateness;
+
+    #[test]
+    fn test_lateness() {
+        assert_eq!(Lateness::from_str("250ms").unwrap(), Lateness(Duration::milliseconds(250)));
+        assert_eq!(Lateness::from_str("0s").unwrap(), Lateness(Duration::zero()));
+        assert_eq!(Lateness::from_str("1440m").unwrap(), Lateness(Duration::days(1)));
+        for bad in ["", "5", "s", "5h", "-1s", "1441m", "153722867280912931m", "9223372036854775807s"] {
+            assert!(Lateness::from_str(bad).is_err(), "{bad}");
+        }
+
+        for text in ["250ms", "0s", "5s", "2m", "90s"] {
+            assert_eq!(Lateness::from_str(text).unwrap().to_string(), text);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! wire.rs
+//!
+//! chart data encodings. Json is the default and what older pages expect; msgpack and Arrow IPC
+//! carry the same ChartMessage in a fraction of the bytes, with millisecond timestamps instead of
+//! an rfc3339 string on every point. static/js/chart_wire.js decodes both in the browser.
+//!
+//! msgpack: {"type":"append","datasets":[{"label":"btc_usd_Coinbase","x":[ms, ..],"y":[f64, ..]}]}
+//!
+//! arrow: one record batch, one row per point: label (dictionary of utf8), x (timestamp ms, UTC),
+//! y (float64). The message type (and an error's message, or an alert as json) are in the schema
+//! metadata.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use chrono::{TimeZone, Utc};
+use datafusion::arrow::array::{Array, DictionaryArray, Float64Array, Int32Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use datafusion::arrow::datatypes::{DataType, Field, Int32Type, Schema, TimeUnit};
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+use crate::alert::Alert;
+use crate::view::ChartMessage;
+use crate::{ChartDataset, ChartTimeSeries};
+
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+pub const CONTENT_TYPE_MSGPACK: &str = "application/msgpack";
+pub const CONTENT_TYPE_ARROW: &str = "application/vnd.apache.arrow.stream";
+
+/// picked per websocket connection (?format=msgpack) or per HTTP request (Accept header)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Display, EnumString, Deserialize)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Msgpack,
+    Arrow,
+}
+
+impl WireFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            WireFormat::Json => CONTENT_TYPE_JSON,
+            WireFormat::Msgpack => CONTENT_TYPE_MSGPACK,
+            WireFormat::Arrow => CONTENT_TYPE_ARROW,
+        }
+    }
+
+    /// first media type in an Accept header we can produce; json otherwise
+    pub fn from_accept(accept: &str) -> WireFormat {
+        accept
+            .split(',')
+            .map(|x| x.split(';').next().unwrap_or_default().trim())
+            .find_map(|x| match x {
+                CONTENT_TYPE_MSGPACK | "application/x-msgpack" => Some(WireFormat::Msgpack),
+                CONTENT_TYPE_ARROW => Some(WireFormat::Arrow),
+                CONTENT_TYPE_JSON => Some(WireFormat::Json),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// from a query string value; anything unknown is json
+    pub fn from_query(value: Option<&str>) -> WireFormat {
+        value.and_then(|x| WireFormat::from_str(x).ok()).unwrap_or_default()
+    }
+
+    pub fn is_binary(&self) -> bool {
+        !matches!(self, WireFormat::Json)
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum WireError {
+    Json(String),
+    Msgpack(String),
+    Arrow(String),
+}
+
+impl std::error::Error for WireError {}
+
+/// columnar dataset for msgpack
+#[derive(Debug, Serialize, Deserialize)]
+struct PackedDataset {
+    label: String,
+    x: Vec<i64>,
+    y: Vec<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PackedMessage {
+    Snapshot { datasets: Vec<PackedDataset> },
+    Append { datasets: Vec<PackedDataset> },
+    Revise { datasets: Vec<PackedDataset> },
+    Error { message: String },
+    Alert { alert: Alert },
+}
+
+pub fn encode(msg: &ChartMessage, format: WireFormat) -> Result<Vec<u8>, WireError> {
+    match format {
+        WireFormat::Json => serde_json::to_vec(msg).map_err(|e| WireError::Json(e.to_string())),
+        WireFormat::Msgpack => rmp_serde::to_vec_named(&pack(msg)).map_err(|e| WireError::Msgpack(e.to_string())),
+        WireFormat::Arrow => to_arrow(msg).map_err(|e| WireError::Arrow(e.to_string())),
+    }
+}
+
+pub fn decode(bytes: &[u8], format: WireFormat) -> Result<ChartMessage, WireError> {
+    match format {
+        WireFormat::Json => serde_json::from_slice(bytes).map_err(|e| WireError::Json(e.to_string())),
+        WireFormat::Msgpack => rmp_serde::from_slice::<PackedMessage>(bytes).map(unpack).map_err(|e| WireError::Msgpack(e.to_string())),
+        WireFormat::Arrow => from_arrow(bytes).map_err(|e| WireError::Arrow(e.to_string())),
+    }
+}
+
+fn pack(msg: &ChartMessage) -> PackedMessage {
+    let packed = |datasets: &[ChartDataset]| -> Vec<PackedDataset> {
+        datasets
+            .iter()
+            .map(|d| PackedDataset { label: d.label.clone(), x: d.data.iter().map(|p| p.x.timestamp_millis()).collect(), y: d.data.iter().map(|p| p.y).collect() })
+            .collect()
+    };
+    match msg {
+        ChartMessage::Snapshot { datasets } => PackedMessage::Snapshot { datasets: packed(datasets) },
+        ChartMessage::Append { datasets } => PackedMessage::Append { datasets: packed(datasets) },
+        ChartMessage::Revise { datasets } => PackedMessage::Revise { datasets: packed(datasets) },
+        ChartMessage::Error { message } => PackedMessage::Error { message: message.clone() },
+        ChartMessage::Alert { alert } => PackedMessage::Alert { alert: alert.clone() },
+    }
+}
+
+fn unpack(msg: PackedMessage) -> ChartMessage {
+    let unpacked = |datasets: Vec<PackedDataset>| -> Vec<ChartDataset> {
+        datasets
+            .into_iter()
+            .map(|d| ChartDataset { label: d.label, data: d.x.iter().zip(d.y.iter()).map(|(x, y)| ChartTimeSeries { x: Utc.timestamp_millis_opt(*x).unwrap(), y: *y }).collect() })
+            .collect()
+    };
+    match msg {
+        PackedMessage::Snapshot { datasets } => ChartMessage::Snapshot { datasets: unpacked(datasets) },
+        PackedMessage::Append { datasets } => ChartMessage::Append { datasets: unpacked(datasets) },
+        PackedMessage::Revise { datasets } => ChartMessage::Revise { datasets: unpacked(datasets) },
+        PackedMessage::Error { message } => ChartMessage::Error { message },
+        PackedMessage::Alert { alert } => ChartMessage::Alert { alert },
+    }
+}
+
+/// query results as an Arrow IPC stream, unchanged from the db
+pub fn batches_to_arrow(schema: &Schema, batches: &[RecordBatch]) -> Result<Vec<u8>, WireError> {
+    let write = || -> Result<Vec<u8>, datafusion::arrow::error::ArrowError> {
+        let mut buf = vec![];
+        {
+            let mut writer = StreamWriter::try_new(&mut buf, schema)?;
+            for batch in batches.iter() {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+        }
+        Ok(buf)
+    };
+    write().map_err(|e| WireError::Arrow(e.to_string()))
+}
+
+fn arrow_schema(metadata: HashMap<String, String>) -> Schema {
+    Schema::new_with_metadata(
+        vec![
+            Field::new("label", DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)), false),
+            Field::new("x", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+            Field::new("y", DataType::Float64, false),
+        ],
+        metadata,
+    )
+}
+
+fn to_arrow(msg: &ChartMessage) -> Result<Vec<u8>, datafusion::arrow::error::ArrowError> {
+    // an alert has no points, so it goes as json in the schema metadata like an error's message
+    let alert;
+    let (kind, datasets, message): (&str, &[ChartDataset], Option<&String>) = match msg {
+        ChartMessage::Snapshot { datasets } => ("snapshot", datasets, None),
+        ChartMessage::Append { datasets } => ("append", datasets, None),
+        ChartMessage::Revise { datasets } => ("revise", datasets, None),
+        ChartMessage::Error { message } => ("error", &[], Some(message)),
+        ChartMessage::Alert { alert: a } => {
+            alert = serde_json::to_string(a).map_err(|e| datafusion::arrow::error::ArrowError::JsonError(e.to_string()))?;
+            ("alert", &[], Some(&alert))
+        }
+    };
+    let mut metadata = HashMap::from([("type".to_string(), kind.to_string())]);
+    if let Some(message) = message {
+        metadata.insert("message".to_string(), message.clone());
+    }
+    let schema = Arc::new(arrow_schema(metadata));
+
+    let points: usize = datasets.iter().map(|d| d.data.len()).sum();
+    let (mut keys, mut xs, mut ys) = (Vec::with_capacity(points), Vec::with_capacity(points), Vec::with_capacity(points));
+    for (index, dataset) in datasets.iter().enumerate() {
+        for point in dataset.data.iter() {
+            keys.push(index as i32);
+            xs.push(point.x.timestamp_millis());
+            ys.push(point.y);
+        }
+    }
+    let labels = StringArray::from(datasets.iter().map(|d| d.label.as_str()).collect::<Vec<&str>>());
+    let label = DictionaryArray::<Int32Type>::try_new(Int32Array::from(keys), Arc::new(labels))?;
+    let x = TimestampMillisecondArray::from(xs).with_timezone("UTC");
+    let y = Float64Array::from(ys);
+    let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(label), Arc::new(x), Arc::new(y)])?;
+
+    let mut buf = vec![];
+    {
+        let mut writer = StreamWriter::try_new(&mut buf, &schema)?;
+        writer.write(&batch)?;
+        writer.finish()?;
+    }
+    Ok(buf)
+}
+
+fn from_arrow(bytes: &[u8]) -> Result<ChartMessage, datafusion::arrow::error::ArrowError> {
+    use datafusion::arrow::error::ArrowError;
+    let reader = StreamReader::try_new(bytes, None)?;
+    let metadata = reader.schema().metadata().clone();
+    let mut datasets: Vec<ChartDataset> = vec![];
+    for batch in reader {
+        let batch = batch?;
+        let column_error = || ArrowError::SchemaError("unexpected chart columns".to_string());
+        let label = batch.column(0).as_any().downcast_ref::<DictionaryArray<Int32Type>>().ok_or_else(column_error)?;
+        let labels = label.values().as_any().downcast_ref::<StringArray>().ok_or_else(column_error)?;
+        let x = batch.column(1).as_any().downcast_ref::<TimestampMillisecondArray>().ok_or_else(column_error)?;
+        let y = batch.column(2).as_any().downcast_ref::<Float64Array>().ok_or_else(column_error)?;
+        for row in 0..batch.num_rows() {
+            let name = labels.value(label.keys().value(row) as usize);
+            let point = ChartTimeSeries { x: Utc.timestamp_millis_opt(x.value(row)).unwrap(), y: y.value(row) };
+            match datasets.iter_mut().find(|d| d.label == name) {
+                Some(dataset) => dataset.data.push(point),
+                None => datasets.push(ChartDataset { label: name.to_string(), data: vec![point] }),
+            }
+        }
+    }
+    match metadata.get("type").map(|x| x.as_str()) {
+        Some("snapshot") => Ok(ChartMessage::Snapshot { datasets }),
+        Some("append") => Ok(ChartMessage::Append { datasets }),
+        Some("revise") => Ok(ChartMessage::Revise { datasets }),
+        Some("error") => Ok(ChartMessage::Error { message: metadata.get("message").cloned().unwrap_or_default() }),
+        Some("alert") => serde_json::from_str(metadata.get("message").map_or("", |x| x.as_str()))
+            .map(|alert| ChartMessage::Alert { alert })
+            .map_err(|e| ArrowError::JsonError(e.to_string())),
+        _ => Err(ArrowError::SchemaError("missing chart message type".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use chrono::{Duration, TimeZone, Utc};
+    use crate::alert::Alert;
+    use crate::cb_ticker::Datasource;
+    use crate::view::ChartMessage;
+    use crate::wire::{decode, encode, WireFormat};
+    use crate::{ChartDataset, ChartTimeSeries, SymbolCommon};
+
+    /// six series of 1000 points each, a second apart; what the chart page holds
+    fn chart() -> ChartMessage {
+        let start = Utc.timestamp_millis_opt(1_705_275_000_123).unwrap();
+        let datasets = ["btc_usd_Coinbase", "btc_usd_Alpaca", "btc_usd_MovingAvg0010_Coinbase", "btc_usd_MovingAvg0100_Coinbase", "btc_usd_MovAvgDiff0010_0100_Coinbase", "btc_usd_MovAvgDiff0100_1000_Coinbase"]
+            .iter()
+            .enumerate()
+            .map(|(i, label)| ChartDataset {
+                label: label.to_string(),
+                data: (0..1000).map(|n| ChartTimeSeries { x: start + Duration::seconds(n), y: 42000.0 + (i * 1000 + n as usize) as f64 * 0.37 }).collect(),
+            })
+            .collect();
+        ChartMessage::Snapshot { datasets }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for format in [WireFormat::Json, WireFormat::Msgpack, WireFormat::Arrow] {
+            let bytes = encode(&chart(), format).unwrap();
+            match decode(&bytes, format).unwrap() {
+                ChartMessage::Snapshot { datasets } => {
+                    assert_eq!(datasets.len(), 6, "{format}");
+                    assert_eq!(datasets[5].label, "btc_usd_MovAvgDiff0100_1000_Coinbase");
+                    assert_eq!(datasets[5].data[999].x.timestamp_millis(), 1_705_275_000_123 + 999_000);
+                    assert_eq!(datasets[5].data[999].y, 42000.0 + 5999.0 * 0.37);
+                }
+                msg => panic!("{format}: unexpected {msg:?}"),
+            }
+            let revise = encode(&ChartMessage::Revise { datasets: vec![ChartDataset { label: "a".to_string(), data: vec![ChartTimeSeries { x: Utc.timestamp_millis_opt(1_705_275_000_123).unwrap(), y: 1.5 }] }] }, format).unwrap();
+            assert!(matches!(decode(&revise, format).unwrap(), ChartMessage::Revise { datasets } if datasets[0].data[0].y == 1.5), "{format}");
+            let error = encode(&ChartMessage::Error { message: "bad".to_string() }, format).unwrap();
+            assert!(matches!(decode(&error, format).unwrap(), ChartMessage::Error { message } if message == "bad"));
+            let alert = Alert { rule: "btc_up".to_string(), dtg: Utc.timestamp_millis_opt(1_705_275_000_123).unwrap(), source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, value: 42000.5, message: "up".to_string() };
+            let bytes = encode(&ChartMessage::Alert { alert: alert.clone() }, format).unwrap();
+            assert!(matches!(decode(&bytes, format).unwrap(), ChartMessage::Alert { alert: a } if a == alert), "{format}");
+        }
+    }
+
+    #[test]
+    fn test_negotiation() {
+        assert_eq!(WireFormat::from_accept("application/vnd.apache.arrow.stream, application/json;q=0.5"), WireFormat::Arrow);
+        assert_eq!(WireFormat::from_accept("text/html, application/msgpack"), WireFormat::Msgpack);
+        assert_eq!(WireFormat::from_accept("*/*"), WireFormat::Json);
+        assert_eq!(WireFormat::from_query(Some("arrow")), WireFormat::Arrow);
+        assert_eq!(WireFormat::from_query(Some("xml")), WireFormat::Json);
+        assert_eq!(WireFormat::from_query(None), WireFormat::Json);
+    }
+
+    /// cargo test -p common_lib --release test_payload_sizes -- --nocapture
+    #[test]
+    fn test_payload_sizes() {
+        let msg = chart();
+        let mut sizes = vec![];
+        for format in [WireFormat::Json, WireFormat::Msgpack, WireFormat::Arrow] {
+            let start = Instant::now();
+            let mut bytes = vec![];
+            for _ in 0..20 {
+                bytes = encode(&msg, format).unwrap();
+            }
+            println!("{format}: {} bytes, {:?} per encode", bytes.len(), start.elapsed() / 20);
+            sizes.push(bytes.len());
+        }
+        // json spends ~30 bytes per point on the rfc3339 string alone
+        assert!(sizes[1] * 2 < sizes[0]);
+        assert!(sizes[2] * 2 < sizes[0]);
+    }
+}
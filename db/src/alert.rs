@@ -0,0 +1,388 @@
+//! alert.rs
+//!
+//! the alert rules (see common_lib::alert), checked by the db thread against each live ticker and
+//! the calculations it produced. A crossing fires on the tick that crosses, then the rule waits
+//! until the value is back past its level by the hysteresis, and stays quiet for its cooldown. Late
+//! tickers revise calculations but fire nothing, and backfills aren't checked at all.
+//!
+
+use std::collections::VecDeque;
+use chrono::{DateTime, Duration, Utc};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use common_lib::alert::{Alert, AlertError, AlertRule, Condition, Direction, Fired};
+use common_lib::cb_ticker::{Datasource, TickerCalc};
+use common_lib::point::{FieldType, FieldValue, MeasurementSchema};
+use common_lib::view::ViewSeries;
+use common_lib::TickerCommon;
+use crate::event_book::TABLE_ALERTS;
+use crate::measurement::{record_batch, Row};
+
+/// alerts kept for the api and the alerts table, oldest forgotten first
+pub const ALERT_HISTORY: usize = 1000;
+
+/// every rule and what each has seen; owned by the db thread
+#[derive(Default)]
+pub struct AlertEngine {
+    rules: Vec<RuleState>,
+    history: VecDeque<Alert>,
+    subscribers: Vec<Sender<Fired>>,
+}
+
+struct RuleState {
+    rule: AlertRule,
+    /// which side of its level the value is on, None before the first value
+    side: Option<Side>,
+    /// a crossover's newest fast and slow values
+    fast: Option<f64>,
+    slow: Option<f64>,
+    /// a percent move's values inside its window
+    window: VecDeque<(DateTime<Utc>, f64)>,
+    /// the newest ticker, or the first staleness check
+    last_seen: Option<DateTime<Utc>>,
+    stale: bool,
+    last_fired: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Above,
+    Below,
+}
+
+impl AlertEngine {
+    pub fn new() -> AlertEngine {
+        AlertEngine { rules: vec![], history: VecDeque::new(), subscribers: vec![] }
+    }
+
+    /// replace every rule; what they'd seen starts over
+    pub fn set_rules(&mut self, rules: Vec<AlertRule>) {
+        self.rules = rules.into_iter().map(RuleState::new).collect();
+    }
+
+    pub fn rules(&self) -> Vec<AlertRule> {
+        self.rules.iter().map(|x| x.rule.clone()).collect()
+    }
+
+    /// add a rule, or replace the one with its name; returns every rule for saving
+    pub fn put(&mut self, rule: AlertRule) -> Result<Vec<AlertRule>, AlertError> {
+        rule.validate()?;
+        match self.rules.iter_mut().find(|x| x.rule.name == rule.name) {
+            Some(state) => *state = RuleState::new(rule),
+            None => self.rules.push(RuleState::new(rule)),
+        }
+        Ok(self.rules())
+    }
+
+    /// returns every rule left for saving
+    pub fn delete(&mut self, name: &str) -> Result<Vec<AlertRule>, AlertError> {
+        let count = self.rules.len();
+        self.rules.retain(|x| x.rule.name != name);
+        match self.rules.len() == count {
+            true => Err(AlertError::NotFound(name.to_string())),
+            false => Ok(self.rules()),
+        }
+    }
+
+    /// oldest first
+    pub fn history(&self) -> Vec<Alert> {
+        self.history.iter().cloned().collect()
+    }
+
+    /// every alert from now on, with its rule's webhook
+    pub fn subscribe(&mut self) -> Receiver<Fired> {
+        let (tx, rx) = unbounded();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// check a live ticker, and the calculations it produced, against the rules for its source and
+    /// symbol; returns what fired
+    pub fn apply(&mut self, source: &Datasource, ticker: &TickerCommon, calcs: &[TickerCalc]) -> Vec<Alert> {
+        let value = |series: &ViewSeries| match series {
+            ViewSeries::Price => Some(ticker.price),
+            ViewSeries::Calc(calc_id) => calcs.iter().find(|c| c.symbol == ticker.symbol && c.calc_id == *calc_id).map(|c| c.val),
+        };
+        let fired: Vec<Fired> = self.rules.iter_mut()
+            .filter(|x| x.rule.source == *source && x.rule.symbol == ticker.symbol)
+            .filter_map(|x| x.apply(ticker.dtg, value))
+            .collect();
+        self.record(fired)
+    }
+
+    /// fire the staleness rules whose source and symbol have gone quiet; a rule's clock starts at
+    /// its first check, so a feed that never connects is caught too
+    pub fn check_stale(&mut self, now: DateTime<Utc>) -> Vec<Alert> {
+        let fired: Vec<Fired> = self.rules.iter_mut().filter_map(|x| x.check_stale(now)).collect();
+        self.record(fired)
+    }
+
+    /// the history as a sql table
+    pub fn record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let rows: Vec<&Alert> = self.history.iter().collect();
+        record_batch(&AlertEngine::alerts_measurement(), &rows)
+    }
+
+    pub fn alerts_measurement() -> MeasurementSchema {
+        MeasurementSchema::new(TABLE_ALERTS)
+            .tag("rule")
+            .tag("source")
+            .tag("symbol")
+            .field("value", FieldType::Float)
+            .field("message", FieldType::String)
+    }
+
+    fn record(&mut self, fired: Vec<Fired>) -> Vec<Alert> {
+        for x in fired.iter() {
+            tracing::info!("[AlertEngine] {}: {}", &x.alert.rule, &x.alert.message);
+            self.history.push_back(x.alert.clone());
+            if self.history.len() > ALERT_HISTORY {
+                self.history.pop_front();
+            }
+        }
+        if !fired.is_empty() {
+            self.subscribers.retain(|tx| fired.iter().all(|x| tx.send(x.clone()).is_ok()));
+        }
+        fired.into_iter().map(|x| x.alert).collect()
+    }
+}
+
+impl RuleState {
+    fn new(rule: AlertRule) -> RuleState {
+        RuleState { rule, side: None, fast: None, slow: None, window: VecDeque::new(), last_seen: None, stale: false, last_fired: None }
+    }
+
+    fn apply(&mut self, dtg: DateTime<Utc>, value: impl Fn(&ViewSeries) -> Option<f64>) -> Option<Fired> {
+        self.last_seen = Some(dtg);
+        self.stale = false;
+        let symbol = self.rule.symbol.clone();
+        let (val, message) = match self.rule.condition.clone() {
+            Condition::Threshold { series, level, direction } => {
+                let val = value(&series)?;
+                let crossed = self.cross(val - level, direction)?;
+                (val, format!("{symbol} {} crossed {} {level} at {val}", series.name(), word(crossed)))
+            }
+            Condition::Crossover { fast, slow, direction } => {
+                let (new_fast, new_slow) = (value(&fast), value(&slow));
+                if new_fast.is_none() && new_slow.is_none() {
+                    return None;
+                }
+                self.fast = new_fast.or(self.fast);
+                self.slow = new_slow.or(self.slow);
+                let diff = self.fast? - self.slow?;
+                let crossed = self.cross(diff, direction)?;
+                (diff, format!("{symbol} {} crossed {} {}", fast.name(), word(crossed), slow.name()))
+            }
+            Condition::PercentMove { series, percent, window, direction } => {
+                let val = value(&series)?;
+                self.window.push_back((dtg, val));
+                while self.window.front().is_some_and(|(x, _)| *x < dtg - Duration::milliseconds(window.millis)) {
+                    self.window.pop_front();
+                }
+                let oldest = self.window.front()?.1;
+                if oldest == 0.0 {
+                    return None;
+                }
+                let change = (val - oldest) / oldest * 100.0;
+                let moved = match direction {
+                    Direction::Up => change,
+                    Direction::Down => -change,
+                    Direction::Either => change.abs(),
+                };
+                self.cross(moved - percent, Direction::Up)?;
+                (change, format!("{symbol} {} moved {change:+.2}% within {}", series.name(), String::from(window)))
+            }
+            Condition::Stale { .. } => return None,
+        };
+        self.fire(dtg, val, message)
+    }
+
+    fn check_stale(&mut self, now: DateTime<Utc>) -> Option<Fired> {
+        let Condition::Stale { after } = &self.rule.condition else {
+            return None;
+        };
+        let last_seen = *self.last_seen.get_or_insert(now);
+        if self.stale || now - last_seen < Duration::milliseconds(after.millis) {
+            return None;
+        }
+        self.stale = true;
+        let secs = (now - last_seen).num_milliseconds() as f64 / 1000.0;
+        let message = format!("no {} ticks for {} in {secs:.0}s", self.rule.source.to_string().to_lowercase(), self.rule.symbol);
+        self.fire(now, secs, message)
+    }
+
+    /// the way distance (value minus level) crossed zero, if the rule wants that direction. A
+    /// crossing the other way only counts once it's the hysteresis past, so a value hovering at the
+    /// level fires once; with Either both ways need it. The first value only sets the side.
+    fn cross(&mut self, distance: f64, wanted: Direction) -> Option<Direction> {
+        let hysteresis = self.rule.hysteresis;
+        let (up_at, down_at) = match wanted {
+            Direction::Up => (0.0, -hysteresis),
+            Direction::Down => (hysteresis, 0.0),
+            Direction::Either => (hysteresis, -hysteresis),
+        };
+        let (side, crossed) = match self.side {
+            None if distance > 0.0 => (Some(Side::Above), None),
+            None if distance < 0.0 => (Some(Side::Below), None),
+            Some(Side::Below) if distance > up_at => (Some(Side::Above), Some(Direction::Up)),
+            Some(Side::Above) if distance < down_at => (Some(Side::Below), Some(Direction::Down)),
+            side => (side, None),
+        };
+        self.side = side;
+        crossed.filter(|x| wanted == Direction::Either || *x == wanted)
+    }
+
+    fn fire(&mut self, dtg: DateTime<Utc>, value: f64, message: String) -> Option<Fired> {
+        if let (Some(last), Some(cooldown)) = (self.last_fired, &self.rule.cooldown) {
+            if dtg < last + Duration::milliseconds(cooldown.millis) {
+                tracing::debug!("[RuleState::fire] {} cooling down: {}", &self.rule.name, &message);
+                return None;
+            }
+        }
+        self.last_fired = Some(dtg);
+        let alert = Alert { rule: self.rule.name.clone(), dtg, source: self.rule.source.clone(), symbol: self.rule.symbol.clone(), value, message };
+        Some(Fired { alert, webhook: self.rule.webhook.clone() })
+    }
+}
+
+fn word(crossed: Direction) -> &'static str {
+    match crossed {
+        Direction::Down => "below",
+        _ => "above",
+    }
+}
+
+impl Row for Alert {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "rule" => Some(self.rule.clone()),
+            "source" => Some(self.source.to_string().to_lowercase()),
+            "symbol" => Some(self.symbol.to_string()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        match key {
+            "value" => Some(FieldValue::Float(self.value)),
+            "message" => Some(FieldValue::String(self.message.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use common_lib::alert::{AlertRule, Condition, Direction};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::view::{Resolution, ViewSeries};
+    use common_lib::{CalculationId, SymbolCommon, TickerCommon};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use crate::alert::AlertEngine;
+    use crate::arrow_db::{insert, Inserted};
+    use crate::event_book::EventBook;
+
+    fn start() -> DateTime<Utc> {
+        DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap())
+    }
+
+    fn rule(name: &str, condition: Condition) -> AlertRule {
+        AlertRule { name: name.to_string(), source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, condition, hysteresis: 0.0, cooldown: None, webhook: None }
+    }
+
+    /// replay prices a second apart through the engine; the seconds at which anything fired
+    fn replay(engine: &mut AlertEngine, prices: &[f64]) -> Vec<i64> {
+        prices.iter().enumerate()
+            .filter_map(|(i, price)| {
+                let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: *price, dtg: start() + Duration::seconds(i as i64), trade_id: None };
+                let fired = engine.apply(&Datasource::Coinbase, &ticker, &[]);
+                (!fired.is_empty()).then_some(i as i64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut engine = AlertEngine::new();
+        engine.set_rules(vec![AlertRule { hysteresis: 5.0, ..rule("up", Condition::Threshold { series: ViewSeries::Price, level: 100.0, direction: Direction::Up }) }]);
+        // starting above doesn't fire; hovering around 100 fires once until it's back under 95
+        let fired = replay(&mut engine, &[101.0, 90.0, 101.0, 99.0, 101.0, 94.0, 100.5, 100.0]);
+        assert_eq!(fired, vec![2, 6]);
+        assert_eq!(engine.history()[0].message, "btc_usd price crossed above 100 at 101");
+
+        // either way, with a cooldown over the second crossing
+        engine.set_rules(vec![AlertRule { cooldown: Some(Resolution { millis: 2000 }), ..rule("either", Condition::Threshold { series: ViewSeries::Price, level: 100.0, direction: Direction::Either }) }]);
+        assert_eq!(replay(&mut engine, &[99.0, 101.0, 99.0, 98.0, 101.0, 99.0]), vec![1, 4]);
+
+        // eth and alpaca ticks aren't this rule's
+        let eth = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::EthUsd, price: 1.0, dtg: start(), trade_id: None };
+        assert!(engine.apply(&Datasource::Coinbase, &eth, &[]).is_empty());
+        assert!(engine.apply(&Datasource::Alpaca, &TickerCommon { source: Datasource::Alpaca, symbol: SymbolCommon::BtcUsd, ..eth }, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_percent_move_and_stale() {
+        let mut engine = AlertEngine::new();
+        engine.set_rules(vec![
+            rule("drop", Condition::PercentMove { series: ViewSeries::Price, percent: 2.0, window: Resolution { millis: 3000 }, direction: Direction::Down }),
+            rule("quiet", Condition::Stale { after: Resolution { millis: 60_000 } }),
+        ]);
+        // a slow slide never drops 2% inside 3s; the fall at 6s does, once
+        assert_eq!(replay(&mut engine, &[100.0, 99.5, 99.0, 98.5, 98.0, 97.5, 95.0, 94.0, 94.0, 94.0, 94.0]), vec![6]);
+        // 95 against 98.5 three seconds before
+        assert!((engine.history()[0].value + 3.553).abs() < 0.001);
+        assert_eq!(engine.history()[0].message, "btc_usd price moved -3.55% within 3s");
+
+        // the last tick was at 10s
+        assert!(engine.check_stale(start() + Duration::seconds(69)).is_empty());
+        let fired = engine.check_stale(start() + Duration::seconds(70));
+        assert_eq!(fired[0].rule, "quiet");
+        assert_eq!(fired[0].message, "no coinbase ticks for btc_usd in 60s");
+        assert!(engine.check_stale(start() + Duration::seconds(200)).is_empty());
+        // a tick re-arms it
+        replay(&mut engine, &[94.0]);
+        assert_eq!(engine.check_stale(start() + Duration::seconds(61)).len(), 1);
+    }
+
+    /// a ramp down then up through the db's insert path crosses the averages once each way
+    #[test]
+    fn test_crossover_replay() {
+        let book = EventBook::new();
+        book.set_indicators(vec![CalculationId::MovingAvg0010, CalculationId::MovingAvg0100]);
+        let mut engine = AlertEngine::new();
+        let crossover = |name: &str, direction| rule(name, Condition::Crossover { fast: ViewSeries::Calc(CalculationId::MovingAvg0010), slow: ViewSeries::Calc(CalculationId::MovingAvg0100), direction });
+        engine.set_rules(vec![crossover("turn_up", Direction::Up), crossover("turn_down", Direction::Down)]);
+        let rx = engine.subscribe();
+
+        let prices = (0..200).map(|i| 1000.0 - i as f64).chain((0..200).map(|i| 800.0 + i as f64)).chain((0..200).map(|i| 1000.0 - i as f64));
+        for (i, price) in prices.enumerate() {
+            let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price, dtg: start() + Duration::seconds(i as i64), trade_id: None };
+            if let Inserted::InOrder(calcs) = insert(Datasource::Coinbase, &ticker, &book) {
+                engine.apply(&Datasource::Coinbase, &ticker, &calcs);
+            }
+        }
+        let fired: Vec<String> = rx.try_iter().map(|x| x.alert.rule).collect();
+        assert_eq!(fired, vec!["turn_up", "turn_down"]);
+        let table = pretty_format_batches(&[engine.record_batch().unwrap()]).unwrap().to_string();
+        assert_eq!(engine.record_batch().unwrap().num_rows(), 2);
+        assert!(table.contains("| turn_down | coinbase | btc_usd |"), "{table}");
+    }
+
+    #[test]
+    fn test_put_delete() {
+        let mut engine = AlertEngine::new();
+        let up = rule("up", Condition::Threshold { series: ViewSeries::Price, level: 100.0, direction: Direction::Up });
+        assert_eq!(engine.put(up.clone()).unwrap().len(), 1);
+        let rules = engine.put(AlertRule { hysteresis: 1.0, ..up.clone() }).unwrap();
+        assert_eq!((rules.len(), rules[0].hysteresis), (1, 1.0));
+        assert!(engine.put(AlertRule { hysteresis: -1.0, ..up }).is_err());
+        assert!(engine.delete("down").is_err());
+        assert!(engine.delete("up").unwrap().is_empty());
+    }
+}
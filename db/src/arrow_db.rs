@@ -6,15 +6,26 @@
 
 use common_lib::heartbeat::start_heartbeat;
 use crossbeam_channel::{unbounded, Sender};
+use std::ops::Bound;
 use std::sync::Arc;
+use strum::IntoEnumIterator;
 use tokio::runtime::Handle;
-use common_lib::{UniversalError, DbMsg};
-use common_lib::cb_ticker::Datasource;
-use crate::calculation::refresh_calculations;
-use crate::event_book::EventBook;
+use chrono::{DateTime, Utc};
+use datafusion::datasource::MemTable;
+use common_lib::{ChartDataset, UniversalError, DbMsg, TickerCommon};
+use common_lib::cb_ticker::{Datasource, TickerCalc};
+use common_lib::metrics::METRICS;
+use common_lib::view::ChartFilter;
+use crate::alert::AlertEngine;
+use crate::backfill::backfill;
+use crate::calculation::{refresh_calculations, revise_calculations};
+use crate::event_book::{BookError, EventBook, TABLE_ALERTS};
+use crate::event_log::{Arrival, TimeRange};
+use crate::health::HealthMonitor;
+use crate::paper::{Mark, PaperEngine};
+use crate::view::ViewEngine;
 
 pub const BOOK_NAME_COINBASE:&str="coinbase";
-pub const LIMIT_RETURN_SIZE:usize = 1000;
 
 /// spawn a thread to listen for messages; return the channel to communicate to this thread
 pub fn run(tr: Handle) -> Sender<DbMsg> {
@@ -25,6 +36,10 @@ pub fn run(tr: Handle) -> Sender<DbMsg> {
     std::thread::spawn(move || {
         tracing::debug!("[run] inside thread::spawn 0");
         let _ = start_heartbeat(tx2);
+        let mut views = ViewEngine::new();
+        let mut alerts = AlertEngine::new();
+        let mut paper = PaperEngine::new();
+        let mut health = HealthMonitor::new();
         loop {
             // tracing::debug!("[run] inside loop");
             match rx.recv() {
@@ -33,7 +48,7 @@ pub fn run(tr: Handle) -> Sender<DbMsg> {
                     let evt_book = event_book.clone();
 
                     // new thread to prevent processing blocking the websocket
-                    if let Err(e) = receive(message, &evt_book, tr.clone()){
+                    if let Err(e) = receive(message, &evt_book, &mut views, &mut alerts, &mut paper, &mut health, tr.clone()){
                         tracing::info!("[run] message error: {:?}", e);
                     }
                 }
@@ -44,64 +59,137 @@ pub fn run(tr: Handle) -> Sender<DbMsg> {
     tx
 }
 
-fn receive(message: DbMsg, evt_book: &EventBook, tr: Handle) -> Result<(), UniversalError>  {
+fn receive(message: DbMsg, evt_book: &EventBook, views: &mut ViewEngine, alerts: &mut AlertEngine, paper: &mut PaperEngine, health: &mut HealthMonitor, tr: Handle) -> Result<(), UniversalError>  {
 
     // tracing::debug!("[db::receive] msg:{:?}", &message);
 
     match message {
 
+        // the heartbeat; also the clock for staleness alerts
         DbMsg::Ping => {
             tracing::debug!("[db] PING");
+            alerts.check_stale(Utc::now());
             Ok(())
         },
 
         DbMsg::Insert(ticker_src, ticker) => {
 
             tracing::debug!("[receive] insert ({ticker_src:?}): {:?}", &ticker);
-
-            match ticker_src {
-                Datasource::Coinbase => {
-                    let _ = evt_book.push_log(Datasource::Coinbase, &ticker);
-                    if let Err(e) = refresh_calculations(Datasource::Coinbase, evt_book, ticker.symbol) {
-                        tracing::error!("[process_message] refresh_calculations error: {:?}", &e);
-                    }
-                    Ok(())
-                }
-                Datasource::Alpaca => {
-                    let _ = evt_book.push_log(Datasource::Alpaca, &ticker);
-                    // if let Err(e) = refresh_calculations(Datasource::Alpaca, evt_book, ticker.symbol) {
-                    //     tracing::error!("[process_message] refresh_calculations error: {:?}", &e);
-                    // }
-                    Ok(())
+            let inserted = insert(ticker_src.clone(), &ticker, evt_book);
+            METRICS.tick(&ticker_src, inserted.name());
+            if let Inserted::InOrder(_) | Inserted::Late = &inserted {
+                health.tick(&ticker_src, &ticker.symbol, Utc::now());
+            }
+            match inserted {
+                Inserted::InOrder(calcs) => {
+                    views.apply(&ticker_src, &ticker, &calcs);
+                    alerts.apply(&ticker_src, &ticker, &calcs);
+                    let marks = trade(paper, evt_book, &ticker_src, &ticker, &calcs);
+                    views.apply_marks(&marks);
                 }
+                // points already sent to views may have moved, so revise them from the late one on
+                Inserted::Late => views.revise(&ticker_src, &ticker.symbol, ticker.dtg, evt_book),
+                Inserted::Dropped => tracing::warn!("[receive] {ticker_src:?} ticker behind the watermark dropped: {:?}", &ticker),
+                Inserted::Duplicate => tracing::debug!("[receive] {ticker_src:?} duplicate trade dropped: {:?}", &ticker),
             }
+            Ok(())
         }
 
-        DbMsg::RqstChartMulti {sender, symbol} => {
-            let evt_book_read_lock = evt_book.book.read().unwrap();
+        DbMsg::SetRetention(retention) => {
+            tracing::info!("[receive] retention {:?}", &retention);
+            evt_book.set_retention(retention);
+            Ok(())
+        }
 
-            let mut chart = vec!();
+        DbMsg::SetIndicators(indicators) => {
+            tracing::info!("[receive] indicators {:?}", &indicators);
+            evt_book.set_indicators(indicators);
+            Ok(())
+        }
 
-            let mut chart_cb = match evt_book_read_lock.get(&Datasource::Coinbase){
-                Some(evt_log)=>{
-                    tr.block_on(async{
-                        evt_log.chart_since(Datasource::Coinbase, &symbol, None, LIMIT_RETURN_SIZE).await
-                    })
-                },
-                None=> Err(UniversalError::DbError("RqstChartMulti".to_string())),
-            }?;
+        DbMsg::SetAlertRules(rules) => {
+            tracing::info!("[receive] {} alert rules", rules.len());
+            alerts.set_rules(rules);
+            Ok(())
+        }
 
-            let mut chart_alp = match evt_book_read_lock.get(&Datasource::Alpaca){
-                Some(evt_log)=>{
-                    tr.block_on(async{
-                        evt_log.chart_since(Datasource::Alpaca, &symbol, None, LIMIT_RETURN_SIZE).await
-                    })
-                },
-                None=> Err(UniversalError::DbError("RqstChartMulti".to_string())),
-            }?;
+        DbMsg::SetStrategies(strategies) => {
+            tracing::info!("[receive] {} paper strategies", strategies.len());
+            paper.set_strategies(strategies);
+            Ok(())
+        }
 
-            chart.append(&mut chart_cb);
-            chart.append(&mut chart_alp);
+        DbMsg::FeedState(source, state) => {
+            health.feed(source, state, Utc::now());
+            Ok(())
+        }
+
+        DbMsg::RqstHealth {sender} => {
+            match sender.send(health.health(evt_book, Utc::now())) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        DbMsg::RqstPaperAccounts {sender} => {
+            match sender.send(paper.accounts()) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // alert rules from the api; the changed list comes back to be saved
+        DbMsg::RqstAlertRules {sender} => {
+            match sender.send(alerts.rules()) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        DbMsg::RqstPutAlertRule {rule, sender} => {
+            match sender.send(alerts.put(rule)) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        DbMsg::RqstDeleteAlertRule {name, sender} => {
+            match sender.send(alerts.delete(&name)) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        DbMsg::RqstAlertHistory {sender} => {
+            match sender.send(alerts.history()) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // every alert from now on, for the websocket and webhooks
+        DbMsg::RqstAlerts {sender} => {
+            match sender.send(alerts.subscribe()) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // a historical file, already read on the runtime, through the same insert path as the live
+        // feeds; paper strategies trade it too
+        DbMsg::RqstBackfill {source, tickers, sender} => {
+            let count = backfill(&source, &tickers, evt_book, |ticker, calcs| {
+                trade(paper, evt_book, &source, ticker, calcs);
+            });
+            views.reseed(evt_book);
+            match sender.send(count) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        DbMsg::RqstChartMulti {sender, filter} => {
+            let chart = charts(evt_book, &filter, None);
 
             // tracing::info!("[returning chart] {:?}", &chart);
             match sender.send(chart) {
@@ -113,40 +201,9 @@ fn receive(message: DbMsg, evt_book: &EventBook, tr: Handle) -> Result<(), Unive
             }
         }
 
-        DbMsg::RqstChartSince {sender, symbol, since} => {
-
-            let mut chart = vec!();
-            let evt_book_read_lock = evt_book.book.read().unwrap();
-            let mut chart_cb = match evt_book_read_lock.get(&Datasource::Coinbase){
-                Some(evt_log)=>{
-                    tr.block_on(async{
-                        evt_log.chart_since(Datasource::Coinbase, &symbol, Some(since), LIMIT_RETURN_SIZE).await
-                    })
-                },
-                None => {
-                    tracing::info!("[DbMsg::RqstChartSince] no coinbase records found");
-                    // Err(UniversalError::DbError("RqstChartMultiSince".to_string()))
-                    Ok(vec!())
-                }
-            }?;
-
-            let mut chart_alpaca = match evt_book_read_lock.get(&Datasource::Alpaca){
-                Some(evt_log)=>{
-                    tr.block_on(async{
-                        evt_log.chart_since(Datasource::Alpaca, &symbol, Some(since), LIMIT_RETURN_SIZE).await
-                    })
-                },
-                None => {
-                    tracing::info!("[DbMsg::RqstChartSince] no alpaca records found");
-                    // Err(UniversalError::DbError("RqstChartMultiSince".to_string()))
-                    Ok(vec!())
-                }
-            }?;
+        DbMsg::RqstChartSince {sender, filter, since} => {
+            let chart = charts(evt_book, &filter, Some(since));
 
-            chart.append(&mut chart_cb);
-            chart.append(&mut chart_alpaca);
-
-            // tracing::info!("[returning chart] {:?}", &chart);
             match sender.send(chart) {
                 Err(e)=> {
                     tracing::error!("[Msg::RequestChartRust] {:?}", &e);
@@ -156,6 +213,69 @@ fn receive(message: DbMsg, evt_book: &EventBook, tr: Handle) -> Result<(), Unive
             }
         }
 
+        // subscribe to a continuous view; the receiver gets a snapshot followed by deltas
+        DbMsg::RqstView {spec, sender} => {
+            let rx_view = views.register(spec, evt_book);
+            match sender.send(rx_view) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // every table as a provider, for sql clients to query on their own threads
+        DbMsg::RqstTables {sender} => {
+            let tables = evt_book.providers()
+                .and_then(|mut tables| {
+                    let batch = alerts.record_batch()?;
+                    tables.0.push((TABLE_ALERTS.to_string(), Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]])?)));
+                    Ok(tables)
+                })
+                .map_err(|e| UniversalError::DbError(e.to_string()));
+            match sender.send(tables) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // line protocol from outside the live feeds; all of the points are stored or none are
+        DbMsg::RqstWritePoints {points, sender} => {
+            let result = evt_book.push_points(points).map_err(|e| match e {
+                BookError::Conflict(reason) => UniversalError::DbError(reason),
+                e => UniversalError::DbError(format!("{e:?}")),
+            });
+            match sender.send(result) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // a measurement with fixed tags and field types; writes that don't fit are rejected
+        DbMsg::RqstDefineMeasurement {schema, sender} => {
+            let result = evt_book.define_measurement(schema).map_err(|e| match e {
+                BookError::Conflict(reason) => UniversalError::DbError(reason),
+                e => UniversalError::DbError(format!("{e:?}")),
+            });
+            match sender.send(result) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        // what the json api can ask for
+        DbMsg::RqstSeriesList {sender} => {
+            match sender.send(evt_book.series_list()) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
+        DbMsg::RqstSeries {query, sender} => {
+            match sender.send(evt_book.series(&query)) {
+                Err(_e)=> Err(UniversalError::SendError),
+                _ => Ok(()),
+            }
+        }
+
         // send a DataFrame back with 'select * from ..."
         DbMsg::RqstRaw {ticker_source, sender}=>{
             let evt_book_read_lock = evt_book.book.read().unwrap();
@@ -188,6 +308,111 @@ fn receive(message: DbMsg, evt_book: &EventBook, tr: Handle) -> Result<(), Unive
     }
 }
 
+/// the datasets filter asks for from every source, each newest first; since (exclusive) narrows
+/// them to points a chart doesn't have yet
+fn charts(evt_book: &EventBook, filter: &ChartFilter, since: Option<DateTime<Utc>>) -> Vec<ChartDataset> {
+    let limit = evt_book.retention().chart_limit;
+    let mut chart: Vec<ChartDataset> = {
+        let book = evt_book.book.read().unwrap();
+        let newest = Datasource::iter()
+            .filter(|ds| filter.sources.is_empty() || filter.sources.contains(ds))
+            .filter_map(|ds| book.get(&ds).and_then(|evt_log| evt_log.newest()))
+            .max();
+        let range = TimeRange {
+            start: filter.since(newest).map_or(Bound::Unbounded, Bound::Included),
+            end: filter.end.map_or(Bound::Unbounded, Bound::Excluded),
+        }.and(TimeRange { start: since.map_or(Bound::Unbounded, Bound::Excluded), end: Bound::Unbounded });
+        Datasource::iter()
+            .filter_map(|ds| book.get(&ds).map(|evt_log| evt_log.chart_in(&ds, filter, &range, limit)))
+            .flatten()
+            .collect()
+    };
+    if filter.is_unfiltered() {
+        chart.append(&mut measurement_charts(evt_book, since.or(filter.start)));
+    }
+    if filter.is_unfiltered() || !filter.strategies.is_empty() {
+        chart.append(&mut evt_book.paper.read().unwrap().chart_since(&filter.strategies, since.or(filter.start), limit));
+    }
+    chart
+}
+
+/// run the paper strategies on a ticker and keep what they did; returns the marks for the views
+fn trade(paper: &mut PaperEngine, evt_book: &EventBook, source: &Datasource, ticker: &TickerCommon, calcs: &[TickerCalc]) -> Vec<Mark> {
+    let events = paper.apply(source, ticker, calcs);
+    if !events.is_empty() {
+        evt_book.paper.write().unwrap().push(&events);
+    }
+    events.marks
+}
+
+/// every numeric field written over line protocol, one dataset per series
+fn measurement_charts(evt_book: &EventBook, since: Option<DateTime<Utc>>) -> Vec<ChartDataset> {
+    let limit = evt_book.retention().chart_limit;
+    let measurements = evt_book.measurements.read().unwrap();
+    let mut names: Vec<&String> = measurements.keys().collect();
+    names.sort();
+    names.into_iter().flat_map(|name| measurements[name].chart_since(since, limit)).collect()
+}
+
+/// what inserting a ticker did
+pub(crate) enum Inserted {
+    /// the newest ticker from its source, with the calculations it produced
+    InOrder(Vec<TickerCalc>),
+    /// stored behind newer tickers; the calculations after it were computed again
+    Late,
+    /// behind its source's watermark, not stored
+    Dropped,
+    /// a trade id its series has already stored
+    Duplicate,
+}
+
+impl Inserted {
+    /// for the metrics' arrival label
+    fn name(&self) -> &'static str {
+        match self {
+            Inserted::InOrder(_) => "in_order",
+            Inserted::Late => "late",
+            Inserted::Dropped => "dropped",
+            Inserted::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// push a ticker into its datasource's event log and update the calculations that depend on it
+pub(crate) fn insert(ticker_src: Datasource, ticker: &TickerCommon, evt_book: &EventBook) -> Inserted {
+    let arrival = match evt_book.push_log(ticker_src.clone(), ticker) {
+        Ok(arrival) => arrival,
+        Err(e) => {
+            tracing::error!("[insert] push_log error: {:?}", &e);
+            return Inserted::Dropped;
+        }
+    };
+    match (ticker_src, arrival) {
+        (_, Arrival::Dropped) => Inserted::Dropped,
+        (_, Arrival::Duplicate) => Inserted::Duplicate,
+        (Datasource::Coinbase, Arrival::InOrder) => {
+            match refresh_calculations(Datasource::Coinbase, evt_book, ticker.symbol.clone()) {
+                Ok(calcs) => Inserted::InOrder(calcs),
+                Err(e) => {
+                    tracing::error!("[process_message] refresh_calculations error: {:?}", &e);
+                    Inserted::InOrder(vec![])
+                }
+            }
+        }
+        (Datasource::Coinbase, Arrival::Late) => {
+            if let Err(e) = revise_calculations(Datasource::Coinbase, evt_book, &ticker.symbol, ticker.dtg) {
+                tracing::error!("[process_message] revise_calculations error: {:?}", &e);
+            }
+            Inserted::Late
+        }
+        // if let Err(e) = refresh_calculations(Datasource::Alpaca, evt_book, ticker.symbol.clone()) {
+        //     tracing::error!("[process_message] refresh_calculations error: {:?}", &e);
+        // }
+        (Datasource::Alpaca, Arrival::InOrder) => Inserted::InOrder(vec![]),
+        (Datasource::Alpaca, Arrival::Late) => Inserted::Late,
+    }
+}
+
 
 
 
@@ -197,7 +422,22 @@ fn receive(message: DbMsg, evt_book: &EventBook, tr: Handle) -> Result<(), Unive
 #[cfg(test)]
 mod tests{
 
-    use common_lib::{ChartAsJson};
+    use serde::Deserialize;
+
+    /// shape of the old analysis chart payload: one x-axis shared by every keyed series
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    struct ChartAsJson {
+        columns: Vec<String>,
+        chart_data: Vec<ChartSeriesAsJson>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize)]
+    struct ChartSeriesAsJson {
+        key: String,
+        val: Vec<f64>,
+    }
 
     /// confirm the Chart object resolves from a known correct json string
     #[test]
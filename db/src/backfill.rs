@@ -0,0 +1,168 @@
+//! backfill.rs
+//!
+//! load historical ticks or bars from CSV/Parquet into the event book so charts open with context
+//! instead of an empty window. Rows go in oldest first through the same insert path as the live
+//! feeds, so the calculations are rebuilt as they would have been live.
+//!
+
+use std::str::FromStr;
+use std::time::Instant;
+use chrono::{TimeZone, Utc};
+use datafusion::arrow::array::{Array, Float64Array, StringArray, TimestampNanosecondArray};
+use datafusion::prelude::*;
+use common_lib::backfill::{BackfillFormat, BackfillSpec};
+use common_lib::cb_ticker::{Datasource, TickerCalc};
+use common_lib::{SymbolCommon, TickerCommon, UniversalError};
+use crate::arrow_db::{insert, Inserted};
+use crate::event_book::EventBook;
+
+const TABLE_NAME: &str = "t_backfill";
+
+/// insert tickers already read by read_tickers into the event book in timestamp order; each ticker
+/// stored as the newest of its source goes to replay with its calculations. Returns the number of
+/// ticks inserted.
+///
+/// the file is read on the tokio runtime before this is called, so the db thread only inserts
+pub fn backfill(source: &Datasource, tickers: &[TickerCommon], evt_book: &EventBook, mut replay: impl FnMut(&TickerCommon, &[TickerCalc])) -> usize {
+    let start = Instant::now();
+    for ticker in tickers.iter() {
+        if let Inserted::InOrder(calcs) = insert(source.clone(), ticker, evt_book) {
+            replay(ticker, &calcs);
+        }
+    }
+
+    tracing::info!("[backfill] {} {source} ticks in {:?}ms", tickers.len(), start.elapsed().as_millis());
+    tickers.len()
+}
+
+/// "select dtg, symbol, price from file order by dtg asc" using the spec's column mapping
+pub async fn read_tickers(spec: &BackfillSpec) -> Result<Vec<TickerCommon>, UniversalError> {
+    let path = spec.path.to_str().ok_or_else(|| UniversalError::DbError(format!("[read_tickers] path: {:?}", &spec.path)))?;
+    let extension = match spec.path.extension().and_then(|x| x.to_str()) {
+        Some(ext) => format!(".{ext}"),
+        None => "".to_string(),
+    };
+
+    let ctx = SessionContext::new();
+    let registered = match spec.format {
+        BackfillFormat::Csv => ctx.register_csv(TABLE_NAME, path, CsvReadOptions::new().file_extension(&extension)).await,
+        BackfillFormat::Parquet => ctx.register_parquet(TABLE_NAME, path, ParquetReadOptions { file_extension: &extension, ..Default::default() }).await,
+    };
+    registered.map_err(|e| UniversalError::DbError(format!("[read_tickers] register {path}: {e:?}")))?;
+
+    let sql = format!(
+        r#"select cast("{}" as timestamp) as dtg, cast("{}" as varchar) as symbol, cast("{}" as double) as price from {TABLE_NAME} order by dtg asc"#,
+        spec.columns.dtg, spec.columns.symbol, spec.columns.price
+    );
+    let batches = match ctx.sql(&sql).await {
+        Ok(df) => df.collect().await,
+        Err(e) => Err(e),
+    }
+    .map_err(|e| UniversalError::DbError(format!("[read_tickers] {sql}: {e:?}")))?;
+
+    let mut tickers = vec![];
+    let mut skipped = 0;
+    for batch in batches.iter() {
+        let dtgs = batch.column(0).as_any().downcast_ref::<TimestampNanosecondArray>();
+        let symbols = batch.column(1).as_any().downcast_ref::<StringArray>();
+        let prices = batch.column(2).as_any().downcast_ref::<Float64Array>();
+        let (dtgs, symbols, prices) = match (dtgs, symbols, prices) {
+            (Some(d), Some(s), Some(p)) => (d, s, p),
+            _ => return Err(UniversalError::DbError("[read_tickers] unexpected column types".to_string())),
+        };
+
+        for i in 0..batch.num_rows() {
+            if dtgs.is_null(i) || symbols.is_null(i) || prices.is_null(i) {
+                skipped += 1;
+                continue;
+            }
+            match symbol_from_str(symbols.value(i)) {
+                Some(symbol) => tickers.push(TickerCommon {
+                    source: spec.source.clone(),
+                    symbol,
+                    price: prices.value(i),
+                    dtg: Utc.timestamp_nanos(dtgs.value(i)),
+                    trade_id: None,
+                }),
+                None => skipped += 1,
+            }
+        }
+    }
+
+    if skipped > 0 {
+        tracing::warn!("[read_tickers] skipped {skipped} rows with nulls or unknown symbols in {path}");
+    }
+    Ok(tickers)
+}
+
+/// accept "btc_usd", "BTC-USD" (coinbase) and "BTC/USD" (alpaca)
+fn symbol_from_str(symbol: &str) -> Option<SymbolCommon> {
+    SymbolCommon::from_str(&symbol.to_lowercase().replace(['-', '/'], "_")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::dataframe::DataFrameWriteOptions;
+    use datafusion::prelude::*;
+    use common_lib::backfill::BackfillSpec;
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::{CalculationId, SymbolCommon};
+    use crate::backfill::{backfill, read_tickers};
+    use crate::event_book::EventBook;
+
+    /// rows in the csv are out of order and use exchange-style symbols
+    #[tokio::test]
+    async fn test_read_tickers_csv() {
+        let spec = BackfillSpec::new(Datasource::Coinbase, "tests/data/ticks.csv").unwrap().columns("dtg=time,symbol=product_id").unwrap();
+        let tickers = read_tickers(&spec).await.unwrap();
+
+        // one row has an unknown symbol
+        assert_eq!(tickers.len(), 5);
+        assert!(tickers.windows(2).all(|x| x[0].dtg <= x[1].dtg));
+        assert_eq!(tickers[0].symbol, SymbolCommon::BtcUsd);
+        assert_eq!(tickers[0].price, 42000.0);
+        assert_eq!(tickers[0].dtg.to_rfc3339(), "2024-01-14T23:30:00.100+00:00");
+        assert_eq!(tickers.iter().filter(|x| x.symbol == SymbolCommon::EthUsd).count(), 1);
+    }
+
+    /// write the csv back out as parquet and load it as bars
+    #[tokio::test]
+    async fn test_read_tickers_parquet() {
+        let ctx = SessionContext::new();
+        let df = ctx
+            .read_csv("tests/data/ticks.csv", CsvReadOptions::new())
+            .await
+            .unwrap()
+            .select_columns(&["time", "product_id", "price"])
+            .unwrap()
+            .with_column_renamed("price", "close")
+            .unwrap();
+        df.write_parquet("tests/data/output/ticks.parquet", DataFrameWriteOptions::new().with_single_file_output(true), None).await.unwrap();
+
+        let spec = BackfillSpec::from_arg("coinbase:bar=tests/data/output/ticks.parquet").unwrap().columns("dtg=time,symbol=product_id").unwrap();
+        let tickers = read_tickers(&spec).await.unwrap();
+        assert_eq!(tickers.len(), 5);
+        assert_eq!(tickers[4].price, 42030.0);
+    }
+
+    /// backfill goes through the live insert path so calculations exist afterwards
+    #[tokio::test]
+    async fn test_backfill_rebuilds_calculations() {
+        let evt_book = EventBook::new();
+        let spec = BackfillSpec::new(Datasource::Coinbase, "tests/data/ticks.csv").unwrap().columns("dtg=time,symbol=product_id").unwrap();
+        let tickers = read_tickers(&spec).await.unwrap();
+
+        let mut replayed = 0;
+        let count = backfill(&spec.source, &tickers, &evt_book, |_, _| replayed += 1);
+        assert_eq!((count, replayed), (5, 5));
+
+        let book = evt_book.book.read().unwrap();
+        let evt_log = book.get(&Datasource::Coinbase).unwrap();
+        assert_eq!(evt_log.len(), 5);
+
+        // newest btc tick is at the front and the 10-tick average covers all four btc ticks
+        let ma_0010 = evt_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &SymbolCommon::BtcUsd).unwrap();
+        assert_eq!(ma_0010.val, 42015.0);
+        assert!(evt_log.calculate_diff_slope(&CalculationId::MovAvgDiff0100_1000, &CalculationId::MovAvgDiffSlope0100_1000, &SymbolCommon::BtcUsd).is_ok());
+    }
+}
@@ -0,0 +1,266 @@
+//! backtest.rs
+//!
+//! paper strategies run over stored history, offline. The files' ticks (anything the backfill
+//! reads, including the ticks table copied out to csv or parquet) are merged oldest first and go
+//! through a fresh event book's insert path, so the calculations are the ones live would have made,
+//! and each strategy trades them as the db thread would. Nothing is bounded, and the run is
+//! reported as sql tables next to its own ticks and calcs:
+//!
+//! backtest_summary    one row per strategy at its last tick: pnl, return, fees, trades, drawdown
+//! backtest_trades     every fill
+//! backtest_equity     every mark: position, equity, pnl and drawdown
+//!
+
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::error::DataFusionError;
+use common_lib::backfill::BackfillSpec;
+use common_lib::paper::{Account, Strategy};
+use common_lib::point::{FieldType, FieldValue, MeasurementSchema};
+use common_lib::{CalculationId, SqlTables, TickerCommon, UniversalError};
+use crate::arrow_db::{insert, Inserted};
+use crate::backfill::read_tickers;
+use crate::event_book::EventBook;
+use crate::measurement::{record_batch, Row};
+use crate::paper::{Fill, Mark, PaperEngine, PaperEvents, PaperLog, Trader};
+use crate::sql::{read_only, session};
+
+pub const TABLE_BACKTEST_SUMMARY: &str = "backtest_summary";
+pub const TABLE_BACKTEST_TRADES: &str = "backtest_trades";
+pub const TABLE_BACKTEST_EQUITY: &str = "backtest_equity";
+
+pub struct Backtest {
+    book: EventBook,
+    engine: PaperEngine,
+    marks: Vec<Mark>,
+    fills: Vec<Fill>,
+    ticks: usize,
+}
+
+/// a strategy's account at the end of the run
+struct Summary<'a>(&'a Account);
+
+impl Backtest {
+    /// the book keeps these calculations, as config "indicators" does live
+    pub fn new(indicators: Vec<CalculationId>) -> Backtest {
+        let book = EventBook::new();
+        book.set_indicators(indicators);
+        Backtest { book, engine: PaperEngine::new(), marks: vec![], fills: vec![], ticks: 0 }
+    }
+
+    /// strategies following their signals, see common_lib::paper
+    pub fn strategies(mut self, strategies: Vec<Strategy>) -> Backtest {
+        self.engine.set_strategies(strategies);
+        self
+    }
+
+    /// a strategy traded by trader instead of its signal
+    pub fn trader(mut self, strategy: Strategy, trader: Box<dyn Trader>) -> Backtest {
+        self.engine.push(strategy, trader);
+        self
+    }
+
+    /// read every file and play their ticks merged in time order; returns the number played
+    pub async fn run(&mut self, specs: &[BackfillSpec]) -> Result<usize, UniversalError> {
+        let mut tickers = vec![];
+        for spec in specs {
+            tickers.extend(read_tickers(spec).await?);
+        }
+        tickers.sort_by_key(|x| x.dtg);
+        for ticker in tickers.iter() {
+            self.apply(ticker);
+        }
+        tracing::info!("[backtest] {} ticks from {} files, {} trades", tickers.len(), specs.len(), self.fills.len());
+        Ok(tickers.len())
+    }
+
+    /// one ticker through the insert path and, when it's its source's newest, the strategies
+    pub fn apply(&mut self, ticker: &TickerCommon) {
+        self.ticks += 1;
+        if let Inserted::InOrder(calcs) = insert(ticker.source.clone(), ticker, &self.book) {
+            let PaperEvents { marks, fills } = self.engine.apply(&ticker.source, ticker, &calcs);
+            self.marks.extend(marks);
+            self.fills.extend(fills);
+        }
+    }
+
+    pub fn accounts(&self) -> Vec<Account> {
+        self.engine.accounts()
+    }
+
+    /// ticks played so far, newest of their source or not
+    pub fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    /// strategies that saw no ticks have no row
+    pub fn summary_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let accounts = self.accounts();
+        let rows: Vec<Summary> = accounts.iter().filter(|x| x.dtg.is_some()).map(Summary).collect();
+        record_batch(&Backtest::summary_measurement(), &rows)
+    }
+
+    pub fn trades_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let schema = MeasurementSchema { name: TABLE_BACKTEST_TRADES.to_string(), ..PaperLog::fills_measurement() };
+        record_batch(&schema, &self.fills)
+    }
+
+    pub fn equity_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let schema = MeasurementSchema { name: TABLE_BACKTEST_EQUITY.to_string(), ..PaperLog::marks_measurement() };
+        record_batch(&schema, &self.marks)
+    }
+
+    pub fn summary_measurement() -> MeasurementSchema {
+        MeasurementSchema::new(TABLE_BACKTEST_SUMMARY)
+            .tag("strategy")
+            .tag("source")
+            .tag("symbol")
+            .field("cash", FieldType::Float)
+            .field("equity", FieldType::Float)
+            .field("pnl", FieldType::Float)
+            .field("return_pct", FieldType::Float)
+            .field("fees", FieldType::Float)
+            .field("trades", FieldType::UInteger)
+            .field("max_drawdown", FieldType::Float)
+            .field("position", FieldType::Float)
+    }
+
+    /// the run's book tables followed by the three report tables
+    pub fn providers(&self) -> Result<SqlTables, DataFusionError> {
+        let mut providers = self.book.providers()?.0;
+        for (name, batch) in [(TABLE_BACKTEST_SUMMARY, self.summary_batch()?), (TABLE_BACKTEST_TRADES, self.trades_batch()?), (TABLE_BACKTEST_EQUITY, self.equity_batch()?)] {
+            let table: Arc<dyn TableProvider> = Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]])?);
+            providers.push((name.to_string(), table));
+        }
+        Ok(SqlTables(providers))
+    }
+
+    /// each query's result as a text table, like psql prints it
+    pub async fn report(&self, queries: &[String]) -> Result<String, UniversalError> {
+        let db_error = |e: DataFusionError| UniversalError::DbError(format!("[backtest] {e}"));
+        let ctx = session(self.providers().map_err(db_error)?).await.map_err(db_error)?;
+        let mut report = String::new();
+        for sql in queries {
+            let batches = ctx.sql_with_options(sql, read_only()).await.map_err(db_error)?.collect().await.map_err(db_error)?;
+            let table = pretty_format_batches(&batches).map_err(|e| db_error(e.into()))?;
+            report.push_str(&format!("{sql}\n{table}\n"));
+        }
+        Ok(report)
+    }
+}
+
+impl Row for Summary<'_> {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.0.dtg.unwrap_or_default()
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "strategy" => Some(self.0.strategy.clone()),
+            "source" => Some(self.0.source.to_string().to_lowercase()),
+            "symbol" => Some(self.0.symbol.to_string()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        let account = self.0;
+        // the starting cash
+        let cash = account.equity - account.pnl;
+        match key {
+            "cash" => Some(FieldValue::Float(cash)),
+            "equity" => Some(FieldValue::Float(account.equity)),
+            "pnl" => Some(FieldValue::Float(account.pnl)),
+            "return_pct" => Some(FieldValue::Float(100.0 * account.pnl / cash)),
+            "fees" => Some(FieldValue::Float(account.fees)),
+            "trades" => Some(FieldValue::UInteger(account.trades as u64)),
+            "max_drawdown" => Some(FieldValue::Float(account.max_drawdown)),
+            "position" => Some(FieldValue::Float(account.position)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_lib::backfill::BackfillSpec;
+    use common_lib::cb_ticker::{Datasource, TickerCalc};
+    use common_lib::paper::{Signal, Strategy};
+    use common_lib::view::ViewSeries;
+    use common_lib::{CalculationId, SymbolCommon, TickerCommon};
+    use crate::backtest::Backtest;
+    use crate::paper::{Order, OrderSide, Trader};
+
+    /// buys one on its first ticker and sells it on its third
+    struct RoundTrip(usize);
+
+    impl Trader for RoundTrip {
+        fn on_tick(&mut self, _position: f64, _ticker: &TickerCommon, _calcs: &[TickerCalc]) -> Option<Order> {
+            self.0 += 1;
+            match self.0 {
+                1 => Some(Order { side: OrderSide::Buy, quantity: 1.0 }),
+                3 => Some(Order { side: OrderSide::Sell, quantity: 1.0 }),
+                _ => None,
+            }
+        }
+    }
+
+    fn strategy(name: &str, source: Datasource) -> Strategy {
+        Strategy { name: name.to_string(), source, symbol: SymbolCommon::BtcUsd, signal: Signal::Sign { series: ViewSeries::Calc(CalculationId::MovAvgDiff0100_1000) }, quantity: 1.0, short: false, slippage_bps: 0.0, fee_bps: 10.0, cash: 1000.0 }
+    }
+
+    /// the same csv played as two sources, merged in time order; each source's btc ticks are
+    /// 42000, 42010, 42020 and 42030
+    #[tokio::test]
+    async fn test_backtest_report() {
+        let spec = |source: &str| BackfillSpec::from_arg(&format!("{source}=tests/data/ticks.csv")).unwrap().columns("dtg=time,symbol=product_id").unwrap();
+        let mut backtest = Backtest::new(vec![CalculationId::MovingAvg0010])
+            .trader(strategy("coinbase_trip", Datasource::Coinbase), Box::new(RoundTrip(0)))
+            .trader(strategy("alpaca_trip", Datasource::Alpaca), Box::new(RoundTrip(0)))
+            .trader(Strategy { symbol: SymbolCommon::EthBtc, ..strategy("eth_btc_trip", Datasource::Coinbase) }, Box::new(RoundTrip(0)));
+        assert_eq!(backtest.run(&[spec("coinbase"), spec("alpaca")]).await.unwrap(), 10);
+        assert_eq!(backtest.ticks(), 10);
+
+        let accounts = backtest.accounts();
+        assert_eq!((accounts[0].position, accounts[0].trades), (0.0, 2));
+        // bought at 42000 and sold at 42020, less 10bps of each
+        assert!((accounts[0].pnl - (20.0 - 84.02)).abs() < 1e-9, "{}", accounts[0].pnl);
+        assert_eq!(accounts[1].pnl, accounts[0].pnl);
+
+        let queries = ["select strategy, source, trades, round(pnl, 3) as pnl from backtest_summary order by strategy".to_string(),
+            "select count(*) as fills from backtest_trades where side = 'sell'".to_string(),
+            "select count(*) as ticks from ticks".to_string()];
+        let report = backtest.report(&queries).await.unwrap();
+        let expected = "+---------------+----------+--------+--------+
+| strategy      | source   | trades | pnl    |
++---------------+----------+--------+--------+
+| alpaca_trip   | alpaca   | 2      | -64.02 |
+| coinbase_trip | coinbase | 2      | -64.02 |
++---------------+----------+--------+--------+";
+        // eth_btc_trip's symbol never traded so it has no row
+        assert!(report.contains(expected), "{report}");
+        assert!(report.contains("| 2     |"), "{report}");
+        assert!(report.contains("| 10    |"), "{report}");
+        assert!(backtest.report(&["drop table ticks".to_string()]).await.is_err());
+    }
+
+    /// the default calculations drive a signal strategy the same as live
+    #[test]
+    fn test_backtest_signal() {
+        let mut backtest = Backtest::new(vec![CalculationId::MovingAvg0010, CalculationId::MovingAvg0100])
+            .strategies(vec![Strategy { signal: Signal::Crossover { fast: ViewSeries::Calc(CalculationId::MovingAvg0010), slow: ViewSeries::Calc(CalculationId::MovingAvg0100) }, fee_bps: 0.0, ..strategy("cross", Datasource::Coinbase) }]);
+        let start = chrono::DateTime::<chrono::Utc>::from(chrono::DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let prices = (0..200).map(|i| 1000.0 - i as f64).chain((0..200).map(|i| 800.0 + i as f64));
+        for (i, price) in prices.enumerate() {
+            backtest.apply(&TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price, dtg: start + chrono::Duration::seconds(i as i64), trade_id: None });
+        }
+        assert_eq!(backtest.accounts()[0].position, 1.0);
+        assert_eq!(backtest.trades_batch().unwrap().num_rows(), 1);
+        assert_eq!(backtest.summary_batch().unwrap().num_rows(), 1);
+        assert!(backtest.equity_batch().unwrap().num_rows() > 1);
+    }
+}
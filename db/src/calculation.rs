@@ -2,64 +2,175 @@
 //!
 
 
+use std::ops::Bound;
 use std::time::Instant;
-use common_lib::{CalculationId, SymbolCommon};
+use chrono::{DateTime, Utc};
+use common_lib::{CalculationId, SymbolCommon, TickerCommon};
 use common_lib::cb_ticker::{TickerCalc, Datasource};
+use common_lib::metrics::METRICS;
 use crate::event_book::EventBook;
-use crate::event_log::{EventLog, EventLogError};
+use crate::event_log::{EventLog, EventLogError, TimeRange};
 
 /// read lock
-pub fn refresh_calculations(ticker_src: Datasource, evt_book: &EventBook, symbol: SymbolCommon) ->Result<(), EventLogError> {
+/// returns the new calculations so they can be passed on to any continuous views
+pub fn refresh_calculations(ticker_src: Datasource, evt_book: &EventBook, symbol: SymbolCommon) ->Result<Vec<TickerCalc>, EventLogError> {
 
     tracing::debug!("[refresh_calculations]");
     let start = Instant::now();
-    let mut temp = vec![];
 
-    {
+    let indicators = evt_book.indicators();
+    let temp = {
         let evt_book_read_lock = evt_book.book.read().unwrap();
         let evt_log: &EventLog = evt_book_read_lock.get(&ticker_src).unwrap();
-
-        // moving averages
-        let ma_0010 = evt_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &symbol)?;
-        let ma_0100 = evt_log.calculate_moving_avg_n(&CalculationId::MovingAvg0100, &symbol)?;
-        let ma_1000 = evt_log.calculate_moving_avg_n(&CalculationId::MovingAvg1000, &symbol)?;
-
-        // calculate the moving average diff (ie in an EMA diff algorithm, positive means trending upward, negative means turning down)
-        // let ma_diff_0010_1000 = TickerCalc {
-        //     dtg: (&ma_0100).dtg.clone(),
-        //     prod_id: (&ma_0100).prod_id.clone(),
-        //     calc_id: CalculationId::MovAvgDiff0010_1000,
-        //     val: (&ma_0010).val.clone() - (&ma_1000).val.clone(),
-        // };
-
-        let ma_diff_0100_1000 = TickerCalc {
-            dtg: (&ma_0100).dtg.clone(),
-            symbol: (&ma_0100).symbol.clone(),
-            calc_id: CalculationId::MovAvgDiff0100_1000,
-            val: (&ma_0100).val.clone() - (&ma_1000).val.clone(),
-        };
-
-        temp.push(ma_0010);
-        temp.push(ma_0100);
-        temp.push(ma_1000);
-        // temp.push(ma_diff_0010_1000);
-        temp.push(ma_diff_0100_1000);
-
-        if let Ok(slope_100) = evt_log.calculate_diff_slope(&CalculationId::MovAvgDiff0100_1000, &CalculationId::MovAvgDiffSlope0100_1000, &symbol) {
-            temp.push(slope_100);
-        }
+        calculations_in(evt_log, &symbol, &TimeRange::default(), &indicators)?
 
         // ...release read lock (holding read blocks write lock)
     };
 
     // write lock...
     for c in temp.iter() {
-        let _ = evt_book.push_calc(&ticker_src, &c);
+        let _ = evt_book.push_calc(&ticker_src, c);
     }
 
+    METRICS.calc(start.elapsed());
     tracing::debug!("[update_moving_averages] {:?}ms", start.elapsed().as_micros() as f64 / 1000.0);
 
-    Ok(())
+    Ok(temp)
+}
+
+/// write lock
+/// a late ticker changes every calculation of its symbol after it; drop those and compute them
+/// again, replaying the symbol's ticks from the late one on in event time order as if they'd
+/// arrived that way. Other symbols are left alone, even though the averages' window is shared
+/// across symbols (see calculate_moving_avg_in). Returns the number of calculations recomputed.
+pub fn revise_calculations(ticker_src: Datasource, evt_book: &EventBook, symbol: &SymbolCommon, since: DateTime<Utc>) -> Result<usize, EventLogError> {
+    let start = Instant::now();
+    let indicators = evt_book.indicators();
+    let mut book_writable = evt_book.book.write().unwrap();
+    let evt_log = book_writable.get_mut(&ticker_src).ok_or(EventLogError::PushError)?;
+
+    evt_log.drop_calcs_since(symbol, since);
+    let replay: Vec<TickerCommon> = evt_log.tickers_in(&TimeRange { start: Bound::Included(since), end: Bound::Unbounded }).iter().rev().filter(|x| x.symbol == *symbol).cloned().collect();
+    let mut count = 0;
+    for ticker in replay.iter() {
+        let calcs = calculations_in(evt_log, &ticker.symbol, &TimeRange { start: Bound::Unbounded, end: Bound::Included(ticker.dtg) }, &indicators)?;
+        for c in calcs.iter() {
+            evt_log.push_calc(c)?;
+        }
+        count += calcs.len();
+    }
+
+    tracing::debug!("[revise_calculations] {count} calcs from {} ticks in {:?}ms", replay.len(), start.elapsed().as_micros() as f64 / 1000.0);
+    Ok(count)
+}
+
+/// the calculations for symbol using only the ticks and earlier calculations inside range; the
+/// averages are always computed, but only the ones in indicators are kept
+fn calculations_in(evt_log: &EventLog, symbol: &SymbolCommon, range: &TimeRange, indicators: &[CalculationId]) -> Result<Vec<TickerCalc>, EventLogError> {
+    let mut temp = vec![];
+
+    // moving averages
+    let ma_0010 = evt_log.calculate_moving_avg_in(&CalculationId::MovingAvg0010, symbol, range)?;
+    let ma_0100 = evt_log.calculate_moving_avg_in(&CalculationId::MovingAvg0100, symbol, range)?;
+    let ma_1000 = evt_log.calculate_moving_avg_in(&CalculationId::MovingAvg1000, symbol, range)?;
+
+    // calculate the moving average diff (ie in an EMA diff algorithm, positive means trending upward, negative means turning down)
+    // let ma_diff_0010_1000 = TickerCalc {
+    //     dtg: (&ma_0100).dtg.clone(),
+    //     prod_id: (&ma_0100).prod_id.clone(),
+    //     calc_id: CalculationId::MovAvgDiff0010_1000,
+    //     val: (&ma_0010).val.clone() - (&ma_1000).val.clone(),
+    // };
+
+    let ma_diff_0100_1000 = TickerCalc {
+        dtg: ma_0100.dtg,
+        symbol: ma_0100.symbol.clone(),
+        calc_id: CalculationId::MovAvgDiff0100_1000,
+        val: ma_0100.val - ma_1000.val,
+    };
+
+    temp.push(ma_0010);
+    temp.push(ma_0100);
+    temp.push(ma_1000);
+    // temp.push(ma_diff_0010_1000);
+    temp.push(ma_diff_0100_1000.clone());
+
+    // stamped with the tick it was computed for, like the averages, rather than the previous diff,
+    // so a revision from a late tick replaces it
+    if let Ok(slope_100) = evt_log.calculate_diff_slope_in(&CalculationId::MovAvgDiff0100_1000, &CalculationId::MovAvgDiffSlope0100_1000, symbol, range) {
+        temp.push(TickerCalc { dtg: ma_diff_0100_1000.dtg, ..slope_100 });
+    }
+
+    temp.retain(|c| indicators.contains(&c.calc_id));
+    Ok(temp)
 }
 
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::{CalculationId, SymbolCommon, TickerCommon};
+    use crate::calculation::{refresh_calculations, revise_calculations};
+    use crate::event_book::EventBook;
+
+    /// a late tick's symbol ends up with the same calculations as if it had arrived in order; the
+    /// other symbols keep what they had
+    #[test]
+    fn test_revise_calculations() {
+        let start = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let ticks: Vec<TickerCommon> = (0..20)
+            .map(|i| TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 100.0 + (i * i) as f64, dtg: start + Duration::milliseconds(i * 100), trade_id: None })
+            .collect();
+        // eth in between, in order
+        let eth: Vec<TickerCommon> = (0..20)
+            .map(|i| TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::EthUsd, price: 10.0 + i as f64, dtg: start + Duration::milliseconds(i * 100 + 50), trade_id: None })
+            .collect();
+        let mut ticks: Vec<TickerCommon> = ticks.into_iter().zip(eth).flat_map(|(btc, eth)| [btc, eth]).collect();
+        let late_tick = ticks.remove(28);
+        assert_eq!(late_tick.symbol, SymbolCommon::BtcUsd);
+
+        let in_order = EventBook::new();
+        let mut sorted = ticks.clone();
+        sorted.insert(28, late_tick.clone());
+        for t in sorted.iter() {
+            in_order.push_log(Datasource::Coinbase, t).unwrap();
+            refresh_calculations(Datasource::Coinbase, &in_order, t.symbol.clone()).unwrap();
+        }
 
+        // the 15th btc tick arrives last
+        let late = EventBook::new();
+        for t in ticks.iter() {
+            late.push_log(Datasource::Coinbase, t).unwrap();
+            refresh_calculations(Datasource::Coinbase, &late, t.symbol.clone()).unwrap();
+        }
+        let calcs = |book: &EventBook, symbol: SymbolCommon, calc_id: CalculationId| -> Vec<(DateTime<Utc>, f64)> {
+            let book = book.book.read().unwrap();
+            book[&Datasource::Coinbase].calcs().filter(|c| c.symbol == symbol && c.calc_id == calc_id).map(|c| (c.dtg, c.val)).collect()
+        };
+        let eth_before = calcs(&late, SymbolCommon::EthUsd, CalculationId::MovingAvg0010);
+        late.push_log(Datasource::Coinbase, &late_tick).unwrap();
+        let revised = revise_calculations(Datasource::Coinbase, &late, &SymbolCommon::BtcUsd, late_tick.dtg).unwrap();
+
+        // only btc's calculations from the late tick on were recomputed
+        let btc_since = late.book.read().unwrap()[&Datasource::Coinbase].calcs().filter(|c| c.symbol == SymbolCommon::BtcUsd && c.dtg >= late_tick.dtg).count();
+        assert_eq!(revised, btc_since);
+        assert_eq!(calcs(&late, SymbolCommon::EthUsd, CalculationId::MovingAvg0010), eth_before);
+        for calc_id in [CalculationId::MovingAvg0010, CalculationId::MovAvgDiff0100_1000, CalculationId::MovAvgDiffSlope0100_1000] {
+            assert_eq!(calcs(&late, SymbolCommon::BtcUsd, calc_id.clone()), calcs(&in_order, SymbolCommon::BtcUsd, calc_id));
+        }
+    }
+
+    /// only the configured indicators are kept
+    #[test]
+    fn test_indicators() {
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let book = EventBook::new();
+        book.set_indicators(vec![CalculationId::MovingAvg0100]);
+        for i in 0..3 {
+            let tick = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 100.0 + i as f64, dtg: dtg + Duration::seconds(i), trade_id: None };
+            book.push_log(Datasource::Coinbase, &tick).unwrap();
+            let calcs = refresh_calculations(Datasource::Coinbase, &book, SymbolCommon::BtcUsd).unwrap();
+            assert_eq!(calcs.iter().map(|c| c.calc_id.clone()).collect::<Vec<_>>(), vec![CalculationId::MovingAvg0100]);
+        }
+    }
+}
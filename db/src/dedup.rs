@@ -0,0 +1,96 @@
+//! dedup.rs
+//!
+//! a feed that reconnects, or two connections to the same venue, resends trades the log already
+//! has. Each series remembers the venue ids of its most recent trades and drops a repeat.
+
+use std::collections::{HashSet, VecDeque};
+use common_lib::config::DEDUP_WINDOW;
+
+/// the last capacity ids seen, oldest forgotten first
+#[derive(Debug)]
+pub struct DedupWindow {
+    ids: HashSet<u64>,
+    order: VecDeque<u64>,
+    capacity: usize,
+    duplicates: u64,
+}
+
+impl Default for DedupWindow {
+    fn default() -> Self {
+        DedupWindow::new(DEDUP_WINDOW)
+    }
+}
+
+impl DedupWindow {
+    pub fn new(capacity: usize) -> DedupWindow {
+        DedupWindow {
+            ids: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+            duplicates: 0,
+        }
+    }
+
+    /// true if id is new to the window, false (and counted) if it's a duplicate
+    pub fn insert(&mut self, id: u64) -> bool {
+        if self.ids.contains(&id) {
+            self.duplicates += 1;
+            return false;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id);
+        self.order.push_back(id);
+        true
+    }
+
+    /// true if id is in the window; nothing is recorded or counted
+    pub fn contains(&self, id: u64) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// ids currently remembered
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// repeats dropped so far
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dedup::DedupWindow;
+
+    #[test]
+    fn test_dedup_window() {
+        let mut window = DedupWindow::new(3);
+        assert!(window.insert(1));
+        assert!(window.insert(2));
+        assert!(window.contains(1) && !window.contains(3));
+        assert_eq!(window.duplicates(), 0);
+        assert!(!window.insert(1));
+        assert_eq!(window.duplicates(), 1);
+
+        // 1 falls out of the window once 3 newer ids have arrived
+        assert!(window.insert(3));
+        assert!(window.insert(4));
+        assert_eq!(window.len(), 3);
+        assert!(window.insert(1));
+        assert!(!window.insert(4));
+        assert_eq!(window.duplicates(), 2);
+    }
+}
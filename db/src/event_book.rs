@@ -1,14 +1,49 @@
 //! event_book.rs
 
 use std::collections::HashMap;
+use std::ops::Bound;
 use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{ArrayRef, StringArray, UInt64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::error::DataFusionError;
+use strum::IntoEnumIterator;
 use common_lib::cb_ticker::{TickerCalc, Datasource};
-use common_lib::TickerCommon;
-use crate::event_log::EventLog;
+use common_lib::point::{FieldType, FieldValue, MeasurementSchema, Point};
+use common_lib::{CalculationId, ChartDataset, ChartTimeSeries, SqlTables, TickerCommon};
+use common_lib::api::{SeriesInfo, SeriesQuery};
+use common_lib::config::{Config, Retention};
+use common_lib::watermark::Watermark;
+use crate::event_log::{Arrival, EventLog, TimeRange};
+use crate::log_table::{LogKind, LogTable};
+use crate::measurement::{record_batch, Measurement, Row};
+use crate::paper::PaperLog;
+
+/// sql table names for the ticks and calcs of every datasource
+pub const TABLE_TICKS: &str = "ticks";
+pub const TABLE_CALCS: &str = "calcs";
+/// sql table of each series' dedup window and the duplicates it has dropped
+pub const TABLE_DEDUP: &str = "dedup";
+/// sql table of the alerts fired, see alert.rs
+pub const TABLE_ALERTS: &str = "alerts";
+/// sql tables of the paper strategies' marks and fills, see paper.rs
+pub const TABLE_PAPER: &str = "paper";
+pub const TABLE_PAPER_FILLS: &str = "paper_fills";
 
 /// Container for multiple event logs keyed by a string
 pub struct EventBook {
     pub book: Arc<RwLock<HashMap<Datasource, EventLog>>>,
+    /// line protocol points keyed by measurement name
+    pub measurements: Arc<RwLock<HashMap<String, Measurement>>>,
+    /// what the paper strategies did
+    pub paper: RwLock<PaperLog>,
+    /// how late a live ticker may be, how many trade ids to remember, how many points to chart
+    retention: RwLock<Retention>,
+    /// calculations kept, see calculation.rs
+    indicators: RwLock<Vec<CalculationId>>,
 }
 impl Default for EventBook {
     fn default() -> Self {
@@ -21,43 +56,89 @@ impl EventBook {
     pub fn new() -> EventBook {
         EventBook {
             book: Arc::new(RwLock::new(HashMap::<Datasource, EventLog>::new())),
+            measurements: Arc::new(RwLock::new(HashMap::new())),
+            paper: RwLock::new(PaperLog::default()),
+            retention: RwLock::new(Retention::default()),
+            indicators: RwLock::new(Config::default().indicators),
         }
     }
 
-    /// get write lock on the entire book and insert a new record
-    pub fn push_log(&self, key: Datasource, val: &TickerCommon) -> Result<(), BookError> {
+    /// get write lock on the entire book and insert a new record; a ticker further behind its
+    /// source's newest than the lateness bound is dropped
+    pub fn push_log(&self, key: Datasource, val: &TickerCommon) -> Result<Arrival, BookError> {
+        let retention = self.retention();
+
         // write lock
         let mut book_writable = self.book.write().unwrap();
 
         match book_writable.get_mut(&key) {
-            Some(event_log) => {
-                // an event log exists for this key
-
-                // TODO un-unwrap
-                event_log.push_log(val).unwrap();
-                Ok(())
-            }
+            // an event log exists for this key
+            Some(event_log) => Ok(event_log.push_live(val, &retention.lateness)),
             None => {
-                // an event log does not exist for this key; create it
+                // an event log does not exist for this key; create it with the ticker in it
+                let mut new_e_log = EventLog::with_dedup_window(retention.dedup_window);
+                let arrival = new_e_log.push_live(val, &retention.lateness);
+                book_writable.insert(key, new_e_log);
+                Ok(arrival)
+            }
+        }
+    }
 
-                // 1. create a new event log since there's none for this key
-                let mut new_e_log = EventLog::new();
+    /// for logs created after this; set it before the feeds start
+    pub fn set_retention(&self, retention: Retention) {
+        *self.retention.write().unwrap() = retention;
+    }
 
-                // 2. put the ticker in the new event log
-                match new_e_log.push_log(val) {
-                    Ok(_) => {
-                        // 3. put the new event log with new ticker in the hashmap
-                        // Option<previous> or none returned
-                        book_writable.insert(key, new_e_log);
-                        Ok(())
-                    }
-                    Err(e) => {
-                        tracing::error!("[push] event log push error: {:?}", &e);
-                        Err(BookError::General)
-                    }
-                }
+    pub fn retention(&self) -> Retention {
+        *self.retention.read().unwrap()
+    }
+
+    pub fn set_indicators(&self, indicators: Vec<CalculationId>) {
+        *self.indicators.write().unwrap() = indicators;
+    }
+
+    pub fn indicators(&self) -> Vec<CalculationId> {
+        self.indicators.read().unwrap().clone()
+    }
+
+    /// each source's event time progress, in Datasource order
+    pub fn watermarks(&self) -> Vec<Watermark> {
+        let lateness = self.retention().lateness;
+        let book = self.book.read().unwrap();
+        Datasource::iter()
+            .filter_map(|ds| book.get(&ds).and_then(|evt_log| evt_log.watermark(&ds, &lateness)))
+            .collect()
+    }
+
+    /// every series with points, in Datasource order
+    pub fn series_list(&self) -> Vec<SeriesInfo> {
+        let book = self.book.read().unwrap();
+        Datasource::iter()
+            .flat_map(|ds| book.get(&ds).map(|evt_log| evt_log.series_list(&ds)).unwrap_or_default())
+            .collect()
+    }
+
+    /// one series for the api, oldest first; the limit keeps the newest points, counted after
+    /// downsampling
+    pub fn series(&self, query: &SeriesQuery) -> ChartDataset {
+        let range = TimeRange {
+            start: query.start.map_or(Bound::Unbounded, Bound::Included),
+            end: query.end.map_or(Bound::Unbounded, Bound::Excluded),
+        };
+        let mut data: Vec<ChartTimeSeries> = {
+            let book = self.book.read().unwrap();
+            match book.get(&query.source) {
+                Some(evt_log) => evt_log.series_in(&query.symbol, &query.series, &range).into_iter().rev().collect(),
+                None => vec![],
             }
+        };
+        if let Some(resolution) = &query.resolution {
+            data = resolution.downsample(&data);
         }
+        if data.len() > query.limit {
+            data.drain(0..data.len() - query.limit);
+        }
+        ChartDataset { label: query.label(), data }
     }
 
     /// get write lock on the entire book and insert a new record
@@ -80,7 +161,7 @@ impl EventBook {
                 // an event log does not exist for this key; create it
 
                 // 1. create a new event log since there's none for this key
-                let mut new_e_log = EventLog::new();
+                let mut new_e_log = EventLog::with_dedup_window(self.retention().dedup_window);
 
                 // 2. put the ticker in the new event log
                 match new_e_log.push_calc(val) {
@@ -100,7 +181,358 @@ impl EventBook {
     }
 }
 
+/// measurements written over line protocol
+impl EventBook {
+    /// store every point or none of them; returns how many were stored
+    pub fn push_points(&self, points: Vec<Point>) -> Result<usize, BookError> {
+        let mut measurements = self.measurements.write().unwrap();
+
+        // check the whole batch, including points that conflict with each other, before storing any
+        let mut columns: HashMap<&str, Measurement> = HashMap::new();
+        for point in points.iter() {
+            if reserved(&point.measurement) {
+                return Err(BookError::Conflict(format!("{} is a reserved table name", point.measurement)));
+            }
+            let m = columns.entry(&point.measurement).or_insert_with(|| match measurements.get(&point.measurement) {
+                Some(m) => m.columns(),
+                None => Measurement::new(&point.measurement),
+            });
+            if let Some(reason) = m.conflict(point) {
+                return Err(BookError::Conflict(reason));
+            }
+            m.declare(point);
+        }
+
+        let count = points.len();
+        for point in points {
+            measurements.entry(point.measurement.clone())
+                .or_insert_with(|| Measurement::new(&point.measurement))
+                .push(point);
+        }
+        Ok(count)
+    }
+
+    /// fix a measurement's tags and field types before anything is written to it; declaring the
+    /// same schema again is fine, a different one isn't
+    pub fn define_measurement(&self, schema: MeasurementSchema) -> Result<(), BookError> {
+        if reserved(&schema.name) {
+            return Err(BookError::Conflict(format!("{} is a reserved table name", schema.name)));
+        }
+        let mut measurements = self.measurements.write().unwrap();
+        match measurements.get(&schema.name) {
+            Some(m) if m.is_declared() && *m.schema() == schema => Ok(()),
+            Some(_) => Err(BookError::Conflict(format!("{} already exists", schema.name))),
+            None => {
+                measurements.insert(schema.name.clone(), Measurement::declared(schema));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// names the tick tables and their per-source views already use
+fn reserved(name: &str) -> bool {
+    [TABLE_DEDUP, TABLE_ALERTS, TABLE_PAPER, TABLE_PAPER_FILLS].contains(&name) || [TABLE_TICKS, TABLE_CALCS].iter().any(|table| {
+        name == *table || Datasource::iter().any(|ds| name == format!("{}_{table}", ds.to_string().to_lowercase()))
+    })
+}
+
+/// a tick or calc with the datasource whose log it's in
+struct Sourced<'a, T> {
+    source: &'a str,
+    row: &'a T,
+}
+
+impl Row for Sourced<'_, TickerCommon> {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.row.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "source" => Some(self.source.to_string()),
+            "symbol" => Some(self.row.symbol.to_string()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        match key {
+            "price" => Some(FieldValue::Float(self.row.price)),
+            _ => None,
+        }
+    }
+}
+
+impl Row for Sourced<'_, TickerCalc> {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.row.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "source" => Some(self.source.to_string()),
+            "symbol" => Some(self.row.symbol.to_string()),
+            "calc_id" => Some(self.row.calc_id.to_string()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        match key {
+            "val" => Some(FieldValue::Float(self.row.val)),
+            _ => None,
+        }
+    }
+}
+
+/// tables handed to sql clients; one row per tick or calc across every datasource
+impl EventBook {
+    pub fn ticks_measurement() -> MeasurementSchema {
+        MeasurementSchema::new(TABLE_TICKS)
+            .tag("source")
+            .tag("symbol")
+            .field("price", FieldType::Float)
+    }
+
+    pub fn calcs_measurement() -> MeasurementSchema {
+        MeasurementSchema::new(TABLE_CALCS)
+            .tag("source")
+            .tag("symbol")
+            .tag("calc_id")
+            .field("val", FieldType::Float)
+    }
+
+    /// copy the book out as (table name, batch), oldest rows first; both tick tables are always
+    /// returned, empty if nothing has arrived yet, followed by a table per measurement
+    pub fn tables(&self) -> Result<Vec<(String, RecordBatch)>, ArrowError> {
+        let book = self.book.read().unwrap();
+        let ticks = ticks_batch(&book, &TimeRange::default())?;
+        let calcs = calcs_batch(&book, &TimeRange::default())?;
+        let mut tables = vec![(TABLE_TICKS.to_string(), ticks), (TABLE_CALCS.to_string(), calcs)];
+
+        let measurements = self.measurements.read().unwrap();
+        let mut names: Vec<&String> = measurements.keys().collect();
+        names.sort();
+        for name in names {
+            tables.push((name.clone(), measurements[name].record_batch()?));
+        }
+        Ok(tables)
+    }
+
+    /// the same tables for a sql session: ticks and calcs read the live logs, measurements are
+    /// copied as they are now
+    pub fn providers(&self) -> Result<SqlTables, DataFusionError> {
+        let mut providers: Vec<(String, Arc<dyn TableProvider>)> = vec![
+            (TABLE_TICKS.to_string(), Arc::new(LogTable::new(self.book.clone(), LogKind::Ticks))),
+            (TABLE_CALCS.to_string(), Arc::new(LogTable::new(self.book.clone(), LogKind::Calcs))),
+        ];
+        let dedup = dedup_batch(&self.book.read().unwrap())?;
+        providers.push((TABLE_DEDUP.to_string(), Arc::new(MemTable::try_new(dedup.schema(), vec![vec![dedup]])?)));
+        let paper = self.paper.read().unwrap();
+        for (name, batch) in [(TABLE_PAPER, paper.marks_batch()?), (TABLE_PAPER_FILLS, paper.fills_batch()?)] {
+            providers.push((name.to_string(), Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]])?)));
+        }
+        let measurements = self.measurements.read().unwrap();
+        let mut names: Vec<&String> = measurements.keys().collect();
+        names.sort();
+        for name in names {
+            let batch = measurements[name].record_batch()?;
+            providers.push((name.clone(), Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]])?)));
+        }
+        Ok(SqlTables(providers))
+    }
+}
+
+/// one row per tick inside range across every datasource, each source's rows oldest first
+pub(crate) fn ticks_batch(book: &HashMap<Datasource, EventLog>, range: &TimeRange) -> Result<RecordBatch, ArrowError> {
+    let sources = sources(book);
+    let ticks: Vec<Sourced<TickerCommon>> = sources.iter()
+        .flat_map(|(source, evt_log)| evt_log.tickers_in(range).iter().rev().map(|row| Sourced { source, row }))
+        .collect();
+    record_batch(&EventBook::ticks_measurement(), &ticks)
+}
+
+/// one row per calculation inside range, like ticks_batch
+pub(crate) fn calcs_batch(book: &HashMap<Datasource, EventLog>, range: &TimeRange) -> Result<RecordBatch, ArrowError> {
+    let sources = sources(book);
+    let calcs: Vec<Sourced<TickerCalc>> = sources.iter()
+        .flat_map(|(source, evt_log)| evt_log.calcs_in(range).iter().rev().map(|row| Sourced { source, row }))
+        .collect();
+    record_batch(&EventBook::calcs_measurement(), &calcs)
+}
+
+/// one row per series that has seen a trade id: how many ids it remembers and how many repeats
+/// it has dropped
+pub(crate) fn dedup_batch(book: &HashMap<Datasource, EventLog>) -> Result<RecordBatch, ArrowError> {
+    let mut rows: Vec<(String, String, u64, u64)> = sources(book).iter()
+        .flat_map(|(source, evt_log)| evt_log.dedup().map(|(symbol, window)| (source.clone(), symbol.to_string(), window.len() as u64, window.duplicates())))
+        .collect();
+    rows.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    let schema = Schema::new(vec![
+        Field::new("source", DataType::Utf8, false),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("window", DataType::UInt64, false),
+        Field::new("duplicates", DataType::UInt64, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|x| x.0.as_str()))),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|x| x.1.as_str()))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|x| x.2))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|x| x.3))),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// the book's logs with their lowercase source names, in Datasource order
+fn sources(book: &HashMap<Datasource, EventLog>) -> Vec<(String, &EventLog)> {
+    Datasource::iter()
+        .filter_map(|ds| book.get(&ds).map(|evt_log| (ds.to_string().to_lowercase(), evt_log)))
+        .collect()
+}
+
 #[derive(Debug)]
 pub enum BookError {
     General,
+    Conflict(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use chrono::{DateTime, Duration, Utc};
+    use common_lib::cb_ticker::{Datasource, TickerCalc};
+    use common_lib::point::{FieldValue, MeasurementSchema, Point};
+    use common_lib::{CalculationId, SymbolCommon, TickerCommon};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use common_lib::api::SeriesQuery;
+    use common_lib::view::{Resolution, ViewSeries};
+    use common_lib::config::Retention;
+    use common_lib::watermark::Lateness;
+    use crate::event_book::{EventBook, TABLE_CALCS, TABLE_TICKS};
+    use crate::event_log::Arrival;
+
+    #[test]
+    fn test_tables() {
+        let book = EventBook::new();
+        let tables = book.tables().unwrap();
+        assert_eq!(tables.len(), 2);
+        assert!(tables.iter().all(|(_, batch)| batch.num_rows() == 0));
+
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        book.push_log(Datasource::Alpaca, &TickerCommon { source: Datasource::Alpaca, symbol: SymbolCommon::EthUsd, price: 2500.0, dtg, trade_id: None }).unwrap();
+        book.push_log(Datasource::Coinbase, &TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 42000.5, dtg, trade_id: None }).unwrap();
+        book.push_calc(&Datasource::Coinbase, &TickerCalc { dtg, symbol: SymbolCommon::BtcUsd, calc_id: CalculationId::MovingAvg0010, val: 42000.0 }).unwrap();
+
+        let tables = book.tables().unwrap();
+        assert_eq!(tables[0].0, TABLE_TICKS);
+        assert_eq!(tables[1].0, TABLE_CALCS);
+        let expected = "+----------------------+----------+---------+---------+
+| dtg                  | source   | symbol  | price   |
++----------------------+----------+---------+---------+
+| 2024-01-14T23:30:00Z | coinbase | btc_usd | 42000.5 |
+| 2024-01-14T23:30:00Z | alpaca   | eth_usd | 2500.0  |
++----------------------+----------+---------+---------+";
+        assert_eq!(pretty_format_batches(&[tables[0].1.clone()]).unwrap().to_string(), expected);
+        assert_eq!(tables[1].1.num_rows(), 1);
+    }
+
+    #[test]
+    fn test_watermarks() {
+        let book = EventBook::new();
+        book.set_retention(Retention { lateness: Lateness(Duration::seconds(1)), ..Retention::default() });
+        let t = |s: &str| DateTime::<Utc>::from(DateTime::parse_from_rfc3339(s).unwrap());
+        let tick = |dtg: &str| TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 1.0, dtg: t(dtg), trade_id: None };
+        assert!(book.watermarks().is_empty());
+
+        assert_eq!(book.push_log(Datasource::Coinbase, &tick("2024-01-14T23:30:10Z")).unwrap(), Arrival::InOrder);
+        assert_eq!(book.push_log(Datasource::Coinbase, &tick("2024-01-14T23:30:09.5Z")).unwrap(), Arrival::Late);
+        assert_eq!(book.push_log(Datasource::Coinbase, &tick("2024-01-14T23:30:08.9Z")).unwrap(), Arrival::Dropped);
+        // another source has its own watermark
+        assert_eq!(book.push_log(Datasource::Alpaca, &tick("2024-01-14T23:30:00Z")).unwrap(), Arrival::InOrder);
+
+        let watermarks = book.watermarks();
+        assert_eq!(watermarks.len(), 2);
+        assert_eq!(watermarks[0].source, Datasource::Coinbase);
+        assert_eq!(watermarks[0].watermark, t("2024-01-14T23:30:09Z"));
+        assert_eq!((watermarks[0].late, watermarks[0].dropped), (1, 1));
+        assert_eq!(book.book.read().unwrap()[&Datasource::Coinbase].len(), 2);
+    }
+
+    #[test]
+    fn test_series() {
+        let book = EventBook::new();
+        let t = |s: &str| DateTime::<Utc>::from(DateTime::parse_from_rfc3339(s).unwrap());
+        for (i, dtg) in ["2024-01-14T23:30:00.2Z", "2024-01-14T23:30:00.7Z", "2024-01-14T23:30:01.1Z", "2024-01-14T23:30:02.4Z"].iter().enumerate() {
+            book.push_log(Datasource::Coinbase, &TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: i as f64, dtg: t(dtg), trade_id: None }).unwrap();
+        }
+        book.push_calc(&Datasource::Coinbase, &TickerCalc { dtg: t("2024-01-14T23:30:02.4Z"), symbol: SymbolCommon::BtcUsd, calc_id: CalculationId::MovingAvg0010, val: 1.5 }).unwrap();
+
+        let list = book.series_list();
+        let names: Vec<(&str, usize)> = list.iter().map(|x| (x.series.as_str(), x.points)).collect();
+        assert_eq!(names, vec![("price", 4), ("MovingAvg0010", 1)]);
+        assert_eq!((list[0].first, list[0].last), (t("2024-01-14T23:30:00.2Z"), t("2024-01-14T23:30:02.4Z")));
+        assert_eq!(list[0].label, "btc_usd_Coinbase");
+
+        // start is inclusive, end exclusive
+        let price = SeriesQuery::new(Datasource::Coinbase, SymbolCommon::BtcUsd, ViewSeries::Price);
+        let dataset = book.series(&price.clone().start(t("2024-01-14T23:30:00.7Z")).end(t("2024-01-14T23:30:02.4Z")));
+        assert_eq!(dataset.data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![1.0, 2.0]);
+
+        // the newest buckets, each with its last value
+        let dataset = book.series(&price.clone().resolution(Resolution::try_from("1s".to_string()).unwrap()).limit(2));
+        assert_eq!(dataset.data.iter().map(|x| (x.x, x.y)).collect::<Vec<_>>(), vec![(t("2024-01-14T23:30:01Z"), 2.0), (t("2024-01-14T23:30:02Z"), 3.0)]);
+
+        assert!(book.series(&SeriesQuery::new(Datasource::Alpaca, SymbolCommon::BtcUsd, ViewSeries::Price)).data.is_empty());
+    }
+
+    #[test]
+    fn test_push_points() {
+        let book = EventBook::new();
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let point = |measurement: &str, field: FieldValue| Point {
+            measurement: measurement.to_string(),
+            tags: BTreeMap::from([("host".to_string(), "a".to_string())]),
+            fields: BTreeMap::from([("load".to_string(), field)]),
+            dtg,
+        };
+
+        assert_eq!(book.push_points(vec![point("cpu", FieldValue::Float(0.5)), point("mem", FieldValue::Integer(3))]).unwrap(), 2);
+
+        // a type conflict anywhere in the batch stores nothing
+        assert!(book.push_points(vec![point("cpu", FieldValue::Float(0.7)), point("cpu", FieldValue::Integer(1))]).is_err());
+        assert!(book.push_points(vec![point("coinbase_ticks", FieldValue::Float(1.0))]).is_err());
+
+        let tables = book.tables().unwrap();
+        let names: Vec<&str> = tables.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec![TABLE_TICKS, TABLE_CALCS, "cpu", "mem"]);
+        assert_eq!(tables[2].1.num_rows(), 1);
+    }
+
+    #[test]
+    fn test_define_measurement() {
+        let book = EventBook::new();
+        let schema = MeasurementSchema::from_arg("fills,symbol,side price=float,qty=integer").unwrap();
+        book.define_measurement(schema.clone()).unwrap();
+        book.define_measurement(schema).unwrap();
+        assert!(book.define_measurement(MeasurementSchema::from_arg("fills,symbol price=float").unwrap()).is_err());
+        assert!(book.define_measurement(MeasurementSchema::from_arg("ticks,symbol price=float").unwrap()).is_err());
+
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let fill = |field: &str, val: FieldValue| Point {
+            measurement: "fills".to_string(),
+            tags: BTreeMap::from([("symbol".to_string(), "btc_usd".to_string())]),
+            fields: BTreeMap::from([(field.to_string(), val)]),
+            dtg,
+        };
+        assert!(book.push_points(vec![fill("qty", FieldValue::Integer(2))]).is_ok());
+        assert!(book.push_points(vec![fill("fee", FieldValue::Float(0.1))]).is_err());
+
+        // the declared columns, even ones nothing has written yet
+        let tables = book.tables().unwrap();
+        let fills = &tables.iter().find(|(name, _)| name == "fills").unwrap().1;
+        let columns: Vec<String> = fills.schema().fields().iter().map(|f| f.name().to_string()).collect();
+        assert_eq!(columns, vec!["dtg", "symbol", "side", "price", "qty"]);
+    }
 }
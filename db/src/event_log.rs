@@ -6,18 +6,24 @@
 //!
 
 use common_lib::cb_ticker::{Datasource, TickerCalc};
-use datafusion::arrow::array::{Date64Array, Float64Array, PrimitiveArray, StringArray};
-use datafusion::arrow::datatypes::{DataType, Date64Type, Field, Schema};
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::arrow::util::pretty::pretty_format_batches;
 use datafusion::dataframe::DataFrameWriteOptions;
 use datafusion::prelude::*;
 use slice_ring_buffer::SliceRingBuffer;
-use std::sync::{Arc};
+use std::collections::HashMap;
+use std::ops::Bound;
 use std::time::{Instant};
 use chrono::{DateTime, Utc};
 use strum::IntoEnumIterator;
-use common_lib::{CalculationId, ChartDataset, ChartTimeSeries, SymbolCommon, TickerCommon, UniversalError};
+use common_lib::point::{FieldType, FieldValue, MeasurementSchema};
+use common_lib::{CalculationId, ChartDataset, ChartTimeSeries, SymbolCommon, TickerCommon};
+use common_lib::api::SeriesInfo;
+use common_lib::config::DEDUP_WINDOW;
+use common_lib::view::{ChartFilter, ViewSeries};
+use common_lib::watermark::{Lateness, Watermark};
+use crate::dedup::DedupWindow;
+use crate::measurement::{record_batch, Row};
 
 const RING_BUF_SIZE: usize = 100;
 const NUM_CALCS: usize = 4;
@@ -30,14 +36,33 @@ const MAX_RANGE:f64 = 10.0;
 pub struct EventLog {
     log: SliceRingBuffer<TickerCommon>,
     calc_log: SliceRingBuffer<TickerCalc>,
+    late: u64,
+    dropped: u64,
+    /// recent venue trade ids per symbol
+    seen: HashMap<SymbolCommon, DedupWindow>,
+    dedup_window: usize,
+}
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[allow(dead_code)]
 impl EventLog {
     pub fn new() -> EventLog {
+        EventLog::with_dedup_window(DEDUP_WINDOW)
+    }
+
+    /// remembering dedup_window trade ids per symbol
+    pub fn with_dedup_window(dedup_window: usize) -> EventLog {
         EventLog {
             log: SliceRingBuffer::<TickerCommon>::with_capacity(RING_BUF_SIZE),
             calc_log: SliceRingBuffer::<TickerCalc>::with_capacity(NUM_CALCS * RING_BUF_SIZE),
+            late: 0,
+            dropped: 0,
+            seen: HashMap::new(),
+            dedup_window,
         }
     }
 
@@ -49,99 +74,191 @@ impl EventLog {
         self.log.is_empty()
     }
 
+    /// ticks held before the ring buffer grows
+    pub fn capacity(&self) -> usize {
+        self.log.capacity()
+    }
+
+    /// keeps the log newest first by event time; a tick that arrives late is inserted where it
+    /// belongs, an equal time goes in front of the ticks already there
     pub fn push_log(&mut self, ticker: &TickerCommon) -> Result<(), EventLogError> {
-        self.log.push_front((*ticker).clone());
+        let i = self.log.as_slice().partition_point(|x| x.dtg > ticker.dtg);
+        insert_at(&mut self.log, i, (*ticker).clone());
         Ok(())
     }
 
-    /// push TickerCalc
+    /// push a live ticker unless its trade id was seen recently or it's more than lateness behind
+    /// the newest one already here
+    ///
+    /// only a stored ticker's trade id goes in the dedup window, so a re-send of one that was
+    /// dropped can still be stored
+    pub fn push_live(&mut self, ticker: &TickerCommon, lateness: &Lateness) -> Arrival {
+        let duplicate = ticker.trade_id.is_some_and(|id| self.seen.get(&ticker.symbol).is_some_and(|seen| seen.contains(id)));
+        let arrival = match self.newest() {
+            _ if duplicate => Arrival::Duplicate,
+            Some(newest) if ticker.dtg < newest - lateness.0 => Arrival::Dropped,
+            Some(newest) if ticker.dtg < newest => Arrival::Late,
+            _ => Arrival::InOrder,
+        };
+        match arrival {
+            Arrival::Dropped => self.dropped += 1,
+            Arrival::Duplicate => self.remember(ticker),
+            Arrival::Late => {
+                self.late += 1;
+                self.remember(ticker);
+                let _ = self.push_log(ticker);
+            }
+            Arrival::InOrder => {
+                self.remember(ticker);
+                let _ = self.push_log(ticker);
+            }
+        }
+        arrival
+    }
+
+    /// put the ticker's trade id in its symbol's dedup window; a repeat is counted there
+    fn remember(&mut self, ticker: &TickerCommon) {
+        if let Some(id) = ticker.trade_id {
+            let _ = self.seen.entry(ticker.symbol.clone()).or_insert_with(|| DedupWindow::new(self.dedup_window)).insert(id);
+        }
+    }
+
+    /// the dedup window of each symbol that has had a ticker with a trade id
+    pub fn dedup(&self) -> impl Iterator<Item = (&SymbolCommon, &DedupWindow)> {
+        self.seen.iter()
+    }
+
+    /// event time of the newest ticker
+    pub fn newest(&self) -> Option<DateTime<Utc>> {
+        self.log.first().map(|x| x.dtg)
+    }
+
+    /// where the log stands in event time; None until a ticker arrives
+    pub fn watermark(&self, source: &Datasource, lateness: &Lateness) -> Option<Watermark> {
+        self.newest().map(|newest| Watermark {
+            source: source.clone(),
+            newest,
+            watermark: newest - lateness.0,
+            late: self.late,
+            dropped: self.dropped,
+        })
+    }
+
+    /// push TickerCalc, newest first by event time like push_log
     pub fn push_calc(&mut self, ticker: &TickerCalc) -> Result<(), EventLogError> {
-        self.calc_log.push_front((*ticker).clone());
+        let i = self.calc_log.as_slice().partition_point(|x| x.dtg > ticker.dtg);
+        insert_at(&mut self.calc_log, i, (*ticker).clone());
         Ok(())
     }
 
-    /// prep for chartjs
+    /// the tickers inside range, newest first, found by binary search
+    pub fn tickers_in(&self, range: &TimeRange) -> &[TickerCommon] {
+        range.slice(self.log.as_slice(), |x| x.dtg)
+    }
+
+    /// the calculations inside range, newest first
+    pub fn calcs_in(&self, range: &TimeRange) -> &[TickerCalc] {
+        range.slice(self.calc_log.as_slice(), |x| x.dtg)
+    }
+
+    /// remove symbol's calculations at or after since, so they can be computed again; returns how many
+    pub fn drop_calcs_since(&mut self, symbol: &SymbolCommon, since: DateTime<Utc>) -> usize {
+        let newer = self.calc_log.as_slice().partition_point(|x| x.dtg >= since);
+        let kept: Vec<TickerCalc> = (0..newer).filter_map(|_| self.calc_log.pop_front()).filter(|x| x.symbol != *symbol).collect();
+        let stale = newer - kept.len();
+        for x in kept.into_iter().rev() {
+            self.calc_log.push_front(x);
+        }
+        stale
+    }
+
+    /// every ticker, oldest first
+    pub fn tickers(&self) -> impl Iterator<Item = &TickerCommon> {
+        self.log.iter().rev()
+    }
+
+    /// every calculation, oldest first
+    pub fn calcs(&self) -> impl Iterator<Item = &TickerCalc> {
+        self.calc_log.iter().rev()
+    }
+
+    /// prep for chartjs: a dataset per symbol and series the filter matches, each newest first
     /// limit: limit the number of values returned
-    ///
-    /// todo: filter by product ID
-    ///
-    pub async fn chart_since(&self, ds:Datasource, symbols: &Vec<SymbolCommon>, since: Option<DateTime<Utc>>, limit: usize) -> Result<Vec<ChartDataset>, UniversalError>  {
+    pub fn chart_in(&self, ds: &Datasource, filter: &ChartFilter, range: &TimeRange, limit: usize) -> Vec<ChartDataset> {
         let mut data: Vec<ChartDataset> = vec!();
-
-        // "select...group by product_id..."
-        for symbol in symbols {
-
-            // 'group by product_id', limit query target to 1000 (or fewer) after filtering(?)
-            let time_series_data: Vec<ChartTimeSeries> = self.log.iter()
-                // .rev()
-                .filter(|f| {
-                    f.symbol == *symbol
-                        &&
-                    if since.is_none() {
-                        // no since specified
-                        true
-                    } else {
-                        f.dtg > since.unwrap()
-                    }
-                })
-                .take(limit)
-                .map(|x| { ChartTimeSeries { x: x.dtg, y: x.price } })
-                .collect();
-
-            let chart = ChartDataset {
-                label: format!("{}_{}", symbol.to_string(), ds.to_string()),
-                data: time_series_data,
-            };
-            data.push(chart);
-
-            // "...group by calculation_id..."
-            for calc_id in CalculationId::iter(){
-                let time_series_f64: Vec<ChartTimeSeries> = self.calc_log
-                    .iter()
-                    .filter(|f|
-                        f.symbol == *symbol && f.calc_id == calc_id
-                        &&
-                        if since.is_none() {
-                            // no since specified
-                            true
-                        } else {
-                            f.dtg > since.unwrap()
-                        }
-                    )
-                    .take(limit)
-                    .map(|x|{ ChartTimeSeries { x: x.dtg, y: x.val } })
-                    .collect();
-
-                let chart = ChartDataset {
-                    label: format!("{}_{}_{}", symbol.to_string(), calc_id.to_string(), ds.to_string()),
-                    data: time_series_f64,
-                };
-                data.push(chart);
+        for symbol in SymbolCommon::iter() {
+            for series in ViewSeries::all() {
+                if filter.matches(ds, &symbol, &series) {
+                    data.push(ChartDataset {
+                        label: series.label(&symbol, ds),
+                        data: self.series_in(&symbol, &series, range).into_iter().take(limit).collect(),
+                    });
+                }
             }
         }
-        Ok(data)
+        data
+    }
+
+    /// one series' points inside range, newest first
+    pub fn series_in(&self, symbol: &SymbolCommon, series: &ViewSeries, range: &TimeRange) -> Vec<ChartTimeSeries> {
+        match series {
+            ViewSeries::Price => self.tickers_in(range).iter()
+                .filter(|x| x.symbol == *symbol)
+                .map(|x| ChartTimeSeries { x: x.dtg, y: x.price })
+                .collect(),
+            ViewSeries::Calc(calc_id) => self.calcs_in(range).iter()
+                .filter(|x| x.symbol == *symbol && x.calc_id == *calc_id)
+                .map(|x| ChartTimeSeries { x: x.dtg, y: x.val })
+                .collect(),
+        }
+    }
+
+    /// every series with at least one point, in symbol then series order
+    pub fn series_list(&self, source: &Datasource) -> Vec<SeriesInfo> {
+        // newest first, so the first row seen is a series' last
+        let mut found: HashMap<(SymbolCommon, ViewSeries), SeriesInfo> = HashMap::new();
+        let rows = self.log.iter().map(|x| (x.symbol.clone(), ViewSeries::Price, x.dtg))
+            .chain(self.calc_log.iter().map(|x| (x.symbol.clone(), ViewSeries::Calc(x.calc_id.clone()), x.dtg)));
+        for (symbol, series, dtg) in rows {
+            let info = found.entry((symbol.clone(), series.clone())).or_insert_with(|| SeriesInfo {
+                source: source.clone(),
+                label: series.label(&symbol, source),
+                series: series.name(),
+                symbol,
+                points: 0,
+                first: dtg,
+                last: dtg,
+            });
+            info.points += 1;
+            info.first = dtg;
+        }
+
+        SymbolCommon::iter()
+            .flat_map(|symbol| ViewSeries::all().into_iter().map(move |series| (symbol.clone(), series)))
+            .filter_map(|key| found.remove(&key))
+            .collect()
     }
 
     /// Compute the average of the last N prices
     pub fn calculate_moving_avg_n(&self, calc_id: &CalculationId, sym: &SymbolCommon) -> Result<TickerCalc, EventLogError> {
+        self.calculate_moving_avg_in(calc_id, sym, &TimeRange::default())
+    }
+
+    /// the average of the last N prices inside range; the newest N is taken before filtering by
+    /// symbol
+    pub fn calculate_moving_avg_in(&self, calc_id: &CalculationId, sym: &SymbolCommon, range: &TimeRange) -> Result<TickerCalc, EventLogError> {
 
         // tracing::debug!("[calculate_moving_avg_n]");
-        let slice_max = calc_id.value();
+        let log = self.tickers_in(range);
 
         // use len if len is less than max slice
-        let log_count = self.len();
-        let slice_max = if log_count < slice_max {
-            log_count
-        } else {
-            slice_max
-        };
+        let slice_max = calc_id.value().min(log.len());
 
         // todo: de-clone
-        let slice_n:&[TickerCommon] = &self.log.as_slice()[0..slice_max];
-        let slice_n:Vec<TickerCommon> = slice_n.iter().filter(|x| x.symbol == *sym).map(|x| x.clone()).collect();
+        let slice_n:Vec<TickerCommon> = log[0..slice_max].iter().filter(|x| x.symbol == *sym).cloned().collect();
         let avg_n: f64 = slice_n.iter().map(|x| x.price).sum::<f64>() / slice_n.len() as f64;
 
-        let dtg_this_calc:DateTime<Utc> = if slice_n.len() > 0{
+        let dtg_this_calc:DateTime<Utc> = if !slice_n.is_empty() {
             slice_n[0].dtg
         } else {
             Utc::now()
@@ -158,23 +275,27 @@ impl EventLog {
 
     /// Rate of change for the moving average diff (quantify up/down rate of the trend)
     /// TODO: hard-coded BTC
-
     pub fn calculate_diff_slope(&self, source_calc_id: &CalculationId, dest_calc_id: &CalculationId, prod_id: &SymbolCommon) -> Result<TickerCalc, EventLogError> {
+        self.calculate_diff_slope_in(source_calc_id, dest_calc_id, prod_id, &TimeRange::default())
+    }
+
+    /// rate of change of the diffs inside range
+    pub fn calculate_diff_slope_in(&self, source_calc_id: &CalculationId, dest_calc_id: &CalculationId, prod_id: &SymbolCommon, range: &TimeRange) -> Result<TickerCalc, EventLogError> {
 
         // tracing::debug!("[calculate_diff_slope]");
 
         let slice_max = source_calc_id.value();
-        let r: Vec<&TickerCalc> = self.calc_log.iter().filter(|x| x.symbol == *prod_id && x.calc_id == *source_calc_id).take(slice_max).collect();
+        let r: Vec<&TickerCalc> = self.calcs_in(range).iter().filter(|x| x.symbol == *prod_id && x.calc_id == *source_calc_id).take(slice_max).collect();
 
         tracing::debug!("[calculate_diff_slope] r len: {}", r.len());
 
         if r.len()>1 {
 
-            let elapsed_sec:f64 = (r.first().unwrap().dtg - r.last().unwrap().dtg).num_milliseconds() as f64 / 1000.0;
+            let elapsed_sec:f64 = (r.first().unwrap().dtg - r.last().unwrap().dtg).num_nanoseconds().unwrap_or(i64::MAX) as f64 / 1e9;
             let value_change:f64 = r.first().unwrap().val - r.last().unwrap().val;
 
             let slope:f64 = value_change / elapsed_sec * VISUAL_CORRECTION_FACTOR;
-            let slope = if slope > MAX_RANGE { MAX_RANGE } else if slope < -MAX_RANGE { -MAX_RANGE} else { slope };
+            let slope = slope.clamp(-MAX_RANGE, MAX_RANGE);
 
             tracing::debug!("[calculate_diff_slope] value_change: {}, elapsed: {},  {}", &value_change, &elapsed_sec, &slope);
 
@@ -193,39 +314,16 @@ impl EventLog {
     }
 
 
-    pub fn schema() -> Schema {
-        Schema::new(vec![
-            Field::new("dtg", DataType::Date64, false),
-            Field::new("product_id", DataType::Utf8, false),
-            Field::new("price", DataType::Float64, false),
-        ])
+    /// one log's ticks; product_id is what the coinbase feed called the symbol
+    pub fn measurement() -> MeasurementSchema {
+        MeasurementSchema::new("t_one")
+            .tag("product_id")
+            .field("price", FieldType::Float)
     }
 
-    /// hacked over from a coinbase websocket stream, hence the product id and price fields
     pub fn record_batch(&self) -> Result<RecordBatch, EventLogError> {
-        let dates: Vec<i64>;
-        let product_ids: Vec<String>;
-        let prices: Vec<f64>;
-        {
-            // lock inside closure to end lock asap
-            // TODO: unwrap
-            // match self.read(){
-            //     Ok(log_readable)=>{
-            dates = self.log.iter().map(|x| x.dtg.timestamp_millis()).collect();
-            product_ids = self
-                .log
-                .iter()
-                .map(|x| (x.symbol.to_string()).clone())
-                .collect();
-            prices = self.log.iter().map(|x| x.price).collect();
-
-        }
-
-        let dates: PrimitiveArray<Date64Type> = Date64Array::from(dates);
-        let product_ids: StringArray = StringArray::from(product_ids);
-        let prices: Float64Array = Float64Array::from(prices);
-
-        match RecordBatch::try_new(Arc::new(EventLog::schema()), vec![Arc::new(dates), Arc::new(product_ids), Arc::new(prices)]) {
+        let rows: Vec<&TickerCommon> = self.log.iter().collect();
+        match record_batch(&EventLog::measurement(), &rows) {
             Ok(x) => {
                 tracing::info!("[record_batch] {:?}", &x);
                 Ok(x)
@@ -297,7 +395,6 @@ impl EventLog {
         Ok(df.clone())
     }
 
-    /// FYI: DataFusion doesn't by default print chrono DateTimes with the time
     pub fn _print_record_batch(&self) {
         // https://docs.rs/arrow/latest/arrow/record_batch/struct.RecordBatch.html
         match self.record_batch() {
@@ -338,6 +435,98 @@ impl EventLog {
     }
 }
 
+impl Row for TickerCommon {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "product_id" | "symbol" => Some(self.symbol.to_string()),
+            "source" => Some(self.source.to_string().to_lowercase()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        match key {
+            "price" => Some(FieldValue::Float(self.price)),
+            _ => None,
+        }
+    }
+}
+
+/// SliceRingBuffer::insert (0.3.4) can overwrite elements, so lift the newer ones off the front
+/// and put them back; a late row only has the rows after it to move
+fn insert_at<T>(buf: &mut SliceRingBuffer<T>, index: usize, item: T) {
+    let newer: Vec<T> = (0..index).filter_map(|_| buf.pop_front()).collect();
+    buf.push_front(item);
+    for x in newer.into_iter().rev() {
+        buf.push_front(x);
+    }
+}
+
+/// where a live ticker landed relative to the newest one from its source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    InOrder,
+    /// older than the newest ticker, inserted in event time order
+    Late,
+    /// behind the watermark, not stored
+    Dropped,
+    /// its trade id is already in the symbol's dedup window, not stored
+    Duplicate,
+}
+
+/// event time bounds for a scan; the default is every row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeRange {
+    pub start: Bound<DateTime<Utc>>,
+    pub end: Bound<DateTime<Utc>>,
+}
+
+impl Default for TimeRange {
+    fn default() -> Self {
+        TimeRange { start: Bound::Unbounded, end: Bound::Unbounded }
+    }
+}
+
+impl TimeRange {
+    /// both ranges at once
+    pub fn and(self, other: TimeRange) -> TimeRange {
+        TimeRange {
+            start: tighter(self.start, other.start, |a, b| a > b),
+            end: tighter(self.end, other.end, |a, b| a < b),
+        }
+    }
+
+    /// the run of a newest-first slice inside the range: rows after end come first, rows before
+    /// start come last, so both edges are a binary search
+    pub fn slice<'a, T>(&self, rows: &'a [T], dtg: impl Fn(&T) -> DateTime<Utc>) -> &'a [T] {
+        let first = match self.end {
+            Bound::Included(end) => rows.partition_point(|x| dtg(x) > end),
+            Bound::Excluded(end) => rows.partition_point(|x| dtg(x) >= end),
+            Bound::Unbounded => 0,
+        };
+        let last = match self.start {
+            Bound::Included(start) => rows.partition_point(|x| dtg(x) >= start),
+            Bound::Excluded(start) => rows.partition_point(|x| dtg(x) > start),
+            Bound::Unbounded => rows.len(),
+        };
+        &rows[first..last.max(first)]
+    }
+}
+
+/// of two bounds on the same side, the one that lets fewer rows through
+fn tighter(a: Bound<DateTime<Utc>>, b: Bound<DateTime<Utc>>, beyond: fn(&DateTime<Utc>, &DateTime<Utc>) -> bool) -> Bound<DateTime<Utc>> {
+    match (a, b) {
+        (Bound::Unbounded, x) | (x, Bound::Unbounded) => x,
+        (Bound::Included(t) | Bound::Excluded(t), Bound::Included(u) | Bound::Excluded(u)) if t != u => if beyond(&t, &u) { a } else { b },
+        (Bound::Excluded(_), _) => a,
+        _ => b,
+    }
+}
+
 /// Not used
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -353,36 +542,108 @@ pub enum EventLogError {
 
 #[cfg(test)]
 mod tests {
-    use db::event_log::EventLog;
+    use std::ops::Bound;
     use chrono::{DateTime, Utc};
-    use common_lib::cb_ticker::{TickerCoinbase};
+    use common_lib::cb_ticker::Datasource;
     use datafusion::arrow::util::pretty::pretty_format_batches;
-    use common_lib::{CalculationId, SymbolCoinbase};
-    use crate::event_log::EventLog;
+    use common_lib::view::{ChartFilter, ViewSeries};
+    use common_lib::{CalculationId, SymbolCommon, TickerCommon};
+    use strum::IntoEnumIterator;
+    use common_lib::watermark::Lateness;
+    use crate::event_log::{Arrival, EventLog, TimeRange};
+
+    #[test]
+    fn test_time_order() {
+        let t = |s: &str| DateTime::<Utc>::from(DateTime::parse_from_rfc3339(s).unwrap());
+        let mut e_log = EventLog::new();
+        // the second tick arrives late, 10ns before the one ahead of it
+        for (dtg, price) in [("2024-01-14T23:36:23.177990090Z", 1.0), ("2024-01-14T23:36:23.177990080Z", 2.0), ("2024-01-14T23:36:24Z", 3.0), ("2024-01-14T23:36:22Z", 4.0)] {
+            e_log.push_log(&TickerCommon { source: Datasource::Alpaca, dtg: t(dtg), symbol: SymbolCommon::BtcUsd, price, trade_id: None }).unwrap();
+        }
+        let prices: Vec<f64> = e_log.tickers().map(|x| x.price).collect();
+        assert_eq!(prices, vec![4.0, 2.0, 1.0, 3.0]);
+
+        let range = TimeRange { start: Bound::Excluded(t("2024-01-14T23:36:23.177990080Z")), end: Bound::Included(t("2024-01-14T23:36:24Z")) };
+        let prices: Vec<f64> = e_log.tickers_in(&range).iter().map(|x| x.price).collect();
+        assert_eq!(prices, vec![3.0, 1.0]);
+        let range = TimeRange { start: Bound::Included(t("2024-01-14T23:36:25Z")), ..TimeRange::default() };
+        assert!(e_log.tickers_in(&range).is_empty());
+        assert_eq!(e_log.tickers_in(&TimeRange::default()).len(), 4);
+
+        // enough late ticks to wrap the ring buffer stay sorted and intact
+        let start = t("2024-01-14T23:36:00Z");
+        let mut e_log = EventLog::new();
+        for i in 0..1000i64 {
+            let dtg = start + chrono::Duration::milliseconds((i * 7919) % 1000);
+            e_log.push_log(&TickerCommon { source: Datasource::Alpaca, dtg, symbol: SymbolCommon::BtcUsd, price: i as f64, trade_id: None }).unwrap();
+        }
+        let dtgs: Vec<DateTime<Utc>> = e_log.tickers().map(|x| x.dtg).collect();
+        assert!(dtgs.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(dtgs.last(), Some(&(start + chrono::Duration::milliseconds(999))));
+    }
+
+    /// a trade id is only remembered once its ticker is stored, so a re-send of a dropped one counts
+    #[test]
+    fn test_push_live_dedup() {
+        let t = |s: &str| DateTime::<Utc>::from(DateTime::parse_from_rfc3339(s).unwrap());
+        let lateness = Lateness(chrono::Duration::seconds(2));
+        let tick = |dtg: &str, trade_id: u64| TickerCommon { source: Datasource::Coinbase, dtg: t(dtg), symbol: SymbolCommon::BtcUsd, price: trade_id as f64, trade_id: Some(trade_id) };
+        let mut e_log = EventLog::new();
+        assert_eq!(e_log.push_live(&tick("2024-01-14T23:30:10Z", 1), &lateness), Arrival::InOrder);
+        assert_eq!(e_log.push_live(&tick("2024-01-14T23:30:10Z", 1), &lateness), Arrival::Duplicate);
+
+        // too late, then sent again by the venue on time
+        assert_eq!(e_log.push_live(&tick("2024-01-14T23:30:05Z", 2), &lateness), Arrival::Dropped);
+        assert_eq!(e_log.push_live(&tick("2024-01-14T23:30:09Z", 2), &lateness), Arrival::Late);
+        assert_eq!(e_log.push_live(&tick("2024-01-14T23:30:09Z", 2), &lateness), Arrival::Duplicate);
+
+        let (_, seen) = e_log.dedup().next().unwrap();
+        assert_eq!((seen.len(), seen.duplicates()), (2, 2));
+        assert_eq!(e_log.tickers().map(|x| x.price).collect::<Vec<f64>>(), vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_chart_in() {
+        let t = |s: &str| DateTime::<Utc>::from(DateTime::parse_from_rfc3339(s).unwrap());
+        let mut e_log = EventLog::new();
+        for (dtg, symbol) in [("2024-01-14T23:30:00Z", SymbolCommon::BtcUsd), ("2024-01-14T23:30:01Z", SymbolCommon::EthUsd), ("2024-01-14T23:30:02Z", SymbolCommon::BtcUsd)] {
+            e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: t(dtg), symbol, price: 1.0, trade_id: None }).unwrap();
+        }
+
+        // every symbol and series for an unfiltered chart, even the empty ones
+        let charts = e_log.chart_in(&Datasource::Coinbase, &ChartFilter::default(), &TimeRange::default(), 10);
+        assert_eq!(charts.len(), SymbolCommon::iter().count() * ViewSeries::all().len());
+
+        let filter = ChartFilter { symbols: vec![SymbolCommon::BtcUsd], series: vec![ViewSeries::Price], ..ChartFilter::default() };
+        let range = TimeRange { start: Bound::Included(t("2024-01-14T23:30:01Z")), ..TimeRange::default() };
+        let charts = e_log.chart_in(&Datasource::Coinbase, &filter, &range, 10);
+        assert_eq!(charts.len(), 1);
+        assert_eq!(charts[0].label, "btc_usd_Coinbase");
+        assert_eq!(charts[0].data.iter().map(|x| x.x).collect::<Vec<_>>(), vec![t("2024-01-14T23:30:02Z")]);
+        assert!(e_log.chart_in(&Datasource::Alpaca, &ChartFilter { sources: vec![Datasource::Coinbase], ..ChartFilter::default() }, &range, 10).is_empty());
+    }
 
     #[test]
     fn test_calculate_moving_avg_n(){
         let d1 = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("1996-12-19T16:39:57-08:00").unwrap());
         let mut e_log = EventLog::new();
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 10.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 10.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 10.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 10.0});
-        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0004, &SymbolCoinbase::BtcUsd).unwrap();
+        for _ in 0..10 {
+            let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::BtcUsd, price: 10.0, trade_id: None});
+        }
+        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &SymbolCommon::BtcUsd).unwrap();
         println!("[test_calculate_moving_avg_n] {:?}", ticker_calc);
         assert_eq!(ticker_calc.val, 10.0);
 
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 30.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 30.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 30.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 30.0});
+        for _ in 0..10 {
+            let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::BtcUsd, price: 30.0, trade_id: None});
+        }
 
-        // last 4 average should be 30; last 10 average should be 20
-        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0004, &SymbolCoinbase::BtcUsd).unwrap();
+        // last 10 average should be 30; last 100 (only 20 in the log) average should be 20
+        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &SymbolCommon::BtcUsd).unwrap();
         println!("[test_calculate_moving_avg_n] {:?}", ticker_calc);
         assert_eq!(ticker_calc.val, 30.0);
 
-        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &SymbolCoinbase::BtcUsd).unwrap();
+        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0100, &SymbolCommon::BtcUsd).unwrap();
         println!("[test_calculate_moving_avg_n] {:?}", ticker_calc);
         assert_eq!(ticker_calc.val, 20.0);
 
@@ -394,16 +655,16 @@ mod tests {
         let d1 = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("1996-12-19T16:39:57-08:00").unwrap());
 
         let mut e_log = EventLog::new();
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 10.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::BtcUsd, price: 10.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::EthUsd, price: 500.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::EthUsd, price: 500.0});
-        let _ = e_log.push_log(&TickerCoinbase { dtg: d1.clone(), symbol: SymbolCoinbase::EthUsd, price: 500.0});
-        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0004, &SymbolCoinbase::BtcUsd).unwrap();
+        let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::BtcUsd, price: 10.0, trade_id: None});
+        let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::BtcUsd, price: 10.0, trade_id: None});
+        let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::EthUsd, price: 500.0, trade_id: None});
+        let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::EthUsd, price: 500.0, trade_id: None});
+        let _ = e_log.push_log(&TickerCommon { source: Datasource::Coinbase, dtg: d1, symbol: SymbolCommon::EthUsd, price: 500.0, trade_id: None});
+        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &SymbolCommon::BtcUsd).unwrap();
         println!("[test_calculate_moving_avg_n] test 2 mixed prod_id {:?}", ticker_calc);
         assert_eq!(ticker_calc.val, 10.0);
 
-        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0004, &SymbolCoinbase::EthUsd).unwrap();
+        let ticker_calc = e_log.calculate_moving_avg_n(&CalculationId::MovingAvg0010, &SymbolCommon::EthUsd).unwrap();
         println!("[test_calculate_moving_avg_n] test 2 mixed prod_id {:?}", ticker_calc);
         assert_eq!(ticker_calc.val, 500.0);
 
@@ -414,10 +675,12 @@ mod tests {
     fn test_struct_array_to_batch() {
         let d1 = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("1996-12-19T16:39:57-08:00").unwrap());
         let mut e_log = EventLog::new();
-        let _ = e_log.push_log(&TickerCoinbase {
-            dtg: d1.clone(),
-            symbol: SymbolCoinbase::BtcUsd,
+        let _ = e_log.push_log(&TickerCommon {
+            source: Datasource::Coinbase,
+            dtg: d1,
+            symbol: SymbolCommon::BtcUsd,
             price: 88.87,
+            trade_id: None,
         });
         // let d2 = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("1997-12-19T16:39:57-08:00").unwrap());
         // let _ = e_log.push(&Ticker{
@@ -429,11 +692,11 @@ mod tests {
         // println!("batch: {:?}", &batch);
         let test_case = pretty_format_batches(&[batch]).unwrap().to_string();
         // println!("{}", &test_case);
-        let expected_result = "+---------------------+------------+-------+
-| dtg                 | product_id | price |
-+---------------------+------------+-------+
-| 1996-12-20T00:39:57 | btc_usd    | 88.87 |
-+---------------------+------------+-------+";
+        let expected_result = "+----------------------+------------+-------+
+| dtg                  | product_id | price |
++----------------------+------------+-------+
+| 1996-12-20T00:39:57Z | btc_usd    | 88.87 |
++----------------------+------------+-------+";
         assert_eq!(test_case, expected_result);
     }
 
@@ -442,10 +705,12 @@ mod tests {
     async fn test_query_memory() -> datafusion::error::Result<()> {
         let mut e_log = EventLog::new();
         let d1 = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("1996-12-19T16:39:57-08:00").unwrap());
-        let _ = e_log.push_log(&TickerCoinbase {
-            dtg: d1.clone(),
-            symbol: SymbolCoinbase::BtcUsd,
+        let _ = e_log.push_log(&TickerCommon {
+            source: Datasource::Coinbase,
+            dtg: d1,
+            symbol: SymbolCommon::BtcUsd,
             price: 88.87,
+            trade_id: None,
         });
         // let d2 = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("1997-12-19T16:39:57-08:00").unwrap());
         // let _ = e_log.push(&Ticker {
@@ -458,11 +723,11 @@ mod tests {
         let test_case = pretty_format_batches(vec_record_batch.as_slice())
             .unwrap()
             .to_string();
-        let expected_result = "+---------------------+------------+-------+
-| dtg                 | product_id | price |
-+---------------------+------------+-------+
-| 1996-12-20T00:39:57 | btc_usd    | 88.87 |
-+---------------------+------------+-------+";
+        let expected_result = "+----------------------+------------+-------+
+| dtg                  | product_id | price |
++----------------------+------------+-------+
+| 1996-12-20T00:39:57Z | btc_usd    | 88.87 |
++----------------------+------------+-------+";
         assert_eq!(test_case, expected_result);
         e_log.write_csv().await;
 
@@ -0,0 +1,98 @@
+//! health.rs
+//!
+//! the db thread's side of /healthz and /readyz: each feed's connection as the feed reports it,
+//! and when each symbol's newest live tick was stored; see common_lib::health
+
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use strum::IntoEnumIterator;
+use common_lib::cb_ticker::Datasource;
+use common_lib::health::{BufferHealth, FeedHealth, FeedState, Health, SymbolHealth};
+use common_lib::SymbolCommon;
+use crate::event_book::EventBook;
+
+/// owned by the db thread
+#[derive(Default)]
+pub struct HealthMonitor {
+    feeds: HashMap<Datasource, FeedHealth>,
+    arrivals: HashMap<(Datasource, SymbolCommon), DateTime<Utc>>,
+}
+
+impl HealthMonitor {
+    pub fn new() -> HealthMonitor {
+        HealthMonitor { feeds: HashMap::new(), arrivals: HashMap::new() }
+    }
+
+    pub fn feed(&mut self, source: Datasource, state: FeedState, now: DateTime<Utc>) {
+        tracing::info!("[HealthMonitor] {source} feed {state}");
+        self.feeds.insert(source.clone(), FeedHealth { source, state, since: now });
+    }
+
+    /// a live ticker was stored
+    pub fn tick(&mut self, source: &Datasource, symbol: &SymbolCommon, now: DateTime<Utc>) {
+        self.arrivals.insert((source.clone(), symbol.clone()), now);
+    }
+
+    /// feeds and buffers in Datasource order, symbols in SymbolCommon order within each source
+    pub fn health(&self, evt_book: &EventBook, now: DateTime<Utc>) -> Health {
+        let feeds = Datasource::iter().filter_map(|ds| self.feeds.get(&ds).cloned()).collect();
+        let symbols = Datasource::iter()
+            .flat_map(|ds| SymbolCommon::iter().map(move |symbol| (ds.clone(), symbol)))
+            .filter_map(|key| self.arrivals.get(&key).map(|last_tick| {
+                let age_secs = (now - *last_tick).num_milliseconds() as f64 / 1000.0;
+                SymbolHealth { source: key.0, symbol: key.1, last_tick: *last_tick, age_secs }
+            }))
+            .collect();
+        let buffers = {
+            let book = evt_book.book.read().unwrap();
+            Datasource::iter()
+                .filter_map(|ds| book.get(&ds).map(|evt_log| {
+                    let (len, capacity) = (evt_log.len(), evt_log.capacity());
+                    BufferHealth { source: ds, len, capacity, fill: len as f64 / capacity.max(1) as f64 }
+                }))
+                .collect()
+        };
+        Health::new(now, feeds, symbols, buffers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::health::FeedState;
+    use common_lib::{SymbolCommon, TickerCommon};
+    use crate::event_book::EventBook;
+    use crate::health::HealthMonitor;
+
+    #[test]
+    fn test_health() {
+        let now = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let book = EventBook::new();
+        let mut monitor = HealthMonitor::new();
+        // nothing started is ready
+        assert!(monitor.health(&book, now).ready);
+
+        monitor.feed(Datasource::Alpaca, FeedState::Connecting, now);
+        monitor.feed(Datasource::Coinbase, FeedState::Connected, now);
+        assert!(!monitor.health(&book, now).ready);
+        monitor.feed(Datasource::Alpaca, FeedState::Connected, now + Duration::seconds(1));
+
+        for symbol in [SymbolCommon::EthUsd, SymbolCommon::BtcUsd] {
+            book.push_log(Datasource::Coinbase, &TickerCommon { source: Datasource::Coinbase, symbol: symbol.clone(), price: 1.0, dtg: now, trade_id: None }).unwrap();
+            monitor.tick(&Datasource::Coinbase, &symbol, now);
+        }
+        let health = monitor.health(&book, now + Duration::milliseconds(2500));
+        assert!(health.ready);
+        let feeds: Vec<Datasource> = health.feeds.iter().map(|x| x.source.clone()).collect();
+        assert_eq!(feeds, vec![Datasource::Coinbase, Datasource::Alpaca]);
+        assert_eq!(health.symbols[0].symbol, SymbolCommon::BtcUsd);
+        assert_eq!(health.symbols[1].age_secs, 2.5);
+        assert_eq!(health.buffers.len(), 1);
+        assert_eq!(health.buffers[0].len, 2);
+        assert!(health.buffers[0].fill > 0.0 && health.buffers[0].fill <= 1.0);
+
+        monitor.feed(Datasource::Coinbase, FeedState::Disconnected, now);
+        assert!(!monitor.health(&book, now).ready);
+    }
+}
@@ -1,6 +1,16 @@
 //! db/lib.rs
 
+pub mod alert;
 pub mod arrow_db;
+pub mod backfill;
+pub mod backtest;
+pub mod dedup;
 pub mod event_log;
 pub mod event_book;
+pub mod health;
+pub mod log_table;
+pub mod measurement;
+pub mod paper;
+pub mod sql;
+pub mod view;
 mod calculation;
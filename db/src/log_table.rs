@@ -0,0 +1,153 @@
+//! log_table.rs
+//!
+//! the ticks and calcs tables as DataFusion TableProviders over the live book. A scan binary
+//! searches each EventLog for the dtg bounds in the query's filters and copies only those rows,
+//! so a time range costs O(log n + k) rather than a copy of the whole log.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::logical_expr::{Between, BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::ExecutionPlan;
+use common_lib::cb_ticker::Datasource;
+use common_lib::point::COLUMN_DTG;
+use crate::event_book::{calcs_batch, ticks_batch, EventBook};
+use crate::event_log::{EventLog, TimeRange};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogKind {
+    Ticks,
+    Calcs,
+}
+
+pub struct LogTable {
+    book: Arc<RwLock<HashMap<Datasource, EventLog>>>,
+    kind: LogKind,
+    schema: SchemaRef,
+}
+
+impl LogTable {
+    pub fn new(book: Arc<RwLock<HashMap<Datasource, EventLog>>>, kind: LogKind) -> LogTable {
+        let schema = match kind {
+            LogKind::Ticks => EventBook::ticks_measurement().arrow_schema(),
+            LogKind::Calcs => EventBook::calcs_measurement().arrow_schema(),
+        };
+        LogTable { book, kind, schema }
+    }
+}
+
+#[async_trait]
+impl TableProvider for LogTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    /// the scan only narrows by time, so datafusion still applies the filter to what comes back
+    fn supports_filters_pushdown(&self, filters: &[&Expr]) -> Result<Vec<TableProviderFilterPushDown>> {
+        Ok(filters.iter()
+            .map(|filter| match time_range(filter) {
+                Some(_) => TableProviderFilterPushDown::Inexact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(&self, _state: &SessionState, projection: Option<&Vec<usize>>, filters: &[Expr], _limit: Option<usize>) -> Result<Arc<dyn ExecutionPlan>> {
+        let range = filters.iter().filter_map(time_range).fold(TimeRange::default(), TimeRange::and);
+        let batch = {
+            let book = self.book.read().unwrap();
+            match self.kind {
+                LogKind::Ticks => ticks_batch(&book, &range),
+                LogKind::Calcs => calcs_batch(&book, &range),
+            }
+        }.map_err(DataFusionError::ArrowError)?;
+        Ok(Arc::new(MemoryExec::try_new(&[vec![batch]], self.schema.clone(), projection.cloned())?))
+    }
+}
+
+/// the event time bounds a filter such as `dtg > '2024-01-14T23:36:00Z'` puts on a scan
+pub fn time_range(filter: &Expr) -> Option<TimeRange> {
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(c), Expr::Literal(v)) if c.name == COLUMN_DTG => bound(*op, timestamp(v)?),
+            (Expr::Literal(v), Expr::Column(c)) if c.name == COLUMN_DTG => bound(op.swap()?, timestamp(v)?),
+            _ => None,
+        },
+        Expr::Between(Between { expr, negated: false, low, high }) => match (expr.as_ref(), low.as_ref(), high.as_ref()) {
+            (Expr::Column(c), Expr::Literal(low), Expr::Literal(high)) if c.name == COLUMN_DTG => {
+                Some(TimeRange { start: Bound::Included(timestamp(low)?), end: Bound::Included(timestamp(high)?) })
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `dtg <op> t`
+fn bound(op: Operator, t: DateTime<Utc>) -> Option<TimeRange> {
+    let all = TimeRange::default();
+    match op {
+        Operator::Gt => Some(TimeRange { start: Bound::Excluded(t), ..all }),
+        Operator::GtEq => Some(TimeRange { start: Bound::Included(t), ..all }),
+        Operator::Lt => Some(TimeRange { end: Bound::Excluded(t), ..all }),
+        Operator::LtEq => Some(TimeRange { end: Bound::Included(t), ..all }),
+        Operator::Eq => Some(TimeRange { start: Bound::Included(t), end: Bound::Included(t) }),
+        _ => None,
+    }
+}
+
+/// a timestamp literal in any unit; the timezone doesn't change the instant
+fn timestamp(v: &ScalarValue) -> Option<DateTime<Utc>> {
+    let nanos = match v {
+        ScalarValue::TimestampNanosecond(Some(t), _) => *t,
+        ScalarValue::TimestampMicrosecond(Some(t), _) => t.checked_mul(1_000)?,
+        ScalarValue::TimestampMillisecond(Some(t), _) => t.checked_mul(1_000_000)?,
+        ScalarValue::TimestampSecond(Some(t), _) => t.checked_mul(1_000_000_000)?,
+        _ => return None,
+    };
+    Some(Utc.timestamp_nanos(nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+    use chrono::{DateTime, Utc};
+    use datafusion::logical_expr::{col, lit};
+    use datafusion::scalar::ScalarValue;
+    use crate::event_log::TimeRange;
+    use crate::log_table::time_range;
+
+    #[test]
+    fn test_time_range() {
+        let t = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:36:23.177990080Z").unwrap());
+        let nanos = lit(ScalarValue::TimestampNanosecond(Some(t.timestamp_nanos_opt().unwrap()), Some("UTC".into())));
+        let millis = lit(ScalarValue::TimestampMillisecond(Some(t.timestamp_millis()), None));
+
+        assert_eq!(time_range(&col("dtg").gt(nanos.clone())), Some(TimeRange { start: Bound::Excluded(t), end: Bound::Unbounded }));
+        assert_eq!(time_range(&nanos.clone().gt(col("dtg"))), Some(TimeRange { start: Bound::Unbounded, end: Bound::Excluded(t) }));
+        assert_eq!(time_range(&col("dtg").between(millis.clone(), nanos.clone())).map(|r| r.end), Some(Bound::Included(t)));
+        assert!(time_range(&col("price").gt(lit(1.0))).is_none());
+        assert!(time_range(&col("dtg").not_eq(nanos.clone())).is_none());
+
+        // two bounds on the same side keep the tighter one
+        let range = [col("dtg").gt_eq(millis), col("dtg").gt(nanos)].iter().filter_map(time_range).fold(TimeRange::default(), TimeRange::and);
+        assert_eq!(range.start, Bound::Excluded(t));
+    }
+}
@@ -0,0 +1,275 @@
+//! measurement.rs
+//!
+//! one log per measurement, and the one place rows become arrow batches: any table the book hands
+//! out (ticks, calcs, line protocol measurements) is a MeasurementSchema plus rows that can answer
+//! for its tags and fields.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{
+    ArrayRef, BooleanArray, Date64Array, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray,
+    TimestampMillisecondArray, TimestampNanosecondArray, TimestampSecondArray, UInt64Array,
+};
+use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use common_lib::point::{FieldType, FieldValue, MeasurementSchema, Point, COLUMN_DTG};
+use common_lib::{ChartDataset, ChartTimeSeries};
+
+/// points kept per measurement; the oldest are dropped first
+pub const MEASUREMENT_CAPACITY: usize = 100_000;
+
+/// anything stored as a row of a measurement table
+pub trait Row {
+    fn dtg(&self) -> DateTime<Utc>;
+    fn tag(&self, key: &str) -> Option<String>;
+    fn field(&self, key: &str) -> Option<FieldValue>;
+}
+
+impl<T: Row> Row for &T {
+    fn dtg(&self) -> DateTime<Utc> {
+        (*self).dtg()
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        (*self).tag(key)
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        (*self).field(key)
+    }
+}
+
+impl Row for Point {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        self.tags.get(key).cloned()
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        self.fields.get(key).cloned()
+    }
+}
+
+/// rows in order, shaped by `schema`; a tag or field a row doesn't have, or has with another type, is null
+pub fn record_batch<R: Row>(schema: &MeasurementSchema, rows: &[R]) -> Result<RecordBatch, ArrowError> {
+    let mut columns = vec![time_column(&schema.time, rows)?];
+    for tag in schema.tags.iter() {
+        columns.push(Arc::new(rows.iter().map(|r| r.tag(tag)).collect::<StringArray>()));
+    }
+    for (key, field_type) in schema.fields.iter() {
+        let values = rows.iter().map(|r| r.field(key));
+        let column: ArrayRef = match field_type {
+            FieldType::Float => Arc::new(values.map(|v| match v { Some(FieldValue::Float(x)) => Some(x), _ => None }).collect::<Float64Array>()),
+            FieldType::Integer => Arc::new(values.map(|v| match v { Some(FieldValue::Integer(x)) => Some(x), _ => None }).collect::<Int64Array>()),
+            FieldType::UInteger => Arc::new(values.map(|v| match v { Some(FieldValue::UInteger(x)) => Some(x), _ => None }).collect::<UInt64Array>()),
+            FieldType::Boolean => Arc::new(values.map(|v| match v { Some(FieldValue::Boolean(x)) => Some(x), _ => None }).collect::<BooleanArray>()),
+            FieldType::String => Arc::new(values.map(|v| match v { Some(FieldValue::String(x)) => Some(x), _ => None }).collect::<StringArray>()),
+        };
+        columns.push(column);
+    }
+    RecordBatch::try_new(schema.arrow_schema(), columns)
+}
+
+fn time_column<R: Row>(data_type: &DataType, rows: &[R]) -> Result<ArrayRef, ArrowError> {
+    let dtg = rows.iter().map(Row::dtg);
+    Ok(match data_type {
+        DataType::Date64 => Arc::new(Date64Array::from(dtg.map(|d| d.timestamp_millis()).collect::<Vec<i64>>())),
+        DataType::Timestamp(TimeUnit::Second, tz) => Arc::new(TimestampSecondArray::from(dtg.map(|d| d.timestamp()).collect::<Vec<i64>>()).with_timezone_opt(tz.clone())),
+        DataType::Timestamp(TimeUnit::Millisecond, tz) => Arc::new(TimestampMillisecondArray::from(dtg.map(|d| d.timestamp_millis()).collect::<Vec<i64>>()).with_timezone_opt(tz.clone())),
+        DataType::Timestamp(TimeUnit::Microsecond, tz) => Arc::new(TimestampMicrosecondArray::from(dtg.map(|d| d.timestamp_micros()).collect::<Vec<i64>>()).with_timezone_opt(tz.clone())),
+        DataType::Timestamp(TimeUnit::Nanosecond, tz) => {
+            let nanos = dtg.map(|d| d.timestamp_nanos_opt().ok_or_else(|| ArrowError::ComputeError(format!("{d} out of range"))))
+                .collect::<Result<Vec<i64>, ArrowError>>()?;
+            Arc::new(TimestampNanosecondArray::from(nanos).with_timezone_opt(tz.clone()))
+        }
+        other => return Err(ArrowError::SchemaError(format!("{COLUMN_DTG} can't be {other}"))),
+    })
+}
+
+/// a line protocol measurement. A declared one only takes the tags and fields it was declared
+/// with; otherwise columns are added as they're written, in name order, and a field keeps the type
+/// it was first written with.
+pub struct Measurement {
+    schema: MeasurementSchema,
+    declared: bool,
+    points: VecDeque<Point>,
+}
+
+impl Measurement {
+    pub fn new(name: &str) -> Measurement {
+        Measurement { schema: MeasurementSchema::new(name), declared: false, points: VecDeque::new() }
+    }
+
+    pub fn declared(schema: MeasurementSchema) -> Measurement {
+        Measurement { schema, declared: true, points: VecDeque::new() }
+    }
+
+    pub fn schema(&self) -> &MeasurementSchema {
+        &self.schema
+    }
+
+    pub fn is_declared(&self) -> bool {
+        self.declared
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// the reason `point` can't be stored here, if any
+    pub fn conflict(&self, point: &Point) -> Option<String> {
+        let name = &self.schema.name;
+        for key in point.tags.keys().chain(point.fields.keys()) {
+            if key == COLUMN_DTG {
+                return Some(format!("{COLUMN_DTG} is reserved in {name}"));
+            }
+        }
+        for key in point.tags.keys() {
+            if point.fields.contains_key(key) || self.schema.field_type(key).is_some() {
+                return Some(format!("{key} is both a tag and a field in {name}"));
+            }
+            if self.declared && !self.schema.has_tag(key) {
+                return Some(format!("{name} has no tag {key}"));
+            }
+        }
+        for (key, val) in point.fields.iter() {
+            match self.schema.field_type(key) {
+                Some(field_type) if field_type != val.field_type() => {
+                    return Some(format!("field {key} in {name} is {field_type}, not {}", val.field_type()));
+                }
+                None if self.declared => return Some(format!("{name} has no field {key}")),
+                None if self.schema.has_tag(key) => return Some(format!("{key} is both a tag and a field in {name}")),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// an empty measurement with the same columns, to check a batch against before storing any of it
+    pub fn columns(&self) -> Measurement {
+        Measurement { schema: self.schema.clone(), declared: self.declared, points: VecDeque::new() }
+    }
+
+    /// add any new tags and fields to an undeclared measurement's columns
+    pub fn declare(&mut self, point: &Point) {
+        if self.declared {
+            return;
+        }
+        for key in point.tags.keys() {
+            if !self.schema.has_tag(key) {
+                self.schema.tags.push(key.clone());
+            }
+        }
+        for (key, val) in point.fields.iter() {
+            if self.schema.field_type(key).is_none() {
+                self.schema.fields.push((key.clone(), val.field_type()));
+            }
+        }
+        self.schema.tags.sort();
+        self.schema.fields.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    /// callers check `conflict` first
+    pub fn push(&mut self, point: Point) {
+        self.declare(&point);
+        if self.points.len() == MEASUREMENT_CAPACITY {
+            self.points.pop_front();
+        }
+        self.points.push_back(point);
+    }
+
+    /// every point, oldest first
+    pub fn record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let rows: Vec<&Point> = self.points.iter().collect();
+        record_batch(&self.schema, &rows)
+    }
+
+    /// one dataset per series and numeric field, newest first, like EventLog::chart_in
+    pub fn chart_since(&self, since: Option<DateTime<Utc>>, limit: usize) -> Vec<ChartDataset> {
+        let mut series: BTreeMap<String, Vec<ChartTimeSeries>> = BTreeMap::new();
+        for point in self.points.iter().rev().filter(|p| since.is_none_or(|since| p.dtg > since)) {
+            for (key, val) in point.fields.iter() {
+                let Some(y) = val.as_f64() else { continue };
+                let data = series.entry(point.label(key)).or_default();
+                if data.len() < limit {
+                    data.push(ChartTimeSeries { x: point.dtg, y });
+                }
+            }
+        }
+        series.into_iter().map(|(label, data)| ChartDataset { label, data }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use chrono::{DateTime, Utc};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use common_lib::point::{FieldType, FieldValue, MeasurementSchema, Point};
+    use crate::measurement::Measurement;
+
+    fn point(tags: &[(&str, &str)], fields: Vec<(&str, FieldValue)>, dtg: &str) -> Point {
+        Point {
+            measurement: "fills".to_string(),
+            tags: tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            fields: fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect::<BTreeMap<_, _>>(),
+            dtg: DateTime::<Utc>::from(DateTime::parse_from_rfc3339(dtg).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_measurement() {
+        let mut m = Measurement::new("fills");
+        m.push(point(&[("symbol", "btc_usd")], vec![("price", FieldValue::Float(42000.5)), ("qty", FieldValue::Integer(2))], "2024-01-14T23:30:00.000000001Z"));
+        m.push(point(&[("side", "buy"), ("symbol", "eth_usd")], vec![("price", FieldValue::Float(2500.0)), ("note", FieldValue::String("late".to_string()))], "2024-01-14T23:30:01Z"));
+
+        let expected = "+--------------------------------+------+---------+------+---------+-----+
+| dtg                            | side | symbol  | note | price   | qty |
++--------------------------------+------+---------+------+---------+-----+
+| 2024-01-14T23:30:00.000000001Z |      | btc_usd |      | 42000.5 | 2   |
+| 2024-01-14T23:30:01Z           | buy  | eth_usd | late | 2500.0  |     |
++--------------------------------+------+---------+------+---------+-----+";
+        assert_eq!(pretty_format_batches(&[m.record_batch().unwrap()]).unwrap().to_string(), expected);
+
+        assert!(m.conflict(&point(&[], vec![("qty", FieldValue::Float(1.0))], "2024-01-14T23:30:02Z")).is_some());
+        assert!(m.conflict(&point(&[("price", "x")], vec![("qty", FieldValue::Integer(1))], "2024-01-14T23:30:02Z")).is_some());
+        assert!(m.conflict(&point(&[], vec![("symbol", FieldValue::Integer(1))], "2024-01-14T23:30:02Z")).is_some());
+        assert!(m.conflict(&point(&[("dtg", "x")], vec![("qty", FieldValue::Integer(1))], "2024-01-14T23:30:02Z")).is_some());
+        assert!(m.conflict(&point(&[("venue", "x")], vec![("qty", FieldValue::Integer(1))], "2024-01-14T23:30:02Z")).is_none());
+
+        let charts = m.chart_since(None, 10);
+        let labels: Vec<&str> = charts.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["fills,side=buy,symbol=eth_usd_price", "fills,symbol=btc_usd_price", "fills,symbol=btc_usd_qty"]);
+        assert_eq!(charts[2].data[0].y, 2.0);
+        let since = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00.5Z").unwrap());
+        assert_eq!(m.chart_since(Some(since), 10).len(), 1);
+    }
+
+    #[test]
+    fn test_declared() {
+        let schema = MeasurementSchema::new("fills").tag("symbol").tag("venue").field("price", FieldType::Float).field("qty", FieldType::Integer);
+        let mut m = Measurement::declared(schema);
+        assert!(m.conflict(&point(&[("symbol", "btc_usd")], vec![("price", FieldValue::Float(1.0))], "2024-01-14T23:30:00Z")).is_none());
+        assert!(m.conflict(&point(&[("side", "buy")], vec![("price", FieldValue::Float(1.0))], "2024-01-14T23:30:00Z")).is_some());
+        assert!(m.conflict(&point(&[], vec![("fee", FieldValue::Float(1.0))], "2024-01-14T23:30:00Z")).is_some());
+        assert!(m.conflict(&point(&[], vec![("qty", FieldValue::Float(1.0))], "2024-01-14T23:30:00Z")).is_some());
+
+        // declared columns keep their order and show up before anything is written
+        m.push(point(&[("venue", "xnas")], vec![("qty", FieldValue::Integer(5))], "2024-01-14T23:30:00Z"));
+        let expected = "+----------------------+--------+-------+-------+-----+
+| dtg                  | symbol | venue | price | qty |
++----------------------+--------+-------+-------+-----+
+| 2024-01-14T23:30:00Z |        | xnas  |       | 5   |
++----------------------+--------+-------+-------+-----+";
+        assert_eq!(pretty_format_batches(&[m.record_batch().unwrap()]).unwrap().to_string(), expected);
+    }
+}
@@ -0,0 +1,499 @@
+//! paper.rs
+//!
+//! the paper trading strategies (see common_lib::paper), run by the db thread on each live ticker
+//! and on backfilled ones, so a replay trades the way live would have. Each strategy's Trader
+//! orders from the tickers (by default its signal: a change of side orders the way to the new
+//! position), the order fills at the tick's price, worse by the slippage, and pays the fee on the
+//! notional; every tick then marks it to market. Marks and fills are kept in the
+//! event book as the paper and paper_fills tables and charted as "paper_<strategy>_<field>".
+//!
+
+use std::collections::VecDeque;
+use chrono::{DateTime, Utc};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use strum::IntoEnumIterator;
+use strum_macros::Display;
+use common_lib::cb_ticker::{Datasource, TickerCalc};
+use common_lib::paper::{Account, PaperField, Signal, Strategy};
+use common_lib::point::{FieldType, FieldValue, MeasurementSchema};
+use common_lib::view::ViewSeries;
+use common_lib::{ChartDataset, ChartTimeSeries, SymbolCommon, TickerCommon};
+use crate::event_book::{TABLE_PAPER, TABLE_PAPER_FILLS};
+use crate::measurement::{record_batch, Row, MEASUREMENT_CAPACITY};
+
+const BPS: f64 = 10_000.0;
+
+/// every strategy's account; owned by the db thread
+#[derive(Default)]
+pub struct PaperEngine {
+    strategies: Vec<StrategyState>,
+}
+
+struct StrategyState {
+    strategy: Strategy,
+    trader: Box<dyn Trader>,
+    account: Account,
+    peak: f64,
+    last_mark: Option<Mark>,
+}
+
+/// what a strategy wants filled at the tick it saw; quantity is above 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Order {
+    pub side: OrderSide,
+    pub quantity: f64,
+}
+
+/// decides a strategy's orders from each ticker of its source and symbol; the engine fills them
+/// and keeps the account. Strategies from json follow their signal (SignalTrader), a backtest can
+/// bring its own
+pub trait Trader: Send {
+    /// position is the account's before this ticker
+    fn on_tick(&mut self, position: f64, ticker: &TickerCommon, calcs: &[TickerCalc]) -> Option<Order>;
+}
+
+/// a strategy's Signal as a Trader
+pub struct SignalTrader {
+    signal: Signal,
+    quantity: f64,
+    short: bool,
+    /// which way the signal points, None before its first value
+    side: Option<Side>,
+    /// a crossover's newest fast and slow values
+    fast: Option<f64>,
+    slow: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+/// a strategy's account at one tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mark {
+    pub strategy: String,
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    pub dtg: DateTime<Utc>,
+    pub position: f64,
+    pub equity: f64,
+    pub pnl: f64,
+    pub drawdown: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub strategy: String,
+    pub source: Datasource,
+    pub symbol: SymbolCommon,
+    pub dtg: DateTime<Utc>,
+    pub side: OrderSide,
+    pub quantity: f64,
+    pub price: f64,
+    pub fee: f64,
+    /// after the fill
+    pub position: f64,
+}
+
+/// what one ticker did to the accounts
+#[derive(Debug, Default)]
+pub struct PaperEvents {
+    pub marks: Vec<Mark>,
+    pub fills: Vec<Fill>,
+}
+
+impl PaperEvents {
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty() && self.fills.is_empty()
+    }
+}
+
+impl PaperEngine {
+    pub fn new() -> PaperEngine {
+        PaperEngine { strategies: vec![] }
+    }
+
+    /// replace every strategy, each following its signal; each starts flat with its cash
+    pub fn set_strategies(&mut self, strategies: Vec<Strategy>) {
+        self.strategies = strategies.into_iter().map(|x| StrategyState::new(Box::new(SignalTrader::new(&x)), x)).collect();
+    }
+
+    /// add a strategy traded by trader instead of its signal; its source, symbol, costs and cash
+    /// still apply
+    pub fn push(&mut self, strategy: Strategy, trader: Box<dyn Trader>) {
+        self.strategies.push(StrategyState::new(trader, strategy));
+    }
+
+    pub fn accounts(&self) -> Vec<Account> {
+        self.strategies.iter().map(|x| x.account.clone()).collect()
+    }
+
+    /// trade and mark every strategy on the ticker's source and symbol
+    pub fn apply(&mut self, source: &Datasource, ticker: &TickerCommon, calcs: &[TickerCalc]) -> PaperEvents {
+        let mut events = PaperEvents::default();
+        for state in self.strategies.iter_mut().filter(|x| x.strategy.source == *source && x.strategy.symbol == ticker.symbol) {
+            let order = state.trader.on_tick(state.account.position, ticker, calcs);
+            if let Some(fill) = order.and_then(|order| state.fill(ticker, order)) {
+                events.fills.push(fill);
+            }
+            if let Some(mark) = state.mark(ticker) {
+                events.marks.push(mark);
+            }
+        }
+        events
+    }
+}
+
+impl SignalTrader {
+    pub fn new(strategy: &Strategy) -> SignalTrader {
+        SignalTrader { signal: strategy.signal.clone(), quantity: strategy.quantity, short: strategy.short, side: None, fast: None, slow: None }
+    }
+}
+
+impl Trader for SignalTrader {
+    /// follow the signal; a change of side orders the difference to the new target position
+    fn on_tick(&mut self, position: f64, ticker: &TickerCommon, calcs: &[TickerCalc]) -> Option<Order> {
+        let value = |series: &ViewSeries| match series {
+            ViewSeries::Price => Some(ticker.price),
+            ViewSeries::Calc(calc_id) => calcs.iter().find(|c| c.symbol == ticker.symbol && c.calc_id == *calc_id).map(|c| c.val),
+        };
+        let distance = match &self.signal {
+            Signal::Sign { series } => value(series)?,
+            Signal::Crossover { fast, slow } => {
+                self.fast = value(fast).or(self.fast);
+                self.slow = value(slow).or(self.slow);
+                self.fast? - self.slow?
+            }
+        };
+        let side = match distance {
+            d if d > 0.0 => Side::Up,
+            d if d < 0.0 => Side::Down,
+            _ => return None,
+        };
+        match self.side.replace(side) {
+            Some(previous) if previous != side => {}
+            _ => return None,
+        }
+        let target = match side {
+            Side::Up => self.quantity,
+            Side::Down if self.short => -self.quantity,
+            Side::Down => 0.0,
+        };
+        match target - position {
+            q if q > 0.0 => Some(Order { side: OrderSide::Buy, quantity: q }),
+            q if q < 0.0 => Some(Order { side: OrderSide::Sell, quantity: -q }),
+            _ => None,
+        }
+    }
+}
+
+impl StrategyState {
+    fn new(trader: Box<dyn Trader>, strategy: Strategy) -> StrategyState {
+        let account = Account {
+            strategy: strategy.name.clone(),
+            source: strategy.source.clone(),
+            symbol: strategy.symbol.clone(),
+            dtg: None,
+            price: None,
+            position: 0.0,
+            cash: strategy.cash,
+            equity: strategy.cash,
+            pnl: 0.0,
+            drawdown: 0.0,
+            max_drawdown: 0.0,
+            fees: 0.0,
+            trades: 0,
+        };
+        StrategyState { peak: strategy.cash, strategy, trader, account, last_mark: None }
+    }
+
+    /// fill the order at the tick's price, worse by the slippage, less the fee
+    fn fill(&mut self, ticker: &TickerCommon, order: Order) -> Option<Fill> {
+        if !order.quantity.is_finite() || order.quantity <= 0.0 {
+            tracing::warn!("[PaperEngine] {}: order of {} not filled", &self.strategy.name, order.quantity);
+            return None;
+        }
+        let (quantity, slippage) = match order.side {
+            OrderSide::Buy => (order.quantity, self.strategy.slippage_bps / BPS),
+            OrderSide::Sell => (-order.quantity, -self.strategy.slippage_bps / BPS),
+        };
+        let price = ticker.price * (1.0 + slippage);
+        let fee = order.quantity * price * self.strategy.fee_bps / BPS;
+        self.account.cash -= quantity * price + fee;
+        self.account.position += quantity;
+        self.account.fees += fee;
+        self.account.trades += 1;
+        tracing::info!("[PaperEngine] {}: {} {} {} at {price} (fee {fee})", &self.strategy.name, order.side, order.quantity, &self.strategy.symbol);
+        Some(Fill {
+            strategy: self.strategy.name.clone(),
+            source: self.strategy.source.clone(),
+            symbol: self.strategy.symbol.clone(),
+            dtg: ticker.dtg,
+            side: order.side,
+            quantity: order.quantity,
+            price,
+            fee,
+            position: self.account.position,
+        })
+    }
+
+    /// value the account at the tick's price; returns a mark when anything charted has changed
+    fn mark(&mut self, ticker: &TickerCommon) -> Option<Mark> {
+        let account = &mut self.account;
+        account.dtg = Some(ticker.dtg);
+        account.price = Some(ticker.price);
+        account.equity = account.cash + account.position * ticker.price;
+        account.pnl = account.equity - self.strategy.cash;
+        self.peak = self.peak.max(account.equity);
+        account.drawdown = self.peak - account.equity;
+        account.max_drawdown = account.max_drawdown.max(account.drawdown);
+
+        let mark = Mark {
+            strategy: self.strategy.name.clone(),
+            source: self.strategy.source.clone(),
+            symbol: self.strategy.symbol.clone(),
+            dtg: ticker.dtg,
+            position: account.position,
+            equity: account.equity,
+            pnl: account.pnl,
+            drawdown: account.drawdown,
+        };
+        match &self.last_mark {
+            Some(last) if last.values() == mark.values() => None,
+            _ => {
+                self.last_mark = Some(mark.clone());
+                Some(mark)
+            }
+        }
+    }
+}
+
+impl Mark {
+    pub fn values(&self) -> [(PaperField, f64); 4] {
+        [(PaperField::Position, self.position), (PaperField::Equity, self.equity), (PaperField::Pnl, self.pnl), (PaperField::Drawdown, self.drawdown)]
+    }
+}
+
+/// marks and fills, newest last, each bounded like a measurement; kept in the event book
+#[derive(Default)]
+pub struct PaperLog {
+    marks: VecDeque<Mark>,
+    fills: VecDeque<Fill>,
+}
+
+impl PaperLog {
+    pub fn push(&mut self, events: &PaperEvents) {
+        self.marks.extend(events.marks.iter().cloned());
+        self.fills.extend(events.fills.iter().cloned());
+        while self.marks.len() > MEASUREMENT_CAPACITY {
+            self.marks.pop_front();
+        }
+        while self.fills.len() > MEASUREMENT_CAPACITY {
+            self.fills.pop_front();
+        }
+    }
+
+    /// oldest first
+    pub fn marks(&self) -> impl Iterator<Item = &Mark> {
+        self.marks.iter()
+    }
+
+    /// one dataset per strategy and field, newest first like the other charts; no strategies means all
+    pub fn chart_since(&self, strategies: &[String], since: Option<DateTime<Utc>>, limit: usize) -> Vec<ChartDataset> {
+        let mut datasets: Vec<ChartDataset> = vec![];
+        for mark in self.marks.iter().rev().filter(|x| strategies.is_empty() || strategies.contains(&x.strategy)) {
+            if since.is_some_and(|since| mark.dtg <= since) {
+                continue;
+            }
+            for (field, y) in mark.values() {
+                let label = field.label(&mark.strategy);
+                let index = match datasets.iter().position(|x| x.label == label) {
+                    Some(index) => index,
+                    None => {
+                        datasets.push(ChartDataset { label, data: vec![] });
+                        datasets.len() - 1
+                    }
+                };
+                if datasets[index].data.len() < limit {
+                    datasets[index].data.push(ChartTimeSeries { x: mark.dtg, y });
+                }
+            }
+        }
+        datasets.sort_by(|a, b| a.label.cmp(&b.label));
+        datasets
+    }
+
+    pub fn marks_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let rows: Vec<&Mark> = self.marks.iter().collect();
+        record_batch(&PaperLog::marks_measurement(), &rows)
+    }
+
+    pub fn fills_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let rows: Vec<&Fill> = self.fills.iter().collect();
+        record_batch(&PaperLog::fills_measurement(), &rows)
+    }
+
+    pub fn marks_measurement() -> MeasurementSchema {
+        PaperField::iter().fold(
+            MeasurementSchema::new(TABLE_PAPER).tag("strategy").tag("source").tag("symbol"),
+            |schema, field| schema.field(&field.to_string(), FieldType::Float),
+        )
+    }
+
+    pub fn fills_measurement() -> MeasurementSchema {
+        MeasurementSchema::new(TABLE_PAPER_FILLS)
+            .tag("strategy")
+            .tag("source")
+            .tag("symbol")
+            .tag("side")
+            .field("quantity", FieldType::Float)
+            .field("price", FieldType::Float)
+            .field("fee", FieldType::Float)
+            .field("position", FieldType::Float)
+    }
+}
+
+impl Row for Mark {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "strategy" => Some(self.strategy.clone()),
+            "source" => Some(self.source.to_string().to_lowercase()),
+            "symbol" => Some(self.symbol.to_string()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        self.values().into_iter().find(|(field, _)| field.to_string() == key).map(|(_, x)| FieldValue::Float(x))
+    }
+}
+
+impl Row for Fill {
+    fn dtg(&self) -> DateTime<Utc> {
+        self.dtg
+    }
+
+    fn tag(&self, key: &str) -> Option<String> {
+        match key {
+            "strategy" => Some(self.strategy.clone()),
+            "source" => Some(self.source.to_string().to_lowercase()),
+            "symbol" => Some(self.symbol.to_string()),
+            "side" => Some(self.side.to_string()),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<FieldValue> {
+        match key {
+            "quantity" => Some(FieldValue::Float(self.quantity)),
+            "price" => Some(FieldValue::Float(self.price)),
+            "fee" => Some(FieldValue::Float(self.fee)),
+            "position" => Some(FieldValue::Float(self.position)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use common_lib::cb_ticker::{Datasource, TickerCalc};
+    use common_lib::paper::{Signal, Strategy};
+    use common_lib::view::ViewSeries;
+    use common_lib::{CalculationId, SymbolCommon, TickerCommon};
+    use crate::arrow_db::{insert, Inserted};
+    use crate::event_book::EventBook;
+    use crate::paper::{OrderSide, PaperEngine, PaperLog};
+
+    fn start() -> DateTime<Utc> {
+        DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap())
+    }
+
+    fn strategy(signal: Signal) -> Strategy {
+        Strategy { name: "ma_turn".to_string(), source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, signal, quantity: 2.0, short: true, slippage_bps: 10.0, fee_bps: 10.0, cash: 1000.0 }
+    }
+
+    #[test]
+    fn test_sign_trades() {
+        let mut engine = PaperEngine::new();
+        engine.set_strategies(vec![strategy(Signal::Sign { series: ViewSeries::Calc(CalculationId::MovAvgDiff0100_1000) })]);
+        let mut log = PaperLog::default();
+        // the first signal only sets the side; down goes short 2, up buys 4 to be long 2
+        for (i, (price, signal)) in [(100.0, 1.0), (100.0, -1.0), (110.0, -1.0), (90.0, 1.0), (90.0, 1.0)].into_iter().enumerate() {
+            let dtg = start() + Duration::seconds(i as i64);
+            let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price, dtg, trade_id: None };
+            let calc = TickerCalc { dtg, symbol: SymbolCommon::BtcUsd, calc_id: CalculationId::MovAvgDiff0100_1000, val: signal };
+            log.push(&engine.apply(&Datasource::Coinbase, &ticker, &[calc]));
+        }
+        let fills: Vec<(OrderSide, f64, f64)> = log.fills.iter().map(|x| (x.side, x.quantity, x.price)).collect();
+        assert_eq!(fills.len(), 2);
+        assert_eq!((fills[0].0, fills[0].1), (OrderSide::Sell, 2.0));
+        assert!((fills[0].2 - 99.9).abs() < 1e-9);
+        assert_eq!((fills[1].0, fills[1].1), (OrderSide::Buy, 4.0));
+        assert!((fills[1].2 - 90.09).abs() < 1e-9);
+
+        let account = &engine.accounts()[0];
+        assert_eq!((account.position, account.trades), (2.0, 2));
+        assert!((account.fees - (0.1998 + 0.36036)).abs() < 1e-9);
+        // short 2 from 99.9 marked at 110, less the first fee
+        assert!((account.max_drawdown - 20.3998).abs() < 1e-9);
+        assert_eq!(account.drawdown, 0.0);
+        // the short covered at 90.09, and the long marked at 90
+        assert!((account.equity - (1000.0 + 2.0 * (99.9 - 90.09) + 2.0 * (90.0 - 90.09) - account.fees)).abs() < 1e-9);
+        // nothing changed on the last tick
+        assert_eq!(log.marks.len(), 4);
+
+        // long only: down just goes flat, so there's nothing to sell first
+        engine.set_strategies(vec![Strategy { short: false, ..strategy(Signal::Sign { series: ViewSeries::Calc(CalculationId::MovAvgDiff0100_1000) }) }]);
+        let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 100.0, dtg: start(), trade_id: None };
+        for signal in [-1.0, 1.0, -1.0] {
+            engine.apply(&Datasource::Coinbase, &ticker, &[TickerCalc { dtg: start(), symbol: SymbolCommon::BtcUsd, calc_id: CalculationId::MovAvgDiff0100_1000, val: signal }]);
+        }
+        assert_eq!((engine.accounts()[0].position, engine.accounts()[0].trades), (0.0, 2));
+    }
+
+    /// a ramp down, up and down again through the db's insert path turns the averages twice
+    #[test]
+    fn test_crossover_replay() {
+        let book = EventBook::new();
+        book.set_indicators(vec![CalculationId::MovingAvg0010, CalculationId::MovingAvg0100]);
+        let mut engine = PaperEngine::new();
+        engine.set_strategies(vec![Strategy { short: false, slippage_bps: 0.0, fee_bps: 0.0, ..strategy(Signal::Crossover { fast: ViewSeries::Calc(CalculationId::MovingAvg0010), slow: ViewSeries::Calc(CalculationId::MovingAvg0100) }) }]);
+
+        let prices = (0..200).map(|i| 1000.0 - i as f64).chain((0..200).map(|i| 800.0 + i as f64)).chain((0..200).map(|i| 1000.0 - i as f64));
+        for (i, price) in prices.enumerate() {
+            let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price, dtg: start() + Duration::seconds(i as i64), trade_id: None };
+            if let Inserted::InOrder(calcs) = insert(Datasource::Coinbase, &ticker, &book) {
+                book.paper.write().unwrap().push(&engine.apply(&Datasource::Coinbase, &ticker, &calcs));
+            }
+        }
+        let log = book.paper.read().unwrap();
+        let sides: Vec<OrderSide> = log.fills.iter().map(|x| x.side).collect();
+        assert_eq!(sides, vec![OrderSide::Buy, OrderSide::Sell]);
+        // bought after the turn up and sold after the turn down, both lagging the averages
+        assert!(engine.accounts()[0].pnl > 0.0);
+
+        let table = pretty_format_batches(&[log.fills_batch().unwrap()]).unwrap().to_string();
+        assert!(table.contains("| ma_turn  | coinbase | btc_usd | sell |"), "{table}");
+        let chart = log.chart_since(&[], None, 10);
+        let labels: Vec<&str> = chart.iter().map(|x| x.label.as_str()).collect();
+        assert_eq!(labels, vec!["paper_ma_turn_drawdown", "paper_ma_turn_equity", "paper_ma_turn_pnl", "paper_ma_turn_position"]);
+        assert_eq!(chart[0].data.len(), 10);
+        assert!(log.chart_since(&["other".to_string()], None, 10).is_empty());
+    }
+}
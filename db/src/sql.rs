@@ -0,0 +1,148 @@
+//! sql.rs
+//!
+//! the DataFusion session every sql frontend (flight, postgres) queries: the book's tables plus a
+//! view per datasource, e.g. `coinbase_ticks`, `alpaca_calcs`
+
+use crossbeam_channel::Sender;
+use datafusion::error::DataFusionError;
+use datafusion::execution::context::SQLOptions;
+use datafusion::prelude::SessionContext;
+use strum::IntoEnumIterator;
+use tokio::sync::oneshot;
+use common_lib::cb_ticker::Datasource;
+use common_lib::{DbMsg, SqlTables, UniversalError};
+use crate::event_book::{TABLE_CALCS, TABLE_TICKS};
+
+/// ask the db thread for its tables and build a session over them
+pub async fn snapshot(tx_db: &Sender<DbMsg>) -> Result<SessionContext, UniversalError> {
+    let (sender, rx) = oneshot::channel();
+    tx_db.send(DbMsg::RqstTables { sender }).map_err(|_| UniversalError::SendError)?;
+    let tables = rx.await.map_err(|_| UniversalError::RecvError)??;
+    session(tables).await.map_err(|e| UniversalError::DbError(e.to_string()))
+}
+
+/// every query runs on a throwaway session over read-only tables, so there's nothing for ddl or
+/// dml to change
+pub fn read_only() -> SQLOptions {
+    SQLOptions::new().with_allow_ddl(false).with_allow_dml(false)
+}
+
+pub async fn session(tables: SqlTables) -> Result<SessionContext, DataFusionError> {
+    let ctx = SessionContext::new();
+    for (name, table) in tables.0 {
+        ctx.register_table(name.as_str(), table)?;
+    }
+    for ds in Datasource::iter() {
+        let ds = ds.to_string().to_lowercase();
+        for table in [TABLE_TICKS, TABLE_CALCS] {
+            ctx.sql(&format!("create view {ds}_{table} as select * from {table} where source = '{ds}'")).await?;
+        }
+    }
+    Ok(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::{SymbolCommon, TickerCommon};
+    use crate::event_book::EventBook;
+    use crate::event_log::Arrival;
+    use crate::sql::{read_only, session};
+
+    #[tokio::test]
+    async fn test_source_views() {
+        let book = EventBook::new();
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        book.push_log(Datasource::Alpaca, &TickerCommon { source: Datasource::Alpaca, symbol: SymbolCommon::BtcUsd, price: 41999.0, dtg, trade_id: None }).unwrap();
+        book.push_log(Datasource::Coinbase, &TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 42000.0, dtg, trade_id: None }).unwrap();
+        book.push_log(Datasource::Coinbase, &TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 42002.0, dtg, trade_id: None }).unwrap();
+
+        let ctx = session(book.providers().unwrap()).await.unwrap();
+        let batches = ctx.sql("select avg(price) as avg from coinbase_ticks").await.unwrap().collect().await.unwrap();
+        let expected = "+---------+
+| avg     |
++---------+
+| 42001.0 |
++---------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+        let batches = ctx.sql("select count(*) from alpaca_calcs").await.unwrap().collect().await.unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        assert!(ctx.sql_with_options("create view v as select 1", read_only()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_time_functions() {
+        let book = EventBook::new();
+        for (dtg, price) in [("2024-01-14T23:36:23.177990080Z", 1.0), ("2024-01-14T23:36:23.177990090Z", 2.0), ("2024-01-14T23:36:23.177991000Z", 3.0), ("2024-01-14T23:36:41.5Z", 4.0)] {
+            let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339(dtg).unwrap());
+            book.push_log(Datasource::Alpaca, &TickerCommon { source: Datasource::Alpaca, symbol: SymbolCommon::BtcUsd, price, dtg, trade_id: None }).unwrap();
+        }
+        let ctx = session(book.providers().unwrap()).await.unwrap();
+
+        // ticks 10ns apart keep their order and can be told apart by a range filter
+        let batches = ctx.sql("select dtg, price from ticks where dtg > '2024-01-14T23:36:23.177990085Z' and dtg < '2024-01-14T23:36:24Z' order by dtg").await.unwrap().collect().await.unwrap();
+        let expected = "+--------------------------------+-------+
+| dtg                            | price |
++--------------------------------+-------+
+| 2024-01-14T23:36:23.177990090Z | 2.0   |
+| 2024-01-14T23:36:23.177991Z    | 3.0   |
++--------------------------------+-------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+
+        let batches = ctx.sql("select date_bin(interval '15 seconds', dtg) as bin, count(*) as n from ticks group by bin order by bin").await.unwrap().collect().await.unwrap();
+        let expected = "+----------------------+---+
+| bin                  | n |
++----------------------+---+
+| 2024-01-14T23:36:15Z | 3 |
+| 2024-01-14T23:36:30Z | 1 |
++----------------------+---+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+
+        let batches = ctx.sql("select date_trunc('minute', dtg) as minute, max(price) as high from ticks group by minute").await.unwrap().collect().await.unwrap();
+        let expected = "+----------------------+------+
+| minute               | high |
++----------------------+------+
+| 2024-01-14T23:36:00Z | 4.0  |
++----------------------+------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+    }
+
+    /// a feed that reconnects resends trades it already sent; only the first copy is stored
+    #[tokio::test]
+    async fn test_dedup() {
+        let book = EventBook::new();
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let trade = |source: Datasource, trade_id: u64| TickerCommon { source, symbol: SymbolCommon::BtcUsd, price: trade_id as f64, dtg, trade_id: Some(trade_id) };
+        for id in [1, 2, 3, 2, 3, 4] {
+            book.push_log(Datasource::Coinbase, &trade(Datasource::Coinbase, id)).unwrap();
+        }
+        // the same id from another venue is a different trade
+        assert_eq!(book.push_log(Datasource::Alpaca, &trade(Datasource::Alpaca, 1)).unwrap(), Arrival::InOrder);
+        assert_eq!(book.push_log(Datasource::Alpaca, &trade(Datasource::Alpaca, 1)).unwrap(), Arrival::Duplicate);
+        // ticks without an id can't be told apart, so they're all kept
+        for _ in 0..2 {
+            book.push_log(Datasource::Alpaca, &TickerCommon { trade_id: None, ..trade(Datasource::Alpaca, 5) }).unwrap();
+        }
+
+        let ctx = session(book.providers().unwrap()).await.unwrap();
+        let batches = ctx.sql("select source, count(*) as n from ticks group by source order by source").await.unwrap().collect().await.unwrap();
+        let expected = "+----------+---+
+| source   | n |
++----------+---+
+| alpaca   | 3 |
+| coinbase | 4 |
++----------+---+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+
+        let batches = ctx.sql("select * from dedup").await.unwrap().collect().await.unwrap();
+        let expected = "+----------+---------+--------+------------+
+| source   | symbol  | window | duplicates |
++----------+---------+--------+------------+
+| alpaca   | btc_usd | 1      | 1          |
+| coinbase | btc_usd | 4      | 2          |
++----------+---------+--------+------------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+    }
+}
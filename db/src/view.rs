@@ -0,0 +1,458 @@
+//! view.rs
+//!
+//! continuous views maintained by the db thread. Each insert is folded into every matching view
+//! and the changed points are pushed to the view's subscribers, so nobody has to poll and re-scan
+//! the ring buffer. A subscriber's channel is bounded; one that falls behind misses deltas and is
+//! sent a fresh snapshot once it has room again.
+//!
+
+use std::collections::{HashMap, VecDeque};
+use chrono::{DateTime, TimeZone, Utc};
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use common_lib::cb_ticker::{Datasource, TickerCalc};
+use common_lib::view::{ViewAggregate, ViewDelta, ViewSeries, ViewSpec};
+use common_lib::{ChartDataset, ChartTimeSeries, SymbolCommon, TickerCommon};
+use crate::event_book::EventBook;
+use crate::paper::Mark;
+
+/// deltas queued for a subscriber before it's counted as lagging
+pub const VIEW_QUEUE_SIZE: usize = 1024;
+
+/// every registered view; owned by the db thread
+pub struct ViewEngine {
+    views: Vec<View>,
+    queue_size: usize,
+}
+
+impl Default for ViewEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct View {
+    spec: ViewSpec,
+    subscribers: Vec<Subscriber>,
+    // keyed by chart label
+    series: HashMap<String, SeriesState>,
+}
+
+struct Subscriber {
+    tx: Sender<ViewDelta>,
+    // a delta didn't fit; the next message is a snapshot instead
+    lagged: bool,
+}
+
+#[derive(Default)]
+struct SeriesState {
+    data: VecDeque<ChartTimeSeries>,
+    window: Option<WindowState>,
+}
+
+/// running aggregate for the open window
+struct WindowState {
+    start: DateTime<Utc>,
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    last: f64,
+}
+
+impl ViewEngine {
+    pub fn new() -> ViewEngine {
+        ViewEngine { views: vec![], queue_size: VIEW_QUEUE_SIZE }
+    }
+
+    /// deltas buffered per subscriber
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size.max(1);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    /// subscribe to a view, creating it from what's already in the book if nobody else has; the
+    /// first message on the returned channel is a snapshot
+    pub fn register(&mut self, spec: ViewSpec, evt_book: &EventBook) -> Receiver<ViewDelta> {
+        let (tx, rx) = bounded(self.queue_size);
+        let index = match self.views.iter().position(|x| x.spec == spec) {
+            Some(index) => index,
+            None => {
+                let mut view = View { spec, subscribers: vec![], series: HashMap::new() };
+                view.seed(evt_book);
+                self.views.push(view);
+                self.views.len() - 1
+            }
+        };
+        let view = &mut self.views[index];
+        let _ = tx.try_send(view.snapshot());
+        view.subscribers.push(Subscriber { tx, lagged: false });
+        tracing::debug!("[ViewEngine::register] {} ({} subscribers)", &view.spec.name, view.subscribers.len());
+        rx
+    }
+
+    /// fold a new ticker and the calculations it produced into every view, then push the changes
+    pub fn apply(&mut self, source: &Datasource, ticker: &TickerCommon, calcs: &[TickerCalc]) {
+        for view in self.views.iter_mut() {
+            let mut changed: HashMap<String, Vec<ChartTimeSeries>> = HashMap::new();
+            if let Some((label, point)) = view.push(source, &ticker.symbol, ViewSeries::Price, ticker.dtg, ticker.price) {
+                changed.entry(label).or_default().push(point);
+            }
+            for calc in calcs.iter() {
+                if let Some((label, point)) = view.push(source, &calc.symbol, ViewSeries::Calc(calc.calc_id.clone()), calc.dtg, calc.val) {
+                    changed.entry(label).or_default().push(point);
+                }
+            }
+            if !changed.is_empty() {
+                let delta = ViewDelta {
+                    view: view.spec.name.clone(),
+                    snapshot: false,
+                    revision: false,
+                    datasets: changed.into_iter().map(|(label, data)| ChartDataset { label, data }).collect(),
+                };
+                view.send(delta);
+            }
+        }
+        self.views.retain(|x| !x.subscribers.is_empty());
+    }
+
+    /// fold paper strategy marks into every view of all series
+    pub fn apply_marks(&mut self, marks: &[Mark]) {
+        for view in self.views.iter_mut() {
+            let mut changed: HashMap<String, Vec<ChartTimeSeries>> = HashMap::new();
+            for mark in marks.iter() {
+                for (label, point) in view.push_mark(mark) {
+                    changed.entry(label).or_default().push(point);
+                }
+            }
+            if !changed.is_empty() {
+                let delta = ViewDelta {
+                    view: view.spec.name.clone(),
+                    snapshot: false,
+                    revision: false,
+                    datasets: changed.into_iter().map(|(label, data)| ChartDataset { label, data }).collect(),
+                };
+                view.send(delta);
+            }
+        }
+        self.views.retain(|x| !x.subscribers.is_empty());
+    }
+
+    /// after a late ticker: rebuild the source and symbol's series in the views that take them and
+    /// send those views a revision from the bucket the ticker fell in; other views hear nothing
+    pub fn revise(&mut self, source: &Datasource, symbol: &SymbolCommon, since: DateTime<Utc>, evt_book: &EventBook) {
+        for view in self.views.iter_mut() {
+            if let Some(delta) = view.revise(source, symbol, since, evt_book) {
+                view.send(delta);
+            }
+        }
+        self.views.retain(|x| !x.subscribers.is_empty());
+    }
+
+    /// rebuild every view from the book (e.g. after a backfill) and send fresh snapshots
+    pub fn reseed(&mut self, evt_book: &EventBook) {
+        for view in self.views.iter_mut() {
+            view.series.clear();
+            view.seed(evt_book);
+            let snapshot = view.snapshot();
+            view.send(snapshot);
+        }
+        self.views.retain(|x| !x.subscribers.is_empty());
+    }
+}
+
+impl View {
+    fn seed(&mut self, evt_book: &EventBook) {
+        let book = evt_book.book.read().unwrap();
+        for (source, evt_log) in book.iter() {
+            for ticker in evt_log.tickers() {
+                self.push(source, &ticker.symbol, ViewSeries::Price, ticker.dtg, ticker.price);
+            }
+            for calc in evt_log.calcs() {
+                self.push(source, &calc.symbol, ViewSeries::Calc(calc.calc_id.clone()), calc.dtg, calc.val);
+            }
+        }
+        for mark in evt_book.paper.read().unwrap().marks() {
+            self.push_mark(mark);
+        }
+    }
+
+    /// the source and symbol's series again from the book, and their points from since's bucket on
+    fn revise(&mut self, source: &Datasource, symbol: &SymbolCommon, since: DateTime<Utc>, evt_book: &EventBook) -> Option<ViewDelta> {
+        let labels: Vec<String> = ViewSeries::all().iter().filter(|x| self.spec.matches(source, symbol, x)).map(|x| x.label(symbol, source)).collect();
+        if labels.is_empty() {
+            return None;
+        }
+        for label in labels.iter() {
+            self.series.remove(label);
+        }
+        {
+            let book = evt_book.book.read().unwrap();
+            if let Some(evt_log) = book.get(source) {
+                for ticker in evt_log.tickers().filter(|x| x.symbol == *symbol) {
+                    self.push(source, symbol, ViewSeries::Price, ticker.dtg, ticker.price);
+                }
+                for calc in evt_log.calcs().filter(|x| x.symbol == *symbol) {
+                    self.push(source, symbol, ViewSeries::Calc(calc.calc_id.clone()), calc.dtg, calc.val);
+                }
+            }
+        }
+
+        let from = match self.spec.window {
+            Some(window) => {
+                let millis = since.timestamp_millis();
+                Utc.timestamp_millis_opt(millis - millis.rem_euclid(window.num_milliseconds().max(1))).unwrap()
+            }
+            None => since,
+        };
+        let mut datasets: Vec<ChartDataset> = labels
+            .into_iter()
+            .filter_map(|label| {
+                let data: Vec<ChartTimeSeries> = self.series.get(&label)?.data.iter().filter(|x| x.x >= from).cloned().collect();
+                (!data.is_empty()).then_some(ChartDataset { label, data })
+            })
+            .collect();
+        datasets.sort_by(|a, b| a.label.cmp(&b.label));
+        match datasets.is_empty() {
+            true => None,
+            false => Some(ViewDelta { view: self.spec.name.clone(), snapshot: false, revision: true, datasets }),
+        }
+    }
+
+    /// a mark's fields, if this view takes every series of its source and symbol
+    fn push_mark(&mut self, mark: &Mark) -> Vec<(String, ChartTimeSeries)> {
+        if !self.spec.series.is_empty() || !self.spec.matches(&mark.source, &mark.symbol, &ViewSeries::Price) {
+            return vec![];
+        }
+        mark.values().into_iter().filter_map(|(field, val)| self.push_label(field.label(&mark.strategy), mark.dtg, val)).collect()
+    }
+
+    /// returns the label and the point that changed, if this view wants it
+    fn push(&mut self, source: &Datasource, symbol: &SymbolCommon, series: ViewSeries, dtg: DateTime<Utc>, val: f64) -> Option<(String, ChartTimeSeries)> {
+        if !self.spec.matches(source, symbol, &series) {
+            return None;
+        }
+        self.push_label(series.label(symbol, source), dtg, val)
+    }
+
+    fn push_label(&mut self, label: String, dtg: DateTime<Utc>, val: f64) -> Option<(String, ChartTimeSeries)> {
+        let state = self.series.entry(label.clone()).or_default();
+
+        let point = match self.spec.window {
+            None => ChartTimeSeries { x: dtg, y: val },
+            Some(window) => {
+                let width = window.num_milliseconds().max(1);
+                let millis = dtg.timestamp_millis();
+                let start = Utc.timestamp_millis_opt(millis - millis.rem_euclid(width)).unwrap();
+                match state.window.as_mut() {
+                    Some(open) if open.start == start => open.add(val),
+                    Some(open) if open.start > start => {
+                        tracing::debug!("[View::push] {} dropped point older than the open window: {}", &label, &dtg);
+                        return None;
+                    }
+                    _ => state.window = Some(WindowState::new(start, val)),
+                }
+                let open = state.window.as_ref().unwrap();
+                ChartTimeSeries { x: open.start, y: open.value(&self.spec.aggregate) }
+            }
+        };
+
+        match state.data.back_mut() {
+            Some(last) if last.x == point.x => *last = point.clone(),
+            _ => state.data.push_back(point.clone()),
+        }
+        while state.data.len() > self.spec.limit {
+            state.data.pop_front();
+        }
+        Some((label, point))
+    }
+
+    fn snapshot(&self) -> ViewDelta {
+        let mut datasets: Vec<ChartDataset> = self.series.iter().map(|(label, state)| ChartDataset { label: label.clone(), data: state.data.iter().cloned().collect() }).collect();
+        datasets.sort_by(|a, b| a.label.cmp(&b.label));
+        ViewDelta { view: self.spec.name.clone(), snapshot: true, revision: false, datasets }
+    }
+
+    /// drop any subscriber whose receiver has gone away; one whose queue is full misses this delta
+    /// and gets a snapshot as soon as there's room, so the db thread never waits on it
+    fn send(&mut self, delta: ViewDelta) {
+        let snapshot = self.subscribers.iter().any(|x| x.lagged).then(|| self.snapshot());
+        let name = &self.spec.name;
+        self.subscribers.retain_mut(|subscriber| {
+            let message = match (&snapshot, subscriber.lagged) {
+                (Some(snapshot), true) => snapshot.clone(),
+                _ => delta.clone(),
+            };
+            match subscriber.tx.try_send(message) {
+                Ok(_) => {
+                    subscriber.lagged = false;
+                    true
+                }
+                Err(TrySendError::Full(_)) => {
+                    if !subscriber.lagged {
+                        tracing::warn!("[View::send] {name} subscriber queue full, sending a snapshot once it drains");
+                    }
+                    subscriber.lagged = true;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+impl WindowState {
+    fn new(start: DateTime<Utc>, val: f64) -> WindowState {
+        WindowState { start, count: 1, sum: val, min: val, max: val, last: val }
+    }
+
+    fn add(&mut self, val: f64) {
+        self.count += 1;
+        self.sum += val;
+        self.min = self.min.min(val);
+        self.max = self.max.max(val);
+        self.last = val;
+    }
+
+    fn value(&self, aggregate: &ViewAggregate) -> f64 {
+        match aggregate {
+            ViewAggregate::Last => self.last,
+            ViewAggregate::Avg => self.sum / self.count as f64,
+            ViewAggregate::Min => self.min,
+            ViewAggregate::Max => self.max,
+            ViewAggregate::Count => self.count as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::view::{ViewAggregate, ViewSeries, ViewSpec};
+    use common_lib::{SymbolCommon, TickerCommon};
+    use crate::event_book::EventBook;
+    use crate::view::ViewEngine;
+
+    fn ticker(rfc3339: &str, symbol: SymbolCommon, price: f64) -> TickerCommon {
+        TickerCommon { source: Datasource::Coinbase, symbol, price, dtg: DateTime::<Utc>::from(DateTime::parse_from_rfc3339(rfc3339).unwrap()), trade_id: None }
+    }
+
+    #[test]
+    fn test_snapshot_then_deltas() {
+        let evt_book = EventBook::new();
+        let _ = evt_book.push_log(Datasource::Coinbase, &ticker("2024-01-14T23:30:00Z", SymbolCommon::BtcUsd, 1.0));
+        let _ = evt_book.push_log(Datasource::Coinbase, &ticker("2024-01-14T23:30:01Z", SymbolCommon::EthUsd, 2.0));
+
+        let mut views = ViewEngine::new();
+        let rx = views.register(ViewSpec::new("btc").symbol(SymbolCommon::BtcUsd).series(ViewSeries::Price), &evt_book);
+        let snapshot = rx.try_recv().unwrap();
+        assert!(snapshot.snapshot);
+        assert_eq!(snapshot.datasets.len(), 1);
+        assert_eq!(snapshot.datasets[0].label, "btc_usd_Coinbase");
+
+        // eth is filtered out, btc comes through as a delta
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:02Z", SymbolCommon::EthUsd, 3.0), &[]);
+        assert!(rx.try_recv().is_err());
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:03Z", SymbolCommon::BtcUsd, 4.0), &[]);
+        let delta = rx.try_recv().unwrap();
+        assert!(!delta.snapshot);
+        assert_eq!(delta.datasets[0].data[0].y, 4.0);
+
+        // the same spec shares one view; dropping every receiver removes it
+        let rx2 = views.register(ViewSpec::new("btc").symbol(SymbolCommon::BtcUsd).series(ViewSeries::Price), &evt_book);
+        assert_eq!(views.len(), 1);
+        assert_eq!(rx2.try_recv().unwrap().datasets[0].data.len(), 2);
+        drop(rx);
+        drop(rx2);
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:04Z", SymbolCommon::BtcUsd, 5.0), &[]);
+        assert!(views.is_empty());
+    }
+
+    /// a full subscriber misses deltas without holding up the others, then gets a snapshot
+    #[test]
+    fn test_lagging_subscriber() {
+        let evt_book = EventBook::new();
+        let mut views = ViewEngine::new().queue_size(2);
+        let spec = ViewSpec::new("btc").symbol(SymbolCommon::BtcUsd).series(ViewSeries::Price);
+        let slow = views.register(spec.clone(), &evt_book);
+        let fast = views.register(spec, &evt_book);
+        let _ = fast.try_recv().unwrap();
+
+        // the slow queue holds its snapshot and one delta
+        for (i, dtg) in ["2024-01-14T23:30:00Z", "2024-01-14T23:30:01Z", "2024-01-14T23:30:02Z"].into_iter().enumerate() {
+            views.apply(&Datasource::Coinbase, &ticker(dtg, SymbolCommon::BtcUsd, i as f64), &[]);
+            assert_eq!(fast.try_recv().unwrap().datasets[0].data[0].y, i as f64);
+        }
+        assert_eq!(slow.len(), 2);
+        assert!(slow.try_recv().unwrap().snapshot);
+        assert_eq!(slow.try_recv().unwrap().datasets[0].data[0].y, 0.0);
+
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:03Z", SymbolCommon::BtcUsd, 3.0), &[]);
+        let caught_up = slow.try_recv().unwrap();
+        assert!(caught_up.snapshot);
+        assert_eq!(caught_up.datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![0.0, 1.0, 2.0, 3.0]);
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:04Z", SymbolCommon::BtcUsd, 4.0), &[]);
+        assert!(!slow.try_recv().unwrap().snapshot);
+    }
+
+    /// a late btc tick revises btc's views from its time on and leaves eth's alone
+    #[test]
+    fn test_revise_late() {
+        let evt_book = EventBook::new();
+        for (rfc3339, symbol, price) in [("2024-01-14T23:30:00Z", SymbolCommon::BtcUsd, 1.0), ("2024-01-14T23:30:01Z", SymbolCommon::EthUsd, 2.0), ("2024-01-14T23:30:02Z", SymbolCommon::BtcUsd, 3.0)] {
+            let _ = evt_book.push_log(Datasource::Coinbase, &ticker(rfc3339, symbol, price));
+        }
+        let mut views = ViewEngine::new();
+        let btc = views.register(ViewSpec::new("btc").symbol(SymbolCommon::BtcUsd).series(ViewSeries::Price), &evt_book);
+        let eth = views.register(ViewSpec::new("eth").symbol(SymbolCommon::EthUsd).series(ViewSeries::Price), &evt_book);
+        let _ = btc.try_recv().unwrap();
+        let eth_snapshot = eth.try_recv().unwrap();
+
+        let late = ticker("2024-01-14T23:30:01.500Z", SymbolCommon::BtcUsd, 9.0);
+        let _ = evt_book.push_log(Datasource::Coinbase, &late);
+        views.revise(&Datasource::Coinbase, &SymbolCommon::BtcUsd, late.dtg, &evt_book);
+
+        let delta = btc.try_recv().unwrap();
+        assert!(delta.revision && !delta.snapshot);
+        assert_eq!(delta.datasets.len(), 1);
+        assert_eq!(delta.datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![9.0, 3.0]);
+        assert!(btc.try_recv().is_err());
+        assert!(eth.try_recv().is_err());
+
+        // eth's view still holds what it had
+        let eth_again = views.register(ViewSpec::new("eth").symbol(SymbolCommon::EthUsd).series(ViewSeries::Price), &evt_book);
+        assert_eq!(eth_again.try_recv().unwrap().datasets, eth_snapshot.datasets);
+    }
+
+    #[test]
+    fn test_window_aggregate() {
+        let evt_book = EventBook::new();
+        let mut views = ViewEngine::new();
+        let rx = views.register(ViewSpec::new("avg").window(Duration::seconds(1), ViewAggregate::Avg), &evt_book);
+        assert!(rx.try_recv().unwrap().datasets.is_empty());
+
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:00.100Z", SymbolCommon::BtcUsd, 10.0), &[]);
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:00.900Z", SymbolCommon::BtcUsd, 20.0), &[]);
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:01.200Z", SymbolCommon::BtcUsd, 40.0), &[]);
+        // late for the closed window
+        views.apply(&Datasource::Coinbase, &ticker("2024-01-14T23:30:00.950Z", SymbolCommon::BtcUsd, 99.0), &[]);
+
+        let ys: Vec<(String, f64)> = rx.try_iter().map(|d| (d.datasets[0].data[0].x.to_rfc3339(), d.datasets[0].data[0].y)).collect();
+        assert_eq!(
+            ys,
+            vec![
+                ("2024-01-14T23:30:00+00:00".to_string(), 10.0),
+                ("2024-01-14T23:30:00+00:00".to_string(), 15.0),
+                ("2024-01-14T23:30:01+00:00".to_string(), 40.0),
+            ]
+        );
+    }
+}
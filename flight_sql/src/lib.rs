@@ -0,0 +1,58 @@
+//! flight_sql
+//!
+//! Arrow Flight SQL over the in-memory store, the "F" in FDAP. DBeaver (Arrow Flight SQL JDBC),
+//! ADBC or pyarrow connect to grpc://127.0.0.1:50051 and query the `ticks` and `calcs` tables (and
+//! per-source views like `coinbase_ticks`, see db::sql) with DataFusion sql; no tls yet.
+//!
+//! With a users file (common_lib::auth) every call needs an "authorization" header: a handshake
+//! with Basic name and password returns a Bearer token for the calls after it (what the jdbc
+//! driver and FlightSqlServiceClient::handshake do), or send a Bearer api token on each call.
+
+// tonic::Status is the error of every grpc call
+#![allow(clippy::result_large_err)]
+
+use std::error::Error;
+use arrow_flight::flight_service_server::FlightServiceServer;
+use crossbeam_channel::Sender;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Status};
+use common_lib::auth::{Auth, Credentials};
+use common_lib::DbMsg;
+use crate::service::TickFlightSql;
+
+pub mod service;
+
+/// bind and serve until the process exits; addr is from common_lib::config::Listeners
+pub async fn run(tx_db: Sender<DbMsg>, addr: &str, auth: Auth) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("[flight_sql] listening on grpc://{}", listener.local_addr()?);
+    serve(tx_db, listener, auth).await?;
+    Ok(())
+}
+
+/// serve on an already bound listener (tests bind port 0)
+pub async fn serve(tx_db: Sender<DbMsg>, listener: TcpListener, auth: Auth) -> Result<(), tonic::transport::Error> {
+    let service = TickFlightSql::new(tx_db, auth.clone());
+    Server::builder()
+        .add_service(FlightServiceServer::with_interceptor(service, move |req| authenticate(&auth, req)))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+}
+
+/// every call, handshakes included; the Caller goes in the request's extensions. A Basic header
+/// is checked with argon2, so clients should handshake once and use the token.
+fn authenticate(auth: &Auth, mut req: Request<()>) -> Result<Request<()>, Status> {
+    if auth.is_open() {
+        return Ok(req);
+    }
+    let credentials = req.metadata().get("authorization").and_then(|x| x.to_str().ok()).and_then(Credentials::from_header);
+    match credentials.and_then(|x| auth.credentials(&x)) {
+        Some(caller) => {
+            req.extensions_mut().insert(caller);
+            Ok(req)
+        }
+        None => Err(Status::unauthenticated("handshake with a name and password, or send a bearer token")),
+    }
+}
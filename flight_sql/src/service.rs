@@ -0,0 +1,365 @@
+//! service.rs
+//!
+//! FlightSqlService over snapshots of the db thread's tables. Each call plans against a fresh
+//! snapshot, so a ticket re-runs its sql when fetched and can see rows newer than the FlightInfo did.
+//!
+//! Supported: statements, prepared statements (no parameters), catalogs, schemas, tables, table
+//! types and sql info. Updates and transactions are refused; the store is fed by the websockets.
+
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::flight_service_server::FlightService;
+use arrow_flight::sql::metadata::{SqlInfoData, SqlInfoDataBuilder};
+use arrow_flight::sql::server::FlightSqlService;
+use arrow_flight::sql::{
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest, ActionCreatePreparedStatementResult,
+    CommandGetCatalogs, CommandGetDbSchemas, CommandGetSqlInfo, CommandGetTableTypes, CommandGetTables,
+    CommandPreparedStatementQuery, CommandStatementQuery, ProstMessageExt, SqlInfo, TicketStatementQuery,
+};
+use arrow_flight::{Action, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse, IpcMessage, SchemaAsIpc, Ticket};
+use crossbeam_channel::Sender;
+use datafusion::arrow::array::StringArray;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::ipc::writer::IpcWriteOptions;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::TableType;
+use datafusion::error::DataFusionError;
+use datafusion::prelude::{DataFrame, SessionContext};
+use futures::{Stream, TryStreamExt};
+use prost::Message;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status, Streaming};
+use common_lib::auth::{Auth, Caller};
+use common_lib::metrics::METRICS;
+use common_lib::{DbMsg, UniversalError};
+
+type DoGetStream = <TickFlightSql as FlightService>::DoGetStream;
+
+/// flight sql's names for DataFusion's table types
+fn table_type_name(table_type: TableType) -> &'static str {
+    match table_type {
+        TableType::Base => "TABLE",
+        TableType::View => "VIEW",
+        TableType::Temporary => "LOCAL TEMPORARY",
+    }
+}
+
+static SQL_INFO: OnceLock<SqlInfoData> = OnceLock::new();
+
+fn sql_info() -> &'static SqlInfoData {
+    SQL_INFO.get_or_init(|| {
+        let mut builder = SqlInfoDataBuilder::new();
+        builder.append(SqlInfo::FlightSqlServerName, "crate");
+        builder.append(SqlInfo::FlightSqlServerVersion, env!("CARGO_PKG_VERSION"));
+        builder.append(SqlInfo::FlightSqlServerArrowVersion, "1.3");
+        builder.append(SqlInfo::FlightSqlServerReadOnly, true);
+        builder.build().unwrap()
+    })
+}
+
+pub struct TickFlightSql {
+    tx_db: Sender<DbMsg>,
+    auth: Auth,
+}
+
+impl TickFlightSql {
+    pub fn new(tx_db: Sender<DbMsg>, auth: Auth) -> TickFlightSql {
+        TickFlightSql { tx_db, auth }
+    }
+
+    /// a session over a fresh snapshot of every table
+    async fn context(&self) -> Result<SessionContext, Status> {
+        db::sql::snapshot(&self.tx_db).await.map_err(|e| match e {
+            UniversalError::DbError(e) => Status::internal(e),
+            e => Status::unavailable(format!("db thread: {e}")),
+        })
+    }
+
+    async fn plan(&self, sql: &str) -> Result<DataFrame, Status> {
+        self.context().await?.sql_with_options(sql, db::sql::read_only()).await.map_err(df_status)
+    }
+
+    async fn execute(&self, sql: &str) -> Result<Response<DoGetStream>, Status> {
+        let start = Instant::now();
+        let df = self.plan(sql).await?;
+        let schema: SchemaRef = Arc::new(df.schema().into());
+        let batches = df.collect().await.map_err(df_status)?;
+        METRICS.query("flight_sql", start.elapsed());
+        Ok(stream(schema, batches))
+    }
+}
+
+/// one endpoint, fetched from this server with `ticket`
+fn flight_info(schema: &Schema, ticket: impl ProstMessageExt, descriptor: FlightDescriptor) -> Result<Response<FlightInfo>, Status> {
+    let endpoint = FlightEndpoint::new().with_ticket(Ticket::new(ticket.as_any().encode_to_vec()));
+    let info = FlightInfo::new()
+        .try_with_schema(schema)
+        .map_err(|e| Status::internal(e.to_string()))?
+        .with_endpoint(endpoint)
+        .with_descriptor(descriptor);
+    Ok(Response::new(info))
+}
+
+fn stream(schema: SchemaRef, batches: Vec<RecordBatch>) -> Response<DoGetStream> {
+    let stream = FlightDataEncoderBuilder::new()
+        .with_schema(schema)
+        .build(futures::stream::iter(batches.into_iter().map(Ok)))
+        .map_err(Status::from);
+    Response::new(Box::pin(stream) as Pin<Box<dyn Stream<Item = _> + Send>>)
+}
+
+/// sql the client got wrong is theirs; anything else is ours
+fn df_status(e: DataFusionError) -> Status {
+    match e {
+        DataFusionError::SQL(_) | DataFusionError::Plan(_) | DataFusionError::SchemaError(_) => Status::invalid_argument(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+fn handle_sql(handle: &[u8]) -> Result<&str, Status> {
+    std::str::from_utf8(handle).map_err(|_| Status::invalid_argument("statement handle is not utf8"))
+}
+
+fn table_types_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![Field::new("table_type", DataType::Utf8, false)]))
+}
+
+#[tonic::async_trait]
+impl FlightSqlService for TickFlightSql {
+    type FlightService = TickFlightSql;
+
+    /// the caller was checked by crate::authenticate; a token saves checking the password again
+    async fn do_handshake(&self, request: Request<Streaming<HandshakeRequest>>) -> Result<Response<Pin<Box<dyn Stream<Item = Result<HandshakeResponse, Status>> + Send>>>, Status> {
+        let Some(caller) = request.extensions().get::<Caller>() else {
+            // auth is off
+            return Ok(Response::new(Box::pin(futures::stream::empty())));
+        };
+        let token = self.auth.issue(caller);
+        tracing::info!("[flight_sql] handshake from {} ({})", caller.name, caller.role);
+        let response = HandshakeResponse { protocol_version: 0, payload: token.clone().into() };
+        let mut response = Response::new(Box::pin(futures::stream::iter([Ok(response)])) as Pin<Box<dyn Stream<Item = _> + Send>>);
+        let bearer = MetadataValue::try_from(format!("Bearer {token}")).map_err(|e| Status::internal(e.to_string()))?;
+        response.metadata_mut().insert("authorization", bearer);
+        Ok(response)
+    }
+
+    /// plan now to report the schema; the ticket carries the sql itself
+    async fn get_flight_info_statement(&self, query: CommandStatementQuery, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        let df = self.plan(&query.query).await?;
+        let ticket = TicketStatementQuery { statement_handle: query.query.into_bytes().into() };
+        flight_info(&df.schema().into(), ticket, request.into_inner())
+    }
+
+    async fn get_flight_info_prepared_statement(&self, cmd: CommandPreparedStatementQuery, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        let df = self.plan(handle_sql(&cmd.prepared_statement_handle)?).await?;
+        flight_info(&df.schema().into(), cmd, request.into_inner())
+    }
+
+    async fn get_flight_info_catalogs(&self, query: CommandGetCatalogs, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        flight_info(&query.clone().into_builder().schema(), query, request.into_inner())
+    }
+
+    async fn get_flight_info_schemas(&self, query: CommandGetDbSchemas, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        flight_info(&query.clone().into_builder().schema(), query, request.into_inner())
+    }
+
+    async fn get_flight_info_tables(&self, query: CommandGetTables, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        flight_info(&query.clone().into_builder().schema(), query, request.into_inner())
+    }
+
+    async fn get_flight_info_table_types(&self, query: CommandGetTableTypes, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        flight_info(&table_types_schema(), query, request.into_inner())
+    }
+
+    async fn get_flight_info_sql_info(&self, query: CommandGetSqlInfo, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        flight_info(&query.clone().into_builder(sql_info()).schema(), query, request.into_inner())
+    }
+
+    async fn do_get_statement(&self, ticket: TicketStatementQuery, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        self.execute(handle_sql(&ticket.statement_handle)?).await
+    }
+
+    async fn do_get_prepared_statement(&self, query: CommandPreparedStatementQuery, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        self.execute(handle_sql(&query.prepared_statement_handle)?).await
+    }
+
+    async fn do_get_catalogs(&self, query: CommandGetCatalogs, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        let ctx = self.context().await?;
+        let mut builder = query.into_builder();
+        for catalog in ctx.catalog_names() {
+            builder.append(catalog);
+        }
+        let schema = builder.schema();
+        let batch = builder.build().map_err(|e| Status::internal(e.to_string()))?;
+        Ok(stream(schema, vec![batch]))
+    }
+
+    async fn do_get_schemas(&self, query: CommandGetDbSchemas, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        let ctx = self.context().await?;
+        let mut builder = query.into_builder();
+        for catalog_name in ctx.catalog_names() {
+            if let Some(catalog) = ctx.catalog(&catalog_name) {
+                for schema_name in catalog.schema_names() {
+                    builder.append(&catalog_name, schema_name);
+                }
+            }
+        }
+        let schema = builder.schema();
+        let batch = builder.build().map_err(|e| Status::internal(e.to_string()))?;
+        Ok(stream(schema, vec![batch]))
+    }
+
+    /// the builder applies the client's catalog, schema, name and type filters
+    async fn do_get_tables(&self, query: CommandGetTables, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        let ctx = self.context().await?;
+        let mut builder = query.into_builder();
+        for catalog_name in ctx.catalog_names() {
+            let Some(catalog) = ctx.catalog(&catalog_name) else { continue };
+            for schema_name in catalog.schema_names() {
+                let Some(db_schema) = catalog.schema(&schema_name) else { continue };
+                for table_name in db_schema.table_names() {
+                    if let Some(table) = db_schema.table(&table_name).await {
+                        builder.append(&catalog_name, &schema_name, &table_name, table_type_name(table.table_type()), &table.schema())
+                            .map_err(|e| Status::internal(e.to_string()))?;
+                    }
+                }
+            }
+        }
+        let schema = builder.schema();
+        let batch = builder.build().map_err(|e| Status::internal(e.to_string()))?;
+        Ok(stream(schema, vec![batch]))
+    }
+
+    async fn do_get_table_types(&self, _query: CommandGetTableTypes, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        let schema = table_types_schema();
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(vec![table_type_name(TableType::Base), table_type_name(TableType::View)]))])
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(stream(schema, vec![batch]))
+    }
+
+    async fn do_get_sql_info(&self, query: CommandGetSqlInfo, _request: Request<Ticket>) -> Result<Response<DoGetStream>, Status> {
+        let builder = query.into_builder(sql_info());
+        let schema = builder.schema();
+        let batch = builder.build().map_err(|e| Status::internal(e.to_string()))?;
+        Ok(stream(schema, vec![batch]))
+    }
+
+    /// the handle is the sql; nothing is held server side, so closing is a no-op
+    async fn do_action_create_prepared_statement(&self, query: ActionCreatePreparedStatementRequest, _request: Request<Action>) -> Result<ActionCreatePreparedStatementResult, Status> {
+        let df = self.plan(&query.query).await?;
+        let schema: Schema = df.schema().into();
+        let IpcMessage(dataset_schema) = SchemaAsIpc::new(&schema, &IpcWriteOptions::default())
+            .try_into()
+            .map_err(|e: datafusion::arrow::error::ArrowError| Status::internal(e.to_string()))?;
+        Ok(ActionCreatePreparedStatementResult {
+            prepared_statement_handle: query.query.into_bytes().into(),
+            dataset_schema,
+            parameter_schema: Default::default(),
+        })
+    }
+
+    async fn do_action_close_prepared_statement(&self, _query: ActionClosePreparedStatementRequest, _request: Request<Action>) -> Result<(), Status> {
+        Ok(())
+    }
+
+    async fn register_sql_info(&self, _id: i32, _result: &SqlInfo) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use arrow_flight::sql::client::FlightSqlServiceClient;
+    use arrow_flight::sql::CommandGetTables;
+    use arrow_flight::FlightInfo;
+    use chrono::{DateTime, Utc};
+    use datafusion::arrow::array::{AsArray, StringArray};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use futures::TryStreamExt;
+    use tokio::net::TcpListener;
+    use tonic::transport::{Channel, Endpoint};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::auth::{hash_password, Auth, Role, UserRecord, UsersFile};
+    use common_lib::{DbMsg, SymbolCommon, TickerCommon};
+    use db::arrow_db;
+
+    async fn fetch(client: &mut FlightSqlServiceClient<Channel>, info: FlightInfo) -> Vec<RecordBatch> {
+        let ticket = info.endpoint[0].ticket.clone().unwrap();
+        client.do_get(ticket).await.unwrap().try_collect().await.unwrap()
+    }
+
+    /// a live db thread with a few ticks, a flight sql server on a free port, and a client
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flight_sql_client() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        for (symbol, price) in [(SymbolCommon::BtcUsd, 42000.5), (SymbolCommon::EthUsd, 2500.0), (SymbolCommon::BtcUsd, 42001.0)] {
+            let ticker = TickerCommon { source: Datasource::Coinbase, symbol, price, dtg, trade_id: None };
+            tx_db.send(DbMsg::Insert(Datasource::Coinbase, ticker)).unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::serve(tx_db, listener, Auth::open()));
+        let channel = Endpoint::from_shared(format!("http://{addr}")).unwrap()
+            .connect_timeout(Duration::from_secs(5))
+            .connect().await.unwrap();
+        let mut client = FlightSqlServiceClient::new(channel);
+
+        // statement
+        let info = client.execute("select source, symbol, price from ticks where symbol = 'btc_usd' order by price".to_string(), None).await.unwrap();
+        let batches = fetch(&mut client, info).await;
+        let expected = "+----------+---------+---------+
+| source   | symbol  | price   |
++----------+---------+---------+
+| coinbase | btc_usd | 42000.5 |
+| coinbase | btc_usd | 42001.0 |
++----------+---------+---------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+
+        // prepared statement, as the jdbc driver sends everything
+        let mut prepared = client.prepare("select count(*) as n from calcs where symbol = 'btc_usd'".to_string(), None).await.unwrap();
+        assert_eq!(prepared.dataset_schema().unwrap().field(0).name(), "n");
+        let info = prepared.execute().await.unwrap();
+        let batches = fetch(&mut client, info).await;
+        assert!(batches[0].column(0).as_primitive::<datafusion::arrow::datatypes::Int64Type>().value(0) > 0);
+        prepared.close().await.unwrap();
+
+        // metadata a sql browser asks for
+        let info = client.get_tables(CommandGetTables { include_schema: true, ..Default::default() }).await.unwrap();
+        let batches = fetch(&mut client, info).await;
+        let names = batches[0].column_by_name("table_name").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        let mut names: Vec<&str> = names.iter().flatten().collect();
+        names.sort();
+        assert_eq!(names, vec!["alerts", "alpaca_calcs", "alpaca_ticks", "calcs", "coinbase_calcs", "coinbase_ticks", "dedup", "paper", "paper_fills", "ticks"]);
+        let info = client.get_tables(CommandGetTables { table_types: vec!["TABLE".to_string()], ..Default::default() }).await.unwrap();
+        assert_eq!(fetch(&mut client, info).await[0].num_rows(), 6);
+
+        // bad sql is the caller's error
+        assert!(client.execute("select nope from ticks".to_string(), None).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flight_sql_auth() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let auth = Auth::new(UsersFile { users: vec![
+            UserRecord { name: "gp".to_string(), role: Role::Read, password: Some(hash_password("hunter2").unwrap()), tokens: vec![] },
+        ]}).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::serve(tx_db, listener, auth));
+        let channel = Endpoint::from_shared(format!("http://{addr}")).unwrap().connect().await.unwrap();
+        let mut client = FlightSqlServiceClient::new(channel);
+
+        assert!(client.execute("select 1".to_string(), None).await.is_err());
+        assert!(client.handshake("gp", "hunter3").await.is_err());
+
+        // the handshake's bearer token is sent from then on
+        let token = client.handshake("gp", "hunter2").await.unwrap();
+        assert_eq!(token.len(), 64);
+        let info = client.execute("select 1 as one".to_string(), None).await.unwrap();
+        assert_eq!(fetch(&mut client, info).await[0].num_rows(), 1);
+    }
+}
@@ -0,0 +1,131 @@
+//! influx
+//!
+//! InfluxDB line protocol ingest, so scripts, telegraf and other services can push metrics and
+//! fills into the store next to the live feeds. Over http (routes in visual) or udp, which has no
+//! auth; with users configured it only listens on loopback:
+//!
+//! ```text
+//! curl -XPOST 'http://127.0.0.1:8080/api/v2/write?precision=ms' --data-binary 'fills,symbol=btc_usd,side=buy price=42000.5,qty=2i 1705275000000'
+//! echo 'cpu,host=a usage=0.5' | nc -u -w0 127.0.0.1 8089
+//! ```
+//!
+//! Each measurement becomes a sql table (dtg, then its tags, then its fields) and each numeric
+//! field a chart series labelled "measurement,tag=value_field".
+
+use std::error::Error;
+use std::net::SocketAddr;
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use common_lib::{DbMsg, UniversalError};
+use crate::line_protocol::{parse, LineError, Precision};
+
+pub mod line_protocol;
+
+/// largest datagram read; telegraf keeps udp payloads under this
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// parse a write and store it; every point is stored or none are. Resolves to the number stored
+pub async fn write(tx_db: &Sender<DbMsg>, body: &str, precision: Precision) -> Result<usize, LineError> {
+    let points = parse(body, precision, Utc::now())?;
+    if points.is_empty() {
+        return Ok(0);
+    }
+    let (sender, rx) = oneshot::channel();
+    tx_db.send(DbMsg::RqstWritePoints { points, sender }).map_err(|_| LineError::Db)?;
+    match rx.await.map_err(|_| LineError::Db)? {
+        Ok(count) => Ok(count),
+        Err(UniversalError::DbError(reason)) => Err(LineError::Rejected { reason }),
+        Err(_) => Err(LineError::Db),
+    }
+}
+
+/// bind and serve until the process exits; addr is from common_lib::config::Listeners. Udp
+/// writes are unauthenticated, so with auth on (auth_open false) only a loopback addr is bound
+pub async fn run_udp(tx_db: Sender<DbMsg>, addr: &str, auth_open: bool) -> Result<(), Box<dyn Error>> {
+    let addr: SocketAddr = addr.parse()?;
+    check_udp_addr(&addr, auth_open)?;
+    let socket = UdpSocket::bind(addr).await?;
+    tracing::info!("[influx] listening on udp://{}", socket.local_addr()?);
+    serve_udp(tx_db, socket).await?;
+    Ok(())
+}
+
+/// anyone who can reach the socket can write, so refuse anything but loopback when auth is on
+pub fn check_udp_addr(addr: &SocketAddr, auth_open: bool) -> Result<(), String> {
+    match auth_open || addr.ip().is_loopback() {
+        true => Ok(()),
+        false => Err(format!("udp {addr} has no auth; bind it to loopback while users are configured")),
+    }
+}
+
+/// each datagram is one write with nanosecond timestamps; there's nobody to answer over udp, so
+/// bad writes are only logged
+pub async fn serve_udp(tx_db: Sender<DbMsg>, socket: UdpSocket) -> std::io::Result<()> {
+    let mut buf = vec![0u8; UDP_BUFFER_SIZE];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        match std::str::from_utf8(&buf[..len]) {
+            Ok(body) => {
+                if let Err(e) = write(&tx_db, body, Precision::Nanosecond).await {
+                    tracing::warn!("[influx] udp write from {addr}: {e}");
+                }
+            }
+            Err(_) => tracing::warn!("[influx] udp write from {addr} isn't utf8"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use db::arrow_db;
+    use crate::line_protocol::{LineError, Precision};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        assert_eq!(crate::write(&tx_db, "fills,symbol=btc_usd price=42000.5,qty=2i 1705275000000", Precision::Millisecond).await.unwrap(), 1);
+        assert_eq!(crate::write(&tx_db, "# nothing\n", Precision::Nanosecond).await.unwrap(), 0);
+        assert!(matches!(crate::write(&tx_db, "fills qty=2.5", Precision::Nanosecond).await, Err(LineError::Rejected { .. })));
+        assert!(matches!(crate::write(&tx_db, "fills qty", Precision::Nanosecond).await, Err(LineError::Syntax { line: 1, .. })));
+
+        // udp writes land in the same tables
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(crate::serve_udp(tx_db.clone(), socket));
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"cpu,host=a usage=0.5\ncpu,host=b usage=0.25", addr).await.unwrap();
+
+        let mut rows = 0;
+        for _ in 0..50 {
+            let ctx = db::sql::snapshot(&tx_db).await.unwrap();
+            if let Ok(df) = ctx.sql("select * from cpu").await {
+                rows = df.count().await.unwrap();
+            }
+            if rows > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(rows, 2);
+    }
+
+    #[test]
+    fn test_check_udp_addr() {
+        let loopback = "127.0.0.1:8089".parse().unwrap();
+        let any = "0.0.0.0:8089".parse().unwrap();
+        assert!(crate::check_udp_addr(&loopback, false).is_ok());
+        assert!(crate::check_udp_addr(&"[::1]:8089".parse().unwrap(), false).is_ok());
+        assert!(crate::check_udp_addr(&any, true).is_ok());
+        assert!(crate::check_udp_addr(&any, false).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_udp_refuses_public_addr_with_auth() {
+        let tx_db = crossbeam_channel::unbounded().0;
+        assert!(crate::run_udp(tx_db, "0.0.0.0:0", false).await.is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! line_protocol.rs
+//!
+//! influx line protocol, one point per line:
+//!
+//! ```text
+//! measurement[,tag=value...] field=value[,field=value...] [timestamp]
+//! ```
+//!
+//! Commas, spaces and equals signs in names are backslash-escaped; string fields are double-quoted.
+//! Integers end in `i`, unsigned integers in `u`, anything else numeric is a float. A line without
+//! a timestamp is stamped with the time it was received.
+
+use std::collections::BTreeMap;
+use chrono::{DateTime, TimeZone, Utc};
+use strum_macros::{Display, EnumString};
+use common_lib::point::{FieldValue, Point};
+
+/// unit of the timestamps in a write; `n`, `u` and the minute/hour units are influx 1.x's names
+#[derive(Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+pub enum Precision {
+    #[default]
+    #[strum(serialize = "ns", serialize = "n")]
+    Nanosecond,
+    #[strum(serialize = "us", serialize = "u")]
+    Microsecond,
+    #[strum(serialize = "ms")]
+    Millisecond,
+    #[strum(serialize = "s")]
+    Second,
+    #[strum(serialize = "m")]
+    Minute,
+    #[strum(serialize = "h")]
+    Hour,
+}
+
+impl Precision {
+    pub fn nanos(&self) -> i64 {
+        match self {
+            Precision::Nanosecond => 1,
+            Precision::Microsecond => 1_000,
+            Precision::Millisecond => 1_000_000,
+            Precision::Second => 1_000_000_000,
+            Precision::Minute => 60 * 1_000_000_000,
+            Precision::Hour => 3600 * 1_000_000_000,
+        }
+    }
+}
+
+/// shown to whoever sent the write, so Display spells out what was wrong
+#[derive(Debug)]
+pub enum LineError {
+    Syntax { line: usize, reason: String },
+    Precision { precision: String },
+    Rejected { reason: String },
+    Db,
+}
+
+impl std::fmt::Display for LineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineError::Syntax { line, reason } => write!(f, "line {line}: {reason}"),
+            LineError::Precision { precision } => write!(f, "unknown precision {precision}"),
+            LineError::Rejected { reason } => write!(f, "{reason}"),
+            LineError::Db => write!(f, "database unavailable"),
+        }
+    }
+}
+
+impl std::error::Error for LineError {}
+
+/// every point in a write, or the first line that's wrong; blank lines and `#` comments are skipped
+pub fn parse(body: &str, precision: Precision, now: DateTime<Utc>) -> Result<Vec<Point>, LineError> {
+    body.lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, text)| parse_line(text, precision, now).map_err(|reason| LineError::Syntax { line, reason }))
+        .collect()
+}
+
+fn parse_line(line: &str, precision: Precision, now: DateTime<Utc>) -> Result<Point, String> {
+    let (series, rest) = split_once(line, ' ', false).ok_or("missing fields")?;
+    let (fields, timestamp) = match split_once(rest, ' ', true) {
+        Some((fields, timestamp)) => (fields, Some(timestamp.trim())),
+        None => (rest, None),
+    };
+
+    let mut keys = split(series, ',', false).into_iter();
+    let measurement = unescape(keys.next().unwrap_or_default(), &[',', ' ']);
+    if measurement.is_empty() {
+        return Err("missing measurement".to_string());
+    }
+    let tags = keys
+        .map(|tag| {
+            let (key, val) = key_value(tag)?;
+            match val.is_empty() {
+                true => Err(format!("tag {key} has no value")),
+                false => Ok((key, unescape(val, &[',', '=', ' ']))),
+            }
+        })
+        .collect::<Result<BTreeMap<_, _>, String>>()?;
+    let fields = split(fields, ',', true).into_iter()
+        .map(|field| {
+            let (key, val) = key_value(field)?;
+            Ok((key, field_value(val)?))
+        })
+        .collect::<Result<BTreeMap<_, _>, String>>()?;
+
+    let dtg = match timestamp {
+        None | Some("") => now,
+        Some(ts) => parse_timestamp(ts, precision)?,
+    };
+    Ok(Point { measurement, tags, fields, dtg })
+}
+
+fn key_value(pair: &str) -> Result<(String, &str), String> {
+    let (key, val) = split_once(pair, '=', false).ok_or_else(|| format!("expected key=value, got {pair}"))?;
+    let key = unescape(key, &[',', '=', ' ']);
+    match key.is_empty() {
+        true => Err(format!("missing key in {pair}")),
+        false => Ok((key, val)),
+    }
+}
+
+fn field_value(val: &str) -> Result<FieldValue, String> {
+    let bad = || format!("bad field value {val}");
+    if val.len() >= 2 && val.starts_with('"') && val.ends_with('"') {
+        return Ok(FieldValue::String(unescape(&val[1..val.len() - 1], &['"', '\\'])));
+    }
+    Ok(match val {
+        "t" | "T" | "true" | "True" | "TRUE" => FieldValue::Boolean(true),
+        "f" | "F" | "false" | "False" | "FALSE" => FieldValue::Boolean(false),
+        _ if val.ends_with('i') => FieldValue::Integer(val[..val.len() - 1].parse().map_err(|_| bad())?),
+        _ if val.ends_with('u') => FieldValue::UInteger(val[..val.len() - 1].parse().map_err(|_| bad())?),
+        _ => match val.parse::<f64>() {
+            Ok(x) if x.is_finite() => FieldValue::Float(x),
+            _ => return Err(bad()),
+        },
+    })
+}
+
+fn parse_timestamp(ts: &str, precision: Precision) -> Result<DateTime<Utc>, String> {
+    let bad = || format!("bad timestamp {ts}");
+    let ts: i64 = ts.parse().map_err(|_| bad())?;
+    let nanos = ts.checked_mul(precision.nanos()).ok_or_else(bad)?;
+    Ok(Utc.timestamp_nanos(nanos))
+}
+
+/// byte offsets of each `delim` that isn't backslash-escaped or, with `quotes`, inside a string field
+fn delimiters(s: &str, delim: char, quotes: bool) -> Vec<usize> {
+    let (mut escaped, mut quoted) = (false, false);
+    let mut found = vec![];
+    for (idx, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' if quotes => quoted = !quoted,
+            c if c == delim && !quoted => found.push(idx),
+            _ => {}
+        }
+    }
+    found
+}
+
+fn split(s: &str, delim: char, quotes: bool) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    for idx in delimiters(s, delim, quotes) {
+        parts.push(&s[start..idx]);
+        start = idx + delim.len_utf8();
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn split_once(s: &str, delim: char, quotes: bool) -> Option<(&str, &str)> {
+    delimiters(s, delim, quotes).first().map(|idx| (&s[..*idx], &s[idx + delim.len_utf8()..]))
+}
+
+/// drop the backslash in front of any of `escapable`; other backslashes are kept as written
+fn unescape(s: &str, escapable: &[char]) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '\\' && escapable.contains(next) => out.push(chars.next().unwrap_or_default()),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use chrono::{DateTime, TimeZone, Utc};
+    use common_lib::point::FieldValue;
+    use crate::line_protocol::{parse, LineError, Precision};
+
+    #[test]
+    fn test_parse() {
+        let now = Utc.timestamp_nanos(1);
+        let body = r#"
+# fills from the paper account
+fills,symbol=btc_usd,side=buy price=42000.5,qty=2i,fee=3u,maker=t,note="said \"hi\", twice" 1705275000000000001
+cpu\ load,host=a\,b\ c,dc=x\=y usage=0.5
+
+"#;
+        let points = parse(body, Precision::Nanosecond, now).unwrap();
+        assert_eq!(points.len(), 2);
+
+        let fill = &points[0];
+        assert_eq!(fill.measurement, "fills");
+        assert_eq!(fill.series(), "fills,side=buy,symbol=btc_usd");
+        assert_eq!(fill.fields["price"], FieldValue::Float(42000.5));
+        assert_eq!(fill.fields["qty"], FieldValue::Integer(2));
+        assert_eq!(fill.fields["fee"], FieldValue::UInteger(3));
+        assert_eq!(fill.fields["maker"], FieldValue::Boolean(true));
+        assert_eq!(fill.fields["note"], FieldValue::String(r#"said "hi", twice"#.to_string()));
+        assert_eq!(fill.dtg, DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00.000000001Z").unwrap()));
+
+        let cpu = &points[1];
+        assert_eq!(cpu.measurement, "cpu load");
+        assert_eq!(cpu.tags["host"], "a,b c");
+        assert_eq!(cpu.tags["dc"], "x=y");
+        assert_eq!(cpu.dtg, now);
+    }
+
+    #[test]
+    fn test_precision() {
+        let now = Utc::now();
+        let points = parse("m v=1 1705275000", Precision::from_str("s").unwrap(), now).unwrap();
+        assert_eq!(points[0].dtg, Utc.timestamp_opt(1705275000, 0).unwrap());
+        assert_eq!(Precision::from_str("u").unwrap(), Precision::from_str("us").unwrap());
+        assert!(Precision::from_str("d").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let now = Utc::now();
+        for bad in ["m", "m v", "m v=", ",t=1 v=1", "m,t v=1", "m,t= v=1", "m v=1x", "m v=NaN", "m =1", "m v=1 tomorrow", "m v=1i 99999999999999999999"] {
+            assert!(parse(bad, Precision::Nanosecond, now).is_err(), "{bad}");
+        }
+        match parse("m v=1\nm v=\"open", Precision::Nanosecond, now) {
+            Err(LineError::Syntax { line, .. }) => assert_eq!(line, 2),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(LineError::Syntax { line: 2, reason: "missing fields".to_string() }.to_string(), "line 2: missing fields");
+    }
+}
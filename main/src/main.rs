@@ -3,20 +3,65 @@
 use std::error::Error;
 use std::time::Duration;
 use chrono::{DateTime, Utc};
-use crossbeam_channel::Sender;
+use crossbeam_channel::{select, tick, Receiver, Sender};
 use tokio::sync::oneshot;
-use common_lib::{ChartDataset, UniversalError, DbMsg, SymbolCommon};
+use common_lib::{ChartDataset, UniversalError, DbMsg};
+use common_lib::alert::{load_rules, Fired};
+use common_lib::paper::load_strategies;
+use common_lib::auth::{hash_password, new_token, Auth};
+use common_lib::backfill::BackfillSpec;
+use common_lib::config::Config;
 use common_lib::init::init;
+use common_lib::point::MeasurementSchema;
+use common_lib::view::{ViewDelta, ViewSpec};
 use db::arrow_db;
+use db::backfill::read_tickers;
+use db::backtest::Backtest;
+use visual::dashboard::LayoutStore;
 use visual::http_server;
+use visual::webhook;
 use ws::client::ConnectSource;
 use ws_broadcast::command::Cmd;
+use ws_broadcast::CLIENT_QUEUE_SIZE;
 
 fn main() {
 
+    // values for a users file, then exit; see common_lib::auth
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|x| x == "--hash-password") {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("no password on stdin: {e}");
+            std::process::exit(1);
+        }
+        match hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{hash}"),
+            Err(e) => {
+                eprintln!("{e:?}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.iter().any(|x| x == "--new-token") {
+        let (token, digest) = new_token();
+        println!("token:  {token}\ndigest: {digest}");
+        return;
+    }
+
     // general logging stuff I always do
     init(env!("CARGO_MANIFEST_DIR"));
 
+    // every setting: --config file, then CRATE_* env, then flags; see common_lib::config
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("[main] config error: {:?}", &e);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("[main] config: {}", serde_json::to_string(&config).unwrap_or_default());
+
     let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
         // .worker_threads(7)
         .on_thread_start(|| {})
@@ -26,34 +71,151 @@ fn main() {
         .build()
         .expect("Tokio runtime didn't start");
 
+    // strategies over the backfill files, offline: print the report and exit, nothing live starts
+    if args.iter().any(|x| x == "--backtest") {
+        match tokio_runtime.block_on(run_backtest(&args, &config)) {
+            Ok(report) => println!("{report}"),
+            Err(e) => {
+                tracing::error!("[main] backtest error: {:?}", &e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     // database thread
     let tx_db = arrow_db::run(tokio_runtime.handle().clone());
+    for msg in [DbMsg::SetRetention(config.retention), DbMsg::SetIndicators(config.indicators.clone())] {
+        if let Err(e) = tx_db.send(msg) {
+            tracing::error!("[main] db not configured: {:?}", &e);
+        }
+    }
+
+    // declare measurements named on the command line before anything can write to them
+    match MeasurementSchema::from_args(&args) {
+        Ok(schemas) => {
+            for schema in schemas {
+                let name = schema.name.clone();
+                match tokio_runtime.block_on(request_define_measurement(tx_db.clone(), schema)) {
+                    Ok(_) => tracing::info!("[main] declared measurement {name}"),
+                    Err(e) => tracing::error!("[main] measurement {name} not declared: {:?}", &e),
+                }
+            }
+        },
+        Err(e) => tracing::error!("[main] measurement argument error: {:?}", &e),
+    }
+
+    // paper trading strategies, before any backfill so they trade it
+    match load_strategies(&config.strategies) {
+        Ok(strategies) => {
+            tracing::info!("[main] {} paper strategies from {:?}", strategies.len(), &config.strategies);
+            if let Err(e) = tx_db.send(DbMsg::SetStrategies(strategies)) {
+                tracing::error!("[main] paper strategies not set: {:?}", &e);
+            }
+        }
+        Err(e) => {
+            tracing::error!("[main] paper strategies error: {:?}", &e);
+            std::process::exit(1);
+        }
+    }
+
+    // load any historical files named on the command line before the live feeds start
+    match BackfillSpec::from_args(&args) {
+        Ok(specs) => {
+            for spec in specs {
+                match tokio_runtime.block_on(request_backfill(tx_db.clone(), spec)) {
+                    Ok(count) => tracing::info!("[main] backfilled {count} ticks"),
+                    Err(e) => tracing::error!("[main] backfill error: {:?}", &e),
+                }
+            }
+        },
+        Err(e) => tracing::error!("[main] backfill argument error: {:?}", &e),
+    }
+
+    // alert rules, checked against live ticks from here on
+    match load_rules(&config.alerts) {
+        Ok(rules) => {
+            tracing::info!("[main] {} alert rules from {:?}", rules.len(), &config.alerts);
+            if let Err(e) = tx_db.send(DbMsg::SetAlertRules(rules)) {
+                tracing::error!("[main] alert rules not set: {:?}", &e);
+            }
+        }
+        Err(e) => {
+            tracing::error!("[main] alert rules error: {:?}", &e);
+            std::process::exit(1);
+        }
+    }
+
+    // saved dashboard layouts
+    let layouts = LayoutStore::new(&config.dashboards);
+
+    // users for the web ui, the apis, flight sql and postgres; none means auth is off
+    let auth = match Auth::from_config(&config.auth) {
+        Ok(auth) => auth,
+        Err(e) => {
+            tracing::error!("[main] users file error: {:?}", &e);
+            std::process::exit(1);
+        }
+    };
 
     // run coinbase and alpaca threads
-    let mut handles = vec!();
-    handles.push(ws::client::run(ConnectSource::Coinbase, tx_db.clone()));
-    handles.push(ws::client::run(ConnectSource::Alpaca, tx_db.clone()));
-
-    // broadcast websocket
-    let (server_tx, server_rx) = crossbeam_channel::unbounded::<ws_broadcast::command::Cmd>();
-    let _h1 = std::thread::spawn(move || {
-        std::thread::spawn(|| {
-            let mut server = ws_broadcast::server::Server::new();
-            server.run(server_rx);
-        });
-    });
+    let mut handles = vec![];
+    for (source, feed) in [(ConnectSource::Coinbase, &config.feeds.coinbase), (ConnectSource::Alpaca, &config.feeds.alpaca)] {
+        match feed.enabled {
+            true => handles.push(ws::client::run(source, feed.clone(), tx_db.clone())),
+            false => tracing::info!("[main] {source:?} feed disabled"),
+        }
+    }
+
+    // chart deltas for the /ws route
+    // bounded like the hub behind it; the refresher and alert threads wait if the hub falls behind
+    let (server_tx, server_rx) = crossbeam_channel::bounded::<Cmd>(CLIENT_QUEUE_SIZE);
 
     // tokio runtime herein
     let tx_db2 = tx_db.clone();
     tokio_runtime.block_on(async {
-        // start 1-minute chart poll for websocket
+        // chart deltas from the db thread, merged each second for the /ws hub
         let tx_db3 = tx_db2.clone();
-        spawn_chart_refresher(tx_db3, server_tx);
+        spawn_chart_refresher(tx_db3, server_tx.clone(), config.retention.chart_limit);
+
+        // fired alerts to chart clients and webhooks
+        spawn_alert_relay(tx_db2.clone(), server_tx);
+
+        // sql over grpc for dbeaver, adbc, pyarrow
+        let tx_db4 = tx_db2.clone();
+        let auth4 = auth.clone();
+        let addr4 = config.listeners.flight_sql.clone();
+        tokio::spawn(async move {
+            if let Err(e) = flight_sql::run(tx_db4, &addr4, auth4).await {
+                tracing::error!("[main] flight sql server not started: {:?}", &e);
+            }
+        });
+
+        // the same tables over the postgres wire protocol for psql
+        let tx_db5 = tx_db2.clone();
+        let auth5 = auth.clone();
+        let addr5 = config.listeners.pg.clone();
+        tokio::spawn(async move {
+            if let Err(e) = pg_server::run(tx_db5, &addr5, auth5).await {
+                tracing::error!("[main] postgres server not started: {:?}", &e);
+            }
+        });
+
+        // line protocol over udp, without auth; the http write routes are on the web server
+        let tx_db6 = tx_db2.clone();
+        let addr6 = config.listeners.influx_udp.clone();
+        let auth_open6 = auth.is_open();
+        tokio::spawn(async move {
+            if let Err(e) = influx::run_udp(tx_db6, &addr6, auth_open6).await {
+                tracing::error!("[main] influx udp listener not started: {:?}", &e);
+            }
+        });
 
         // start web server
-        tracing::info!("[main] web server starting on http://127.0.0.1:8080");
-        match http_server::run(tx_db2).await{
-            Ok(_) => tracing::debug!("[main] web server started on http://127.0.0.1:8080"),
+        let url = config.http_url();
+        tracing::info!("[main] web server starting on {}", &url);
+        match http_server::run(tx_db2, server_rx, layouts, auth, config).await{
+            Ok(_) => tracing::debug!("[main] web server stopped on {}", &url),
             Err(e) => tracing::debug!("[main] web server not started: {:?}", &e),
         }
     });
@@ -66,27 +228,80 @@ fn main() {
 
 /// Here's where the websocket server broadcasts data every second.
 ///
-/// todo: potentially only send data since last websocket broadcast (results in annoying client side
-/// javascript so this current sends all chart data every second)
+/// The db thread keeps a continuous view of every price and calculation and pushes deltas as ticks
+/// arrive; a second's worth are merged and handed to the websocket server, which sends each client
+/// only the series it subscribed to, past its high-water marks.
 ///
 /// TODO: move this to websocket server.rs
-fn spawn_chart_refresher(tx_db: Sender<DbMsg>, server_tx: Sender<Cmd>) {
+fn spawn_chart_refresher(tx_db: Sender<DbMsg>, server_tx: Sender<Cmd>, chart_limit: usize) {
     tokio::spawn(async move {
-        // a long, long time ago...
-        let most_recent_date: DateTime<Utc> = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2023-12-24T04:08:00-00:00").unwrap());
-        loop {
-
-            // match request_chart_since(tx_db.clone(), Datasource::Alpaca, most_recent_date).await{
-            match request_chart_since(tx_db.clone(), most_recent_date).await{
-                Ok(chart_vec) => {
-                    if let Ok(json_str) = serde_json::to_string::<Vec<ChartDataset>>(&chart_vec){
-                        server_tx.send(Cmd::Broadcast(json_str)).unwrap();
+        let spec = ViewSpec::new("chart_ws").limit(chart_limit);
+        let rx_view = match request_view(tx_db, spec).await {
+            Ok(rx_view) => rx_view,
+            Err(e) => {
+                tracing::error!("[spawn_chart_refresher] view not registered: {:?}", &e);
+                return;
+            }
+        };
+
+        // crossbeam receives block, so merge the deltas on their own thread
+        std::thread::spawn(move || {
+            let mut pending: Option<ViewDelta> = None;
+            let ticker = tick(Duration::from_secs(1));
+            loop {
+                select! {
+                    recv(rx_view) -> delta => match delta {
+                        Ok(delta) => match pending.as_mut() {
+                            Some(p) => p.merge(delta),
+                            None => pending = Some(delta),
+                        },
+                        Err(e) => {
+                            tracing::error!("[spawn_chart_refresher] view closed: {:?}", &e);
+                            break;
+                        }
+                    },
+                    recv(ticker) -> _ => {
+                        if let Some(delta) = pending.take() {
+                            if let Err(e) = server_tx.send(Cmd::ChartDelta(delta)) {
+                                tracing::error!("[spawn_chart_refresher] websocket hub closed: {:?}", &e);
+                                break;
+                            }
+                        }
                     }
-                },
-                Err(_) => {}
+                }
             }
-            std::thread::sleep(Duration::from_secs(1));
-        }
+        });
+    });
+}
+
+/// alerts go to every subscribed chart client, and to the rule's webhook if it has one
+fn spawn_alert_relay(tx_db: Sender<DbMsg>, server_tx: Sender<Cmd>) {
+    let handle = tokio::runtime::Handle::current();
+    tokio::spawn(async move {
+        let rx_alerts = match request_alerts(tx_db).await {
+            Ok(rx_alerts) => rx_alerts,
+            Err(e) => {
+                tracing::error!("[spawn_alert_relay] alerts not subscribed: {:?}", &e);
+                return;
+            }
+        };
+
+        std::thread::spawn(move || {
+            for Fired { alert, webhook } in rx_alerts.iter() {
+                tracing::info!("[spawn_alert_relay] {}: {}", &alert.rule, &alert.message);
+                if let Some(url) = webhook {
+                    let alert = alert.clone();
+                    handle.spawn(async move {
+                        if let Err(e) = webhook::post(&url, &alert).await {
+                            tracing::error!("[spawn_alert_relay] {} webhook not delivered: {:?}", &alert.rule, &e);
+                        }
+                    });
+                }
+                if server_tx.send(Cmd::Alert(alert)).is_err() {
+                    break;
+                }
+            }
+        });
     });
 }
 
@@ -94,25 +309,73 @@ fn _get_most_recent_date(chart_data: Vec<ChartDataset>) -> DateTime<Utc> {
     // todo: de-clone() all this
     let test: Vec<Vec<DateTime<Utc>>> = chart_data.iter().map(|a| a.data.iter().map(|b| b.x).collect()).collect();
     tracing::debug!("[main] chart_vec, test len: {}", &test.len());
-    let test2: Vec<DateTime<Utc>> = test.iter().map(|x|x.clone()).flatten().collect::<Vec<DateTime<Utc>>>();
+    let test2: Vec<DateTime<Utc>> = test.iter().flatten().cloned().collect::<Vec<DateTime<Utc>>>();
     tracing::debug!("[main] chart_vec, test2 len: {}", &test2.len());
     let max:DateTime<Utc> = match test2.iter().max(){
-        Some(m) => m.clone(),
+        Some(m) => *m,
         None => DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2023-12-01T04:08:00-00:00").unwrap())
     };
     tracing::debug!("[get_most_recent_date] {}", max);
     max
 }
 
-/// duplicated from visual
-///
-/// TODO: currently coinbase-specific
-async fn request_chart_since(tx_db: Sender<DbMsg>, since: DateTime<Utc>) -> Result<Vec<ChartDataset>, Box<dyn Error>> {
+/// "--backtest-sql <query>" as many times as wanted, or the summary and the trades
+async fn run_backtest(args: &[String], config: &Config) -> Result<String, Box<dyn Error>> {
+    let strategies = load_strategies(&config.strategies)?;
+    let specs = BackfillSpec::from_args(args)?;
+    let mut queries = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--backtest-sql" {
+            queries.push(iter.next().ok_or_else(|| UniversalError::DbError(format!("{arg} needs a query")))?.clone());
+        }
+    }
+    if queries.is_empty() {
+        queries.push("select * from backtest_summary order by strategy".to_string());
+        queries.push("select * from backtest_trades order by dtg".to_string());
+    }
+    tracing::info!("[main] backtest of {} strategies over {} files", strategies.len(), specs.len());
+    let mut backtest = Backtest::new(config.indicators.clone()).strategies(strategies);
+    backtest.run(&specs).await?;
+    Ok(backtest.report(&queries).await?)
+}
+
+/// read a historical file here on the runtime, then hand its rows to the database thread; resolves
+/// once every row is inserted
+async fn request_backfill(tx_db: Sender<DbMsg>, spec: BackfillSpec) -> Result<usize, Box<dyn Error>> {
+    let tickers = read_tickers(&spec).await?;
+    let (sender, rx) = oneshot::channel();
+    match tx_db.send(DbMsg::RqstBackfill { source: spec.source, tickers, sender }) {
+        Ok(_)=> Ok(rx.await?),
+        Err(_)=> Err(Box::new(UniversalError::SendError))
+    }
+}
+
+/// fix a measurement's tags and field types
+async fn request_define_measurement(tx_db: Sender<DbMsg>, schema: MeasurementSchema) -> Result<(), Box<dyn Error>> {
+    let (sender, rx) = oneshot::channel();
+    match tx_db.send(DbMsg::RqstDefineMeasurement { schema, sender }) {
+        Ok(_)=> Ok(rx.await??),
+        Err(_)=> Err(Box::new(UniversalError::SendError))
+    }
+}
+
+/// every alert fired from now on
+async fn request_alerts(tx_db: Sender<DbMsg>) -> Result<Receiver<Fired>, Box<dyn Error>> {
+    let (sender, rx) = oneshot::channel();
+    match tx_db.send(DbMsg::RqstAlerts { sender }) {
+        Ok(_)=> Ok(rx.await?),
+        Err(_)=> Err(Box::new(UniversalError::SendError))
+    }
+}
+
+/// subscribe to a continuous view; the first delta on the returned channel is a snapshot
+async fn request_view(tx_db: Sender<DbMsg>, spec: ViewSpec) -> Result<Receiver<ViewDelta>, Box<dyn Error>> {
     let (sender, rx) = oneshot::channel();
-    match tx_db.send(DbMsg::RqstChartSince { sender, symbol: vec![SymbolCommon::BtcUsd], since}) {
+    match tx_db.send(DbMsg::RqstView { spec, sender }) {
         Ok(_)=> {
-            let chart = rx.await?;
-            Ok(chart)
+            let rx_view = rx.await?;
+            Ok(rx_view)
         },
         Err(_)=> Err(Box::new(UniversalError::SendError))
     }
@@ -0,0 +1,107 @@
+//! auth.rs
+//!
+//! postgres logins against common_lib::auth's users: the client is asked for a cleartext password,
+//! which is the user's password or one of their api tokens. With auth off any login is accepted.
+
+use std::fmt::Debug;
+use async_trait::async_trait;
+use futures::sink::{Sink, SinkExt};
+use pgwire::api::auth::{finish_authentication, save_startup_parameters_to_metadata, DefaultServerParameterProvider, StartupHandler};
+use pgwire::api::{ClientInfo, PgWireConnectionState, METADATA_USER};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::response::ErrorResponse;
+use pgwire::messages::startup::Authentication;
+use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+use common_lib::auth::{Auth, Caller};
+
+pub struct PasswordStartupHandler {
+    auth: Auth,
+    parameters: DefaultServerParameterProvider,
+}
+
+impl PasswordStartupHandler {
+    pub fn new(auth: Auth) -> PasswordStartupHandler {
+        PasswordStartupHandler { auth, parameters: DefaultServerParameterProvider::default() }
+    }
+
+    /// argon2 is slow, so off the connection's task
+    async fn check(&self, user: String, password: String) -> Option<Caller> {
+        let auth = self.auth.clone();
+        tokio::task::spawn_blocking(move || auth.login(&user, &password).or_else(|| auth.token(&password).filter(|x| x.name == user)))
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+#[async_trait]
+impl StartupHandler for PasswordStartupHandler {
+    async fn on_startup<C>(&self, client: &mut C, message: PgWireFrontendMessage) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        match message {
+            PgWireFrontendMessage::Startup(ref startup) => {
+                save_startup_parameters_to_metadata(client, startup);
+                if self.auth.is_open() {
+                    finish_authentication(client, &self.parameters).await;
+                } else {
+                    client.set_state(PgWireConnectionState::AuthenticationInProgress);
+                    client.send(PgWireBackendMessage::Authentication(Authentication::CleartextPassword)).await?;
+                }
+            }
+            PgWireFrontendMessage::PasswordMessageFamily(password) => {
+                let password = password.into_password()?.password().to_string();
+                let user = client.metadata().get(METADATA_USER).cloned().unwrap_or_default();
+                match self.check(user.clone(), password).await {
+                    Some(caller) => {
+                        tracing::info!("[pg_server] {} logged in ({})", caller.name, caller.role);
+                        finish_authentication(client, &self.parameters).await;
+                    }
+                    None => {
+                        tracing::info!("[pg_server] failed login for {user}");
+                        let error = ErrorInfo::new("FATAL".to_string(), "28P01".to_string(), format!("password authentication failed for user \"{user}\""));
+                        client.feed(PgWireBackendMessage::ErrorResponse(ErrorResponse::from(error))).await?;
+                        client.close().await?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_postgres::NoTls;
+    use common_lib::auth::{hash_password, new_token, Auth, Role, UserRecord, UsersFile};
+    use db::arrow_db;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_password_startup() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let (token, digest) = new_token();
+        let auth = Auth::new(UsersFile { users: vec![
+            UserRecord { name: "gp".to_string(), role: Role::Read, password: Some(hash_password("hunter2").unwrap()), tokens: vec![digest] },
+        ]}).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(crate::serve(tx_db, listener, auth));
+
+        for (user, password, ok) in [("gp", "hunter2", true), ("gp", token.as_str(), true), ("gp", "hunter3", false), ("nobody", token.as_str(), false)] {
+            let connected = tokio_postgres::connect(&format!("host=127.0.0.1 port={port} user={user} password={password}"), NoTls).await;
+            match connected {
+                Ok((client, connection)) => {
+                    assert!(ok, "{user} {password}");
+                    tokio::spawn(connection);
+                    assert_eq!(client.query_one("select 1 + 1 as two", &[]).await.unwrap().get::<_, i64>("two"), 2);
+                }
+                Err(e) => assert!(!ok, "{user} {password}: {e}"),
+            }
+        }
+    }
+}
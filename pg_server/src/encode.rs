@@ -0,0 +1,190 @@
+//! encode.rs
+//!
+//! arrow <-> postgres: column types, result rows and bound parameters
+
+use std::sync::Arc;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use datafusion::arrow::array::{Array, ArrayRef, AsArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{
+    DataType, Date32Type, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, TimeUnit, TimestampMicrosecondType,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::{DFSchema, ScalarValue};
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::LogicalPlan;
+use pgwire::api::portal::{Format, Portal};
+use pgwire::api::results::{DataRowEncoder, FieldInfo};
+use pgwire::api::Type;
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::data::DataRow;
+
+/// sqlstate for sql the client got wrong, and for everything else
+const SQLSTATE_SYNTAX: &str = "42601";
+const SQLSTATE_INTERNAL: &str = "XX000";
+
+fn user_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new("ERROR".to_string(), code.to_string(), message)))
+}
+
+pub fn internal_error(message: String) -> PgWireError {
+    user_error(SQLSTATE_INTERNAL, message)
+}
+
+pub fn df_error(e: DataFusionError) -> PgWireError {
+    match e {
+        DataFusionError::SQL(_) | DataFusionError::Plan(_) | DataFusionError::SchemaError(_) => user_error(SQLSTATE_SYNTAX, e.to_string()),
+        _ => user_error(SQLSTATE_INTERNAL, e.to_string()),
+    }
+}
+
+/// postgres type for an arrow column; the column is cast to `canonical` before encoding
+fn pg_type(data_type: &DataType) -> PgWireResult<Type> {
+    Ok(match canonical(data_type)? {
+        DataType::Boolean => Type::BOOL,
+        DataType::Int16 => Type::INT2,
+        DataType::Int32 => Type::INT4,
+        DataType::Int64 => Type::INT8,
+        DataType::Float32 => Type::FLOAT4,
+        DataType::Float64 => Type::FLOAT8,
+        DataType::Utf8 => Type::TEXT,
+        DataType::Date32 => Type::DATE,
+        DataType::Timestamp(_, Some(_)) => Type::TIMESTAMPTZ,
+        DataType::Timestamp(_, None) => Type::TIMESTAMP,
+        DataType::Null => Type::UNKNOWN,
+        other => return Err(user_error(SQLSTATE_INTERNAL, format!("unsupported column type {other}"))),
+    })
+}
+
+/// the handful of arrow types rows are encoded from
+fn canonical(data_type: &DataType) -> PgWireResult<DataType> {
+    Ok(match data_type {
+        DataType::Int8 | DataType::UInt8 => DataType::Int16,
+        DataType::UInt16 => DataType::Int32,
+        DataType::UInt32 | DataType::UInt64 => DataType::Int64,
+        DataType::Float16 => DataType::Float32,
+        DataType::LargeUtf8 | DataType::Dictionary(_, _) => DataType::Utf8,
+        DataType::Date64 => DataType::Date32,
+        DataType::Timestamp(_, tz) => DataType::Timestamp(TimeUnit::Microsecond, tz.clone()),
+        DataType::Boolean | DataType::Int16 | DataType::Int32 | DataType::Int64 | DataType::Float32 | DataType::Float64
+        | DataType::Utf8 | DataType::Date32 | DataType::Null => data_type.clone(),
+        other => return Err(user_error(SQLSTATE_INTERNAL, format!("unsupported column type {other}"))),
+    })
+}
+
+/// row description for a result; `format` is what the client bound, text for simple queries
+pub fn fields(schema: &DFSchema, format: &Format) -> PgWireResult<Vec<FieldInfo>> {
+    schema.fields().iter().enumerate()
+        .map(|(idx, f)| Ok(FieldInfo::new(f.name().to_string(), None, None, pg_type(f.data_type())?, format.format_for(idx))))
+        .collect()
+}
+
+pub fn rows(batches: &[RecordBatch], fields: Arc<Vec<FieldInfo>>) -> PgWireResult<Vec<PgWireResult<DataRow>>> {
+    let mut rows = vec![];
+    for batch in batches {
+        let columns = batch.columns().iter()
+            .map(|c| cast(c, &canonical(c.data_type())?).map_err(|e| df_error(e.into())))
+            .collect::<PgWireResult<Vec<ArrayRef>>>()?;
+        for row in 0..batch.num_rows() {
+            let mut encoder = DataRowEncoder::new(fields.clone());
+            for column in columns.iter() {
+                encode_value(&mut encoder, column, row)?;
+            }
+            rows.push(encoder.finish());
+        }
+    }
+    Ok(rows)
+}
+
+fn encode_value(encoder: &mut DataRowEncoder, column: &ArrayRef, row: usize) -> PgWireResult<()> {
+    if column.is_null(row) {
+        return encoder.encode_field(&None::<i8>);
+    }
+    match column.data_type() {
+        DataType::Boolean => encoder.encode_field(&column.as_boolean().value(row)),
+        DataType::Int16 => encoder.encode_field(&column.as_primitive::<Int16Type>().value(row)),
+        DataType::Int32 => encoder.encode_field(&column.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => encoder.encode_field(&column.as_primitive::<Int64Type>().value(row)),
+        DataType::Float32 => encoder.encode_field(&column.as_primitive::<Float32Type>().value(row)),
+        DataType::Float64 => encoder.encode_field(&column.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => encoder.encode_field(&column.as_string::<i32>().value(row)),
+        DataType::Date32 => {
+            let days = column.as_primitive::<Date32Type>().value(row);
+            let date = NaiveDate::from_num_days_from_ce_opt(days + 719_163).unwrap_or_default();
+            encoder.encode_field(&date)
+        }
+        DataType::Timestamp(_, tz) => {
+            let micros = column.as_primitive::<TimestampMicrosecondType>().value(row);
+            let dtg = DateTime::<Utc>::from_timestamp(micros.div_euclid(1_000_000), (micros.rem_euclid(1_000_000) * 1000) as u32).unwrap_or_default();
+            match tz {
+                Some(_) => encoder.encode_field(&dtg),
+                None => encoder.encode_field(&dtg.naive_utc()),
+            }
+        }
+        other => Err(user_error(SQLSTATE_INTERNAL, format!("unsupported column type {other}"))),
+    }
+}
+
+/// $1..$n in order, typed from the plan; anything DataFusion can't infer goes over as text
+pub fn param_types(plan: &LogicalPlan) -> PgWireResult<Vec<Type>> {
+    let mut types: Vec<(usize, Type)> = plan.get_parameter_types().map_err(df_error)?
+        .into_iter()
+        .map(|(id, data_type)| {
+            let idx = id.trim_start_matches('$').parse::<usize>().map_err(|_| user_error(SQLSTATE_SYNTAX, format!("bad parameter {id}")))?;
+            let pg = data_type.map(|t| pg_type(&t)).transpose()?.unwrap_or(Type::TEXT);
+            Ok((idx, pg))
+        })
+        .collect::<PgWireResult<_>>()?;
+    types.sort_by_key(|(idx, _)| *idx);
+    Ok(types.into_iter().map(|(_, t)| t).collect())
+}
+
+/// bound parameters as DataFusion values, in either wire format
+pub fn params(portal: &Portal<String>, types: &[Type]) -> PgWireResult<Vec<ScalarValue>> {
+    (0..portal.parameter_len())
+        .map(|idx| {
+            let pg = types.get(idx).unwrap_or(&Type::TEXT);
+            if portal.parameter_format().is_binary(idx) {
+                binary_param(portal, idx, pg)
+            } else {
+                text_param(portal.parameters()[idx].as_deref(), pg)
+            }
+        })
+        .collect()
+}
+
+fn binary_param(portal: &Portal<String>, idx: usize, pg: &Type) -> PgWireResult<ScalarValue> {
+    Ok(match *pg {
+        Type::BOOL => ScalarValue::Boolean(portal.parameter(idx, pg)?),
+        Type::INT2 => ScalarValue::Int16(portal.parameter(idx, pg)?),
+        Type::INT4 => ScalarValue::Int32(portal.parameter(idx, pg)?),
+        Type::INT8 => ScalarValue::Int64(portal.parameter(idx, pg)?),
+        Type::FLOAT4 => ScalarValue::Float32(portal.parameter(idx, pg)?),
+        Type::FLOAT8 => ScalarValue::Float64(portal.parameter(idx, pg)?),
+        Type::TIMESTAMPTZ => {
+            let dtg: Option<DateTime<Utc>> = portal.parameter(idx, pg)?;
+            ScalarValue::TimestampMicrosecond(dtg.map(|d| d.timestamp_micros()), Some("UTC".into()))
+        }
+        Type::TIMESTAMP => {
+            let dtg: Option<NaiveDateTime> = portal.parameter(idx, pg)?;
+            ScalarValue::TimestampMicrosecond(dtg.map(|d| d.timestamp_micros()), None)
+        }
+        _ => ScalarValue::Utf8(portal.parameter(idx, pg)?),
+    })
+}
+
+fn text_param(bytes: Option<&[u8]>, pg: &Type) -> PgWireResult<ScalarValue> {
+    let text = bytes.map(std::str::from_utf8).transpose()
+        .map_err(|_| user_error(SQLSTATE_SYNTAX, "parameter is not utf8".to_string()))?;
+    let bad = || user_error(SQLSTATE_SYNTAX, format!("bad {} parameter: {}", pg.name(), text.unwrap_or_default()));
+    Ok(match *pg {
+        Type::BOOL => ScalarValue::Boolean(text.map(|t| matches!(t, "t" | "true" | "1" | "on" | "yes"))),
+        Type::INT2 => ScalarValue::Int16(text.map(str::parse).transpose().map_err(|_| bad())?),
+        Type::INT4 => ScalarValue::Int32(text.map(str::parse).transpose().map_err(|_| bad())?),
+        Type::INT8 => ScalarValue::Int64(text.map(str::parse).transpose().map_err(|_| bad())?),
+        Type::FLOAT4 => ScalarValue::Float32(text.map(str::parse).transpose().map_err(|_| bad())?),
+        Type::FLOAT8 => ScalarValue::Float64(text.map(str::parse).transpose().map_err(|_| bad())?),
+        // DataFusion casts strings to timestamps wherever they're compared with one
+        _ => ScalarValue::Utf8(text.map(str::to_string)),
+    })
+}
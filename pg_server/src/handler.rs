@@ -0,0 +1,181 @@
+//! handler.rs
+//!
+//! one handler per connection. Simple queries plan and run straight away; extended queries keep
+//! their sql in the portal store and are planned again on describe and execute, each time against a
+//! fresh snapshot of the tables.
+
+use std::sync::Arc;
+use std::time::Instant;
+use async_trait::async_trait;
+use crossbeam_channel::Sender;
+use datafusion::prelude::DataFrame;
+use datafusion::sql::parser::DFParser;
+use pgwire::api::portal::{Format, Portal};
+use pgwire::api::query::{ExtendedQueryHandler, SimpleQueryHandler, StatementOrPortal};
+use pgwire::api::results::{DescribeResponse, QueryResponse, Response};
+use pgwire::api::stmt::NoopQueryParser;
+use pgwire::api::store::MemPortalStore;
+use pgwire::api::ClientInfo;
+use pgwire::error::PgWireResult;
+use common_lib::DbMsg;
+use common_lib::metrics::METRICS;
+use crate::encode;
+use crate::encode::{df_error, internal_error};
+
+pub struct PgHandler {
+    tx_db: Sender<DbMsg>,
+    portal_store: Arc<MemPortalStore<String>>,
+    query_parser: Arc<NoopQueryParser>,
+}
+
+impl PgHandler {
+    pub fn new(tx_db: Sender<DbMsg>) -> PgHandler {
+        PgHandler {
+            tx_db,
+            portal_store: Arc::new(MemPortalStore::new()),
+            query_parser: Arc::new(NoopQueryParser::new()),
+        }
+    }
+
+    async fn plan(&self, sql: &str) -> PgWireResult<DataFrame> {
+        let ctx = db::sql::snapshot(&self.tx_db).await.map_err(|e| internal_error(e.to_string()))?;
+        ctx.sql_with_options(sql, db::sql::read_only()).await.map_err(df_error)
+    }
+
+    /// plan a portal's statement with its parameters bound; types the client didn't give in its
+    /// parse are the ones describe reported
+    async fn bind(&self, portal: &Portal<String>) -> PgWireResult<DataFrame> {
+        let statement = portal.statement();
+        let df = self.plan(statement.statement()).await?;
+        let types = match statement.parameter_types().is_empty() {
+            true => encode::param_types(df.logical_plan())?,
+            false => statement.parameter_types().clone(),
+        };
+        df.with_param_values(encode::params(portal, &types)?).map_err(df_error)
+    }
+}
+
+/// collect the planned query; start is when planning began, for the query latency
+async fn query_response<'a>(df: DataFrame, format: &Format, start: Instant) -> PgWireResult<QueryResponse<'a>> {
+    let fields = Arc::new(encode::fields(df.schema(), format)?);
+    let batches = df.collect().await.map_err(df_error)?;
+    METRICS.query("postgres", start.elapsed());
+    let rows = encode::rows(&batches, fields.clone())?;
+    Ok(QueryResponse::new(fields, futures::stream::iter(rows)))
+}
+
+#[async_trait]
+impl SimpleQueryHandler for PgHandler {
+    /// one response per statement; results are always text
+    async fn do_query<'a, 'b: 'a, C>(&'b self, _client: &C, query: &'a str) -> PgWireResult<Vec<Response<'a>>>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let statements = DFParser::parse_sql(query).map_err(|e| df_error(e.into()))?;
+        let mut responses = vec![];
+        for statement in statements {
+            let start = Instant::now();
+            let df = self.plan(&statement.to_string()).await?;
+            responses.push(Response::Query(query_response(df, &Format::UnifiedText, start).await?));
+        }
+        Ok(responses)
+    }
+}
+
+#[async_trait]
+impl ExtendedQueryHandler for PgHandler {
+    type Statement = String;
+    type QueryParser = NoopQueryParser;
+    type PortalStore = MemPortalStore<String>;
+
+    fn portal_store(&self) -> Arc<Self::PortalStore> {
+        self.portal_store.clone()
+    }
+
+    fn query_parser(&self) -> Arc<Self::QueryParser> {
+        self.query_parser.clone()
+    }
+
+    async fn do_describe<C>(&self, _client: &mut C, target: StatementOrPortal<'_, String>) -> PgWireResult<DescribeResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        match target {
+            // result formats aren't known until bind, so fields go out as text
+            StatementOrPortal::Statement(statement) => {
+                let df = self.plan(statement.statement()).await?;
+                let params = encode::param_types(df.logical_plan())?;
+                Ok(DescribeResponse::new(Some(params), encode::fields(df.schema(), &Format::UnifiedText)?))
+            }
+            StatementOrPortal::Portal(portal) => {
+                let df = self.bind(portal).await?;
+                Ok(DescribeResponse::new(None, encode::fields(df.schema(), portal.result_column_format())?))
+            }
+        }
+    }
+
+    async fn do_query<'a, 'b: 'a, C>(&'b self, _client: &mut C, portal: &'a Portal<String>, _max_rows: usize) -> PgWireResult<Response<'a>>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let start = Instant::now();
+        let df = self.bind(portal).await?;
+        Ok(Response::Query(query_response(df, portal.result_column_format(), start).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use tokio::net::TcpListener;
+    use tokio_postgres::{NoTls, SimpleQueryMessage};
+    use common_lib::auth::Auth;
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::{DbMsg, SymbolCommon, TickerCommon};
+    use db::arrow_db;
+
+    /// a live db thread with a few ticks, a postgres listener on a free port, and tokio-postgres
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_postgres_client() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let dtg = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        for (source, symbol, price) in [
+            (Datasource::Coinbase, SymbolCommon::BtcUsd, 42000.0),
+            (Datasource::Coinbase, SymbolCommon::BtcUsd, 42002.0),
+            (Datasource::Coinbase, SymbolCommon::EthUsd, 2500.0),
+            (Datasource::Alpaca, SymbolCommon::BtcUsd, 41990.0),
+        ] {
+            let ticker = TickerCommon { source: source.clone(), symbol, price, dtg, trade_id: None };
+            tx_db.send(DbMsg::Insert(source, ticker)).unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(crate::serve(tx_db, listener, Auth::open()));
+        let (client, connection) = tokio_postgres::connect(&format!("host=127.0.0.1 port={port} user=test"), NoTls).await.unwrap();
+        tokio::spawn(connection);
+
+        // simple query, as psql -c sends it
+        let messages = client.simple_query("select avg(price) as avg from coinbase_ticks where symbol = 'btc_usd'").await.unwrap();
+        let row = messages.iter().find_map(|m| match m {
+            SimpleQueryMessage::Row(row) => Some(row),
+            _ => None,
+        });
+        assert_eq!(row.unwrap().get("avg"), Some("42001"));
+
+        // extended query with typed, binary parameters and results
+        let rows = client.query("select source, price, dtg from ticks where symbol = $1 and price > $2 order by price", &[&"btc_usd", &41995.0f64]).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get::<_, &str>("source"), "coinbase");
+        assert_eq!(rows[0].get::<_, f64>("price"), 42000.0);
+        assert_eq!(rows[1].get::<_, DateTime<Utc>>("dtg"), dtg);
+
+        let statement = client.prepare("select count(*) as n from alpaca_ticks").await.unwrap();
+        assert_eq!(client.query_one(&statement, &[]).await.unwrap().get::<_, i64>("n"), 1);
+
+        // errors come back as errors and the connection carries on
+        assert!(client.simple_query("select nope from ticks").await.is_err());
+        assert!(client.simple_query("create view v as select 1").await.is_err());
+        assert_eq!(client.query_one("select 1 + 1 as two", &[]).await.unwrap().get::<_, i64>("two"), 2);
+    }
+}
@@ -0,0 +1,49 @@
+//! pg_server
+//!
+//! postgres wire protocol over the in-memory store, so psql and anything else that speaks postgres
+//! can query the live tables (see db::sql) with DataFusion sql:
+//!
+//! ```text
+//! psql -h localhost -c "select avg(price) from coinbase_ticks"
+//! ```
+//!
+//! Simple and extended (prepared, $1 parameters) queries; no tls, and pg_catalog isn't emulated, so
+//! psql's \d and friends don't work. With a users file logins are checked, see auth.rs.
+
+use std::error::Error;
+use std::sync::Arc;
+use crossbeam_channel::Sender;
+use pgwire::tokio::process_socket;
+use tokio::net::TcpListener;
+use common_lib::auth::Auth;
+use common_lib::DbMsg;
+use crate::auth::PasswordStartupHandler;
+use crate::handler::PgHandler;
+
+pub mod auth;
+mod encode;
+pub mod handler;
+
+/// bind and serve until the process exits; addr is from common_lib::config::Listeners
+pub async fn run(tx_db: Sender<DbMsg>, addr: &str, auth: Auth) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("[pg_server] listening on postgres://{}", listener.local_addr()?);
+    serve(tx_db, listener, auth).await?;
+    Ok(())
+}
+
+/// accept connections on an already bound listener (tests bind port 0); each connection gets its
+/// own handler, which holds that connection's prepared statements and portals
+pub async fn serve(tx_db: Sender<DbMsg>, listener: TcpListener, auth: Auth) -> std::io::Result<()> {
+    let startup = Arc::new(PasswordStartupHandler::new(auth));
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let handler = Arc::new(PgHandler::new(tx_db.clone()));
+        let startup = startup.clone();
+        tokio::spawn(async move {
+            if let Err(e) = process_socket(socket, None, startup, handler.clone(), handler).await {
+                tracing::debug!("[pg_server] {addr} closed: {e:?}");
+            }
+        });
+    }
+}
@@ -0,0 +1,333 @@
+//! dashboard.rs
+//!
+//! a dashboard is a stack of panels, each its own chart.js canvas with its own y-axes, so a slope
+//! near zero isn't drawn against prices near 40,000. Layouts are saved as one json file per
+//! dashboard in the directory given by "--dashboards <dir>" (default ./dashboards).
+//!
+//! {"name":"btc","panels":[
+//!     {"title":"price","symbols":["btc_usd"],"axes":[{"id":"price","series":["price",{"calc":"MovingAvg0100"}]}]},
+//!     {"title":"slope","symbols":["btc_usd"],"height":200,"axes":[{"id":"slope","series":[{"calc":"MovAvgDiffSlope0100_1000"}],"min":-10.0,"max":10.0}]}
+//! ]}
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use strum::{Display, IntoEnumIterator};
+use common_lib::cb_ticker::Datasource;
+use common_lib::view::ViewSeries;
+use common_lib::{CalculationId, SymbolCommon};
+
+/// served when nothing has been saved under that name
+pub const DASHBOARD_DEFAULT: &str = "default";
+
+pub const PANEL_HEIGHT_DEFAULT: u32 = 400;
+pub const PANEL_HEIGHT_MIN: u32 = 100;
+pub const PANEL_HEIGHT_MAX: u32 = 2000;
+
+const NAME_LEN_MAX: usize = 64;
+
+/// a saved dashboard; panels are drawn top to bottom
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub name: String,
+    pub panels: Vec<Panel>,
+}
+
+/// one canvas; empty sources or symbols mean "all", like ChartFilter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Panel {
+    pub title: String,
+    #[serde(default)]
+    pub sources: Vec<Datasource>,
+    #[serde(default)]
+    pub symbols: Vec<SymbolCommon>,
+    /// pixels
+    #[serde(default = "panel_height_default")]
+    pub height: u32,
+    pub axes: Vec<PanelAxis>,
+}
+
+/// a y-axis of a panel and the series drawn against it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PanelAxis {
+    pub id: String,
+    pub series: Vec<ViewSeries>,
+    #[serde(default)]
+    pub position: AxisPosition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AxisPosition {
+    Left,
+    #[default]
+    Right,
+}
+
+fn panel_height_default() -> u32 {
+    PANEL_HEIGHT_DEFAULT
+}
+
+impl Layout {
+    /// prices and moving averages, the diffs, then the slope on its own clamped axis
+    pub fn default_layout() -> Layout {
+        let calcs = |ids: &[CalculationId]| ids.iter().cloned().map(ViewSeries::Calc).collect::<Vec<ViewSeries>>();
+        let panel = |title: &str, height: u32, axis: PanelAxis| Panel { title: title.to_string(), sources: vec![], symbols: vec![], height, axes: vec![axis] };
+        let axis = |id: &str, series: Vec<ViewSeries>| PanelAxis { id: id.to_string(), series, position: AxisPosition::Right, min: None, max: None };
+
+        let mut price = vec![ViewSeries::Price];
+        price.extend(calcs(&[CalculationId::MovingAvg0010, CalculationId::MovingAvg0100, CalculationId::MovingAvg1000]));
+        let slope = PanelAxis { min: Some(-10.0), max: Some(10.0), ..axis("slope", calcs(&[CalculationId::MovAvgDiffSlope0100_1000])) };
+        Layout {
+            name: DASHBOARD_DEFAULT.to_string(),
+            panels: vec![
+                panel("price", PANEL_HEIGHT_DEFAULT, axis("price", price)),
+                panel("moving average diff", 250, axis("diff", calcs(&[CalculationId::MovAvgDiff0010_1000, CalculationId::MovAvgDiff0100_1000]))),
+                panel("moving average diff slope", 200, slope),
+            ],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        validate_name(&self.name)?;
+        if self.panels.is_empty() {
+            return Err(LayoutError::Invalid(format!("{}: no panels", self.name)));
+        }
+        for panel in &self.panels {
+            panel.validate()?;
+        }
+        Ok(())
+    }
+
+    /// one subscribe message covering every panel; a panel wanting all sources or symbols wins
+    pub fn subscription(&self) -> serde_json::Value {
+        let sources: Vec<Datasource> = match self.panels.iter().any(|panel| panel.sources.is_empty()) {
+            true => vec![],
+            false => Datasource::iter().filter(|x| self.panels.iter().any(|panel| panel.sources.contains(x))).collect(),
+        };
+        let symbols: Vec<SymbolCommon> = match self.panels.iter().any(|panel| panel.symbols.is_empty()) {
+            true => vec![],
+            false => SymbolCommon::iter().filter(|x| self.panels.iter().any(|panel| panel.symbols.contains(x))).collect(),
+        };
+        let series: Vec<ViewSeries> = ViewSeries::all().into_iter()
+            .filter(|x| self.panels.iter().flat_map(|panel| &panel.axes).any(|axis| axis.series.contains(x)))
+            .collect();
+        json!({"action": "subscribe", "symbols": symbols, "sources": sources, "series": series})
+    }
+
+    /// what dashboard.html draws: per panel its axes and the dataset labels on each
+    pub fn page(&self) -> serde_json::Value {
+        let panels: Vec<serde_json::Value> = self.panels.iter().enumerate()
+            .map(|(i, panel)| json!({
+                "id": format!("panel_{i}"),
+                "title": panel.title,
+                "height": panel.height,
+                "axes": panel.axes.iter().map(|axis| json!({"id": axis.id, "position": axis.position, "min": axis.min, "max": axis.max})).collect::<Vec<_>>(),
+                "datasets": panel.datasets().into_iter().map(|(label, axis)| json!({"label": label, "axis": axis})).collect::<Vec<_>>(),
+            }))
+            .collect();
+        json!({"name": self.name, "panels": panels})
+    }
+}
+
+impl Panel {
+    fn validate(&self) -> Result<(), LayoutError> {
+        let invalid = |reason: String| Err(LayoutError::Invalid(format!("{}: {reason}", self.title)));
+        if !(PANEL_HEIGHT_MIN..=PANEL_HEIGHT_MAX).contains(&self.height) {
+            return invalid(format!("height {} isn't {PANEL_HEIGHT_MIN} to {PANEL_HEIGHT_MAX}", self.height));
+        }
+        if self.axes.is_empty() {
+            return invalid("no axes".to_string());
+        }
+        let mut ids = HashSet::new();
+        let mut series = HashSet::new();
+        for axis in &self.axes {
+            if axis.id.is_empty() || !ids.insert(&axis.id) {
+                return invalid(format!("axis id \"{}\" is empty or repeated", axis.id));
+            }
+            if axis.series.is_empty() {
+                return invalid(format!("axis {} has no series", axis.id));
+            }
+            if let Some(x) = axis.series.iter().find(|x| !series.insert(*x)) {
+                return invalid(format!("{} is on more than one axis", x.name()));
+            }
+            if let (Some(min), Some(max)) = (axis.min, axis.max) {
+                if min >= max {
+                    return invalid(format!("axis {} min {min} isn't below max {max}", axis.id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// (label, axis id) of every dataset the panel draws, same labels as the chart websocket
+    pub fn datasets(&self) -> Vec<(String, String)> {
+        let mut datasets = vec![];
+        for source in Datasource::iter().filter(|x| self.sources.is_empty() || self.sources.contains(x)) {
+            for symbol in SymbolCommon::iter().filter(|x| self.symbols.is_empty() || self.symbols.contains(x)) {
+                for axis in &self.axes {
+                    for series in &axis.series {
+                        datasets.push((series.label(&symbol, &source), axis.id.clone()));
+                    }
+                }
+            }
+        }
+        datasets
+    }
+}
+
+/// names become file names, so only letters, digits, '_' and '-'
+pub fn validate_name(name: &str) -> Result<(), LayoutError> {
+    if name.is_empty() || name.len() > NAME_LEN_MAX || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-') {
+        return Err(LayoutError::Name(name.to_string()));
+    }
+    Ok(())
+}
+
+/// one <name>.json per layout
+#[derive(Debug, Clone)]
+pub struct LayoutStore {
+    dir: PathBuf,
+}
+
+impl LayoutStore {
+    pub fn new(dir: impl AsRef<Path>) -> LayoutStore {
+        LayoutStore { dir: dir.as_ref().to_path_buf() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// saved names, sorted; the default is listed even when it isn't saved
+    pub fn list(&self) -> Result<Vec<String>, LayoutError> {
+        let mut names = vec![DASHBOARD_DEFAULT.to_string()];
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+            Err(e) => return Err(LayoutError::Io(e.to_string())),
+        };
+        for entry in entries {
+            let path = entry.map_err(|e| LayoutError::Io(e.to_string()))?.path();
+            if path.extension().is_some_and(|x| x == "json") {
+                if let Some(name) = path.file_stem().and_then(|x| x.to_str()).filter(|x| validate_name(x).is_ok()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Layout, LayoutError> {
+        validate_name(name)?;
+        match fs::read(self.path(name)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| LayoutError::Json(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && name == DASHBOARD_DEFAULT => Ok(Layout::default_layout()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(LayoutError::NotFound(name.to_string())),
+            Err(e) => Err(LayoutError::Io(e.to_string())),
+        }
+    }
+
+    /// written to a temp file and renamed, so a reader never sees half a layout
+    pub fn save(&self, layout: &Layout) -> Result<(), LayoutError> {
+        layout.validate()?;
+        fs::create_dir_all(&self.dir).map_err(|e| LayoutError::Io(e.to_string()))?;
+        let bytes = serde_json::to_vec_pretty(layout).map_err(|e| LayoutError::Json(e.to_string()))?;
+        let tmp = self.dir.join(format!(".{}.json.tmp", layout.name));
+        fs::write(&tmp, bytes).map_err(|e| LayoutError::Io(e.to_string()))?;
+        fs::rename(&tmp, self.path(&layout.name)).map_err(|e| LayoutError::Io(e.to_string()))
+    }
+
+    /// deleting the saved default brings back the built in one
+    pub fn delete(&self, name: &str) -> Result<(), LayoutError> {
+        validate_name(name)?;
+        match fs::remove_file(self.path(name)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(LayoutError::NotFound(name.to_string())),
+            Err(e) => Err(LayoutError::Io(e.to_string())),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum LayoutError {
+    Name(String),
+    Invalid(String),
+    NotFound(String),
+    Json(String),
+    Io(String),
+}
+
+impl std::error::Error for LayoutError {}
+
+#[cfg(test)]
+mod tests {
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::view::ViewSeries;
+    use common_lib::{CalculationId, SymbolCommon};
+    use crate::dashboard::{Layout, LayoutError, LayoutStore, DASHBOARD_DEFAULT};
+
+    #[test]
+    fn test_layout() {
+        let mut layout = Layout::default_layout();
+        layout.validate().unwrap();
+        assert_eq!(layout.subscription()["series"].as_array().unwrap().len(), ViewSeries::all().len());
+
+        layout.name = "btc".to_string();
+        layout.panels.truncate(1);
+        layout.panels[0].symbols = vec![SymbolCommon::BtcUsd];
+        layout.panels[0].sources = vec![Datasource::Coinbase];
+        layout.panels[0].axes[0].series = vec![ViewSeries::Price, ViewSeries::Calc(CalculationId::MovingAvg0100)];
+        assert_eq!(layout.panels[0].datasets(), vec![
+            ("btc_usd_Coinbase".to_string(), "price".to_string()),
+            ("btc_usd_MovingAvg0100_Coinbase".to_string(), "price".to_string()),
+        ]);
+        assert_eq!(layout.subscription(), serde_json::json!({"action": "subscribe", "symbols": ["btc_usd"], "sources": ["coinbase"], "series": ["price", {"calc": "MovingAvg0100"}]}));
+
+        let json = r#"{"name":"btc","panels":[{"title":"price","symbols":["btc_usd"],"sources":["coinbase"],"axes":[{"id":"price","series":["price",{"calc":"MovingAvg0100"}]}]}]}"#;
+        assert_eq!(serde_json::from_str::<Layout>(json).unwrap(), layout);
+
+        for bad in [r#"{"name":"../x","panels":[]}"#, r#"{"name":"x","panels":[]}"#,
+            r#"{"name":"x","panels":[{"title":"p","axes":[]}]}"#,
+            r#"{"name":"x","panels":[{"title":"p","height":5,"axes":[{"id":"a","series":["price"]}]}]}"#,
+            r#"{"name":"x","panels":[{"title":"p","axes":[{"id":"a","series":["price"]},{"id":"a","series":[{"calc":"MovingAvg0010"}]}]}]}"#,
+            r#"{"name":"x","panels":[{"title":"p","axes":[{"id":"a","series":["price"]},{"id":"b","series":["price"]}]}]}"#,
+            r#"{"name":"x","panels":[{"title":"p","axes":[{"id":"a","series":["price"],"min":1.0,"max":1.0}]}]}"#] {
+            assert!(serde_json::from_str::<Layout>(bad).unwrap().validate().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_layout_store() {
+        let dir = std::env::temp_dir().join(format!("test_layout_store_{}", std::process::id()));
+        let store = LayoutStore::new(&dir);
+        assert_eq!(store.list().unwrap(), vec![DASHBOARD_DEFAULT]);
+        assert_eq!(store.load(DASHBOARD_DEFAULT).unwrap(), Layout::default_layout());
+        assert!(matches!(store.load("btc"), Err(LayoutError::NotFound(_))));
+        assert!(matches!(store.load("../etc/passwd"), Err(LayoutError::Name(_))));
+
+        let mut layout = Layout::default_layout();
+        layout.name = "btc".to_string();
+        layout.panels[0].symbols = vec![SymbolCommon::BtcUsd];
+        store.save(&layout).unwrap();
+        assert_eq!(store.list().unwrap(), vec!["btc", DASHBOARD_DEFAULT]);
+        assert_eq!(store.load("btc").unwrap(), layout);
+
+        store.delete("btc").unwrap();
+        assert!(matches!(store.delete("btc"), Err(LayoutError::NotFound(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! handler_api.rs
+//!
+//! the versioned json api; what's in the db without scraping the chart pages
+//!
+//! GET /api/v1/sources
+//! GET /api/v1/symbols
+//! GET /api/v1/series?source=coinbase&symbol=btc_usd
+//! GET /api/v1/series/{source}/{symbol}/{series}?start=&end=&limit=&resolution=&format=
+//! GET|PUT|DELETE /api/v1/dashboards/{name}, see handler_dashboard.rs
+//! GET /api/v1/alerts, GET|PUT|DELETE /api/v1/alerts/rules/{name}, see handler_alert.rs
+//! GET /api/v1/paper                   each paper strategy's position, equity, pnl and drawdown
+//! GET /api/v1/config                  the running settings, secrets redacted
+//!
+//! start and end are rfc3339 (start inclusive, end exclusive), resolution is "500ms", "1s", "5m"
+//! or "1h", and format (or the Accept header) picks json, msgpack or arrow for the points.
+
+use std::str::FromStr;
+use actix_web::{error, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::Deserialize;
+use serde_json::json;
+use strum::IntoEnumIterator;
+use tokio::sync::oneshot;
+use common_lib::api::{SeriesInfo, SeriesQuery};
+use common_lib::cb_ticker::Datasource;
+use common_lib::config::Config;
+use common_lib::paper::Account;
+use common_lib::view::{ChartMessage, Resolution, ViewSeries};
+use common_lib::wire::{encode, WireFormat};
+use common_lib::{ChartDataset, DbMsg, SymbolCommon, UniversalError};
+use crate::handler_alert::alerts_api;
+use crate::handler_chart::accepted_format;
+use crate::handler_dashboard::dashboards_api;
+
+pub const API_V1: &str = "/api/v1";
+
+/// routes under API_V1
+pub fn api_v1(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::QueryConfig::default().error_handler(|e, _req| {
+        let response = bad_request(e.to_string());
+        error::InternalError::from_response(e, response).into()
+    }))
+    .route("/sources", web::get().to(list_sources))
+    .route("/symbols", web::get().to(list_symbols))
+    .route("/series", web::get().to(list_series))
+    .route("/series/{source}/{symbol}/{series}", web::get().to(get_series))
+    .route("/paper", web::get().to(list_accounts))
+    .route("/config", web::get().to(get_config))
+    .configure(dashboards_api)
+    .configure(alerts_api);
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SeriesFilter {
+    source: Option<Datasource>,
+    symbol: Option<SymbolCommon>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SeriesParams {
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+    resolution: Option<Resolution>,
+    format: Option<String>,
+}
+
+/// GET '/api/v1/sources'; every datasource and the symbols it has points for
+async fn list_sources(tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match request_series_list(&tx_db).await {
+        Ok(list) => {
+            let sources: Vec<serde_json::Value> = Datasource::iter()
+                .map(|source| {
+                    let symbols: Vec<SymbolCommon> = SymbolCommon::iter().filter(|symbol| list.iter().any(|x| x.source == source && x.symbol == *symbol)).collect();
+                    json!({"source": source, "symbols": symbols})
+                })
+                .collect();
+            HttpResponse::Ok().json(sources)
+        }
+        Err(e) => unavailable(e),
+    }
+}
+
+/// GET '/api/v1/symbols'; every symbol and the datasources that have points for it
+async fn list_symbols(tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match request_series_list(&tx_db).await {
+        Ok(list) => {
+            let symbols: Vec<serde_json::Value> = SymbolCommon::iter()
+                .map(|symbol| {
+                    let sources: Vec<Datasource> = Datasource::iter().filter(|source| list.iter().any(|x| x.source == *source && x.symbol == symbol)).collect();
+                    json!({"symbol": symbol, "sources": sources})
+                })
+                .collect();
+            HttpResponse::Ok().json(symbols)
+        }
+        Err(e) => unavailable(e),
+    }
+}
+
+/// GET '/api/v1/series'; every series with points, optionally for one source and/or symbol
+async fn list_series(filter: web::Query<SeriesFilter>, tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match request_series_list(&tx_db).await {
+        Ok(list) => {
+            let list: Vec<SeriesInfo> = list.into_iter()
+                .filter(|x| filter.source.as_ref().is_none_or(|source| x.source == *source))
+                .filter(|x| filter.symbol.as_ref().is_none_or(|symbol| x.symbol == *symbol))
+                .collect();
+            HttpResponse::Ok().json(list)
+        }
+        Err(e) => unavailable(e),
+    }
+}
+
+/// GET '/api/v1/series/{source}/{symbol}/{series}'; the points as a chart snapshot with one dataset
+async fn get_series(req: HttpRequest, path: web::Path<(String, String, String)>, params: web::Query<SeriesParams>, tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    let (source, symbol, series) = path.into_inner();
+    let (query, format) = match series_query(&source, &symbol, &series, params.into_inner()) {
+        Ok((query, format)) => (query, format.unwrap_or_else(|| accepted_format(&req))),
+        Err(reason) => return bad_request(reason),
+    };
+    match request_series(&tx_db, query).await {
+        Ok(dataset) => match encode(&ChartMessage::Snapshot { datasets: vec![dataset] }, format) {
+            Ok(bytes) => HttpResponse::Ok().content_type(format.content_type()).append_header(("cache-control", "no-store")).body(bytes),
+            Err(e) => {
+                tracing::error!("[get_series] {format} encode error: {:?}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Err(e) => unavailable(e),
+    }
+}
+
+/// the db query and, if the caller named one, the format
+fn series_query(source: &str, symbol: &str, series: &str, params: SeriesParams) -> Result<(SeriesQuery, Option<WireFormat>), String> {
+    let source = Datasource::from_str(source).map_err(|_| format!("unknown source: {source}"))?;
+    let symbol = SymbolCommon::from_str(symbol).map_err(|_| format!("unknown symbol: {symbol}"))?;
+    let series = ViewSeries::from_str(series)?;
+    let format = match params.format.as_deref() {
+        Some(format) => Some(WireFormat::from_str(format).map_err(|_| format!("unknown format: {format}"))?),
+        None => None,
+    };
+    if let (Some(start), Some(end)) = (params.start, params.end) {
+        if start >= end {
+            return Err(format!("start {start} isn't before end {end}"));
+        }
+    }
+
+    let mut query = SeriesQuery::new(source, symbol, series);
+    query.start = params.start;
+    query.end = params.end;
+    query.resolution = params.resolution;
+    if let Some(limit) = params.limit {
+        query = query.limit(limit);
+    }
+    Ok((query, format))
+}
+
+fn bad_request(reason: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({"error": reason}))
+}
+
+fn unavailable(e: UniversalError) -> HttpResponse {
+    tracing::error!("[api] db error: {:?}", &e);
+    HttpResponse::ServiceUnavailable().json(json!({"error": e.to_string()}))
+}
+
+/**************** Message Passing ******************************************************************/
+
+/// GET '/api/v1/paper'; where every paper strategy stands, see common_lib::paper
+async fn list_accounts(tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match request_accounts(&tx_db).await {
+        Ok(accounts) => HttpResponse::Ok().json(accounts),
+        Err(e) => unavailable(e),
+    }
+}
+
+/// GET '/api/v1/config'; read-only, and Config never serializes its secrets
+async fn get_config(config: web::Data<Config>) -> HttpResponse {
+    HttpResponse::Ok().json(config.get_ref())
+}
+
+async fn request_series_list(tx_db: &Sender<DbMsg>) -> Result<Vec<SeriesInfo>, UniversalError> {
+    let (sender, rx) = oneshot::channel();
+    tx_db.send(DbMsg::RqstSeriesList { sender }).map_err(|_| UniversalError::SendError)?;
+    rx.await.map_err(|_| UniversalError::RecvError)
+}
+
+async fn request_accounts(tx_db: &Sender<DbMsg>) -> Result<Vec<Account>, UniversalError> {
+    let (sender, rx) = oneshot::channel();
+    tx_db.send(DbMsg::RqstPaperAccounts { sender }).map_err(|_| UniversalError::SendError)?;
+    rx.await.map_err(|_| UniversalError::RecvError)
+}
+
+async fn request_series(tx_db: &Sender<DbMsg>, query: SeriesQuery) -> Result<ChartDataset, UniversalError> {
+    let (sender, rx) = oneshot::channel();
+    tx_db.send(DbMsg::RqstSeries { query, sender }).map_err(|_| UniversalError::SendError)?;
+    rx.await.map_err(|_| UniversalError::RecvError)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use actix_web::http::StatusCode;
+    use chrono::{DateTime, Duration, Utc};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::config::Config;
+    use common_lib::wire::{decode, WireFormat};
+    use common_lib::view::ChartMessage;
+    use common_lib::{DbMsg, SymbolCommon, TickerCommon};
+    use db::arrow_db;
+    use crate::handler_api::{api_v1, API_V1};
+
+    #[actix_web::test]
+    async fn test_api_v1() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let start = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        for i in 0..10 {
+            let ticker = TickerCommon { source: Datasource::Alpaca, symbol: SymbolCommon::BtcUsd, price: i as f64, dtg: start + Duration::milliseconds(i * 250), trade_id: None };
+            tx_db.send(DbMsg::Insert(Datasource::Alpaca, ticker)).unwrap();
+        }
+        let mut config = Config::default();
+        config.feeds.alpaca.secret = Some("hunter2".to_string());
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(tx_db))
+            .app_data(web::Data::new(config))
+            .service(web::scope(API_V1).configure(api_v1))).await;
+
+        let req = test::TestRequest::get().uri("/api/v1/config").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["listeners"]["http"], "127.0.0.1:8080");
+        assert_eq!(body["feeds"]["alpaca"]["secret"], "********");
+
+        let req = test::TestRequest::get().uri("/api/v1/paper").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([]));
+
+        let req = test::TestRequest::get().uri("/api/v1/sources").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!([{"source": "coinbase", "symbols": []}, {"source": "alpaca", "symbols": ["btc_usd"]}]));
+
+        let req = test::TestRequest::get().uri("/api/v1/symbols").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body[0], serde_json::json!({"symbol": "btc_usd", "sources": ["alpaca"]}));
+
+        let req = test::TestRequest::get().uri("/api/v1/series?source=alpaca").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body[0]["series"], "price");
+        assert_eq!(body[0]["points"], 10);
+        assert_eq!(body[0]["first"], "2024-01-14T23:30:00Z");
+
+        // 2.5s of ticks in 1s buckets, the newest two
+        let req = test::TestRequest::get().uri("/api/v1/series/alpaca/btc_usd/price?start=2024-01-14T23:30:00Z&resolution=1s&limit=2").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"type": "snapshot", "datasets": [{"label": "btc_usd_Alpaca", "data": [{"x": "2024-01-14T23:30:01Z", "y": 7.0}, {"x": "2024-01-14T23:30:02Z", "y": 9.0}]}]}));
+
+        let req = test::TestRequest::get().uri("/api/v1/series/alpaca/btc_usd/price?end=2024-01-14T23:30:00.500Z&format=msgpack").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), WireFormat::Msgpack.content_type());
+        match decode(&test::read_body(resp).await, WireFormat::Msgpack).unwrap() {
+            ChartMessage::Snapshot { datasets } => assert_eq!(datasets[0].data.len(), 2),
+            msg => panic!("expected a snapshot: {msg:?}"),
+        }
+
+        for uri in ["/api/v1/series/nyse/btc_usd/price", "/api/v1/series/alpaca/btc_usd/MovingAvg9999", "/api/v1/series/alpaca/btc_usd/price?resolution=1d",
+            "/api/v1/series/alpaca/btc_usd/price?resolution=0ms", "/api/v1/series/alpaca/btc_usd/price?resolution=144115188075855872h", "/api/v1/series/alpaca/btc_usd/price?resolution=-1s",
+            "/api/v1/series/alpaca/btc_usd/price?start=2024-01-14T23:31:00Z&end=2024-01-14T23:30:00Z", "/api/v1/series/alpaca/btc_usd/price?format=xml"] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{uri}");
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert!(body["error"].is_string(), "{uri}");
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! handler_auth.rs
+//!
+//! login, logout and the guard in front of every route. A caller is a session cookie from
+//! /login, or an "Authorization: Bearer <api token>" (or Basic) header for scripts; see
+//! common_lib::auth for users and roles.
+//!
+//! GETs (pages, /raw, /chart_data, /ws, /api/v1, /metrics) need a read-only user, anything else
+//! (writes, saving dashboards) an admin; /healthz and /readyz are open for probes, which only get
+//! the details with a read-only user. A page without a caller redirects to /login, anything
+//! else gets a 401. The cookie is SameSite=Lax, so other sites can't post or open /ws with it.
+
+use actix_session::config::PersistentSession;
+use actix_session::storage::CookieSessionStore;
+use actix_session::{Session, SessionExt, SessionMiddleware};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::{time, Key, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse};
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+use common_lib::auth::{Auth, Caller, Credentials, Role, SESSION_TTL_HOURS};
+use crate::handler_health::{HEALTHZ, READYZ};
+
+pub const LOGIN: &str = "/login";
+pub const LOGOUT: &str = "/logout";
+
+const LOGIN_NAME: &str = "login";
+const SESSION_COOKIE: &str = "crate_session";
+const SESSION_USER: &str = "user";
+
+/// encrypted cookie sessions; key is per process, so a restart logs everyone out.
+/// secure cookies are only sent over https, so it's off for a plain http listener
+pub fn session_middleware(key: Key, secure: bool) -> SessionMiddleware<CookieSessionStore> {
+    SessionMiddleware::builder(CookieSessionStore::default(), key)
+        .cookie_name(SESSION_COOKIE.to_string())
+        .cookie_secure(secure)
+        .cookie_same_site(SameSite::Lax)
+        .session_lifecycle(PersistentSession::default().session_ttl(time::Duration::hours(SESSION_TTL_HOURS)))
+        .build()
+}
+
+/// login routes; the Auth is app data
+pub fn login(cfg: &mut web::ServiceConfig) {
+    cfg.route(LOGIN, web::get().to(present_login))
+        .route(LOGIN, web::post().to(post_login))
+        .route(LOGOUT, web::post().to(post_logout));
+}
+
+/// None for routes anyone can use
+pub(crate) fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if path == LOGIN || path == LOGOUT || path == HEALTHZ || path == READYZ || path.starts_with("/js/") {
+        return None;
+    }
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => Some(Role::Read),
+        _ => Some(Role::Admin),
+    }
+}
+
+/// middleware::from_fn; puts the Caller in the request's extensions for the handlers
+pub async fn authorize(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(auth) = req.app_data::<web::Data<Auth>>().cloned() else {
+        tracing::error!("[authorize] no Auth app data; refusing {}", req.path());
+        let response = HttpResponse::InternalServerError().finish();
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+    let caller = caller(&req, &auth).await;
+    let response = match (required_role(req.method(), req.path()), &caller) {
+        (None, _) => None,
+        (Some(role), Some(caller)) if caller.allows(role) => None,
+        (Some(role), Some(caller)) => Some(HttpResponse::Forbidden().json(json!({"error": format!("{} is {}, this needs {role}", caller.name, caller.role)}))),
+        (Some(_), None) if wants_page(req.request()) => {
+            let next = serde_urlencoded::to_string([("next", req.uri().to_string())]).unwrap_or_default();
+            Some(HttpResponse::Found().append_header(("location", format!("{LOGIN}?{next}"))).finish())
+        }
+        (Some(_), None) => Some(HttpResponse::Unauthorized().append_header((WWW_AUTHENTICATE, "Bearer")).json(json!({"error": "log in or send an api token"}))),
+    };
+    match response {
+        Some(response) => Ok(req.into_response(response).map_into_right_body()),
+        None => {
+            if let Some(caller) = caller {
+                req.extensions_mut().insert(caller);
+            }
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
+        }
+    }
+}
+
+/// the authorization header if there is one, else the session's user
+async fn caller(req: &ServiceRequest, auth: &Auth) -> Option<Caller> {
+    if auth.is_open() {
+        return auth.user("anonymous");
+    }
+    if let Some(credentials) = req.headers().get(AUTHORIZATION).and_then(|x| x.to_str().ok()).and_then(Credentials::from_header) {
+        let auth = auth.clone();
+        return web::block(move || auth.credentials(&credentials)).await.ok().flatten();
+    }
+    let name = req.get_session().get::<String>(SESSION_USER).ok().flatten()?;
+    auth.user(&name)
+}
+
+fn wants_page(req: &HttpRequest) -> bool {
+    req.method() == Method::GET && req.headers().get(ACCEPT).and_then(|x| x.to_str().ok()).is_some_and(|x| x.contains("text/html"))
+}
+
+/// base0's is_logged_in and session_username for a page; no name when auth is off
+pub(crate) fn login_state(req: &HttpRequest) -> (bool, Option<String>) {
+    let open = req.app_data::<web::Data<Auth>>().is_none_or(|auth| auth.is_open());
+    match req.extensions().get::<Caller>() {
+        Some(caller) => (true, (!open).then(|| caller.name.clone())),
+        None => (open, None),
+    }
+}
+
+/// whether the guard let the request through as a caller with role; none without a guard
+pub(crate) fn caller_allows(req: &HttpRequest, role: Role) -> bool {
+    req.extensions().get::<Caller>().is_some_and(|caller| caller.allows(role))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    name: String,
+    password: String,
+    next: Option<String>,
+}
+
+/// GET '/login'
+async fn present_login(query: web::Query<LoginQuery>, auth: web::Data<Auth>, hb: web::Data<Handlebars<'_>>) -> HttpResponse {
+    if auth.is_open() {
+        return redirect(&query.next);
+    }
+    render_login(&hb, query.into_inner().next, None)
+}
+
+/// POST '/login'; a new session for the user, then back to where they were going
+async fn post_login(form: web::Form<LoginForm>, session: Session, auth: web::Data<Auth>, hb: web::Data<Handlebars<'_>>) -> HttpResponse {
+    let LoginForm { name, password, next } = form.into_inner();
+    let auth = auth.into_inner();
+    let login_name = name.clone();
+    match web::block(move || auth.login(&login_name, &password)).await {
+        Ok(Some(caller)) => {
+            session.renew();
+            if let Err(e) = session.insert(SESSION_USER, &caller.name) {
+                tracing::error!("[post_login] session error: {:?}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+            tracing::info!("[post_login] {} logged in as {}", caller.name, caller.role);
+            redirect(&next)
+        }
+        Ok(None) => {
+            tracing::info!("[post_login] failed login for {name}");
+            let mut response = render_login(&hb, next, Some("wrong name or password"));
+            *response.status_mut() = actix_web::http::StatusCode::UNAUTHORIZED;
+            response
+        }
+        Err(e) => {
+            tracing::error!("[post_login] {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// POST '/logout'
+async fn post_logout(session: Session) -> HttpResponse {
+    session.purge();
+    HttpResponse::Found().append_header(("location", LOGIN)).finish()
+}
+
+fn render_login(hb: &Handlebars<'_>, next: Option<String>, error: Option<&str>) -> HttpResponse {
+    let data = json!({
+        "title": "login",
+        "parent": "base0",
+        "is_logged_in": false,
+        "next": next.unwrap_or_default(),
+        "error": error,
+    });
+    match hb.render(LOGIN_NAME, &data) {
+        Ok(body) => HttpResponse::Ok().append_header(("cache-control", "no-store")).body(body),
+        Err(e) => {
+            tracing::error!("[render_login] render error: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// only to a path on this site, so a crafted link can't bounce a login elsewhere
+fn redirect(next: &Option<String>) -> HttpResponse {
+    let location = next.as_deref().filter(|x| x.starts_with('/') && !x.starts_with("//") && !x.contains('\\')).unwrap_or("/");
+    HttpResponse::Found().append_header(("location", location)).append_header(("cache-control", "no-store")).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::cookie::Key;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, web, App, HttpResponse};
+    use handlebars::Handlebars;
+    use common_lib::auth::{hash_password, new_token, Auth, Role, UserRecord, UsersFile};
+    use crate::handler_auth::{authorize, login, required_role, session_middleware};
+
+    #[actix_web::test]
+    async fn test_required_role() {
+        assert_eq!(required_role(&Method::GET, "/login"), None);
+        assert_eq!(required_role(&Method::GET, "/readyz"), None);
+        assert_eq!(required_role(&Method::GET, "/metrics"), Some(Role::Read));
+        assert_eq!(required_role(&Method::GET, "/js/chart.js"), None);
+        assert_eq!(required_role(&Method::GET, "/ws"), Some(Role::Read));
+        assert_eq!(required_role(&Method::GET, "/api/v1/series"), Some(Role::Read));
+        assert_eq!(required_role(&Method::POST, "/write"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::PUT, "/api/v1/dashboards/btc"), Some(Role::Admin));
+    }
+
+    #[actix_web::test]
+    async fn test_authorize() {
+        let (token, digest) = new_token();
+        let auth = Auth::new(UsersFile { users: vec![
+            UserRecord { name: "gp".to_string(), role: Role::Admin, password: Some(hash_password("hunter2").unwrap()), tokens: vec![] },
+            UserRecord { name: "grafana".to_string(), role: Role::Read, password: None, tokens: vec![digest] },
+        ]}).unwrap();
+        let mut hb = Handlebars::new();
+        hb.register_templates_directory(".html", format!("{}/static/templates", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(auth))
+            .app_data(web::Data::new(hb))
+            .wrap(from_fn(authorize))
+            .wrap(session_middleware(Key::generate(), false))
+            .configure(login)
+            .route("/", web::get().to(HttpResponse::Ok))
+            .route("/write", web::post().to(HttpResponse::NoContent))).await;
+
+        // no caller: pages go to the login page, everything else is refused
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/?symbols=btc_usd").insert_header(("accept", "text/html")).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(resp.headers().get("location").unwrap(), "/login?next=%2F%3Fsymbols%3Dbtc_usd");
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/login").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // a read-only token can read but not write
+        let bearer = ("authorization", format!("Bearer {token}"));
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").insert_header(bearer.clone()).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/write").insert_header(bearer).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").insert_header(("authorization", "Bearer nope")).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // a wrong password stays on the login page, the right one sets the session cookie
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/login").set_form([("name", "gp"), ("password", "nope")]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/login").set_form([("name", "gp"), ("password", "hunter2"), ("next", "//evil.example")]).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(resp.headers().get("location").unwrap(), "/");
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/write").cookie(cookie.clone()).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/logout").cookie(cookie).to_request()).await;
+        let cookie = resp.response().cookies().next().unwrap().into_owned();
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/write").cookie(cookie).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_open() {
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(Auth::open()))
+            .wrap(from_fn(authorize))
+            .wrap(session_middleware(Key::generate(), false))
+            .route("/write", web::post().to(HttpResponse::NoContent))).await;
+        let resp = test::call_service(&app, test::TestRequest::post().uri("/write").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+}
@@ -1,21 +1,57 @@
 //! analysis
 //!
 
+use std::collections::HashMap;
 use std::error::Error;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::header::ACCEPT;
 use crossbeam_channel::Sender;
 use handlebars::Handlebars;
 use serde_json::json;
 use tokio::sync::oneshot;
 use common_lib::{ChartDataset, UniversalError, DbMsg};
+use common_lib::view::{ChartFilter, ChartMessage};
+use common_lib::wire::{batches_to_arrow, encode, WireFormat};
+use crate::handler_auth::login_state;
 
 const CHART_MULTI_NAME:&str = "chart_multi";
 
 /**************** HTTP handlers ********************************************************************/
 
-/// GET '/raw'
-pub async fn present_raw_data(tx: web::Data<Sender<DbMsg>>) -> impl Responder {
-    request_raw_data(tx).await
+pub(crate) fn accepted_format(req: &HttpRequest) -> WireFormat {
+    WireFormat::from_accept(req.headers().get(ACCEPT).and_then(|x| x.to_str().ok()).unwrap_or_default())
+}
+
+/// GET '/raw'; a text table, or the record batches themselves for Accept: application/vnd.apache.arrow.stream
+pub async fn present_raw_data(req: HttpRequest, tx: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match accepted_format(&req) {
+        WireFormat::Arrow => request_raw_arrow(tx).await,
+        _ => HttpResponse::Ok().body(request_raw_data(tx).await),
+    }
+}
+
+/// GET '/chart_data'; the multi-line chart's datasets as a snapshot in json, msgpack or arrow per the Accept header,
+/// filtered by the same query parameters as the chart page
+pub async fn present_chart_data(req: HttpRequest, params: web::Query<HashMap<String, String>>, tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    let format = accepted_format(&req);
+    let filter = match ChartFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(reason) => return HttpResponse::BadRequest().body(reason),
+    };
+    let tx_db = tx_db.into_inner().as_ref().clone();
+    match request_chart_multi_data(tx_db, filter).await {
+        Ok(datasets) => match encode(&ChartMessage::Snapshot { datasets }, format) {
+            Ok(bytes) => HttpResponse::Ok().content_type(format.content_type()).append_header(("cache-control", "no-store")).body(bytes),
+            Err(e) => {
+                tracing::error!("[present_chart_data] {format} encode error: {:?}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Err(e) => {
+            tracing::error!("[present_chart_data] database error getting chart data: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
 }
 
 pub async fn redirect_home() -> HttpResponse {
@@ -30,11 +66,18 @@ pub async fn redirect_home() -> HttpResponse {
 }
 
 /// show multiple datasets on the same chart, regardless of x-axis count
-pub async fn present_chart_multi_line_static(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>/*, session: Session*/) -> HttpResponse {
+/// GET '/?symbols=btc_usd&sources=coinbase&series=price,MovingAvg0100&window=15m'; see ChartFilter
+pub async fn present_chart_multi_line_static(req: HttpRequest, params: web::Query<HashMap<String, String>>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>/*, session: Session*/) -> HttpResponse {
     // tracing::debug!("[present_chart]");
+    let filter = match ChartFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(reason) => return HttpResponse::BadRequest().body(reason),
+    };
+    let chart_title = params.get("title").cloned().unwrap_or_else(|| filter.title());
+    let (is_logged_in, session_username) = login_state(&req);
     let tx_db = tx_db.into_inner().as_ref().clone();
 
-    match request_chart_multi_data(tx_db).await {
+    match request_chart_multi_data(tx_db, filter).await {
         Ok(vec_chart2)=> {
             // tracing::debug!("[present_chart_multi_line] data: {:?}", &vec_chart2);
             match serde_json::to_string(&vec_chart2) {
@@ -42,8 +85,9 @@ pub async fn present_chart_multi_line_static(tx_db: web::Data<Sender<DbMsg>>, hb
                     let data = json!({
                         "title": "",
                         "parent": "base0",
-                        "is_logged_in": true,
-                        "chart_title": "Coinbase",
+                        "is_logged_in": is_logged_in,
+                        "session_username": session_username,
+                        "chart_title": chart_title,
                         "data_vec": data_vec_json,
                     });
                     let body = hb.render(CHART_MULTI_NAME, &data).unwrap();
@@ -68,19 +112,10 @@ pub async fn present_chart_multi_line_static(tx_db: web::Data<Sender<DbMsg>>, hb
 
 
 /// Ask the database for data for the chart
-/// TODO: add an enum for the kind of chart to fetch
-///
-/// TODO: Currently selects ALL product_id
-async fn request_chart_multi_data(tx_db: Sender<DbMsg>) -> Result<Vec<ChartDataset>, Box<dyn Error>> {
+async fn request_chart_multi_data(tx_db: Sender<DbMsg>, filter: ChartFilter) -> Result<Vec<ChartDataset>, Box<dyn Error>> {
     let (sender, rx) = oneshot::channel();
 
-    use strum::IntoEnumIterator;
-    let prods:Vec<SymbolCoinbase> = SymbolCoinbase::iter().collect();
-    let symbols_common = prods.iter().map(|x| x.to_common()).collect();
-
-    // send a list of product IDs we want to filter on; here it's 'select *'
-
-    match tx_db.send(DbMsg::RqstChartMulti {sender, symbol: symbols_common }) {
+    match tx_db.send(DbMsg::RqstChartMulti {sender, filter }) {
         Ok(_)=> {
             let chart = rx.await?;
             Ok(chart)
@@ -90,14 +125,15 @@ async fn request_chart_multi_data(tx_db: Sender<DbMsg>) -> Result<Vec<ChartDatas
 }
 
 
+use datafusion::arrow::datatypes::Schema;
 use datafusion::arrow::util::pretty::pretty_format_batches;
 use datafusion::dataframe::DataFrame;
-use common_lib::cb_ticker::{Datasource, SymbolCoinbase};
+use common_lib::cb_ticker::Datasource;
 
 /// clear the mechanics of sending a cross-thread message out of the HTTP handler
 ///
 /// TODO: handle multiple ticker sources
-pub async fn request_raw_data(tx: web::Data<Sender<DbMsg>>) -> impl Responder{
+pub async fn request_raw_data(tx: web::Data<Sender<DbMsg>>) -> String {
     let (tx_web, rx_web) = tokio::sync::oneshot::channel::<DataFrame>();
 
     match tx.send(DbMsg::RqstRaw {ticker_source: Datasource::Coinbase, sender: tx_web}) {
@@ -118,3 +154,34 @@ pub async fn request_raw_data(tx: web::Data<Sender<DbMsg>>) -> impl Responder{
         }
     }
 }
+
+/// same query as request_raw_data, returned as an Arrow IPC stream
+async fn request_raw_arrow(tx: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    let (tx_web, rx_web) = tokio::sync::oneshot::channel::<DataFrame>();
+    if let Err(e) = tx.send(DbMsg::RqstRaw { ticker_source: Datasource::Coinbase, sender: tx_web }) {
+        tracing::error!("[request_raw_arrow] send error: {:?}", &e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    let df = match rx_web.await {
+        Ok(df) => df,
+        Err(e) => {
+            tracing::error!("[request_raw_arrow] receive error: {:?}", &e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let schema: Schema = df.schema().into();
+    let bytes = match df.collect().await {
+        Ok(batches) => batches_to_arrow(&schema, &batches),
+        Err(e) => {
+            tracing::error!("[request_raw_arrow] query error: {:?}", &e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    match bytes {
+        Ok(bytes) => HttpResponse::Ok().content_type(WireFormat::Arrow.content_type()).body(bytes),
+        Err(e) => {
+            tracing::error!("[request_raw_arrow] encode error: {:?}", &e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
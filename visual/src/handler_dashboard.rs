@@ -0,0 +1,187 @@
+//! handler_dashboard.rs
+//!
+//! dashboard pages and the json routes that save and load their layouts, see dashboard.rs
+//!
+//! GET /dashboards/{name}              the page, rendered from the saved layout
+//! GET /api/v1/dashboards              saved names
+//! GET|PUT|DELETE /api/v1/dashboards/{name}
+
+use actix_web::{error, web, HttpRequest, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+use crate::dashboard::{Layout, LayoutError, LayoutStore, DASHBOARD_DEFAULT};
+use crate::handler_auth::login_state;
+
+const DASHBOARD_NAME: &str = "dashboard";
+
+/// page routes; the store is app data
+pub fn dashboards(cfg: &mut web::ServiceConfig) {
+    cfg.route("/dashboards", web::get().to(redirect_default))
+        .route("/dashboards/{name}", web::get().to(present_dashboard));
+}
+
+/// json routes under API_V1
+pub fn dashboards_api(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(|e, _req| {
+        let response = HttpResponse::BadRequest().json(json!({"error": e.to_string()}));
+        error::InternalError::from_response(e, response).into()
+    }))
+    .route("/dashboards", web::get().to(list_layouts))
+    .service(web::resource("/dashboards/{name}")
+        .route(web::get().to(get_layout))
+        .route(web::put().to(put_layout))
+        .route(web::delete().to(delete_layout)));
+}
+
+async fn redirect_default() -> HttpResponse {
+    HttpResponse::Found()
+        .append_header(("location", format!("/dashboards/{DASHBOARD_DEFAULT}")))
+        .append_header(("Cache-Control", "no-store"))
+        .finish()
+}
+
+/// GET '/dashboards/{name}'; one canvas per panel, fed by a single chart websocket subscription
+async fn present_dashboard(req: HttpRequest, name: web::Path<String>, store: web::Data<LayoutStore>, hb: web::Data<Handlebars<'_>>) -> HttpResponse {
+    let name = name.into_inner();
+    let (layout, names) = match blocking(&store, move |store| Ok((store.load(&name)?, store.list()?))).await {
+        Ok(x) => x,
+        Err(e) => return layout_error(e),
+    };
+    let page = layout.page();
+    let (is_logged_in, session_username) = login_state(&req);
+    let data = json!({
+        "title": format!("dashboard {}", layout.name),
+        "parent": "base0",
+        "is_logged_in": is_logged_in,
+        "session_username": session_username,
+        "name": layout.name,
+        "names": names,
+        "panels": page["panels"],
+        "page": page.to_string(),
+        "layout": serde_json::to_string_pretty(&layout).unwrap_or_default(),
+        "subscription": layout.subscription().to_string(),
+    });
+    match hb.render(DASHBOARD_NAME, &data) {
+        Ok(body) => HttpResponse::Ok().append_header(("cache-control", "no-store")).body(body),
+        Err(e) => {
+            tracing::error!("[present_dashboard] render error: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// GET '/api/v1/dashboards'
+async fn list_layouts(store: web::Data<LayoutStore>) -> HttpResponse {
+    match blocking(&store, |store| store.list()).await {
+        Ok(names) => HttpResponse::Ok().json(names),
+        Err(e) => layout_error(e),
+    }
+}
+
+/// GET '/api/v1/dashboards/{name}'
+async fn get_layout(name: web::Path<String>, store: web::Data<LayoutStore>) -> HttpResponse {
+    let name = name.into_inner();
+    match blocking(&store, move |store| store.load(&name)).await {
+        Ok(layout) => HttpResponse::Ok().json(layout),
+        Err(e) => layout_error(e),
+    }
+}
+
+/// PUT '/api/v1/dashboards/{name}'; the path names the layout, whatever the body says
+async fn put_layout(name: web::Path<String>, layout: web::Json<Layout>, store: web::Data<LayoutStore>) -> HttpResponse {
+    let mut layout = layout.into_inner();
+    layout.name = name.into_inner();
+    match blocking(&store, move |store| store.save(&layout).map(|_| layout)).await {
+        Ok(layout) => {
+            tracing::info!("[put_layout] saved dashboard {} to {:?}", &layout.name, store.dir());
+            HttpResponse::Ok().json(layout)
+        }
+        Err(e) => layout_error(e),
+    }
+}
+
+/// DELETE '/api/v1/dashboards/{name}'
+async fn delete_layout(name: web::Path<String>, store: web::Data<LayoutStore>) -> HttpResponse {
+    let name = name.into_inner();
+    match blocking(&store, move |store| store.delete(&name)).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => layout_error(e),
+    }
+}
+
+/// layouts are small files, but still off the actix worker
+async fn blocking<T: Send + 'static>(store: &web::Data<LayoutStore>, f: impl FnOnce(&LayoutStore) -> Result<T, LayoutError> + Send + 'static) -> Result<T, LayoutError> {
+    let store = store.clone();
+    web::block(move || f(&store)).await.map_err(|e| LayoutError::Io(e.to_string()))?
+}
+
+fn layout_error(e: LayoutError) -> HttpResponse {
+    match &e {
+        LayoutError::Name(reason) | LayoutError::Invalid(reason) => {
+            HttpResponse::BadRequest().json(json!({"error": format!("{e}: {reason}")}))
+        }
+        LayoutError::NotFound(name) => HttpResponse::NotFound().json(json!({"error": format!("no dashboard named {name}")})),
+        LayoutError::Json(_) | LayoutError::Io(_) => {
+            tracing::error!("[dashboard] {:?}", &e);
+            HttpResponse::InternalServerError().json(json!({"error": e.to_string()}))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use actix_web::http::StatusCode;
+    use handlebars::Handlebars;
+    use crate::dashboard::{Layout, LayoutStore};
+    use crate::handler_api::API_V1;
+    use crate::handler_dashboard::{dashboards, dashboards_api};
+
+    #[actix_web::test]
+    async fn test_dashboards() {
+        let dir = std::env::temp_dir().join(format!("test_dashboards_{}", std::process::id()));
+        let mut hb = Handlebars::new();
+        hb.register_templates_directory(".html", format!("{}/static/templates", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(LayoutStore::new(&dir)))
+            .app_data(web::Data::new(hb))
+            .configure(dashboards)
+            .service(web::scope(API_V1).configure(dashboards_api))).await;
+
+        let req = test::TestRequest::get().uri("/api/v1/dashboards").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!(["default"]));
+
+        // the name comes from the path
+        let mut layout = Layout::default_layout();
+        layout.panels.truncate(2);
+        let req = test::TestRequest::put().uri("/api/v1/dashboards/two_panels").set_json(&layout).to_request();
+        let body: Layout = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.name, "two_panels");
+
+        let req = test::TestRequest::get().uri("/api/v1/dashboards/two_panels").to_request();
+        let body: Layout = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.panels, layout.panels);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/dashboards/two_panels").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("id=\"panel_1\""));
+        assert!(!body.contains("id=\"panel_2\""));
+
+        layout.panels.clear();
+        for (req, status) in [
+            (test::TestRequest::put().uri("/api/v1/dashboards/empty").set_json(&layout), StatusCode::BAD_REQUEST),
+            (test::TestRequest::put().uri("/api/v1/dashboards/bad").set_payload("{").insert_header(("content-type", "application/json")), StatusCode::BAD_REQUEST),
+            (test::TestRequest::get().uri("/api/v1/dashboards/missing"), StatusCode::NOT_FOUND),
+            (test::TestRequest::get().uri("/dashboards/missing"), StatusCode::NOT_FOUND),
+            (test::TestRequest::get().uri("/dashboards/a.b"), StatusCode::BAD_REQUEST),
+            (test::TestRequest::delete().uri("/api/v1/dashboards/two_panels"), StatusCode::NO_CONTENT),
+            (test::TestRequest::delete().uri("/api/v1/dashboards/two_panels"), StatusCode::NOT_FOUND),
+        ] {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), status);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+//! handler_health.rs
+//!
+//! GET /healthz    200 while the db thread answers, else 503
+//! GET /readyz     the same, but 503 until every started feed is connected
+//! GET /metrics    prometheus text, see common_lib::metrics
+//!
+//! the probes need no login; anonymous callers get {"status":..} only, a read-only user gets the
+//! Health as json. /metrics needs a read-only user, prometheus can send its token
+
+use std::time::Duration;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use serde_json::json;
+use tokio::sync::oneshot;
+use common_lib::auth::Role;
+use common_lib::health::Health;
+use common_lib::metrics::METRICS;
+use common_lib::{DbMsg, UniversalError};
+use crate::handler_auth::caller_allows;
+
+pub const HEALTHZ: &str = "/healthz";
+pub const READYZ: &str = "/readyz";
+pub const METRICS_PATH: &str = "/metrics";
+
+/// a db thread slower than this to answer isn't healthy
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn health(cfg: &mut web::ServiceConfig) {
+    cfg.route(HEALTHZ, web::get().to(healthz))
+        .route(READYZ, web::get().to(readyz))
+        .route(METRICS_PATH, web::get().to(metrics));
+}
+
+/// GET '/healthz'
+async fn healthz(req: HttpRequest, tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match request_health(&tx_db).await {
+        Ok(health) => probe(&req, StatusCode::OK, "ok", health),
+        Err(e) => unavailable(&req, e),
+    }
+}
+
+/// GET '/readyz'
+async fn readyz(req: HttpRequest, tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    match request_health(&tx_db).await {
+        Ok(health) if health.ready => probe(&req, StatusCode::OK, "ready", health),
+        Ok(health) => probe(&req, StatusCode::SERVICE_UNAVAILABLE, "not ready", health),
+        Err(e) => unavailable(&req, e),
+    }
+}
+
+/// the Health for a read-only user, just the status for anyone else
+fn probe(req: &HttpRequest, code: StatusCode, status: &str, health: Health) -> HttpResponse {
+    match caller_allows(req, Role::Read) {
+        true => HttpResponse::build(code).json(health),
+        false => HttpResponse::build(code).json(json!({"status": status})),
+    }
+}
+
+/// GET '/metrics'; the counters are still served if the db thread doesn't answer, without its gauges
+async fn metrics(tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    let health = match request_health(&tx_db).await {
+        Ok(health) => health,
+        Err(e) => {
+            tracing::error!("[metrics] db error: {:?}", &e);
+            Health { db_queue: tx_db.len(), ..Health::new(Utc::now(), vec![], vec![], vec![]) }
+        }
+    };
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(METRICS.render(&health))
+}
+
+fn unavailable(req: &HttpRequest, e: UniversalError) -> HttpResponse {
+    tracing::error!("[health] db error: {:?}", &e);
+    match caller_allows(req, Role::Read) {
+        true => HttpResponse::ServiceUnavailable().json(json!({"error": e.to_string()})),
+        false => HttpResponse::ServiceUnavailable().json(json!({"status": "unavailable"})),
+    }
+}
+
+/**************** Message Passing ******************************************************************/
+
+/// the db's queue is measured before this request joins it
+async fn request_health(tx_db: &Sender<DbMsg>) -> Result<Health, UniversalError> {
+    let db_queue = tx_db.len();
+    let (sender, rx) = oneshot::channel();
+    tx_db.send(DbMsg::RqstHealth { sender }).map_err(|_| UniversalError::SendError)?;
+    let health = tokio::time::timeout(HEALTH_TIMEOUT, rx).await
+        .map_err(|_| UniversalError::DbError(format!("no answer in {HEALTH_TIMEOUT:?}")))?
+        .map_err(|_| UniversalError::RecvError)?;
+    Ok(Health { db_queue, ..health })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpMessage};
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use serde_json::json;
+    use common_lib::auth::{Caller, Role};
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::health::{FeedState, Health};
+    use common_lib::{DbMsg, SymbolCommon, TickerCommon};
+    use db::arrow_db;
+    use crate::handler_health::health;
+
+    #[actix_web::test]
+    async fn test_health_routes() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let app = test::init_service(App::new().app_data(web::Data::new(tx_db.clone())).configure(health)).await;
+
+        // no feeds started is ready
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        tx_db.send(DbMsg::FeedState(Datasource::Coinbase, FeedState::Connecting)).unwrap();
+        let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 42000.0, dtg: chrono::Utc::now(), trade_id: None };
+        tx_db.send(DbMsg::Insert(Datasource::Coinbase, ticker)).unwrap();
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        // anonymous probes say nothing about the feeds, symbols or buffers
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!({"status": "not ready"}));
+
+        tx_db.send(DbMsg::FeedState(Datasource::Coinbase, FeedState::Connected)).unwrap();
+        for (uri, status) in [("/healthz", "ok"), ("/readyz", "ready")] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body, json!({"status": status}));
+        }
+
+        let body = test::call_and_read_body(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("crate_ticks_total{source=\"coinbase\",arrival=\"in_order\"}"), "{text}");
+        assert!(text.contains("crate_feed_connected{source=\"coinbase\"} 1"), "{text}");
+        assert!(text.contains("crate_ring_buffer_len{source=\"coinbase\"} 1"), "{text}");
+        assert!(text.contains("crate_last_tick_age_seconds{source=\"coinbase\",symbol=\"btc_usd\"}"), "{text}");
+        assert!(text.contains("crate_calc_seconds_count"), "{text}");
+    }
+
+    /// a read-only user gets the whole Health from the probes
+    #[actix_web::test]
+    async fn test_health_details() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(tx_db.clone()))
+            .wrap_fn(|req, srv| {
+                req.extensions_mut().insert(Caller { name: "someone".to_string(), role: Role::Read });
+                srv.call(req)
+            })
+            .configure(health)).await;
+
+        tx_db.send(DbMsg::FeedState(Datasource::Coinbase, FeedState::Connecting)).unwrap();
+        let ticker = TickerCommon { source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, price: 42000.0, dtg: chrono::Utc::now(), trade_id: None };
+        tx_db.send(DbMsg::Insert(Datasource::Coinbase, ticker)).unwrap();
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let health: Health = test::read_body_json(resp).await;
+        assert_eq!(health.feeds[0].state, FeedState::Connecting);
+        assert_eq!(health.symbols[0].symbol, SymbolCommon::BtcUsd);
+        assert_eq!(health.buffers[0].len, 1);
+        assert!(health.buffers[0].fill > 0.0 && health.buffers[0].fill <= 1.0);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/healthz").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let health: Health = test::read_body_json(resp).await;
+        assert_eq!(health.feeds.len(), 1);
+    }
+}
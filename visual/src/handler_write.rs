@@ -0,0 +1,70 @@
+//! handler_write.rs
+//!
+//! influx line protocol writes over http. The 1.x (/write) and 2.x (/api/v2/write) paths both
+//! work; db, bucket, org and credentials are ignored, there's only the one store.
+
+use std::str::FromStr;
+use actix_web::{web, HttpResponse};
+use crossbeam_channel::Sender;
+use serde::Deserialize;
+use serde_json::json;
+use common_lib::DbMsg;
+use influx::line_protocol::{LineError, Precision};
+
+/// largest write body accepted; telegraf sends 1000 points at a time by default
+pub const MAX_WRITE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct WriteParams {
+    precision: Option<String>,
+}
+
+/// POST '/write', '/api/v2/write'; 204 once every point is stored, otherwise nothing is stored and
+/// the body is influx's {"code","message"} error
+pub async fn write_line_protocol(params: web::Query<WriteParams>, body: web::Bytes, tx_db: web::Data<Sender<DbMsg>>) -> HttpResponse {
+    let precision = match params.precision.as_deref() {
+        None => Ok(Precision::default()),
+        Some(p) => Precision::from_str(p).map_err(|_| LineError::Precision { precision: p.to_string() }),
+    };
+    let result = match (precision, std::str::from_utf8(&body)) {
+        (Ok(precision), Ok(body)) => influx::write(tx_db.get_ref(), body, precision).await,
+        (Err(e), _) => Err(e),
+        (_, Err(_)) => Err(LineError::Syntax { line: 0, reason: "body isn't utf8".to_string() }),
+    };
+    match result {
+        Ok(count) => {
+            tracing::debug!("[write_line_protocol] stored {count} points");
+            HttpResponse::NoContent().finish()
+        }
+        Err(LineError::Db) => HttpResponse::ServiceUnavailable().json(json!({"code": "unavailable", "message": LineError::Db.to_string()})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"code": "invalid", "message": e.to_string()})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+    use actix_web::http::StatusCode;
+    use db::arrow_db;
+    use crate::handler_write::write_line_protocol;
+
+    #[actix_web::test]
+    async fn test_write_line_protocol() {
+        let tx_db = arrow_db::run(tokio::runtime::Handle::current());
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(tx_db))
+            .route("/api/v2/write", web::post().to(write_line_protocol))).await;
+
+        let req = test::TestRequest::post().uri("/api/v2/write?bucket=b&precision=ms").set_payload("fills,symbol=btc_usd price=42000.5 1705275000000").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NO_CONTENT);
+
+        let req = test::TestRequest::post().uri("/api/v2/write").set_payload("fills price=1i").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "invalid");
+
+        let req = test::TestRequest::post().uri("/api/v2/write?precision=d").set_payload("fills price=1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+}
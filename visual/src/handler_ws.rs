@@ -0,0 +1,124 @@
+//! handler_ws.rs
+//!
+//! live chart websocket at /ws; actix owns the socket, the broadcast hub decides what it's sent
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use common_lib::wire::WireFormat;
+use ws_broadcast::server::{Frame, HubHandle};
+
+#[derive(Debug, Deserialize)]
+pub struct WsParams {
+    #[serde(default)]
+    format: WireFormat,
+}
+
+/// GET ws://127.0.0.1:8080/ws?format=json|msgpack|arrow
+///
+/// the client sends subscribe/unsubscribe json (see ws_broadcast::subscription) and receives chart
+/// snapshots and appends for what it subscribed to, encoded as it asked (see common_lib::wire)
+pub async fn chart_ws(req: HttpRequest, body: web::Payload, params: web::Query<WsParams>, hub: web::Data<HubHandle>) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+    let hub = hub.get_ref().clone();
+    let (id, mut rx) = hub.connect(params.format).await;
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                out = rx.recv() => match out {
+                    Some(frame) => {
+                        let sent = match frame {
+                            Frame::Text(txt) => session.text(txt).await,
+                            Frame::Binary(bytes) => session.binary(bytes).await,
+                        };
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                    // shutdown, or the hub gave up on a slow client
+                    None => break,
+                },
+                incoming = msg_stream.next() => match incoming {
+                    Some(Ok(Message::Text(txt))) => hub.request(id, txt.to_string()).await,
+                    Some(Ok(Message::Ping(bytes))) => if session.pong(&bytes).await.is_err() {
+                        break;
+                    },
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(e)) => {
+                        tracing::debug!("[chart_ws] client {id} read error: {e:?}");
+                        break;
+                    }
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+        hub.disconnect(id).await;
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpServer};
+    use chrono::{DateTime, Utc};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+    use common_lib::view::{ChartMessage, ViewDelta};
+    use common_lib::wire::{decode, WireFormat};
+    use common_lib::{ChartDataset, ChartTimeSeries};
+    use ws_broadcast::command::Cmd;
+    use ws_broadcast::server::{HubHandle, SlowConsumer};
+    use crate::handler_ws::chart_ws;
+
+    async fn next_message(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> ChartMessage {
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                Message::Text(txt) => return decode(txt.as_bytes(), WireFormat::Json).unwrap(),
+                msg => assert!(msg.is_ping() || msg.is_pong(), "{msg:?}"),
+            }
+        }
+    }
+
+    /// a subscriber gets its snapshot over /ws, then the chart deltas that follow
+    #[actix_web::test]
+    async fn test_chart_ws() {
+        let (s_tx, s_rx) = crossbeam_channel::unbounded::<Cmd>();
+        let hub = web::Data::new(HubHandle::start(s_rx, 4, SlowConsumer::Drop));
+        let server = HttpServer::new(move || App::new().app_data(hub.clone()).route("/ws", web::get().to(chart_ws)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws?format=json")).await.unwrap();
+        ws.send(Message::Text(r#"{"action":"subscribe","symbols":["btc_usd"]}"#.to_string())).await.unwrap();
+        assert!(matches!(next_message(&mut ws).await, ChartMessage::Snapshot { datasets } if datasets.is_empty()));
+
+        let x = DateTime::<Utc>::from(DateTime::parse_from_rfc3339("2024-01-14T23:30:00Z").unwrap());
+        let datasets = vec![
+            ChartDataset { label: "btc_usd_Coinbase".to_string(), data: vec![ChartTimeSeries { x, y: 1.0 }] },
+            ChartDataset { label: "eth_usd_Coinbase".to_string(), data: vec![ChartTimeSeries { x, y: 2.0 }] },
+        ];
+        s_tx.send(Cmd::ChartDelta(ViewDelta { view: "chart_ws".to_string(), snapshot: false, revision: false, datasets })).unwrap();
+        match next_message(&mut ws).await {
+            ChartMessage::Append { datasets } => {
+                assert_eq!(datasets.len(), 1);
+                assert_eq!(datasets[0].label, "btc_usd_Coinbase");
+                assert_eq!(datasets[0].data, vec![ChartTimeSeries { x, y: 1.0 }]);
+            }
+            msg => panic!("unexpected {msg:?}"),
+        }
+
+        ws.close(None).await.unwrap();
+        handle.stop(true).await;
+    }
+}
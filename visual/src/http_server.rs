@@ -1,92 +1,118 @@
 //! http_server.rs
 
 
-use std::fs;
-use std::io::{BufReader, Read};
-use std::str::FromStr;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 use actix_files::NamedFile;
-use actix_web::{web, App, HttpServer, Responder, HttpResponse};
-use crossbeam_channel::Sender;
+use actix_web::{web, App, HttpRequest, HttpServer, Responder, HttpResponse};
+use actix_web::cookie::Key;
+use actix_web::middleware::from_fn;
+use crossbeam_channel::{Receiver, Sender};
 use handlebars::Handlebars;
 use serde_json::json;
 
 use tokio::try_join;
 use common_lib::init::ConfigLocation;
+use common_lib::auth::Auth;
+use common_lib::config::Config;
 use common_lib::DbMsg;
-use crate::handler_chart::{present_raw_data, present_chart_multi_line_static};
+use common_lib::view::ChartFilter;
+use ws_broadcast::command::Cmd;
+use ws_broadcast::server::{HubHandle, SlowConsumer};
+use ws_broadcast::CLIENT_QUEUE_SIZE;
+use crate::dashboard::LayoutStore;
+use crate::handler_api::{api_v1, API_V1};
+use crate::handler_auth::{authorize, login, login_state, session_middleware};
+use crate::handler_chart::{present_chart_data, present_raw_data, present_chart_multi_line_static};
+use crate::handler_dashboard::dashboards;
+use crate::handler_health::health;
+use crate::handler_ws::chart_ws;
+use crate::handler_write::{write_line_protocol, MAX_WRITE_BYTES};
+use crate::tls::{CertResolver, TlsConfig};
+
+/// templates and js: ./static in docker, else this crate's static dir wherever cargo runs from
+struct StaticDir(PathBuf);
+
+impl StaticDir {
+    fn new(location: ConfigLocation) -> StaticDir {
+        match location {
+            ConfigLocation::Docker => StaticDir(PathBuf::from("./static")),
+            ConfigLocation::NotDocker => StaticDir(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static")),
+        }
+    }
+}
 
-/// start actix in a new blocking thread
-pub async fn run(tx_operator2: Sender<DbMsg>) -> Result<(), std::io::Error> {
+/// start actix in a new blocking thread; chart deltas arriving on server_rx go out over /ws,
+/// dashboard layouts are kept in layouts, and auth guards every route (see handler_auth).
+/// with tls in config everything, /ws included, is served over https only; config is shown
+/// read-only at /api/v1/config
+pub async fn run(tx_operator2: Sender<DbMsg>, server_rx: Receiver<Cmd>, layouts: LayoutStore, auth: Auth, config: Config) -> Result<(), std::io::Error> {
 
     // handlebars
     // refs:
     // https://github.com/actix/examples/blob/master/templating/handlebars/src/main.rs
     // https://github.com/sunng87/handlebars-rust/tree/master/examples
     let mut handlebars = Handlebars::new();
-    let config_location:ConfigLocation = ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION");
-    // or CARGO_PKG_NAME
-    let package_name = env!("CARGO_MANIFEST_DIR");
-    let handlebar_static_path = match config_location {
-        ConfigLocation::Docker => "./static/templates".to_string(),
-        ConfigLocation::NotDocker => {
-            // frontend/static/templates
-            // /Users/xyz/.../trade/frontend/static/templates
-            format!("{}/static/templates", &package_name)
-        }
-    };
-    tracing::debug!("[web_server] registering handlebars static files to: {}",&handlebar_static_path);
+    let static_dir = StaticDir::new(ConfigLocation::from_env());
+    let handlebar_static_path = static_dir.0.join("templates");
+    tracing::debug!("[web_server] registering handlebars static files to: {:?}",&handlebar_static_path);
     handlebars.register_templates_directory(".html", handlebar_static_path).unwrap();
     let handlebars_ref = web::Data::new(handlebars);
+    let static_dir = web::Data::new(static_dir);
 
 
-    // TLS
-    // https://github.com/rustls/rustls/blob/main/examples/src/bin/tlsserver-mio.rs
-    // let certs = load_certs("/Users/gp/trade/frontend/certificate/cert.pem");
-    // let privkey = load_private_key("/Users/gp/trade/frontend/certificate/key.pem");
-    // let (certs, privkey) = match config_location{
-    //     ConfigLocation::Docker => {
-    //         let certs = load_certs("./static/certificate/cert.pem");
-    //         let privkey = load_private_key("./static/certificate/key.pem");
-    //         // let ocsp = load_ocsp(&args.flag_ocsp);
-    //         (certs, privkey)
-    //     },
-    //     ConfigLocation::NotDocker => {
-    //         // TODO: don't hard code paths
-    //         let certs = load_certs("visual/static/certificate/cert.pem");
-    //         let privkey = load_private_key("visual/static/certificate/key.pem");
-    //         // let ocsp = load_ocsp(&args.flag_ocsp);
-    //         (certs, privkey)
-    //     }
-    // };
-
-    // let config = rustls::ServerConfig::builder()
-    //     .with_cipher_suites(&rustls::ALL_CIPHER_SUITES.to_vec())
-    //     .with_safe_default_kx_groups()
-    //     .with_protocol_versions(&rustls::ALL_VERSIONS.to_vec())
-    //     .expect("inconsistent cipher-suites/versions specified")
-    //     // .with_client_cert_verifier(NoClientAuth::)
-    //     // .with_single_cert_with_ocsp(certs, privkey, ocsp)
-    //     .with_no_client_auth()
-    //     .with_single_cert(certs, privkey)
-    //     .expect("bad certificates/private key");
-
     let tx_operator = web::Data::new(tx_operator2.clone());
+    let layouts = web::Data::new(layouts);
+    if auth.is_open() {
+        tracing::warn!("[web_server] no users file, so auth is off and every caller is an admin");
+    }
+    let auth = web::Data::new(auth);
+    let session_key = Key::generate();
+    let tls = TlsConfig::from_settings(&config.listeners.tls);
+    let secure = tls.is_some();
+    let addr = config.listeners.http.clone();
+    let config = web::Data::new(config);
 
+    // the chart websocket hub runs on this runtime; each actix worker hands it its /ws clients
+    let hub = web::Data::new(HubHandle::start(server_rx, CLIENT_QUEUE_SIZE, SlowConsumer::Drop));
 
-    // note: the websocket is handled entirely outside actix, by tungstenite
     let http_server = HttpServer::new(move || {
         App::new()
             .app_data(tx_operator.clone())
             .app_data(handlebars_ref.clone())
+            .app_data(hub.clone())
+            .app_data(layouts.clone())
+            .app_data(auth.clone())
+            .app_data(config.clone())
+            .app_data(static_dir.clone())
+            .wrap(from_fn(authorize))
+            .wrap(session_middleware(session_key.clone(), secure))
+            .configure(login)
+            .configure(health)
             .route("/", web::get().to(present_chart_multi_line_static))
             .route("/js/chart.js", web::get().to(get_file_chart_js))
             .route("/js/chartjs-adapter-date-fns.js", web::get().to(get_file_chart_js_date))
+            .route("/js/chart_wire.js", web::get().to(get_file_chart_wire_js))
             .route("/raw", web::get().to(present_raw_data))
+            .route("/chart_data", web::get().to(present_chart_data))
             .route("/chart_ws", web::get().to(present_chart_dynamic))
+            .route("/ws", web::get().to(chart_ws))
+            .configure(dashboards)
+            .service(web::scope(API_V1).configure(api_v1))
+            .service(web::resource(["/write", "/api/v2/write"])
+                .app_data(web::PayloadConfig::new(MAX_WRITE_BYTES))
+                .route(web::post().to(write_line_protocol)))
 
-    })
-    // .bind_rustls(("127.0.0.1", 8443), config)?
-    .bind(("127.0.0.1", 8080))?
+    });
+    let http_server = match &tls {
+        Some(tls) => {
+            let resolver = Arc::new(CertResolver::load(tls.clone()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{e:?}")))?);
+            tokio::spawn(resolver.clone().watch());
+            http_server.bind_rustls(&addr, resolver.server_config())?
+        }
+        None => http_server.bind(&addr)?,
+    }
     .run();
 
     try_join!(http_server)?;
@@ -97,13 +123,21 @@ pub async fn run(tx_operator2: Sender<DbMsg>) -> Result<(), std::io::Error> {
 
 
 /// show multiple datasets on the same chart, regardless of x-axis count
-/// websocket does the data loading
-pub async fn present_chart_dynamic(_tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>/*, session: Session*/) -> HttpResponse {
+/// websocket does the data loading; takes the same query parameters as '/', see ChartFilter
+pub async fn present_chart_dynamic(req: HttpRequest, params: web::Query<HashMap<String, String>>, _tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>/*, session: Session*/) -> HttpResponse {
+    let filter = match ChartFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(reason) => return HttpResponse::BadRequest().body(reason),
+    };
+    let (is_logged_in, session_username) = login_state(&req);
     let data = json!({
         "title": "chart_ws",
         "parent": "base0",
-        "is_logged_in": true,
-        "chart_title": "Coinbase",
+        "is_logged_in": is_logged_in,
+        "session_username": session_username,
+        "chart_title": params.get("title").cloned().unwrap_or_else(|| filter.title()),
+        "subscription": filter.subscription().to_string(),
+        "window_ms": filter.window.map_or(0, |x| x.num_milliseconds()),
     });
     let body = hb.render("chart_ws", &data).unwrap();
     HttpResponse::Ok().append_header(("cache-control", "no-store")).body(body)
@@ -112,66 +146,19 @@ pub async fn present_chart_dynamic(_tx_db: web::Data<Sender<DbMsg>>, hb: web::Da
 
 /// GET http://127.0.0.1:8080/js/chart.js
 /// https://www.chartjs.org/docs/latest/getting-started/installation.html
-async fn get_file_chart_js() -> impl Responder{
-    // tracing::debug!("[get_chart_js]");
-    let config_location:ConfigLocation = ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION");
-
-    match config_location{
-        ConfigLocation::Docker => NamedFile::open_async("./static/js/chart.js").await,
-        // TODO: un-hard-code paths
-        ConfigLocation::NotDocker => NamedFile::open_async("visual/static/js/chart.js").await
-    }
-}
-
-/// GET http://127.0.0.1:8080/js/chart.js
-/// https://www.chartjs.org/docs/latest/getting-started/installation.html
-/// TODO: un-hard-code paths
-async fn get_file_chart_js_date() -> impl Responder{
-    // tracing::debug!("[get_chart_js]");
-    let config_location:ConfigLocation = ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION");
-    match config_location{
-        ConfigLocation::Docker => NamedFile::open_async("./static/js/chartjs-adapter-date-fns.js").await,
-        ConfigLocation::NotDocker => NamedFile::open_async("visual/static/js/chartjs-adapter-date-fns.js").await
-    }
+async fn get_file_chart_js(dir: web::Data<StaticDir>) -> impl Responder{
+    NamedFile::open_async(dir.0.join("js/chart.js")).await
 }
 
-fn _load_private_key(filename: &str) -> rustls::PrivateKey {
-    let keyfile = fs::File::open(filename).expect("cannot open private key file");
-    let mut reader = BufReader::new(keyfile);
-
-    loop {
-        match rustls_pemfile::read_one(&mut reader).expect("cannot parse private key .pem file") {
-            Some(rustls_pemfile::Item::RSAKey(key)) => return rustls::PrivateKey(key),
-            Some(rustls_pemfile::Item::PKCS8Key(key)) => return rustls::PrivateKey(key),
-            Some(rustls_pemfile::Item::ECKey(key)) => return rustls::PrivateKey(key),
-            None => break,
-            _ => {}
-        }
-    }
-    panic!("no keys found in {:?} (encrypted keys not supported)", filename);
+/// GET http://127.0.0.1:8080/js/chartjs-adapter-date-fns.js
+async fn get_file_chart_js_date(dir: web::Data<StaticDir>) -> impl Responder{
+    NamedFile::open_async(dir.0.join("js/chartjs-adapter-date-fns.js")).await
 }
 
-fn _load_certs(filename: &str) -> Vec<rustls::Certificate> {
-    let certfile = fs::File::open(filename).expect("cannot open certificate file");
-    let mut reader = BufReader::new(certfile);
-    rustls_pemfile::certs(&mut reader)
-        .unwrap()
-        .iter()
-        .map(|v| rustls::Certificate(v.clone()))
-        .collect()
-}
-
-fn _load_ocsp(filename: &Option<String>) -> Vec<u8> {
-    let mut ret = Vec::new();
-
-    if let Some(name) = filename {
-        fs::File::open(name)
-            .expect("cannot open ocsp file")
-            .read_to_end(&mut ret)
-            .unwrap();
-    }
-
-    ret
+/// GET http://127.0.0.1:8080/js/chart_wire.js
+/// msgpack and arrow decoders for the chart websocket
+async fn get_file_chart_wire_js(dir: web::Data<StaticDir>) -> impl Responder{
+    NamedFile::open_async(dir.0.join("js/chart_wire.js")).await
 }
 
 #[cfg(test)]
@@ -197,5 +184,29 @@ mod tests {
     //     let resp = test::call_service(&app, req).await;
     //     assert!(resp.status().is_client_error());
     // }
+
+    use actix_web::{test, web, App};
+    use common_lib::DbMsg;
+    use handlebars::Handlebars;
+    use crate::http_server::present_chart_dynamic;
+
+    /// the title only ever lands in an attribute, escaped, never in script text
+    #[actix_web::test]
+    async fn test_chart_title_escaped() {
+        let mut hb = Handlebars::new();
+        hb.register_templates_directory(".html", format!("{}/static/templates", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let (tx_db, _rx_db) = crossbeam_channel::unbounded::<DbMsg>();
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(hb))
+            .app_data(web::Data::new(tx_db))
+            .route("/chart_ws", web::get().to(present_chart_dynamic))).await;
+
+        // '</script>\ and a line separator
+        let req = test::TestRequest::get().uri("/chart_ws?title=%27%3C%2Fscript%3E%5C%E2%80%A8").to_request();
+        let body = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        assert!(body.contains("data-title=\"&#x27;&lt;/script&gt;\\\u{2028}\""), "{body}");
+        assert!(!body.contains("'</script>"));
+        assert!(!body.contains("{{"));
+    }
 }
 
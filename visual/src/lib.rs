@@ -1,4 +1,14 @@
 //! lib.rs
 pub mod http_server;
+pub mod dashboard;
+pub mod tls;
+pub mod webhook;
+mod handler_alert;
+mod handler_api;
+mod handler_auth;
 mod handler_chart;
+mod handler_dashboard;
+mod handler_health;
+mod handler_ws;
+mod handler_write;
 
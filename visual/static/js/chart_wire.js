@@ -0,0 +1,241 @@
+/*
+
+  chart_wire.js
+
+  Decodes the binary chart encodings from common_lib::wire into the same shape as the json messages,
+  except x is epoch milliseconds instead of an rfc3339 string (chart.js takes either):
+
+    {type: 'snapshot' | 'append' | 'revise' | 'error' | 'alert', message, alert, datasets: [{label, data: [{x, y}, ...]}, ...]}
+
+  ChartWire.decode('msgpack' | 'arrow', arrayBuffer)
+
+  msgpack carries {type, datasets: [{label, x: [...], y: [...]}]}. Arrow is an IPC stream with one
+  record batch of (label: dictionary<int32, utf8>, x: timestamp[ms], y: float64); only that layout
+  is read, not arbitrary arrow.
+
+*/
+
+const ChartWire = (() => {
+
+    /**************** msgpack ****************/
+
+    function msgpackDecode(buffer) {
+        const view = new DataView(buffer);
+        const bytes = new Uint8Array(buffer);
+        const text = new TextDecoder();
+        let pos = 0;
+
+        function str(len) {
+            const s = text.decode(bytes.subarray(pos, pos + len));
+            pos += len;
+            return s;
+        }
+        function array(len) {
+            const out = new Array(len);
+            for (let i = 0; i < len; i++) {
+                out[i] = value();
+            }
+            return out;
+        }
+        function map(len) {
+            const out = {};
+            for (let i = 0; i < len; i++) {
+                const k = value();
+                out[k] = value();
+            }
+            return out;
+        }
+        function value() {
+            const b = bytes[pos++];
+            if (b <= 0x7f) return b;
+            if (b >= 0xe0) return b - 0x100;
+            if ((b & 0xf0) === 0x80) return map(b & 0x0f);
+            if ((b & 0xf0) === 0x90) return array(b & 0x0f);
+            if ((b & 0xe0) === 0xa0) return str(b & 0x1f);
+            let v;
+            switch (b) {
+                case 0xc0: return null;
+                case 0xc2: return false;
+                case 0xc3: return true;
+                case 0xc4: v = bytes[pos]; pos += 1; return bytes.slice(pos, pos += v);
+                case 0xc5: v = view.getUint16(pos); pos += 2; return bytes.slice(pos, pos += v);
+                case 0xc6: v = view.getUint32(pos); pos += 4; return bytes.slice(pos, pos += v);
+                case 0xca: v = view.getFloat32(pos); pos += 4; return v;
+                case 0xcb: v = view.getFloat64(pos); pos += 8; return v;
+                case 0xcc: v = view.getUint8(pos); pos += 1; return v;
+                case 0xcd: v = view.getUint16(pos); pos += 2; return v;
+                case 0xce: v = view.getUint32(pos); pos += 4; return v;
+                case 0xcf: v = Number(view.getBigUint64(pos)); pos += 8; return v;
+                case 0xd0: v = view.getInt8(pos); pos += 1; return v;
+                case 0xd1: v = view.getInt16(pos); pos += 2; return v;
+                case 0xd2: v = view.getInt32(pos); pos += 4; return v;
+                case 0xd3: v = Number(view.getBigInt64(pos)); pos += 8; return v;
+                case 0xd9: v = view.getUint8(pos); pos += 1; return str(v);
+                case 0xda: v = view.getUint16(pos); pos += 2; return str(v);
+                case 0xdb: v = view.getUint32(pos); pos += 4; return str(v);
+                case 0xdc: v = view.getUint16(pos); pos += 2; return array(v);
+                case 0xdd: v = view.getUint32(pos); pos += 4; return array(v);
+                case 0xde: v = view.getUint16(pos); pos += 2; return map(v);
+                case 0xdf: v = view.getUint32(pos); pos += 4; return map(v);
+            }
+            throw new Error('msgpack: unsupported type 0x' + b.toString(16));
+        }
+        return value();
+    }
+
+    function fromMsgpack(buffer) {
+        const packed = msgpackDecode(buffer);
+        const datasets = (packed.datasets || []).map((d) => ({
+            label: d.label,
+            data: d.x.map((x, i) => ({x: x, y: d.y[i]})),
+        }));
+        return {type: packed.type, message: packed.message, alert: packed.alert, datasets: datasets};
+    }
+
+    /**************** arrow ipc ****************/
+
+    // just enough flatbuffers to walk the arrow Message tables
+    function table(view, pos) {
+        const vtable = pos - view.getInt32(pos, true);
+        const vtableSize = view.getUint16(vtable, true);
+        return {
+            // absolute position of field `id`, or 0 if absent
+            field(id) {
+                const at = 4 + 2 * id;
+                if (at >= vtableSize) return 0;
+                const offset = view.getUint16(vtable + at, true);
+                return offset === 0 ? 0 : pos + offset;
+            },
+            int64(id) {
+                const f = this.field(id);
+                return f ? Number(view.getBigInt64(f, true)) : 0;
+            },
+            uint8(id) {
+                const f = this.field(id);
+                return f ? view.getUint8(f) : 0;
+            },
+            // follow an offset to a table, vector or string
+            ref(id) {
+                const f = this.field(id);
+                return f ? f + view.getUint32(f, true) : 0;
+            },
+        };
+    }
+
+    function vector(view, pos) {
+        return {length: view.getUint32(pos, true), start: pos + 4};
+    }
+
+    function string(view, pos) {
+        const v = vector(view, pos);
+        return new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + v.start, v.length));
+    }
+
+    const HEADER_SCHEMA = 1;
+    const HEADER_DICTIONARY_BATCH = 2;
+    const HEADER_RECORD_BATCH = 3;
+
+    // RecordBatch table -> {length, nodes: [{length}], buffers: [{offset, length}]}
+    function recordBatch(view, pos) {
+        const t = table(view, pos);
+        const nodes = vector(view, t.ref(1));
+        const buffers = vector(view, t.ref(2));
+        const out = {length: t.int64(0), nodes: [], buffers: []};
+        for (let i = 0; i < nodes.length; i++) {
+            out.nodes.push({length: Number(view.getBigInt64(nodes.start + 16 * i, true))});
+        }
+        for (let i = 0; i < buffers.length; i++) {
+            const at = buffers.start + 16 * i;
+            out.buffers.push({offset: Number(view.getBigInt64(at, true)), length: Number(view.getBigInt64(at + 8, true))});
+        }
+        return out;
+    }
+
+    function fromArrow(buffer) {
+        const view = new DataView(buffer);
+        let pos = 0;
+        let metadata = {};
+        let labels = [];
+        const datasets = [];
+
+        while (pos + 8 <= buffer.byteLength) {
+            let size = view.getInt32(pos, true);
+            pos += 4;
+            // continuation marker, then the metadata length
+            if (size === -1) {
+                size = view.getInt32(pos, true);
+                pos += 4;
+            }
+            if (size === 0) break;
+
+            const meta = new DataView(buffer, pos, size);
+            const message = table(meta, meta.getUint32(0, true));
+            const headerType = message.uint8(1);
+            const header = message.ref(2);
+            const bodyLength = message.int64(3);
+            const body = pos + size;
+            pos = body + bodyLength;
+
+            if (headerType === HEADER_SCHEMA) {
+                const kvs = table(meta, header).ref(2);
+                if (kvs) {
+                    const v = vector(meta, kvs);
+                    for (let i = 0; i < v.length; i++) {
+                        const at = v.start + 4 * i;
+                        const kv = table(meta, at + meta.getUint32(at, true));
+                        metadata[string(meta, kv.ref(0))] = string(meta, kv.ref(1));
+                    }
+                }
+            } else if (headerType === HEADER_DICTIONARY_BATCH) {
+                // data: a batch with one utf8 column; buffers are validity, offsets, bytes
+                const batch = recordBatch(meta, table(meta, header).ref(1));
+                const offsets = new Int32Array(buffer.slice(body + batch.buffers[1].offset, body + batch.buffers[1].offset + batch.buffers[1].length));
+                const chars = new Uint8Array(buffer, body + batch.buffers[2].offset, batch.buffers[2].length);
+                const text = new TextDecoder();
+                labels = [];
+                for (let i = 0; i < batch.nodes[0].length; i++) {
+                    labels.push(text.decode(chars.subarray(offsets[i], offsets[i + 1])));
+                }
+            } else if (headerType === HEADER_RECORD_BATCH) {
+                // label keys, x, y; each column is a validity buffer then its values
+                const batch = recordBatch(meta, header);
+                const column = (index, Type) => {
+                    const b = batch.buffers[2 * index + 1];
+                    return new Type(buffer.slice(body + b.offset, body + b.offset + b.length));
+                };
+                const keys = column(0, Int32Array);
+                const xs = column(1, BigInt64Array);
+                const ys = column(2, Float64Array);
+                const byLabel = {};
+                for (let row = 0; row < batch.length; row++) {
+                    const label = labels[keys[row]];
+                    let dataset = byLabel[label];
+                    if (!dataset) {
+                        dataset = byLabel[label] = {label: label, data: []};
+                        datasets.push(dataset);
+                    }
+                    dataset.data.push({x: Number(xs[row]), y: ys[row]});
+                }
+            }
+        }
+        // an alert is json in the metadata
+        if (metadata['type'] === 'alert') {
+            return {type: 'alert', alert: JSON.parse(metadata['message']), datasets: datasets};
+        }
+        return {type: metadata['type'], message: metadata['message'], datasets: datasets};
+    }
+
+    return {
+        decode(format, buffer) {
+            switch (format) {
+                case 'msgpack': return fromMsgpack(buffer);
+                case 'arrow': return fromArrow(buffer);
+                default: return JSON.parse(new TextDecoder().decode(buffer));
+            }
+        },
+    };
+})();
+
+if (typeof module !== 'undefined') {
+    module.exports = ChartWire;
+}
@@ -4,6 +4,9 @@
 //!
 
 use common_lib::{DbMsg};
+use common_lib::cb_ticker::Datasource;
+use common_lib::config::Feed;
+use common_lib::health::FeedState;
 use std::error::Error;
 use std::thread::JoinHandle;
 use crossbeam::channel::Sender;
@@ -11,43 +14,51 @@ use tungstenite::{connect};
 use url::Url;
 use crate::{ws_alpaca, ws_coinbase};
 
-const ALPACA_CRYPTOCURRENCY_URL: &str = "wss://stream.data.alpaca.markets/v1beta3/crypto/us";
-// const COINBASE_URL: &str = "wss://ws_client-feed.pro.coinbase.com";
-const COINBASE_URL: &str = "wss://ws-feed.exchange.coinbase.com";
-
 #[derive(Debug)]
 pub enum ConnectSource {
     Alpaca,
     Coinbase,
 }
 
-/// Start a new thread listening to the coinbase websocket
-pub fn run(source: ConnectSource, tx_db: Sender<DbMsg>) -> JoinHandle<()> {
+impl ConnectSource {
+    pub fn datasource(&self) -> Datasource {
+        match self {
+            ConnectSource::Alpaca => Datasource::Alpaca,
+            ConnectSource::Coinbase => Datasource::Coinbase,
+        }
+    }
+}
+
+/// Start a new thread listening to the source's websocket, see common_lib::config::Feed. The feed
+/// is reported connecting before this returns, so readiness waits for it
+pub fn run(source: ConnectSource, feed: Feed, tx_db: Sender<DbMsg>) -> JoinHandle<()> {
     tracing::debug!("[run] spawning websocket...");
+    let datasource = source.datasource();
+    let _ = tx_db.send(DbMsg::FeedState(datasource.clone(), FeedState::Connecting));
     std::thread::spawn(move || {
-        let _ws = ws_connect(source, tx_db);
+        if let Err(e) = ws_connect(source, &feed, tx_db.clone()) {
+            tracing::error!("[run] {} not connected: {:?}", &feed.url, &e);
+        }
+        let _ = tx_db.send(DbMsg::FeedState(datasource, FeedState::Disconnected));
     })
 }
 
 /// connect to alpaca or coinbase websocket
-pub fn ws_connect(source: ConnectSource, tx_db: Sender<DbMsg>) -> Result<(), Box<dyn Error>> {
+pub fn ws_connect(source: ConnectSource, feed: &Feed, tx_db: Sender<DbMsg>) -> Result<(), Box<dyn Error>> {
     // https://doc.rust-lang.org/book/ch09-02-recoverable-errors-with-result.html
-    let url = match source {
-        ConnectSource::Alpaca => std::env::var("ALPACA_CRYPTOCURRENCY_URL").unwrap_or_else(|_| ALPACA_CRYPTOCURRENCY_URL.to_string()),
-        ConnectSource::Coinbase => std::env::var("COINBASE_URL").unwrap_or_else(|_| COINBASE_URL.to_string()),
-    };
-    tracing::debug!("[ws_connect] url: {}", &url);
+    tracing::debug!("[ws_connect] url: {}", &feed.url);
     #[allow(unused_mut)]
-    let (mut socket, response) = connect(Url::parse(&url)?)?;
+    let (mut socket, response) = connect(Url::parse(&feed.url)?)?;
 
     tracing::debug!("[ws_connect] {source:?} response: {response:?}");
+    let _ = tx_db.send(DbMsg::FeedState(source.datasource(), FeedState::Connected));
 
     match source {
         ConnectSource::Alpaca => {
-            ws_alpaca::parse(socket, tx_db);
+            ws_alpaca::parse(socket, feed, tx_db);
         },
         ConnectSource::Coinbase => {
-            ws_coinbase::parse(socket, tx_db);
+            ws_coinbase::parse(socket, &feed.symbols, tx_db);
         }
     }
 
@@ -13,8 +13,9 @@ use tungstenite::{Message, WebSocket};
 use tungstenite::stream::MaybeTlsStream;
 use common_lib::{DbMsg, SymbolCommon, TickerCommon};
 use common_lib::cb_ticker::{Datasource};
+use common_lib::config::Feed;
 
-fn stock_list_to_uppercase(lower_stock: &Vec<String>) -> Vec<String> {
+fn stock_list_to_uppercase(lower_stock: &[String]) -> Vec<String> {
     lower_stock.iter().map(|x| x.to_uppercase()).collect()
 }
 
@@ -103,6 +104,18 @@ pub struct AlpacaTrade {
 
 }
 
+impl AlpacaTrade {
+    fn to_common(&self) -> TickerCommon {
+        TickerCommon{
+            source: Datasource::Alpaca,
+            symbol: self.symbol.to_common(),
+            price: self.price,
+            dtg: self.dtg,
+            trade_id: Some(self.id_trade),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum AlpacaSymbol {
     #[serde(alias="BTC/USD")]
@@ -140,6 +153,7 @@ impl AlpacaQuote {
             symbol: self.symbol.to_common(),
             price: self.quote_bp,
             dtg: self.dtg,
+            trade_id: None,
         }
     }
 }
@@ -167,8 +181,8 @@ pub struct AlpacaBar {
     pub dtg: DateTime<Utc>,
 }
 
-pub fn parse(mut ws: WebSocket<MaybeTlsStream<TcpStream>>, _tx_db: Sender<DbMsg>) {
-    let _ = ws.send(Message::Text(authenticate().to_string()));
+pub fn parse(mut ws: WebSocket<MaybeTlsStream<TcpStream>>, feed: &Feed, _tx_db: Sender<DbMsg>) {
+    let _ = ws.send(Message::Text(authenticate(feed).to_string()));
     loop {
         let msg_result = ws.read();
         match msg_result {
@@ -199,14 +213,15 @@ pub fn parse(mut ws: WebSocket<MaybeTlsStream<TcpStream>>, _tx_db: Sender<DbMsg>
                                         DataMesgSuccess::Connected=> tracing::debug!("[parse] connected"),
                                         DataMesgSuccess::Authenticated=>{
                                             tracing::debug!("[parse] authenticated");
-                                            subscribe(&mut ws);
+                                            subscribe(&mut ws, &feed.symbols);
                                         },
                                     }
                                 },
 
                                 AlpacaPacket::Trade(trade)=>{
-                                    tracing::error!("[parse][trade] {:?}", &trade);
-                                    // let _ = tx_db.send(DbMsg::TradeAlpaca(trade.to_owned()));
+                                    // a resent trade after a reconnect carries the same "i" and is dropped by the db
+                                    tracing::debug!("[parse][trade] {:?}", &trade);
+                                    let _ = _tx_db.send(DbMsg::Insert(Datasource::Alpaca, trade.to_common()));
                                 },
                                 AlpacaPacket::Bar(b)=>{
                                     tracing::error!("[parse][bar] {:?}", &b);
@@ -261,17 +276,15 @@ pub fn parse(mut ws: WebSocket<MaybeTlsStream<TcpStream>>, _tx_db: Sender<DbMsg>
 ///                    >  {"action": "listen", "data": {"streams": ["T.SPY"]}}
 ///                    < {"stream":"listening","data":{"streams":["T.SPY"]}}
 ///
-fn authenticate() -> serde_json::Value {
+/// the keys are checked by Config::validate when the feed is enabled
+fn authenticate(feed: &Feed) -> serde_json::Value {
     // {"action": "authenticate","data": {"key_id": "???", "secret_key": "???"}}
 
     // TODO: add database setting "use_paper_or_live_key"
-    let api_key = std::env::var("ALPACA_API_ID").expect("ALPACA_API_ID not found");
-    let api_secret = std::env::var("ALPACA_API_SECRET").expect("ALPACA_API_SECRET not found");
-
     let json_obj = RequestAuthenticate {
         action: RequestAction::Auth, // "auth".to_owned()
-        key: api_key,
-        secret: api_secret,
+        key: feed.key_id.clone().unwrap_or_default(),
+        secret: feed.secret.clone().unwrap_or_default(),
     };
 
     let j: serde_json::Value = serde_json::to_value(&json_obj).expect("[gen_subscribe_json] json serialize failed");
@@ -280,9 +293,10 @@ fn authenticate() -> serde_json::Value {
 
 /// subscribe to stock feeds
 /// https://alpaca.markets/docs/api-references/market-data-api/stock-pricing-data/realtime/#subscribe
-fn subscribe(ws: &mut WebSocket<MaybeTlsStream<TcpStream>>) {
+fn subscribe(ws: &mut WebSocket<MaybeTlsStream<TcpStream>>, symbols: &[SymbolCommon]) {
 
-    let symbols = vec!("BTC/USD".to_string());
+    // btc_usd is BTC/USD
+    let symbols: Vec<String> = symbols.iter().map(|x| x.to_string().replace('_', "/")).collect();
 
     let json = json!({
         "action": RequestAction::Subscribe,
@@ -1,7 +1,6 @@
 //! ws_inbound
 
 #[allow(dead_code)]
-
 use crossbeam::channel::Sender;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -9,7 +8,7 @@ use std::net::TcpStream;
 use strum::IntoEnumIterator;
 use tungstenite::stream::MaybeTlsStream;
 use tungstenite::{Message, WebSocket};
-use common_lib::{DbMsg};
+use common_lib::{DbMsg, SymbolCommon};
 use common_lib::cb_ticker::{Datasource, SymbolCoinbase};
 use chrono::{DateTime, Utc};
 use common_lib::cb_ticker::TickerCoinbase;
@@ -54,9 +53,9 @@ pub struct Error {
 }
 
 /// Todo: make websocket post-processing asynchronous
-pub fn parse(mut ws: WebSocket<MaybeTlsStream<TcpStream>>, tx_db: Sender<DbMsg>) {
+pub fn parse(mut ws: WebSocket<MaybeTlsStream<TcpStream>>, symbols: &[SymbolCommon], tx_db: Sender<DbMsg>) {
     // subscribe to coinbase.rs socket for heartbeat and tickers
-    let _ = ws.send(Message::Text(subscribe().to_string()));
+    let _ = ws.send(Message::Text(subscribe(symbols).to_string()));
 
     // parse incoming
     loop {
@@ -144,10 +143,10 @@ pub struct Subscribe {
     pub channels: Vec<String>,
 }
 
-/// here's where we subscribe to all the possible products (BTC-USD, ETH-USC, ETH-BTC
-fn subscribe() -> serde_json::Value {
+/// here's where we subscribe to the configured products (BTC-USD, ETH-USD, ETH-BTC)
+fn subscribe(symbols: &[SymbolCommon]) -> serde_json::Value {
 
-    let prod_ids = SymbolCoinbase::iter().map(|x|{x.to_string_coinbase()}).collect();
+    let prod_ids = SymbolCoinbase::iter().filter(|x| symbols.contains(&x.to_common())).map(|x|{x.to_string_coinbase()}).collect();
 
     let cb_sub = Subscribe {
         typ: "subscribe".to_owned(),
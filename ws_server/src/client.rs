@@ -95,7 +95,7 @@ impl Client {
     }
 
 
-    /// PING
+    // PING
 
 
     pub fn run(&mut self) {
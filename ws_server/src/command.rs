@@ -2,9 +2,16 @@
 //!
 //! send this to either the client or server as a crossbeam inter-thread message
 
+use common_lib::alert::Alert;
+use common_lib::view::ViewDelta;
+
 #[derive(Debug)]
 pub enum Cmd {
     Shutdown,
     StartPing,
     Broadcast(String),
+    // chart points; each client is sent only what it hasn't seen
+    ChartDelta(ViewDelta),
+    // to every client that has subscribed to anything
+    Alert(Alert),
 }
\ No newline at end of file
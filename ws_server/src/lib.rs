@@ -5,21 +5,23 @@
 pub mod client;
 pub mod server;
 pub mod command;
+pub mod subscription;
 
 pub const TEST_SHUTDOWN_TIMER_SEC:u64 = 1;
-pub const SERVER_READ_POLL_MS:u64 = 1;
+pub const SERVER_ADDR:&str = "127.0.0.1:3012";
+// per client; past this the server's SlowConsumer policy applies
+pub const CLIENT_QUEUE_SIZE:usize = 64;
 pub const CLIENT_READ_POLL_MS:u64 = 1;
+pub const CHART_HISTORY_SIZE:usize = 1000;
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
     use common_lib::init;
-    use crate::{client, init, server};
     use crate::command::Cmd;
     use crate::server::Server;
 
-    /// client PINGs server once per second; server replies with PONG
-    /// Client gets told to shutdown after four seconds
+    /// server broadcasts a few messages to whoever is connected, then shuts down
     #[test]
     fn simulated_main() {
         init::init("no-block-websocket");
@@ -29,28 +31,16 @@ mod tests {
         let (server_tx, server_rx) = crossbeam_channel::unbounded::<Cmd>();
 
         let h1 = std::thread::spawn(|| {
-            let mut server = Server::new();
+            let mut server = Server::new().addr("127.0.0.1:0");
             server.run(server_rx);
         });
 
-        // let (client_tx, client_rx) = crossbeam_channel::unbounded::<Cmd>();
-        // let h2 = std::thread::spawn(|| {
-        //     let mut c = client::Client::new(client_rx);
-        //     c.run();
-        // });
-        
-        // test
-        // client_tx.send(Cmd::StartPing).unwrap();
-        // std::thread::sleep(Duration::from_secs(4));
-        // client_tx.send(Cmd::Shutdown).unwrap();
-
-
-        for i in 0..100 {
+        for i in 0..5 {
             server_tx.send(Cmd::Broadcast(format!("hello from test: {i}"))).unwrap();
-            std::thread::sleep(Duration::from_secs(1));
+            std::thread::sleep(Duration::from_millis(100));
         }
+        server_tx.send(Cmd::Shutdown).unwrap();
 
         h1.join().unwrap();
-        // h2.join().unwrap();
     }
-}
\ No newline at end of file
+}
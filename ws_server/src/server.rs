@@ -1,182 +1,705 @@
-//! client
-
-use std::{net::TcpListener};
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
-use std::thread::spawn;
+//! server.rs
+//!
+//! chart broadcast websocket server. One hub task owns every client and the chart; feed commands,
+//! new connections and client requests all arrive on its one channel, so there are no per-client
+//! polling threads and no locks. Each client has a bounded send queue drained by its own connection
+//! task; a client that can't keep up has messages dropped or is disconnected (see SlowConsumer).
+//! The hub's own channel is bounded too: a feed or client that outpaces it waits rather than queueing
+//! without limit.
+//!
+//! The hub doesn't know about sockets: Server below feeds it from tungstenite, the actix /ws route
+//! in visual feeds it through a HubHandle.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use crossbeam_channel::{Receiver};
-use tungstenite::{Message, WebSocket};
-use tungstenite::protocol::CloseFrame;
-use tungstenite::protocol::frame::coding::CloseCode;
+use crossbeam_channel::Receiver;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+use common_lib::alert::Alert;
+use common_lib::metrics::METRICS;
+use common_lib::view::{ChartMessage, ViewDelta, ViewResult};
+use common_lib::wire::{encode, WireFormat};
+use common_lib::{ChartDataset, ChartTimeSeries};
 use crate::command::Cmd;
-use crate::{SERVER_READ_POLL_MS, TEST_SHUTDOWN_TIMER_SEC};
+use crate::subscription::{ClientRequest, Resolution};
+use crate::{CHART_HISTORY_SIZE, CLIENT_QUEUE_SIZE, SERVER_ADDR, TEST_SHUTDOWN_TIMER_SEC};
+
+pub type ClientId = u64;
+
+/// what to do with a client whose send queue is full
+#[derive(Debug, Clone, PartialEq)]
+pub enum SlowConsumer {
+    /// skip the message; chart points are resent from the client's high-water marks once it drains
+    Drop,
+    /// close the socket
+    Disconnect,
+}
 
 #[derive(Debug, Clone)]
 pub struct Server {
-    clients: Arc<Mutex<Vec<Arc<Mutex<WebSocket<TcpStream>>>>>>
+    addr: String,
+    queue_size: usize,
+    slow_consumer: SlowConsumer,
 }
-impl Server {
 
-    pub fn new()->Self {
-        Server{ clients: Arc::new(Mutex::new(vec![])) }
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn run(&mut self, s_rx: Receiver<Cmd>) {
+/// a running hub; clone one into every transport that accepts clients
+#[derive(Debug, Clone)]
+pub struct HubHandle {
+    tx: mpsc::Sender<Event>,
+    next_id: Arc<AtomicU64>,
+    queue_size: usize,
+}
+
+/// one outbound websocket message; binary for msgpack and arrow clients
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// everything the hub hears about
+#[derive(Debug)]
+enum Event {
+    Cmd(Cmd),
+    Connected(ClientId, WireFormat, mpsc::Sender<Frame>),
+    Request(ClientId, String),
+    Disconnected(ClientId),
+}
+
+/// owns the clients and the chart; runs as a single task
+struct Hub {
+    clients: HashMap<ClientId, Subscriber>,
+    // every series; each client gets the part it subscribed to
+    chart: ViewResult,
+    slow_consumer: SlowConsumer,
+}
+
+/// a client's send queue, the chart labels it subscribed to (at what resolution) and, per label,
+/// the last point it was sent (its high-water mark)
+#[derive(Debug)]
+struct Subscriber {
+    tx: mpsc::Sender<Frame>,
+    format: WireFormat,
+    subscribed: HashMap<String, Option<Resolution>>,
+    high_water: HashMap<String, ChartTimeSeries>,
+    // a snapshot didn't fit in the queue; send one instead of the next append
+    needs_snapshot: bool,
+}
 
-        let mut handles = vec![];
+impl Server {
+    pub fn new() -> Self {
+        Server { addr: SERVER_ADDR.to_string(), queue_size: CLIENT_QUEUE_SIZE, slow_consumer: SlowConsumer::Drop }
+    }
+
+    pub fn addr(mut self, addr: &str) -> Self {
+        self.addr = addr.to_string();
+        self
+    }
 
-        let tcp_listener = TcpListener::bind("127.0.0.1:3012").unwrap();
+    /// messages buffered per client before the slow consumer policy applies
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size.max(1);
+        self
+    }
 
-        // for stream in listener.incoming()
+    pub fn slow_consumer(mut self, slow_consumer: SlowConsumer) -> Self {
+        self.slow_consumer = slow_consumer;
+        self
+    }
 
-        let clients = self.clients.clone();
+    /// blocking; runs the server on its own runtime until Cmd::Shutdown or every sender of s_rx is dropped
+    pub fn run(&mut self, s_rx: Receiver<Cmd>) {
+        let runtime = match tokio::runtime::Builder::new_multi_thread().thread_name("ws_broadcast").enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                tracing::error!("[server] runtime error: {e:?}");
+                return;
+            }
+        };
+        runtime.block_on(async {
+            match TcpListener::bind(&self.addr).await {
+                Ok(listener) => self.serve(listener, s_rx).await,
+                Err(e) => tracing::error!("[server] bind error {}: {e:?}", &self.addr),
+            }
+        });
+    }
+
+    pub async fn serve(&self, listener: TcpListener, s_rx: Receiver<Cmd>) {
+        let hub = HubHandle::start(s_rx, self.queue_size, self.slow_consumer.clone());
+        let mut connections = JoinSet::new();
         loop {
-            match tcp_listener.accept() {
-                Ok((tcp_stream, _addr)) => {
-                    let tcp_stream = Arc::new(tcp_stream);
-
-                    match tungstenite::accept(tcp_stream.try_clone().unwrap()) {
-                        Ok(ws) => {
-
-                            // prevent read() from blocking everything; has to be done after handshake
-                            tcp_stream.set_nonblocking(true).unwrap();
-
-                            // command/control thread may want to write so need to get a lock
-                            let ws_arc: Arc<Mutex<WebSocket<TcpStream>>> = Arc::new(Mutex::new(ws));
-
-
-                            clients.lock().unwrap().push(ws_arc.clone());
-
-
-                            // listen for control commands (inter-thread)
-                            let ws0 = ws_arc.clone();
-                            let s_rx2 = s_rx.clone();
-
-                            let self2 = self.clone();
-                            let h = spawn(move || {
-                                Server::control_comms(s_rx2, ws0, self2); }
-                            );
-                            handles.push(h);
-
-                            // Read loop
-                            let ws2 = ws_arc.clone();
-                            let handle = spawn(move || {
-
-                                // read loop
-                                loop {
-                                    {
-                                        let mut ws2 = ws2.lock().unwrap();
-
-                                        // read...
-                                        match ws2.read() {
-                                            Ok(msg) => {
-                                                match msg {
-                                                    Message::Text(txt) => {
-                                                        tracing::info!("[server] received text: {}", &txt);
-                                                        match ws2.send(Message::Text(format!("server rcvd: {txt}"))) {
-                                                            Ok(_) => {},
-                                                            Err(e) => {
-                                                                tracing::error!("[server] send after close: {e:?}");
-                                                                break;
-                                                            }
-                                                        }
-                                                    }
-                                                    Message::Ping(_ping) => {
-                                                        tracing::info!("[server] rcvd: PING");
-                                                        let _ = ws2.send(Message::Pong(vec![]));
-                                                    },
-                                                    // Message::Binary(Vec<u8>)=>{},
-                                                    // Message::Pong(Vec<u8>)=>{},
-                                                    // Message::Close(Option<CloseFrame<'static>>),
-                                                    // Message::Frame(Frame),
-                                                    _ => {}
-                                                }
-                                            }
-                                            Err(_e) => {}, // tracing::error!("[server] read error {e:?}");
-                                        }
-                                    }
-                                    // mutex lock released
-
-                                    // tiny sleep; what's the right number? no clue, just need to not block the websocket w/read() in case we need to send something
-                                    std::thread::sleep(Duration::from_millis(SERVER_READ_POLL_MS));
-                                }
-                            });
-                            handles.push(handle);
-                        }
-                        Err(e) => tracing::error!("[server] no-block-websocket accept error: {e:?}"),
+            tokio::select! {
+                _ = hub.closed() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((tcp_stream, _addr)) => {
+                        connections.spawn(Server::connection(tcp_stream, hub.clone()));
                     }
+                    Err(e) => tracing::error!("[server] TcpStream accept error: {e:?}"),
                 },
-                Err(e) => tracing::error!("[server] TcpStream accept error: {e:?}"),
+                // reap finished connections so the set doesn't grow
+                Some(_) = connections.join_next(), if !connections.is_empty() => {},
             }
         }
 
-        // for h in handles {
-        //     h.join().unwrap();
-        // }
-
+        // the hub dropped every send queue, so each connection is closing its socket
+        let _ = tokio::time::timeout(Duration::from_secs(TEST_SHUTDOWN_TIMER_SEC), async { while connections.join_next().await.is_some() {} }).await;
+        tracing::info!("[server] shut down");
     }
 
+    /// one task per socket: drain the send queue and forward whatever the client says to the hub;
+    /// ws://host:3012/?format=msgpack picks the encoding
+    async fn connection(tcp_stream: TcpStream, hub: HubHandle) {
+        let mut format = WireFormat::default();
+        // the callback's error type is tungstenite's
+        #[allow(clippy::result_large_err)]
+        let negotiate = |request: &Request, response: Response| {
+            format = WireFormat::from_query(request.uri().query().and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("format="))));
+            Ok(response)
+        };
+        let ws = match tokio_tungstenite::accept_hdr_async(tcp_stream, negotiate).await {
+            Ok(ws) => ws,
+            Err(e) => {
+                tracing::error!("[server] websocket accept error: {e:?}");
+                return;
+            }
+        };
+        let (id, mut rx) = hub.connect(format).await;
 
-    fn control_comms(rx: Receiver<Cmd>, ws_arc: Arc<Mutex<WebSocket<TcpStream>>>, mut self2: Server) {
-
+        let (mut sink, mut stream) = ws.split();
         loop {
-            match rx.recv() {
-                Ok(msg) => {
-
-                    // tracing::debug!("[server::control_comms] {msg}");
-
-                    match msg {
-                        Cmd::Shutdown => {
-                            // todo: send multiple
-                            Server::shutdown(ws_arc.clone());
-                            break;
-                        },
-                        Cmd::Broadcast(msg_out) => {
-                            // tracing::debug!("[server::control_comms] outbound broadcast: {msg_out}");
-                            self2.send_broadcast(msg_out);
-                        }
-                        _ => {},
+            tokio::select! {
+                out = rx.recv() => match out {
+                    Some(frame) => if let Err(e) = sink.send(match frame { Frame::Text(txt) => Message::Text(txt), Frame::Binary(bytes) => Message::Binary(bytes) }).await {
+                        tracing::debug!("[server] client {id} send error: {e:?}");
+                        break;
+                    },
+                    // shutdown, or the hub gave up on a slow client
+                    None => break,
+                },
+                incoming = stream.next() => match incoming {
+                    Some(Ok(Message::Text(txt))) => hub.request(id, txt).await,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(e)) => {
+                        tracing::debug!("[server] client {id} read error: {e:?}");
+                        break;
                     }
+                    // tungstenite answers pings itself
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = sink.close().await;
+        hub.disconnect(id).await;
+    }
+}
+
+impl HubHandle {
+    /// spawn the hub on the current tokio runtime, fed by s_rx; it stops on Cmd::Shutdown or once
+    /// every sender of s_rx is dropped. The hub's channel and each client's send queue hold
+    /// queue_size messages
+    pub fn start(s_rx: Receiver<Cmd>, queue_size: usize, slow_consumer: SlowConsumer) -> HubHandle {
+        let queue_size = queue_size.max(1);
+        let (tx, rx) = mpsc::channel::<Event>(queue_size);
+
+        // the feed is a crossbeam channel; its blocking recv gets a plain thread, which waits for
+        // room in the hub's channel the same way
+        let feed_tx = tx.clone();
+        std::thread::spawn(move || {
+            while let Ok(cmd) = s_rx.recv() {
+                if feed_tx.blocking_send(Event::Cmd(cmd)).is_err() {
+                    return;
                 }
-                Err(e) => {
-                    tracing::error!("[server][control_comms] {e:?}");
+            }
+            let _ = feed_tx.blocking_send(Event::Cmd(Cmd::Shutdown));
+        });
 
+        tokio::spawn(Hub::new(slow_consumer).run(rx));
+        HubHandle { tx, next_id: Arc::new(AtomicU64::new(1)), queue_size }
+    }
 
-                    // TODO: this occurs when a client disconnects....
+    /// a new client; everything for it arrives on the returned queue, which closes on shutdown or
+    /// if the hub drops the client for being too slow
+    pub async fn connect(&self, format: WireFormat) -> (ClientId, mpsc::Receiver<Frame>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel::<Frame>(self.queue_size);
+        let _ = self.tx.send(Event::Connected(id, format, tx)).await;
+        (id, rx)
+    }
 
+    /// text from a client: a subscribe/unsubscribe request; waits while the hub is behind, so a
+    /// chatty client is slowed down rather than queued for
+    pub async fn request(&self, id: ClientId, txt: String) {
+        tracing::debug!("[HubHandle] client {id} received text: {}", &txt);
+        let _ = self.tx.send(Event::Request(id, txt)).await;
+    }
 
-                    // panic!("[server][control_comms]");
+    pub async fn disconnect(&self, id: ClientId) {
+        let _ = self.tx.send(Event::Disconnected(id)).await;
+    }
 
+    /// resolves once the hub has shut down
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+}
 
+impl Hub {
+    fn new(slow_consumer: SlowConsumer) -> Hub {
+        Hub { clients: HashMap::new(), chart: ViewResult::new(CHART_HISTORY_SIZE), slow_consumer }
+    }
 
+    async fn run(mut self, mut rx: mpsc::Receiver<Event>) {
+        while let Some(event) = rx.recv().await {
+            let clients = self.clients.len();
+            match event {
+                Event::Cmd(Cmd::Shutdown) => break,
+                Event::Cmd(Cmd::Broadcast(msg)) => self.send_broadcast(msg),
+                Event::Cmd(Cmd::ChartDelta(delta)) => self.send_chart_delta(delta),
+                Event::Cmd(Cmd::Alert(alert)) => self.send_alert(alert),
+                Event::Cmd(_) => {}
+                Event::Connected(id, format, tx) => {
+                    // nothing is sent until the client subscribes
+                    self.clients.insert(id, Subscriber { tx, format, subscribed: HashMap::new(), high_water: HashMap::new(), needs_snapshot: false });
+                    tracing::debug!("[Hub::run] client {id} connected ({} clients)", self.clients.len());
+                }
+                Event::Request(id, txt) => self.handle_request(id, &txt),
+                Event::Disconnected(id) => {
+                    self.clients.remove(&id);
+                    tracing::debug!("[Hub::run] client {id} disconnected ({} clients)", self.clients.len());
                 }
             }
+            // slow clients can go on any event
+            METRICS.ws_clients(self.clients.len() as i64 - clients as i64);
         }
+        tracing::info!("[Hub::run] shutting down {} clients", self.clients.len());
+        METRICS.ws_clients(-(self.clients.len() as i64));
     }
 
-    fn send_broadcast(&mut self, msg:String) {
-        for client in self.clients.lock().unwrap().iter() {
-            match client.lock().unwrap().send(Message::Text(msg.clone())) {
-                Ok(_) => {},
-                Err(e) =>{
-                    tracing::error!("[send_broadcast] send error: {e:?}");
-                },
+    /// true if the client stays; a closed queue always goes, a full one depends on the policy
+    fn keep(id: ClientId, sent: Result<(), TrySendError<Frame>>, slow_consumer: &SlowConsumer) -> bool {
+        match (sent, slow_consumer) {
+            (Ok(_), _) => true,
+            (Err(TrySendError::Full(_)), SlowConsumer::Drop) => {
+                tracing::debug!("[Hub] client {id} queue full, message dropped");
+                true
+            }
+            (Err(TrySendError::Full(_)), SlowConsumer::Disconnect) => {
+                tracing::info!("[Hub] client {id} queue full, disconnecting");
+                false
+            }
+            (Err(TrySendError::Closed(_)), _) => false,
+        }
+    }
+
+    fn send_broadcast(&mut self, msg: String) {
+        let slow_consumer = &self.slow_consumer;
+        self.clients.retain(|id, client| Hub::keep(*id, client.tx.try_send(Frame::Text(msg.clone())), slow_consumer));
+    }
+
+    /// an alert goes to every client following something, whatever it follows
+    fn send_alert(&mut self, alert: Alert) {
+        let msg = ChartMessage::Alert { alert };
+        let slow_consumer = &self.slow_consumer;
+        self.clients.retain(|id, client| {
+            if client.subscribed.is_empty() {
+                return true;
+            }
+            match Hub::frame(&msg, client.format) {
+                Some(frame) => Hub::keep(*id, client.tx.try_send(frame), slow_consumer),
+                None => true,
+            }
+        });
+    }
+
+    /// subscribe/unsubscribe from a client, answered with a snapshot of everything it now follows;
+    /// a request that doesn't parse gets an error message back
+    fn handle_request(&mut self, id: ClientId, txt: &str) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        let sent = match serde_json::from_str::<ClientRequest>(txt) {
+            Ok(ClientRequest::Subscribe(subscription)) => {
+                subscription.subscribe(&mut client.subscribed);
+                Hub::send_snapshot(client, &self.chart)
             }
+            Ok(ClientRequest::Unsubscribe(subscription)) => {
+                subscription.unsubscribe(&mut client.subscribed);
+                Hub::send_snapshot(client, &self.chart)
+            }
+            Err(e) => match Hub::frame(&ChartMessage::Error { message: e.to_string() }, client.format) {
+                Some(frame) => client.tx.try_send(frame),
+                None => Ok(()),
+            },
+        };
+        if !Hub::keep(id, sent, &self.slow_consumer) {
+            self.clients.remove(&id);
+        }
+    }
+
+    /// fold the delta into the chart, then queue for each client whatever it subscribed to that is
+    /// past its high-water marks; a client whose queue is full keeps its marks and catches up later.
+    /// A revision goes only to the clients following one of its series.
+    fn send_chart_delta(&mut self, delta: ViewDelta) {
+        self.chart.apply(&delta);
+        let chart = &self.chart;
+        let slow_consumer = &self.slow_consumer;
+        self.clients.retain(|id, client| {
+            let sent = match (delta.snapshot || client.needs_snapshot, delta.revision) {
+                (true, _) => Hub::send_snapshot(client, chart),
+                (false, true) => Hub::send_revision(client, chart, &delta),
+                (false, false) => Hub::send_append(client, chart),
+            };
+            Hub::keep(*id, sent, slow_consumer)
+        });
+    }
+
+    /// the chart's points for each revised series the client follows, from the bucket the revision
+    /// starts in at the client's resolution
+    fn send_revision(client: &mut Subscriber, chart: &ViewResult, delta: &ViewDelta) -> Result<(), TrySendError<Frame>> {
+        let datasets: Vec<ChartDataset> = delta
+            .datasets
+            .iter()
+            .filter_map(|revised| {
+                let resolution = client.subscribed.get(&revised.label)?;
+                let first = revised.data.first()?;
+                let from = match resolution {
+                    Some(resolution) => resolution.bucket(first.x),
+                    None => first.x,
+                };
+                let current = chart.datasets.iter().find(|x| x.label == revised.label)?;
+                let data: Vec<ChartTimeSeries> = current.data.iter().filter(|x| x.x >= from).cloned().collect();
+                (!data.is_empty()).then_some(ChartDataset { label: revised.label.clone(), data })
+            })
+            .collect();
+        if datasets.is_empty() {
+            return Ok(());
         }
+        if let Some(frame) = Hub::frame(&ChartMessage::Revise { datasets: Hub::downsample(&client.subscribed, &datasets) }, client.format) {
+            client.tx.try_send(frame)?;
+            Hub::raise_high_water(&mut client.high_water, &datasets);
+        }
+        Ok(())
     }
 
-    fn shutdown(ws1: Arc<Mutex<WebSocket<TcpStream>>>) {
-        tracing::error!("[server] closing in {TEST_SHUTDOWN_TIMER_SEC} seconds");
-        std::thread::sleep(Duration::from_secs(TEST_SHUTDOWN_TIMER_SEC));
-        tracing::error!("[server] closing...");
-        let mut ws1 = ws1.lock().unwrap();
-        match ws1.close(Some(CloseFrame{ code: CloseCode::Normal, reason: Default::default() })) {
-            Ok(_) => { tracing::debug!("[server] closed)"); },
-            Err(e) => {tracing::debug!("[server] close error: {e:?})"); },
+    fn send_append(client: &mut Subscriber, chart: &ViewResult) -> Result<(), TrySendError<Frame>> {
+        let datasets = Hub::unseen(&client.high_water, &Hub::subscribed(&client.subscribed, &chart.datasets));
+        if datasets.is_empty() {
+            return Ok(());
+        }
+        if let Some(frame) = Hub::frame(&ChartMessage::Append { datasets: Hub::downsample(&client.subscribed, &datasets) }, client.format) {
+            client.tx.try_send(frame)?;
+            Hub::raise_high_water(&mut client.high_water, &datasets);
+        }
+        Ok(())
+    }
+
+    fn send_snapshot(client: &mut Subscriber, chart: &ViewResult) -> Result<(), TrySendError<Frame>> {
+        client.high_water.clear();
+        client.needs_snapshot = true;
+        let datasets = Hub::subscribed(&client.subscribed, &chart.datasets);
+        if let Some(frame) = Hub::frame(&ChartMessage::Snapshot { datasets: Hub::downsample(&client.subscribed, &datasets) }, client.format) {
+            client.tx.try_send(frame)?;
+            Hub::raise_high_water(&mut client.high_water, &datasets);
+            client.needs_snapshot = false;
+        }
+        Ok(())
+    }
+
+    /// encode for one client; json goes as text
+    fn frame(msg: &ChartMessage, format: WireFormat) -> Option<Frame> {
+        match encode(msg, format) {
+            Ok(bytes) if format.is_binary() => Some(Frame::Binary(bytes)),
+            Ok(bytes) => String::from_utf8(bytes).ok().map(Frame::Text),
+            Err(e) => {
+                tracing::error!("[Hub::frame] {format} encode error: {e:?}");
+                None
+            }
         }
     }
 
+    /// only the series a client subscribed to
+    fn subscribed(subscribed: &HashMap<String, Option<Resolution>>, datasets: &[ChartDataset]) -> Vec<ChartDataset> {
+        datasets.iter().filter(|x| subscribed.contains_key(&x.label)).cloned().collect()
+    }
+
+    /// bucket each series at the resolution it was subscribed at; high-water marks stay on the raw
+    /// points so a partly filled bucket is sent again as it fills
+    fn downsample(subscribed: &HashMap<String, Option<Resolution>>, datasets: &[ChartDataset]) -> Vec<ChartDataset> {
+        datasets
+            .iter()
+            .map(|dataset| match subscribed.get(&dataset.label) {
+                Some(Some(resolution)) => ChartDataset { label: dataset.label.clone(), data: resolution.downsample(&dataset.data) },
+                _ => dataset.clone(),
+            })
+            .collect()
+    }
+
+    /// points newer than the high-water mark, plus the mark itself if its value has since changed
+    /// (an open window)
+    fn unseen(high_water: &HashMap<String, ChartTimeSeries>, datasets: &[ChartDataset]) -> Vec<ChartDataset> {
+        datasets
+            .iter()
+            .filter_map(|dataset| {
+                let data: Vec<ChartTimeSeries> = match high_water.get(&dataset.label) {
+                    None => dataset.data.clone(),
+                    Some(mark) => {
+                        let newer = dataset.data.iter().rev().take_while(|p| p.x > mark.x || (p.x == mark.x && p.y != mark.y)).count();
+                        dataset.data[dataset.data.len() - newer..].to_vec()
+                    }
+                };
+                match data.is_empty() {
+                    true => None,
+                    false => Some(ChartDataset { label: dataset.label.clone(), data }),
+                }
+            })
+            .collect()
+    }
+
+    fn raise_high_water(high_water: &mut HashMap<String, ChartTimeSeries>, sent: &[ChartDataset]) {
+        for dataset in sent.iter() {
+            if let Some(last) = dataset.data.last() {
+                high_water.insert(dataset.label.clone(), last.clone());
+            }
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use chrono::{DateTime, Utc};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::sync::mpsc;
+    use tokio::task::JoinSet;
+    use tokio_tungstenite::tungstenite::Message;
+    use common_lib::alert::Alert;
+    use common_lib::cb_ticker::Datasource;
+    use common_lib::view::{ChartMessage, ViewDelta};
+    use common_lib::wire::{decode, WireFormat};
+    use common_lib::{ChartDataset, ChartTimeSeries, SymbolCommon};
+    use crate::command::Cmd;
+    use crate::server::{Frame, Hub, Server, SlowConsumer, Subscriber};
+    use crate::subscription::Resolution;
+
+    fn point(rfc3339: &str, y: f64) -> ChartTimeSeries {
+        ChartTimeSeries { x: DateTime::<Utc>::from(DateTime::parse_from_rfc3339(rfc3339).unwrap()), y }
+    }
 
+    fn delta(label: &str, data: Vec<ChartTimeSeries>) -> ViewDelta {
+        ViewDelta { view: "v".to_string(), snapshot: false, revision: false, datasets: vec![ChartDataset { label: label.to_string(), data }] }
+    }
+
+    fn received(rx: &mut mpsc::Receiver<Frame>) -> ChartMessage {
+        match rx.try_recv().unwrap() {
+            Frame::Text(txt) => serde_json::from_str(&txt).unwrap(),
+            Frame::Binary(bytes) => decode(&bytes, WireFormat::Msgpack).unwrap(),
+        }
+    }
+
+    fn subscriber(tx: mpsc::Sender<Frame>, format: WireFormat) -> Subscriber {
+        Subscriber { tx, format, subscribed: HashMap::new(), high_water: HashMap::new(), needs_snapshot: false }
+    }
+
+    /// only points past the mark go out; a changed value at the mark (open window) goes out again
+    #[test]
+    fn test_unseen_and_high_water() {
+        let mut chart = vec![
+            ChartDataset { label: "a".to_string(), data: vec![point("2024-01-14T23:30:00Z", 1.0), point("2024-01-14T23:30:01Z", 2.0)] },
+            ChartDataset { label: "b".to_string(), data: vec![point("2024-01-14T23:30:00Z", 3.0)] },
+        ];
+        let mut high_water = HashMap::new();
+        assert_eq!(Hub::unseen(&high_water, &chart).len(), 2);
+        Hub::raise_high_water(&mut high_water, &chart);
+        assert!(Hub::unseen(&high_water, &chart).is_empty());
+
+        chart[0].data.push(point("2024-01-14T23:30:02Z", 4.0));
+        chart[1].data[0].y = 5.0;
+        let unseen = Hub::unseen(&high_water, &chart);
+        assert_eq!(unseen.len(), 2);
+        assert_eq!(unseen[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![4.0]);
+        assert_eq!(unseen[1].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![5.0]);
+    }
+
+    /// a client only gets the labels it subscribed to, bucketed at its resolution
+    #[test]
+    fn test_subscribed_and_downsample() {
+        let chart = vec![
+            ChartDataset { label: "btc_usd_Coinbase".to_string(), data: vec![point("2024-01-14T23:30:00.100Z", 1.0), point("2024-01-14T23:30:00.600Z", 2.0)] },
+            ChartDataset { label: "eth_usd_Coinbase".to_string(), data: vec![point("2024-01-14T23:30:00Z", 3.0)] },
+        ];
+        let mut subscribed = HashMap::new();
+        assert!(Hub::subscribed(&subscribed, &chart).is_empty());
+
+        subscribed.insert("btc_usd_Coinbase".to_string(), Some(Resolution::try_from("1s".to_string()).unwrap()));
+        let datasets = Hub::subscribed(&subscribed, &chart);
+        assert_eq!(datasets.len(), 1);
+        let downsampled = Hub::downsample(&subscribed, &datasets);
+        assert_eq!(downsampled[0].data.len(), 1);
+        assert_eq!(downsampled[0].data[0].y, 2.0);
+        assert_eq!(datasets[0].data.len(), 2);
+    }
+
+    /// a full queue drops the append and the client catches up from its marks; with Disconnect the
+    /// client is removed and its queue closed
+    #[test]
+    fn test_slow_consumer() {
+        let mut hub = Hub::new(SlowConsumer::Drop);
+        let (tx, mut rx) = mpsc::channel(1);
+        hub.clients.insert(1, subscriber(tx, WireFormat::Json));
+        hub.handle_request(1, r#"{"action":"subscribe","symbols":["btc_usd"]}"#);
+
+        // the snapshot is still queued, so this one is dropped
+        hub.send_chart_delta(delta("btc_usd_Coinbase", vec![point("2024-01-14T23:30:00Z", 1.0)]));
+        assert!(matches!(received(&mut rx), ChartMessage::Snapshot { datasets } if datasets.is_empty()));
+        assert!(rx.try_recv().is_err());
+
+        hub.send_chart_delta(delta("btc_usd_Coinbase", vec![point("2024-01-14T23:30:01Z", 2.0)]));
+        match received(&mut rx) {
+            ChartMessage::Append { datasets } => assert_eq!(datasets[0].data.len(), 2),
+            msg => panic!("unexpected {msg:?}"),
+        }
+
+        let mut hub = Hub::new(SlowConsumer::Disconnect);
+        let (tx, mut rx) = mpsc::channel(1);
+        hub.clients.insert(1, subscriber(tx, WireFormat::Json));
+        hub.send_broadcast("one".to_string());
+        hub.send_broadcast("two".to_string());
+        assert!(hub.clients.is_empty());
+        assert_eq!(rx.try_recv().unwrap(), Frame::Text("one".to_string()));
+        assert!(rx.try_recv().is_err());
+    }
+
+    /// msgpack clients get binary frames carrying the same message
+    #[test]
+    fn test_binary_client() {
+        let mut hub = Hub::new(SlowConsumer::Drop);
+        let (tx, mut rx) = mpsc::channel(4);
+        hub.clients.insert(1, subscriber(tx, WireFormat::Msgpack));
+        hub.handle_request(1, r#"{"action":"subscribe","symbols":["btc_usd"]}"#);
+        hub.send_chart_delta(delta("btc_usd_Coinbase", vec![point("2024-01-14T23:30:00Z", 1.0)]));
+        assert!(matches!(rx.try_recv().unwrap(), Frame::Binary(_)));
+        match received(&mut rx) {
+            ChartMessage::Append { datasets } => assert_eq!(datasets[0].data[0], point("2024-01-14T23:30:00Z", 1.0)),
+            msg => panic!("unexpected {msg:?}"),
+        }
+    }
+
+    /// a revision goes to the clients following its series, from the revised point on
+    #[test]
+    fn test_revision() {
+        let mut hub = Hub::new(SlowConsumer::Drop);
+        let (btc_tx, mut btc_rx) = mpsc::channel(4);
+        let (eth_tx, mut eth_rx) = mpsc::channel(4);
+        hub.clients.insert(1, subscriber(btc_tx, WireFormat::Json));
+        hub.clients.insert(2, subscriber(eth_tx, WireFormat::Json));
+        hub.handle_request(1, r#"{"action":"subscribe","symbols":["btc_usd"],"series":["price"]}"#);
+        hub.handle_request(2, r#"{"action":"subscribe","symbols":["eth_usd"],"series":["price"]}"#);
+        hub.send_chart_delta(delta("btc_usd_Coinbase", vec![point("2024-01-14T23:30:00Z", 1.0), point("2024-01-14T23:30:02Z", 3.0)]));
+        let _ = received(&mut btc_rx);
+        let _ = received(&mut btc_rx);
+        let _ = received(&mut eth_rx);
+
+        let mut revision = delta("btc_usd_Coinbase", vec![point("2024-01-14T23:30:01Z", 2.0), point("2024-01-14T23:30:02Z", 3.0)]);
+        revision.revision = true;
+        hub.send_chart_delta(revision);
+        match received(&mut btc_rx) {
+            ChartMessage::Revise { datasets } => assert_eq!(datasets[0].data.iter().map(|x| x.y).collect::<Vec<f64>>(), vec![2.0, 3.0]),
+            msg => panic!("unexpected {msg:?}"),
+        }
+        assert!(btc_rx.try_recv().is_err());
+        assert!(eth_rx.try_recv().is_err());
+        assert_eq!(hub.chart.datasets[0].data.len(), 3);
+    }
+
+    /// alerts skip clients that haven't subscribed to anything
+    #[test]
+    fn test_alert() {
+        let mut hub = Hub::new(SlowConsumer::Drop);
+        let (tx, mut rx) = mpsc::channel(4);
+        let (idle_tx, mut idle_rx) = mpsc::channel(4);
+        hub.clients.insert(1, subscriber(tx, WireFormat::Msgpack));
+        hub.clients.insert(2, subscriber(idle_tx, WireFormat::Json));
+        hub.handle_request(1, r#"{"action":"subscribe","symbols":["eth_usd"]}"#);
+        let _snapshot = received(&mut rx);
+
+        let alert = Alert { rule: "btc_up".to_string(), dtg: point("2024-01-14T23:30:00Z", 0.0).x, source: Datasource::Coinbase, symbol: SymbolCommon::BtcUsd, value: 1.0, message: "up".to_string() };
+        hub.send_alert(alert.clone());
+        assert!(matches!(received(&mut rx), ChartMessage::Alert { alert: a } if a == alert));
+        assert!(idle_rx.try_recv().is_err());
+    }
+
+    /// hundreds of local clients subscribe, all get the same delta, then all are closed on shutdown
+    #[test]
+    fn test_load_many_clients() {
+        const CLIENTS: usize = 300;
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}", listener.local_addr().unwrap());
+            let (s_tx, s_rx) = crossbeam_channel::unbounded::<Cmd>();
+            let server = tokio::spawn(async move { Server::new().serve(listener, s_rx).await });
+
+            // connect and subscribe; each client reports once its snapshot arrives
+            let (ready_tx, mut ready_rx) = mpsc::channel::<()>(CLIENTS);
+            let mut clients = JoinSet::new();
+            for n in 0..CLIENTS {
+                // half json, half msgpack
+                let format = if n % 2 == 0 { WireFormat::Json } else { WireFormat::Msgpack };
+                let (url, ready_tx) = (format!("{url}/?format={format}"), ready_tx.clone());
+                clients.spawn(async move {
+                    let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+                    ws.send(Message::Text(r#"{"action":"subscribe","symbols":["btc_usd"]}"#.to_string())).await.unwrap();
+                    let mut appended = vec![];
+                    while let Some(Ok(msg)) = ws.next().await {
+                        let chart_msg = match msg {
+                            Message::Text(txt) => decode(txt.as_bytes(), WireFormat::Json).unwrap(),
+                            Message::Binary(bytes) => decode(&bytes, format).unwrap(),
+                            Message::Close(_) => break,
+                            _ => continue,
+                        };
+                        match chart_msg {
+                            ChartMessage::Snapshot { .. } => ready_tx.send(()).await.unwrap(),
+                            ChartMessage::Append { datasets } | ChartMessage::Revise { datasets } => appended.extend(datasets.into_iter().map(|x| x.label)),
+                            ChartMessage::Error { message } => panic!("{message}"),
+                            ChartMessage::Alert { alert } => panic!("{alert:?}"),
+                        }
+                    }
+                    appended
+                });
+            }
+            for _ in 0..CLIENTS {
+                ready_rx.recv().await.unwrap();
+            }
+
+            let mut delta = delta("btc_usd_Coinbase", vec![point("2024-01-14T23:30:00Z", 1.0)]);
+            delta.datasets.push(ChartDataset { label: "eth_usd_Coinbase".to_string(), data: vec![point("2024-01-14T23:30:00Z", 2.0)] });
+            s_tx.send(Cmd::ChartDelta(delta)).unwrap();
+            s_tx.send(Cmd::Shutdown).unwrap();
+
+            let done = tokio::time::timeout(Duration::from_secs(30), async {
+                let mut count = 0;
+                while let Some(appended) = clients.join_next().await {
+                    assert_eq!(appended.unwrap(), vec!["btc_usd_Coinbase".to_string()]);
+                    count += 1;
+                }
+                count
+            });
+            assert_eq!(done.await.unwrap(), CLIENTS);
+            server.await.unwrap();
+        });
+    }
+}
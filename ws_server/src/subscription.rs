@@ -0,0 +1,124 @@
+//! subscription.rs
+//!
+//! what a websocket client asks for: which symbols, datasources and series, and at what resolution
+//!
+//! {"action":"subscribe","symbols":["eth_usd"],"sources":["coinbase"],"series":["price",{"calc":"MovingAvg0100"}],"resolution":"1s"}
+//! {"action":"unsubscribe","symbols":["eth_usd"]}
+//! {"action":"subscribe","symbols":["btc_usd"],"series":["price"],"strategies":["ma_turn"]}
+//!
+//! Empty or missing lists mean "all", except strategies: paper strategies' series are only sent
+//! for the ones named. No resolution means every point.
+
+use std::collections::HashMap;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
+use common_lib::cb_ticker::Datasource;
+use common_lib::paper::PaperField;
+use common_lib::view::ViewSeries;
+pub use common_lib::view::Resolution;
+use common_lib::SymbolCommon;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientRequest {
+    Subscribe(Subscription),
+    Unsubscribe(Subscription),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Subscription {
+    #[serde(default)]
+    pub symbols: Vec<SymbolCommon>,
+    #[serde(default)]
+    pub sources: Vec<Datasource>,
+    #[serde(default)]
+    pub series: Vec<ViewSeries>,
+    /// paper strategies, see common_lib::paper
+    #[serde(default)]
+    pub strategies: Vec<String>,
+    #[serde(default)]
+    pub resolution: Option<Resolution>,
+}
+
+impl Subscription {
+    /// every chart label this subscription covers
+    pub fn labels(&self) -> Vec<String> {
+        let symbols: Vec<SymbolCommon> = if self.symbols.is_empty() { SymbolCommon::iter().collect() } else { self.symbols.clone() };
+        let sources: Vec<Datasource> = if self.sources.is_empty() { Datasource::iter().collect() } else { self.sources.clone() };
+        let series: Vec<ViewSeries> = if self.series.is_empty() { ViewSeries::all() } else { self.series.clone() };
+
+        let mut labels = vec![];
+        for symbol in symbols.iter() {
+            for source in sources.iter() {
+                for s in series.iter() {
+                    labels.push(s.label(symbol, source));
+                }
+            }
+        }
+        for strategy in self.strategies.iter() {
+            labels.extend(PaperField::iter().map(|field| field.label(strategy)));
+        }
+        labels
+    }
+
+    /// add this subscription's labels to a client's set; a later resolution replaces an earlier one
+    pub fn subscribe(&self, subscribed: &mut HashMap<String, Option<Resolution>>) {
+        for label in self.labels() {
+            subscribed.insert(label, self.resolution.clone());
+        }
+    }
+
+    pub fn unsubscribe(&self, subscribed: &mut HashMap<String, Option<Resolution>>) {
+        for label in self.labels() {
+            subscribed.remove(&label);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::subscription::{ClientRequest, Resolution};
+
+    #[test]
+    fn test_subscribe_unsubscribe() {
+        let mut subscribed = HashMap::new();
+        let request = r#"{"action":"subscribe","symbols":["eth_usd"],"sources":["coinbase"],"series":["price",{"calc":"MovingAvg0100"}],"resolution":"1s"}"#;
+        match serde_json::from_str::<ClientRequest>(request).unwrap() {
+            ClientRequest::Subscribe(s) => s.subscribe(&mut subscribed),
+            _ => panic!("expected subscribe"),
+        }
+        let mut labels: Vec<&String> = subscribed.keys().collect();
+        labels.sort();
+        assert_eq!(labels, vec!["eth_usd_Coinbase", "eth_usd_MovingAvg0100_Coinbase"]);
+        assert_eq!(subscribed["eth_usd_Coinbase"], Some(Resolution { millis: 1000 }));
+
+        // everything for btc: 2 sources x (price + every calc)
+        match serde_json::from_str::<ClientRequest>(r#"{"action":"subscribe","symbols":["btc_usd"]}"#).unwrap() {
+            ClientRequest::Subscribe(s) => s.subscribe(&mut subscribed),
+            _ => panic!("expected subscribe"),
+        }
+        assert_eq!(subscribed.len(), 2 + 14);
+
+        match serde_json::from_str::<ClientRequest>(r#"{"action":"unsubscribe","symbols":["eth_usd"]}"#).unwrap() {
+            ClientRequest::Unsubscribe(s) => s.unsubscribe(&mut subscribed),
+            _ => panic!("expected unsubscribe"),
+        }
+        assert_eq!(subscribed.len(), 14);
+        assert!(subscribed.keys().all(|x| x.starts_with("btc_usd")));
+
+        // a paper strategy's four series, on top of btc's price
+        match serde_json::from_str::<ClientRequest>(r#"{"action":"subscribe","symbols":["btc_usd"],"series":["price"],"strategies":["ma_turn"]}"#).unwrap() {
+            ClientRequest::Subscribe(s) => s.subscribe(&mut subscribed),
+            _ => panic!("expected subscribe"),
+        }
+        assert_eq!(subscribed.len(), 14 + 4);
+        assert!(subscribed.contains_key("paper_ma_turn_equity"));
+
+        for resolution in ["1d", "0s", "144115188075855872h"] {
+            let request = format!(r#"{{"action":"subscribe","resolution":"{resolution}"}}"#);
+            assert!(serde_json::from_str::<ClientRequest>(&request).is_err(), "{resolution}");
+        }
+        assert!(serde_json::from_str::<ClientRequest>(r#"{"action":"subscribe","symbols":["doge_usd"]}"#).is_err());
+    }
+}